@@ -0,0 +1,98 @@
+use crate::device::Backend;
+use crate::hub::{self, ModelFiles};
+use crate::provider::{EmbeddingProvider, SPECIAL_TOKENS};
+use anyhow::{Context, Result};
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use std::fs;
+use tokenizers::{Encoding, Tokenizer};
+
+// Texts run through the model at once
+const BATCH_SIZE: usize = 32;
+
+// A BERT-family sentence embedding model from the Hugging Face Hub, run with
+// candle instead of libtorch: mean pooling over the last hidden states of the
+// real tokens, then L2 normalization, as sentence-transformers does
+pub struct BertProvider {
+    model: BertModel,
+    // Pads and truncates batches for the model
+    tokenizer: Tokenizer,
+    // Tokenizes whole texts to size chunks
+    counter: Tokenizer,
+    device: Device,
+    max_tokens: usize,
+}
+
+impl BertProvider {
+    // Load a model repository such as `sentence-transformers/all-MiniLM-L6-v2`,
+    // or a directory with the same files
+    pub fn new(name: &str, backend: &Backend) -> Result<Self> {
+        let files = ModelFiles::open(name)?;
+        let config: Config = serde_json::from_str(&fs::read_to_string(files.get("config.json")?)?)
+            .with_context(|| format!("{} is not a BERT model", name))?;
+        let max_tokens = files.max_tokens()?;
+        let (tokenizer, counter) = files.tokenizers(max_tokens)?;
+
+        let device = backend.candle.clone();
+        let weights = files.get("model.safetensors")?;
+        // Safety: the weights file isn't modified while mapped
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)
+            .with_context(|| format!("Failed to load weights of {}", name))?;
+
+        Ok(Self {
+            model,
+            tokenizer,
+            counter,
+            device,
+            max_tokens,
+        })
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(anyhow::Error::msg)?;
+        let stack = |field: fn(&Encoding) -> &[u32]| -> Result<Tensor> {
+            let rows = encodings
+                .iter()
+                .map(|encoding| Tensor::new(field(encoding), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Ok(Tensor::stack(&rows, 0)?)
+        };
+        let ids = stack(Encoding::get_ids)?;
+        let type_ids = stack(Encoding::get_type_ids)?;
+        let mask = stack(Encoding::get_attention_mask)?;
+
+        let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+        // Average over the tokens that aren't padding
+        let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        let pooled = hidden
+            .broadcast_mul(&mask)?
+            .sum(1)?
+            .broadcast_div(&mask.sum(1)?)?;
+        let norms = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+        Ok(pooled.broadcast_div(&norms)?.to_vec2::<f32>()?)
+    }
+}
+
+impl EmbeddingProvider for BertProvider {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(self.embed_batch(batch)?);
+        }
+        Ok(vectors)
+    }
+
+    // Most tokens a chunk can hold without being truncated by the model
+    fn max_chunk_tokens(&self) -> usize {
+        self.max_tokens - SPECIAL_TOKENS
+    }
+
+    fn token_starts(&self, text: &str) -> Vec<usize> {
+        hub::token_starts(&self.counter, text)
+    }
+}
@@ -0,0 +1,26 @@
+// Binary quantization: one bit per dimension, set where the component is
+// positive, packed 64 to a word. Vectors with many bits in common point the
+// same way, so the Hamming distance between codes ranks vectors roughly as
+// cosine distance does, at a fraction of the cost.
+
+pub fn encode(vector: &[f32]) -> Vec<u64> {
+    let mut words = vec![0u64; vector.len().div_ceil(64)];
+    for (i, &x) in vector.iter().enumerate() {
+        if x > 0.0 {
+            words[i / 64] |= 1 << (i % 64);
+        }
+    }
+    words
+}
+
+// Number of bits that differ between two codes
+pub fn hamming(a: &[u64], b: &[u64]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+// Cosine distance estimated from the share of bits that differ, which
+// approximates the angle between the vectors as a fraction of pi
+pub fn estimated_distance(hamming: u32, dimension: usize) -> f32 {
+    let angle = std::f32::consts::PI * hamming as f32 / dimension.max(1) as f32;
+    1.0 - angle.cos()
+}
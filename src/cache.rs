@@ -0,0 +1,222 @@
+use crate::provider::ModelSpec;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 8] = b"ARWEMBC2";
+// Vectors kept; beyond this the least recently used are dropped when the
+// cache is opened
+const MAX_ENTRIES: usize = 50_000;
+
+type Key = [u8; 32];
+
+// Vectors of previously embedded texts keyed by the SHA-256 of the text, so
+// re-adding unchanged chunks or repeating a query skips the model. Kept in
+// `<database>.embeddings` next to the store: a header naming the model and
+// its vector length, followed by fixed-size records appended as texts are
+// embedded or reused.
+// A later record for the same text marks it as more recently used.
+pub struct EmbeddingCache {
+    // None for a cache that is never written
+    path: Option<PathBuf>,
+    model: String,
+    // Vector length, learned from the first vector if the model doesn't say
+    dim: Option<usize>,
+    entries: HashMap<Key, Vec<f32>>,
+    // Keys from least to most recently used
+    order: Vec<Key>,
+    // Entries added since the cache was opened or last saved
+    pending: Vec<Key>,
+    // Records in the file, including repeats of the same text
+    records: usize,
+    // Whether the file must be rewritten rather than appended to
+    rewrite: bool,
+}
+
+impl EmbeddingCache {
+    // Open the cache for a store. A missing, damaged or other-model cache
+    // starts out empty and is replaced on the next save.
+    pub fn open(store_path: &Path, model: &ModelSpec) -> Self {
+        let path = cache_path(store_path);
+        let mut cache = Self::in_memory(model);
+        match fs::read(&path) {
+            Ok(bytes) => cache.rewrite = !cache.read_records(&bytes),
+            Err(_) => cache.rewrite = true,
+        }
+        // Compact once repeated records make up most of the file
+        if cache.records > 2 * cache.order.len().max(MAX_ENTRIES / 10) {
+            cache.rewrite = true;
+        }
+        if cache.order.len() > MAX_ENTRIES {
+            let dropped: Vec<Key> = cache
+                .order
+                .drain(..cache.order.len() - MAX_ENTRIES)
+                .collect();
+            for key in dropped {
+                cache.entries.remove(&key);
+            }
+            cache.rewrite = true;
+        }
+        cache.path = Some(path);
+        cache
+    }
+
+    // A cache for this process only, for encrypted stores, whose texts the
+    // hashes in a cache file would help guess
+    pub fn in_memory(model: &ModelSpec) -> Self {
+        Self {
+            path: None,
+            model: model.to_string(),
+            dim: model.dimension(),
+            entries: HashMap::new(),
+            order: Vec::new(),
+            pending: Vec::new(),
+            records: 0,
+            rewrite: false,
+        }
+    }
+
+    pub fn get(&self, text: &str) -> Option<&[f32]> {
+        self.entries.get(&key(text)).map(Vec::as_slice)
+    }
+
+    // Mark a cached text as just used, so it outlives older entries
+    pub fn touch(&mut self, text: &str) {
+        let key = key(text);
+        if !self.entries.contains_key(&key) {
+            return;
+        }
+        if let Some(position) = self.order.iter().rposition(|k| *k == key) {
+            self.order.remove(position);
+        }
+        self.order.push(key);
+        self.pending.push(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn insert(&mut self, text: &str, vector: Vec<f32>) {
+        match self.dim {
+            Some(dim) if vector.len() != dim => return,
+            Some(_) => {}
+            None => self.dim = Some(vector.len()),
+        }
+        let key = key(text);
+        if self.entries.insert(key, vector).is_none() {
+            self.order.push(key);
+            self.pending.push(key);
+        }
+    }
+
+    // Write new entries to disk, appending to the file when it is intact
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let (path, dim) = match (&self.path, self.dim) {
+            (Some(path), Some(dim)) => (path, dim),
+            _ => return Ok(()),
+        };
+        if self.pending.is_empty() && !self.rewrite {
+            return Ok(());
+        }
+
+        if self.rewrite {
+            self.records = self.order.len();
+            let tmp_path = path.with_extension("embeddings.tmp");
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(&header(&self.model, dim))?;
+            for key in &self.order {
+                write_record(&mut writer, key, &self.entries[key])?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            drop(writer);
+            fs::rename(&tmp_path, path)?;
+        } else {
+            let mut writer = BufWriter::new(OpenOptions::new().append(true).open(path)?);
+            for key in &self.pending {
+                write_record(&mut writer, key, &self.entries[key])?;
+            }
+            writer.flush()?;
+            self.records += self.pending.len();
+        }
+        self.pending.clear();
+        self.rewrite = false;
+        Ok(())
+    }
+
+    // Load the records of a cache file. Returns false if the file is for
+    // another model or damaged; a record cut short by a crash is ignored.
+    fn read_records(&mut self, bytes: &[u8]) -> bool {
+        let name_header = header(&self.model, 0);
+        let name_len = name_header.len() - 4;
+        if bytes.len() < name_header.len() || bytes[..name_len] != name_header[..name_len] {
+            return false;
+        }
+        let dim = u32::from_le_bytes([
+            bytes[name_len],
+            bytes[name_len + 1],
+            bytes[name_len + 2],
+            bytes[name_len + 3],
+        ]) as usize;
+        if dim == 0 || self.dim.is_some_and(|expected| expected != dim) {
+            return false;
+        }
+        self.dim = Some(dim);
+
+        let header = header(&self.model, dim);
+        let record_len = 32 + dim * 4;
+        let mut records = bytes[header.len()..].chunks_exact(record_len);
+        let mut keys = Vec::new();
+        for record in &mut records {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&record[..32]);
+            let vector = record[32..]
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+            self.entries.insert(key, vector);
+            keys.push(key);
+        }
+        self.records = keys.len();
+
+        // Order keys by their last record
+        let mut seen = HashSet::new();
+        self.order = keys
+            .into_iter()
+            .rev()
+            .filter(|key| seen.insert(*key))
+            .collect();
+        self.order.reverse();
+        records.remainder().is_empty()
+    }
+}
+
+fn cache_path(store_path: &Path) -> PathBuf {
+    let mut path = store_path.as_os_str().to_owned();
+    path.push(".embeddings");
+    PathBuf::from(path)
+}
+
+// Magic bytes, the length-prefixed model name and the vector length
+fn header(model: &str, dim: usize) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&(model.len() as u32).to_le_bytes());
+    header.extend_from_slice(model.as_bytes());
+    header.extend_from_slice(&(dim as u32).to_le_bytes());
+    header
+}
+
+fn key(text: &str) -> Key {
+    Sha256::digest(text.as_bytes()).into()
+}
+
+fn write_record(writer: &mut impl Write, key: &Key, vector: &[f32]) -> std::io::Result<()> {
+    writer.write_all(key)?;
+    for value in vector {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use std::path::Path;
+
+// How a language delimits blocks
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    Braces,
+    Indent,
+}
+
+// A source language with a chunking heuristic
+#[derive(Clone, Copy)]
+pub struct Language {
+    syntax: Syntax,
+    // Joins a nested symbol to its parent, e.g. `Store::save` or `Store.save`
+    separator: &'static str,
+}
+
+// A top-level item of a source file (function, type, impl block, ...), or the
+// code between items, with the symbol it defines if one was recognized
+pub struct Unit {
+    pub start: usize,
+    pub end: usize,
+    pub symbol: Option<String>,
+}
+
+// Keywords followed by the name of the item they declare
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "fn", "struct", "enum", "trait", "union", "mod", "class", "interface", "function", "func",
+    "def", "type", "namespace", "record", "object", "macro_rules",
+];
+
+// Keywords declaring items whose bodies hold further items
+const CONTAINER_KEYWORDS: &[&str] = &[
+    "impl", "class", "trait", "mod", "interface", "namespace", "object", "extension",
+];
+
+// Keywords that can precede a parenthesis without declaring anything
+const CONTROL_KEYWORDS: &[&str] = &[
+    "if", "for", "while", "switch", "match", "return", "catch", "sizeof", "else",
+];
+
+// Language for a source file, judged by its extension
+pub fn detect(path: &Path) -> Option<Language> {
+    let extension = path.extension()?.to_str()?;
+    let (syntax, separator) = match extension {
+        "rs" | "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" => (Syntax::Braces, "::"),
+        "go" | "java" | "js" | "jsx" | "ts" | "tsx" | "cs" | "kt" | "swift" | "scala"
+        | "php" | "dart" => (Syntax::Braces, "."),
+        "py" => (Syntax::Indent, "."),
+        _ => return None,
+    };
+    Some(Language { syntax, separator })
+}
+
+// Split a source file into its top-level units
+pub fn units(text: &str, language: Language) -> Vec<Unit> {
+    let ranges = match language.syntax {
+        Syntax::Braces => brace_units(text, 0, text.len(), 0),
+        Syntax::Indent => indent_units(text, 0, text.len(), 0),
+    };
+    to_units(text, ranges, language, None)
+}
+
+// Split a unit into the units nested one level inside it, such as the methods
+// of a class or impl block. Symbols are qualified with the parent's. Units
+// that aren't containers (a function body, say) have no nested units.
+pub fn nested_units(text: &str, unit: &Unit, language: Language) -> Vec<Unit> {
+    let is_container = declaration(&text[unit.start..unit.end])
+        .map(|line| identifiers(line).iter().any(|word| CONTAINER_KEYWORDS.contains(word)))
+        .unwrap_or(false);
+    if !is_container {
+        return Vec::new();
+    }
+
+    let ranges = match language.syntax {
+        Syntax::Braces => brace_units(text, unit.start, unit.end, 1),
+        Syntax::Indent => {
+            let body_indent = lines(text, unit.start, unit.end)
+                .filter(|&(start, end)| !text[start..end].trim().is_empty())
+                .nth(1)
+                .map(|(start, end)| indentation(&text[start..end]))
+                .unwrap_or(0);
+            if body_indent == 0 {
+                return Vec::new();
+            }
+            indent_units(text, unit.start, unit.end, body_indent)
+        }
+    };
+    to_units(text, ranges, language, unit.symbol.as_deref())
+}
+
+fn to_units(
+    text: &str,
+    ranges: Vec<(usize, usize)>,
+    language: Language,
+    parent: Option<&str>,
+) -> Vec<Unit> {
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let symbol = symbol(&text[start..end], language.syntax);
+            let symbol = match (parent, symbol) {
+                (Some(parent), Some(symbol)) if symbol != parent => {
+                    Some(format!("{}{}{}", parent, language.separator, symbol))
+                }
+                (Some(parent), _) => Some(parent.to_string()),
+                (None, symbol) => symbol,
+            };
+            Unit { start, end, symbol }
+        })
+        .collect()
+}
+
+// Byte ranges of the lines in `text[start..end]`, including line endings
+fn lines(text: &str, start: usize, end: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut line_start = start;
+    text[start..end].split_inclusive('\n').map(move |line| {
+        let range = (line_start, line_start + line.len());
+        line_start = range.1;
+        range
+    })
+}
+
+// Units separated by blank lines at brace depth `level`, each ending when a
+// block that opened inside it closes back to `level`
+fn brace_units(text: &str, start: usize, end: usize, level: usize) -> Vec<(usize, usize)> {
+    let mut units = Vec::new();
+    let mut depth = 0;
+    let mut in_comment = false;
+    let mut unit_start = start;
+    let mut has_content = false;
+
+    for (line_start, line_end) in lines(text, start, end) {
+        let line = &text[line_start..line_end];
+        let before = depth;
+        depth = brace_depth(depth, line, &mut in_comment);
+
+        if before <= level && line.trim().is_empty() {
+            if has_content {
+                units.push((unit_start, line_start));
+            }
+            unit_start = line_end;
+            has_content = false;
+        } else if before > level && depth <= level {
+            units.push((unit_start, line_end));
+            unit_start = line_end;
+            has_content = false;
+        } else {
+            has_content |= !line.trim().is_empty();
+        }
+    }
+    if has_content {
+        units.push((unit_start, end));
+    }
+    merge_closing_lines(text, units)
+}
+
+// Fold units that are nothing but closing delimiters (the `}` ending an impl
+// block once its methods are split off) into the unit before them
+fn merge_closing_lines(text: &str, units: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in units {
+        let closing_only = text[start..end]
+            .chars()
+            .all(|c| c.is_whitespace() || matches!(c, '}' | ')' | ']' | ';'));
+        match merged.last_mut() {
+            Some(last) if closing_only => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// Brace depth after a line, skipping braces in strings, character literals
+// and comments
+fn brace_depth(mut depth: usize, line: &str, in_comment: &mut bool) -> usize {
+    let chars: Vec<char> = line.chars().collect();
+    let mut in_string: Option<char> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if *in_comment {
+            if c == '*' && next == Some('/') {
+                *in_comment = false;
+                i += 1;
+            }
+        } else if let Some(quote) = in_string {
+            if c == '\\' {
+                i += 1;
+            } else if c == quote {
+                in_string = None;
+            }
+        } else {
+            match (c, next) {
+                ('/', Some('/')) => break,
+                ('/', Some('*')) => {
+                    *in_comment = true;
+                    i += 1;
+                }
+                ('"', _) | ('`', _) => in_string = Some(c),
+                // Character literals like '{' or '\n', but not Rust lifetimes
+                ('\'', Some('\\')) => i += 3,
+                ('\'', Some(_)) if chars.get(i + 2) == Some(&'\'') => i += 2,
+                ('{', _) => depth += 1,
+                ('}', _) => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    depth
+}
+
+// Units starting at each `def`/`class` (with its decorators) at the given
+// indentation, with any other code at that indentation grouped between them
+fn indent_units(text: &str, start: usize, end: usize, indent: usize) -> Vec<(usize, usize)> {
+    let mut units = Vec::new();
+    let mut unit_start = start;
+    let mut has_content = false;
+    let mut in_definition = false;
+    let mut after_decorator = false;
+
+    for (line_start, line_end) in lines(text, start, end) {
+        let line = &text[line_start..line_end];
+        let trimmed = line.trim();
+        if trimmed.is_empty() || indentation(line) != indent {
+            continue;
+        }
+
+        let starts_definition = is_definition(trimmed) || trimmed.starts_with('@');
+        let split = if starts_definition {
+            !after_decorator
+        } else {
+            in_definition
+        };
+        if split && has_content {
+            units.push((unit_start, line_start));
+            unit_start = line_start;
+        }
+        if split || !has_content {
+            in_definition = starts_definition;
+        }
+        has_content = true;
+        after_decorator = trimmed.starts_with('@');
+    }
+    if has_content {
+        units.push((unit_start, end));
+    }
+    units
+}
+
+fn is_definition(line: &str) -> bool {
+    ["def ", "async def ", "class "]
+        .iter()
+        .any(|keyword| line.starts_with(keyword))
+}
+
+fn indentation(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+// First line of a unit that isn't a comment, attribute or decorator
+fn declaration(unit: &str) -> Option<&str> {
+    unit.lines().map(str::trim).find(|line| {
+        !line.is_empty()
+            && !["//", "/*", "*", "#", "@", "\"\"\""]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+    })
+}
+
+// Name of the item a unit declares
+fn symbol(unit: &str, syntax: Syntax) -> Option<String> {
+    let declaration = declaration(unit)?;
+    let words = identifiers(declaration);
+
+    if syntax == Syntax::Braces {
+        // `impl Trait for Type` and `impl Type` are named after the type
+        if let Some(position) = words.iter().position(|&word| word == "impl") {
+            let after = &words[position + 1..];
+            let name = match after.iter().position(|&word| word == "for") {
+                Some(for_position) => after.get(for_position + 1),
+                None => after.iter().find(|word| word.chars().next().is_some_and(char::is_uppercase)),
+            };
+            return name.or(after.first()).map(|name| name.to_string());
+        }
+    }
+
+    for (i, word) in words.iter().enumerate() {
+        if DECLARATION_KEYWORDS.contains(word) {
+            // Go methods put the receiver between `func` and the name
+            let rest = declaration[declaration.find(word)? + word.len()..].trim_start();
+            if *word == "func" && rest.starts_with('(') {
+                let after_receiver = &rest[rest.find(')')? + 1..];
+                return identifiers(after_receiver).first().map(|name| name.to_string());
+            }
+            return words.get(i + 1).map(|name| name.to_string());
+        }
+    }
+
+    // Otherwise a C-style function: the identifier right before the first `(`
+    let before_paren = &declaration[..declaration.find('(')?];
+    let name = *identifiers(before_paren).last()?;
+    let is_keyword = CONTROL_KEYWORDS.contains(&name)
+        || identifiers(before_paren)
+            .first()
+            .is_some_and(|first| CONTROL_KEYWORDS.contains(first));
+    if is_keyword {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+fn identifiers(text: &str) -> Vec<&str> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.chars().next().is_some_and(|c| !c.is_ascii_digit()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUST: &str = "\
+use std::fmt;
+
+/// A store
+pub struct Store {
+    path: String,
+}
+
+impl Store {
+    pub fn open(path: &str) -> Self {
+        let brace = '{';
+        Store { path: path.to_string() }
+    }
+
+    fn save(&self) {
+        // a stray } in a comment
+        println!(\"{}\", self.path);
+    }
+}
+
+fn main() {
+    if true {
+
+        println!(\"blank line inside\");
+    }
+}
+";
+
+    const PYTHON: &str = "\
+import os
+
+@decorator
+def first():
+    return 1
+
+class Store:
+    def open(self):
+        pass
+
+    def save(self):
+        pass
+";
+
+    fn symbols(units: &[Unit]) -> Vec<Option<&str>> {
+        units.iter().map(|unit| unit.symbol.as_deref()).collect()
+    }
+
+    // Units are in order, don't overlap, and lie within the text
+    fn assert_ordered(units: &[Unit], text: &str) {
+        let mut previous_end = 0;
+        for unit in units {
+            assert!(unit.start >= previous_end && unit.start < unit.end && unit.end <= text.len());
+            previous_end = unit.end;
+        }
+    }
+
+    #[test]
+    fn detects_languages_by_extension() {
+        assert!(detect(Path::new("src/main.rs")).is_some());
+        assert!(detect(Path::new("app.py")).is_some());
+        assert!(detect(Path::new("notes.md")).is_none());
+        assert!(detect(Path::new("Makefile")).is_none());
+    }
+
+    #[test]
+    fn splits_brace_code_into_items() {
+        let language = detect(Path::new("lib.rs")).unwrap();
+        let units = units(RUST, language);
+        assert_ordered(&units, RUST);
+        assert_eq!(symbols(&units), [None, Some("Store"), Some("Store"), Some("main")]);
+        // Braces in character literals, comments and strings don't end a unit,
+        // nor does a blank line inside a block
+        assert!(RUST[units[2].start..units[2].end].ends_with("}\n}\n"));
+        assert!(RUST[units[3].start..units[3].end].contains("blank line inside"));
+    }
+
+    #[test]
+    fn qualifies_nested_brace_units() {
+        let language = detect(Path::new("lib.rs")).unwrap();
+        let units = units(RUST, language);
+        let methods = nested_units(RUST, &units[2], language);
+        assert_ordered(&methods, RUST);
+        // The block's header stays with its first item, under the parent's name
+        assert_eq!(symbols(&methods), [Some("Store"), Some("Store::save")]);
+        assert!(RUST[methods[0].start..methods[0].end].starts_with("impl Store {"));
+        assert_eq!(methods[1].end, units[2].end);
+        // A function isn't a container
+        assert!(nested_units(RUST, &units[3], language).is_empty());
+    }
+
+    #[test]
+    fn splits_indented_code_into_definitions() {
+        let language = detect(Path::new("app.py")).unwrap();
+        let units = units(PYTHON, language);
+        assert_ordered(&units, PYTHON);
+        assert_eq!(symbols(&units), [None, Some("first"), Some("Store")]);
+        // A decorator stays with the function it decorates
+        assert!(PYTHON[units[1].start..units[1].end].starts_with("@decorator"));
+
+        let methods = nested_units(PYTHON, &units[2], language);
+        assert_eq!(symbols(&methods), [Some("Store"), Some("Store.save")]);
+        assert!(PYTHON[methods[0].start..methods[0].end].contains("def open"));
+    }
+
+    #[test]
+    fn names_go_methods_after_the_receiver() {
+        let unit = "func (s *Store) Save() error {\n\treturn nil\n}\n";
+        assert_eq!(symbol(unit, Syntax::Braces).as_deref(), Some("Save"));
+        assert_eq!(symbol("if (ready) {\n}\n", Syntax::Braces), None);
+    }
+}
@@ -0,0 +1,62 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Name shown for the collection stored in the database file itself
+pub const DEFAULT_COLLECTION: &str = "default";
+
+// Named collections live as separate stores in `<database>.collections/`, each
+// with its own index parameters, embedding model, WAL and backups
+pub fn collections_dir(database: &str) -> PathBuf {
+    PathBuf::from(format!("{}.collections", database))
+}
+
+// Store path for a collection, or the database file for the default collection
+pub fn collection_path(database: &str, name: Option<&str>) -> Result<String> {
+    let name = match name {
+        None | Some(DEFAULT_COLLECTION) => return Ok(database.to_string()),
+        Some(name) => name,
+    };
+    validate_name(name)?;
+
+    let path = collections_dir(database).join(format!("{}.json", name));
+    Ok(path.to_string_lossy().to_string())
+}
+
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        bail!(
+            "Invalid collection name '{}': use letters, digits, '-' and '_'",
+            name
+        );
+    }
+    Ok(())
+}
+
+// Names of every collection in the database, including the default one if it exists
+pub fn list(database: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    if Path::new(database).exists() {
+        names.push(DEFAULT_COLLECTION.to_string());
+    }
+
+    let dir = collections_dir(database);
+    if dir.is_dir() {
+        let mut named = Vec::new();
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(name) = file_name.strip_suffix(".json") {
+                if validate_name(name).is_ok() {
+                    named.push(name.to_string());
+                }
+            }
+        }
+        named.sort();
+        names.extend(named);
+    }
+    Ok(names)
+}
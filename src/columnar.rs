@@ -0,0 +1,313 @@
+use crate::dump::DumpRecord;
+use anyhow::{bail, Context, Result};
+use arrow_array::builder::{FixedSizeListBuilder, Float32Builder};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, UInt64Type};
+use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampSecondArray, UInt64Array};
+use arrow_cast::{can_cast_types, cast};
+use arrow_ipc::reader::FileReader;
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, TimeUnit};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+// Rows per record batch, so readers can stream large exports
+const BATCH_ROWS: usize = 8192;
+
+// Columns imports take each record's text and main vector from
+pub struct Columns<'a> {
+    pub text: &'a str,
+    pub vector: &'a str,
+}
+
+// Write records as an Arrow IPC (Feather v2) file, returning how many were
+// written. Each metadata key becomes a nullable `metadata.<key>` string
+// column, and the main vector and each named vector field become
+// fixed-size-list float32 columns (`vector`, `vector.<field>`), null for
+// records without them. Records must have their vectors.
+pub fn write_ipc(out: impl Write, records: &[DumpRecord]) -> Result<usize> {
+    let mut batches = batches(records)?;
+    let first = batches.next().expect("there is always a first batch")?;
+    let mut writer = FileWriter::try_new(out, &first.schema())?;
+    writer.write(&first)?;
+    for batch in batches {
+        writer.write(&batch?)?;
+    }
+    writer.finish()?;
+    Ok(records.len())
+}
+
+// Write records as a Snappy-compressed Parquet file, with the same columns
+// as `write_ipc`
+pub fn write_parquet(out: impl Write + Send, records: &[DumpRecord]) -> Result<usize> {
+    let mut batches = batches(records)?;
+    let first = batches.next().expect("there is always a first batch")?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(out, first.schema(), Some(properties))?;
+    writer.write(&first)?;
+    for batch in batches {
+        writer.write(&batch?)?;
+    }
+    writer.close()?;
+    Ok(records.len())
+}
+
+// Read records from an Arrow IPC file, in the layout `write_ipc` writes
+pub fn read_ipc(path: &Path, columns: &Columns) -> Result<Vec<DumpRecord>> {
+    let file = File::open(path)?;
+    read_batches(FileReader::try_new(file, None)?, columns)
+}
+
+// Read records from a Parquet file, in the layout `write_parquet` writes or
+// any table with a text column and a list-of-numbers vector column
+pub fn read_parquet(path: &Path, columns: &Columns) -> Result<Vec<DumpRecord>> {
+    let file = File::open(path)?;
+    read_batches(ParquetRecordBatchReaderBuilder::try_new(file)?.build()?, columns)
+}
+
+// Record batches of at most BATCH_ROWS records. There is always at least
+// one, empty when there are no records, so writers know the schema.
+fn batches(records: &[DumpRecord]) -> Result<impl Iterator<Item = Result<RecordBatch>> + '_> {
+    let mut keys: Vec<&str> = records
+        .iter()
+        .flat_map(|record| record.metadata.keys().map(String::as_str))
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut fields: BTreeMap<&str, usize> = BTreeMap::new();
+    for record in records {
+        for (name, vector) in &record.fields {
+            fields.entry(name.as_str()).or_insert(vector.len());
+        }
+    }
+    let dimension = records.iter().find_map(|record| record.vector.as_ref()).map(Vec::len);
+    if !records.is_empty() && dimension.is_none() {
+        bail!("Records have no vectors to export");
+    }
+
+    let empty: &[DumpRecord] = &[];
+    let chunks = records.chunks(BATCH_ROWS).chain(records.is_empty().then_some(empty));
+    Ok(chunks.map(move |chunk| record_batch(chunk, &keys, dimension, &fields)))
+}
+
+fn record_batch(
+    records: &[DumpRecord],
+    keys: &[&str],
+    dimension: Option<usize>,
+    fields: &BTreeMap<&str, usize>,
+) -> Result<RecordBatch> {
+    let strings = |value: fn(&DumpRecord) -> Option<&str>| -> ArrayRef {
+        Arc::new(records.iter().map(value).collect::<StringArray>())
+    };
+    let times = |value: fn(&DumpRecord) -> Option<u64>| -> ArrayRef {
+        let seconds = records.iter().map(|record| value(record).map(|t| t as i64));
+        Arc::new(seconds.collect::<TimestampSecondArray>().with_timezone("UTC"))
+    };
+    let offsets = |value: fn(&DumpRecord) -> Option<u64>| -> ArrayRef {
+        Arc::new(records.iter().map(value).collect::<UInt64Array>())
+    };
+
+    let mut columns: Vec<(String, ArrayRef, bool)> = vec![
+        ("id".to_string(), strings(|r| r.id.as_deref()), false),
+        ("external_id".to_string(), strings(|r| r.external_id.as_deref()), true),
+        ("text".to_string(), strings(|r| Some(r.text.as_str())), false),
+        ("filename".to_string(), strings(|r| r.filename.as_deref()), true),
+        ("span_start".to_string(), offsets(|r| r.span.map(|s| s.0 as u64)), true),
+        ("span_end".to_string(), offsets(|r| r.span.map(|s| s.1 as u64)), true),
+        ("added".to_string(), times(|r| r.added), true),
+        ("updated".to_string(), times(|r| r.updated), true),
+        ("expires".to_string(), times(|r| r.expires), true),
+    ];
+    for key in keys {
+        let values = records.iter().map(|record| record.metadata.get(*key).map(String::as_str));
+        let array: ArrayRef = Arc::new(values.collect::<StringArray>());
+        columns.push((format!("metadata.{}", key), array, true));
+    }
+    if let Some(dimension) = dimension {
+        let vectors = records.iter().map(|record| record.vector.as_deref());
+        columns.push(("vector".to_string(), vector_column(vectors, dimension)?, false));
+    }
+    for (&name, &dimension) in fields {
+        let vectors = records.iter().map(|record| record.fields.get(name).map(Vec::as_slice));
+        columns.push((format!("vector.{}", name), vector_column(vectors, dimension)?, true));
+    }
+    Ok(RecordBatch::try_from_iter_with_nullable(columns)?)
+}
+
+// A fixed-size-list float32 column, null where a vector is missing
+fn vector_column<'a>(
+    vectors: impl Iterator<Item = Option<&'a [f32]>>,
+    dimension: usize,
+) -> Result<ArrayRef> {
+    let mut builder = FixedSizeListBuilder::new(Float32Builder::new(), dimension as i32);
+    for vector in vectors {
+        match vector {
+            Some(vector) if vector.len() == dimension => {
+                builder.values().append_slice(vector);
+                builder.append(true);
+            }
+            Some(vector) => bail!(
+                "Vector has {} dimensions where {} were expected",
+                vector.len(),
+                dimension
+            ),
+            None => {
+                builder.values().append_nulls(dimension);
+                builder.append(false);
+            }
+        }
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+fn read_batches(
+    batches: impl Iterator<Item = Result<RecordBatch, ArrowError>>,
+    columns: &Columns,
+) -> Result<Vec<DumpRecord>> {
+    let mut records = Vec::new();
+    for batch in batches {
+        let batch = batch?;
+        let first_row = records.len();
+        records.extend(batch_records(&batch, columns, first_row)?);
+    }
+    Ok(records)
+}
+
+// A batch's records. Columns named as in exports fill in the matching record
+// fields, `vector.<field>` columns become vector fields, and any other
+// scalar column becomes metadata, named without its `metadata.` prefix.
+fn batch_records(
+    batch: &RecordBatch,
+    columns: &Columns,
+    first_row: usize,
+) -> Result<Vec<DumpRecord>> {
+    let texts = string_values(required(batch, columns.text, "--text-col")?)?;
+    let vectors = vector_values(columns.vector, required(batch, columns.vector, "--vector-col")?)?;
+    let mut records = texts
+        .into_iter()
+        .zip(vectors)
+        .enumerate()
+        .map(|(i, (text, vector))| {
+            let text = text.with_context(|| format!("Row {} has no text", first_row + i + 1))?;
+            Ok(DumpRecord {
+                text,
+                vector,
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let schema = batch.schema();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        let name = field.name().as_str();
+        if name == columns.text || name == columns.vector {
+            continue;
+        }
+        if let Some(field) = name.strip_prefix("vector.") {
+            assign(&mut records, vector_values(name, column)?, |record, vector| {
+                if let Some(vector) = vector {
+                    record.fields.insert(field.to_string(), vector);
+                }
+            });
+            continue;
+        }
+        match name {
+            "id" => assign(&mut records, string_values(column)?, |record, id| record.id = id),
+            "external_id" => assign(&mut records, string_values(column)?, |record, id| {
+                record.external_id = id
+            }),
+            "filename" => assign(&mut records, string_values(column)?, |record, filename| {
+                record.filename = filename
+            }),
+            "added" => assign(&mut records, seconds(column)?, |record, t| record.added = t),
+            "updated" => assign(&mut records, seconds(column)?, |record, t| record.updated = t),
+            "expires" => assign(&mut records, seconds(column)?, |record, t| record.expires = t),
+            // Read below, as a pair
+            "span_start" | "span_end" => {}
+            // Lists and structs have no single text value
+            _ if field.data_type().is_nested()
+                || !can_cast_types(field.data_type(), &DataType::Utf8) => {}
+            _ => {
+                let key = name.strip_prefix("metadata.").unwrap_or(name);
+                assign(&mut records, string_values(column)?, |record, value| {
+                    if let Some(value) = value {
+                        record.metadata.insert(key.to_string(), value);
+                    }
+                });
+            }
+        }
+    }
+    if let (Some(starts), Some(ends)) =
+        (batch.column_by_name("span_start"), batch.column_by_name("span_end"))
+    {
+        let spans = u64_values(starts)?.into_iter().zip(u64_values(ends)?);
+        assign(&mut records, spans.collect(), |record, span| {
+            if let (Some(start), Some(end)) = span {
+                record.span = Some((start as usize, end as usize));
+            }
+        });
+    }
+    Ok(records)
+}
+
+fn required<'a>(batch: &'a RecordBatch, name: &str, option: &str) -> Result<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .with_context(|| format!("No '{}' column; choose another with {}", name, option))
+}
+
+fn assign<T>(records: &mut [DumpRecord], values: Vec<T>, set: impl Fn(&mut DumpRecord, T)) {
+    for (record, value) in records.iter_mut().zip(values) {
+        set(record, value);
+    }
+}
+
+fn string_values(column: &ArrayRef) -> Result<Vec<Option<String>>> {
+    let column = cast(column, &DataType::Utf8)?;
+    Ok(column.as_string::<i32>().iter().map(|value| value.map(str::to_string)).collect())
+}
+
+fn u64_values(column: &ArrayRef) -> Result<Vec<Option<u64>>> {
+    let column = cast(column, &DataType::UInt64)?;
+    Ok(column.as_primitive::<UInt64Type>().iter().collect())
+}
+
+// Seconds since the Unix epoch, from timestamps of any unit or plain integers
+fn seconds(column: &ArrayRef) -> Result<Vec<Option<u64>>> {
+    match column.data_type() {
+        DataType::Timestamp(..) => {
+            let column = cast(column, &DataType::Timestamp(TimeUnit::Second, None))?;
+            u64_values(&cast(&column, &DataType::Int64)?)
+        }
+        _ => u64_values(column),
+    }
+}
+
+// Vectors from a list or fixed-size list column of any numeric type
+fn vector_values(name: &str, column: &ArrayRef) -> Result<Vec<Option<Vec<f32>>>> {
+    let item = Arc::new(Field::new("item", DataType::Float32, true));
+    let column = cast(column, &DataType::List(item))
+        .with_context(|| format!("Column '{}' isn't a list of numbers", name))?;
+    column
+        .as_list::<i32>()
+        .iter()
+        .map(|vector| {
+            vector
+                .map(|values| {
+                    if values.null_count() > 0 {
+                        bail!("Column '{}' has a vector with missing values", name);
+                    }
+                    Ok(values.as_primitive::<Float32Type>().values().to_vec())
+                })
+                .transpose()
+        })
+        .collect()
+}
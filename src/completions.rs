@@ -0,0 +1,91 @@
+use clap::Command;
+use clap_complete::Shell;
+use std::io::Write;
+
+// Name the scripts complete, whatever the binary was invoked as
+const BIN_NAME: &str = "arrow";
+
+// Print clap's completion script for `shell`. The bash, zsh and fish scripts
+// also complete collection names after `--collection` and `collections drop`,
+// asking `arrow collections list --names` for those in the database given
+// with `-d`, or the default one.
+pub fn generate(shell: Shell, command: &mut Command, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, command, BIN_NAME, &mut script);
+    let mut script = String::from_utf8_lossy(&script).into_owned();
+    match shell {
+        Shell::Bash => script.push_str(BASH_COLLECTIONS),
+        Shell::Fish => script.push_str(FISH_COLLECTIONS),
+        Shell::Zsh => {
+            // clap's zsh script completes these with files; the helper has to
+            // be defined before the script's first call of `_arrow`
+            script = script
+                .replace(":COLLECTION:_default'", ":COLLECTION:_arrow_collections'")
+                .replace(
+                    ":name -- Name of the collection to delete:_default'",
+                    ":name -- Name of the collection to delete:_arrow_collections'",
+                );
+            let end = script.rfind("\nif [ \"$funcstack[1]\"").unwrap_or(script.len());
+            script.insert_str(end, ZSH_COLLECTIONS);
+        }
+        _ => {}
+    }
+    out.write_all(script.as_bytes())?;
+    out.flush()
+}
+
+const BASH_COLLECTIONS: &str = r#"
+_arrow_collections() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local before="${COMP_WORDS[COMP_CWORD-2]}"
+    if [[ "$prev" == "-c" || "$prev" == "--collection" ]] \
+        || [[ "$prev" == "drop" && "$before" == "collections" ]]; then
+        local database=() i
+        for ((i = 1; i < COMP_CWORD - 1; i++)); do
+            if [[ "${COMP_WORDS[i]}" == "-d" || "${COMP_WORDS[i]}" == "--database" ]]; then
+                database=(-d "${COMP_WORDS[i+1]}")
+            fi
+        done
+        local names
+        names="$(arrow "${database[@]}" collections list --names 2>/dev/null)"
+        COMPREPLY=( $(compgen -W "${names}" -- "${cur}") )
+        return 0
+    fi
+    _arrow "$@"
+}
+
+if [[ "${BASH_VERSINFO[0]}" -eq 4 && "${BASH_VERSINFO[1]}" -ge 4 \
+    || "${BASH_VERSINFO[0]}" -gt 4 ]]; then
+    complete -F _arrow_collections -o nosort -o bashdefault -o default arrow
+else
+    complete -F _arrow_collections -o bashdefault -o default arrow
+fi
+"#;
+
+const ZSH_COLLECTIONS: &str = r#"
+(( $+functions[_arrow_collections] )) ||
+_arrow_collections() {
+    local -a database names
+    local i=${words[(I)-d|--database]}
+    (( i && i < CURRENT - 1 )) && database=(-d "${(Q)words[i+1]}")
+    names=(${(f)"$(_call_program collections \
+        arrow $database collections list --names 2>/dev/null)"})
+    _describe -t collections 'collection' names
+}
+"#;
+
+const FISH_COLLECTIONS: &str = r#"
+function __fish_arrow_collections
+    set -l tokens (commandline -opc)
+    set -l database
+    for i in (seq 2 (math (count $tokens) - 1))
+        if contains -- $tokens[$i] -d --database
+            set database -d $tokens[(math $i + 1)]
+        end
+    end
+    arrow $database collections list --names 2>/dev/null
+end
+complete -c arrow -s c -l collection -x -a "(__fish_arrow_collections)"
+complete -c arrow -f -a "(__fish_arrow_collections)" \
+    -n "__fish_arrow_using_subcommand collections; and __fish_seen_subcommand_from drop"
+"#;
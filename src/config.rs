@@ -0,0 +1,156 @@
+use crate::device::DeviceChoice;
+use crate::llm::LlmProvider;
+use crate::provider::{ModelSpec, Provider};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+// Keys that can be set in the config file, in the order they are shown
+pub const KEYS: &[&str] = &[
+    "database",
+    "provider",
+    "model",
+    "device",
+    "chunk_tokens",
+    "chunk_overlap",
+    "exact_below",
+    "nprobe",
+    "llm",
+    "llm_model",
+];
+
+// User defaults from `~/.config/arrow/config.toml`. Command-line flags and
+// `ARROW_*` environment variables take precedence over these.
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_overlap: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exact_below: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nprobe: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm: Option<LlmProvider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_model: Option<String>,
+}
+
+// Location of the config file: `$ARROW_CONFIG` if set, otherwise
+// `$XDG_CONFIG_HOME/arrow/config.toml` or `~/.config/arrow/config.toml`
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("ARROW_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("arrow").join("config.toml"))
+}
+
+impl Config {
+    // Read the config file, or the empty config if there is none
+    pub fn load() -> Result<Self> {
+        let path = match path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Invalid config file: {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = path().context("Can't locate the config file; set ARROW_CONFIG or HOME")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        let content = toml::to_string(self).context("Failed to serialize config")?;
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write config file: {}", path.display()))
+    }
+
+    // The configured device, checked since the file may have been hand-edited
+    pub fn device(&self) -> Result<Option<DeviceChoice>> {
+        self.device
+            .as_deref()
+            .map(|device| device.parse().context("Invalid device in config file"))
+            .transpose()
+    }
+
+    // Value of a key as shown to the user, or None if it isn't set
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(match key {
+            "database" => self.database.clone(),
+            "provider" => self.provider.map(Provider::name),
+            "model" => self.model.clone(),
+            "device" => self.device.clone(),
+            "chunk_tokens" => self.chunk_tokens.map(|tokens| tokens.to_string()),
+            "chunk_overlap" => self.chunk_overlap.map(|tokens| tokens.to_string()),
+            "exact_below" => self.exact_below.map(|documents| documents.to_string()),
+            "nprobe" => self.nprobe.map(|lists| lists.to_string()),
+            "llm" => self.llm.map(LlmProvider::name),
+            "llm_model" => self.llm_model.clone(),
+            _ => bail!("Unknown config key '{}'; expected one of: {}", key, KEYS.join(", ")),
+        })
+    }
+
+    // Parse and set a key, or clear it when `value` is None
+    pub fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        match key {
+            "database" => self.database = value.map(str::to_string),
+            "provider" => {
+                self.provider = value
+                    .map(|value| Provider::from_str(value, true))
+                    .transpose()
+                    .map_err(|e| anyhow::anyhow!("Invalid provider: {}", e))?
+            }
+            // Checked against the configured provider, so set that first
+            "model" => {
+                if let Some(value) = value {
+                    ModelSpec::new(self.provider.unwrap_or_default(), value)?;
+                }
+                self.model = value.map(str::to_string)
+            }
+            "device" => {
+                if let Some(value) = value {
+                    value.parse::<DeviceChoice>()?;
+                }
+                self.device = value.map(str::to_string)
+            }
+            "chunk_tokens" => self.chunk_tokens = value.map(parse_count).transpose()?,
+            "chunk_overlap" => self.chunk_overlap = value.map(parse_count).transpose()?,
+            "exact_below" => self.exact_below = value.map(parse_count).transpose()?,
+            "nprobe" => self.nprobe = value.map(parse_count).transpose()?,
+            "llm" => {
+                self.llm = value
+                    .map(|value| LlmProvider::from_str(value, true))
+                    .transpose()
+                    .map_err(|e| anyhow::anyhow!("Invalid LLM provider: {}", e))?
+            }
+            "llm_model" => self.llm_model = value.map(str::to_string),
+            _ => bail!("Unknown config key '{}'; expected one of: {}", key, KEYS.join(", ")),
+        }
+        Ok(())
+    }
+}
+
+fn parse_count(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .with_context(|| format!("Expected a non-negative integer, got '{}'", value))
+}
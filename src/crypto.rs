@@ -0,0 +1,267 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use console::Term;
+use rand::RngCore;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+// First bytes of an encrypted store file
+pub const MAGIC: &[u8; 8] = b"ARWENC01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+// Magic, Argon2 memory (KiB), iterations and lanes as little-endian u32s, salt
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN;
+
+// Where the secret comes from, set from --key-file
+static KEY_FILE: OnceLock<PathBuf> = OnceLock::new();
+// The secret, once read or prompted for, so a process asks only once
+static SECRET: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+// Encryption of a store's file, WAL and segments with ChaCha20-Poly1305,
+// under a key derived from a passphrase or key file with Argon2id. The store
+// file starts with a header holding the Argon2 parameters and salt, which is
+// authenticated along with every record, so records can't be moved between
+// stores with the same passphrase.
+#[derive(Clone)]
+pub struct Cipher {
+    header: [u8; HEADER_LEN],
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    // A cipher with a fresh salt, for a new store
+    pub fn new(secret: &[u8]) -> io::Result<Self> {
+        let params = Params::default();
+        let mut header = [0; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        let fields = [params.m_cost(), params.t_cost(), params.p_cost()];
+        for (i, value) in fields.iter().enumerate() {
+            let start = MAGIC.len() + 4 * i;
+            header[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        rand::thread_rng().fill_bytes(&mut header[HEADER_LEN - SALT_LEN..]);
+        Self::derive(secret, header)
+    }
+
+    // The cipher of an encrypted store file, from its header
+    pub fn from_file(secret: &[u8], bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+            return Err(invalid("not an encrypted store"));
+        }
+        let mut header = [0; HEADER_LEN];
+        header.copy_from_slice(&bytes[..HEADER_LEN]);
+        Self::derive(secret, header)
+    }
+
+    fn derive(secret: &[u8], header: [u8; HEADER_LEN]) -> io::Result<Self> {
+        let field = |i: usize| {
+            let start = MAGIC.len() + 4 * i;
+            u32::from_le_bytes(header[start..start + 4].try_into().unwrap())
+        };
+        let params = Params::new(field(0), field(1), field(2), Some(32))
+            .map_err(|e| invalid(&format!("invalid key derivation parameters: {}", e)))?;
+        let mut key = [0; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(secret, &header[HEADER_LEN - SALT_LEN..], &mut key)
+            .map_err(|e| invalid(&format!("key derivation failed: {}", e)))?;
+        Ok(Self {
+            header,
+            aead: ChaCha20Poly1305::new(&key.into()),
+        })
+    }
+
+    // A whole store file: the header, then the sealed contents
+    pub fn seal_file(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut bytes = self.header.to_vec();
+        bytes.extend(self.seal(plaintext)?);
+        Ok(bytes)
+    }
+
+    pub fn open_file(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        if bytes.len() < HEADER_LEN || bytes[..HEADER_LEN] != self.header {
+            return Err(invalid("the store's encryption header has changed"));
+        }
+        self.open(&bytes[HEADER_LEN..])
+    }
+
+    // A random nonce followed by the ciphertext and tag
+    pub fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: plaintext,
+            aad: &self.header,
+        };
+        let ciphertext = self
+            .aead
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| invalid("encryption failed"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    // A sealed record as a line of hex, for the WAL
+    pub fn seal_line(&self, plaintext: &[u8]) -> io::Result<String> {
+        Ok(self.seal(plaintext)?.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn open_line(&self, line: &str) -> io::Result<Vec<u8>> {
+        let sealed = (0..line.len())
+            .step_by(2)
+            .map(|i| line.get(i..i + 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| invalid("malformed encrypted record"))?;
+        self.open(&sealed)
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid("truncated encrypted record"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: &self.header,
+        };
+        self.aead
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| invalid("wrong passphrase or key file, or the store is damaged"))
+    }
+}
+
+// Whether a store file is encrypted, told by its first bytes
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut magic = [0; MAGIC.len()];
+    match fs::File::open(path) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && &magic == MAGIC,
+        Err(_) => false,
+    }
+}
+
+pub fn set_key_file(path: PathBuf) {
+    let _ = KEY_FILE.set(path);
+}
+
+// The secret for an encrypted store: the contents of the key file, else
+// `$ARROW_PASSPHRASE`, else a passphrase typed at the terminal. With
+// `confirm`, as when creating a store, a typed passphrase is asked twice.
+pub fn secret(store_path: &Path, confirm: bool) -> io::Result<Vec<u8>> {
+    let mut cached = SECRET.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(secret) = cached.as_ref() {
+        return Ok(secret.clone());
+    }
+
+    let secret = if let Some(path) = KEY_FILE.get() {
+        let mut bytes = fs::read(path)?;
+        // Key files written with `echo` end in a newline
+        while bytes.last().is_some_and(|b| b.is_ascii_whitespace()) {
+            bytes.pop();
+        }
+        bytes
+    } else if let Some(passphrase) = std::env::var_os("ARROW_PASSPHRASE") {
+        passphrase.to_string_lossy().as_bytes().to_vec()
+    } else {
+        prompt(store_path, confirm)?.into_bytes()
+    };
+    if secret.is_empty() {
+        return Err(invalid("the passphrase or key file is empty"));
+    }
+    *cached = Some(secret.clone());
+    Ok(secret)
+}
+
+fn prompt(store_path: &Path, confirm: bool) -> io::Result<String> {
+    let term = Term::stderr();
+    if !term.is_term() {
+        return Err(invalid(
+            "no passphrase for the encrypted store; give --key-file or set ARROW_PASSPHRASE",
+        ));
+    }
+    term.write_str(&format!("Passphrase for {}: ", store_path.display()))?;
+    let passphrase = term.read_secure_line()?;
+    if confirm {
+        term.write_str("Repeat passphrase: ")?;
+        if term.read_secure_line()? != passphrase {
+            return Err(invalid("the passphrases don't match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cipher with the cheapest Argon2 parameters, so tests don't spend
+    // seconds deriving keys
+    fn cipher(secret: &[u8], salt: u8) -> Cipher {
+        let mut header = [salt; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        for (i, value) in [8u32, 1, 1].iter().enumerate() {
+            let start = MAGIC.len() + 4 * i;
+            header[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        Cipher::from_file(secret, &header).unwrap()
+    }
+
+    #[test]
+    fn opens_what_it_seals() {
+        let cipher = cipher(b"secret", 1);
+        let sealed = cipher.seal(b"record").unwrap();
+        assert_ne!(sealed[NONCE_LEN..].to_vec(), b"record".to_vec());
+        assert_eq!(cipher.open(&sealed).unwrap(), b"record");
+        let line = cipher.seal_line(b"record").unwrap();
+        assert_eq!(cipher.open_line(&line).unwrap(), b"record");
+        let file = cipher.seal_file(b"store").unwrap();
+        assert_eq!(cipher.open_file(&file).unwrap(), b"store");
+    }
+
+    #[test]
+    fn reads_the_parameters_back_from_the_header() {
+        let file = cipher(b"secret", 1).seal_file(b"store").unwrap();
+        let reopened = Cipher::from_file(b"secret", &file).unwrap();
+        assert_eq!(reopened.open_file(&file).unwrap(), b"store");
+        assert!(Cipher::from_file(b"secret", b"{\"documents\": []}").is_err());
+    }
+
+    #[test]
+    fn rejects_the_wrong_secret_and_other_stores() {
+        let sealed = cipher(b"secret", 1).seal(b"record").unwrap();
+        assert!(cipher(b"guess", 1).open(&sealed).is_err());
+        // Same passphrase, different salt: the record belongs to another store
+        assert!(cipher(b"secret", 2).open(&sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_damaged_records() {
+        let cipher = cipher(b"secret", 1);
+        let mut sealed = cipher.seal(b"record").unwrap();
+        *sealed.last_mut().unwrap() ^= 1;
+        assert!(cipher.open(&sealed).is_err());
+        assert!(cipher.open(&sealed[..NONCE_LEN - 1]).is_err());
+        assert!(cipher.open_line("0g").is_err());
+        assert!(cipher.open_line("abc").is_err());
+        let mut file = cipher.seal_file(b"store").unwrap();
+        file[MAGIC.len()] ^= 1;
+        assert!(cipher.open_file(&file).is_err());
+    }
+
+    #[test]
+    fn tells_encrypted_files_by_their_magic() {
+        let path = std::env::temp_dir().join(format!("arrow-crypto-{}", std::process::id()));
+        fs::write(&path, cipher(b"secret", 1).seal_file(b"store").unwrap()).unwrap();
+        assert!(is_encrypted(&path));
+        fs::write(&path, b"{}").unwrap();
+        assert!(!is_encrypted(&path));
+        fs::remove_file(&path).unwrap();
+        assert!(!is_encrypted(&path));
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// Parse a point in time given as a UTC date (`2024-05-01`), a date and time
+// (`2024-05-01T13:45`, seconds and a trailing `Z` optional), an age relative
+// to now (`30m`, `12h`, `7d`, `2w`) or raw Unix seconds
+pub fn parse(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    if let Some(age) = parse_age(value) {
+        return Ok(now().saturating_sub(age));
+    }
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+        return value.parse().map_err(|e| format!("invalid timestamp '{}': {}", value, e));
+    }
+    parse_datetime(value).ok_or_else(|| {
+        format!(
+            "invalid time '{}'; use a date like 2024-05-01, a date and time like \
+             2024-05-01T13:45, or an age like 7d",
+            value
+        )
+    })
+}
+
+// A timestamp as `YYYY-MM-DD HH:MM UTC`
+pub fn format(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / DAY) as i64);
+    let seconds = timestamp % DAY;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / HOUR,
+        seconds % HOUR / MINUTE
+    )
+}
+
+// A timestamp in the basic ISO 8601 form AWS request signatures use,
+// `YYYYMMDDTHHMMSSZ`
+pub fn format_basic(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / DAY) as i64);
+    let seconds = timestamp % DAY;
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / HOUR,
+        seconds % HOUR / MINUTE,
+        seconds % MINUTE
+    )
+}
+
+// Seconds in a span such as `30s`, `30m`, `12h`, `7d` or `2w`
+pub fn parse_age(value: &str) -> Option<u64> {
+    let unit = match value.chars().last()? {
+        's' => 1,
+        'm' => MINUTE,
+        'h' => HOUR,
+        'd' => DAY,
+        'w' => 7 * DAY,
+        _ => return None,
+    };
+    let count: u64 = value[..value.len() - 1].parse().ok()?;
+    count.checked_mul(unit)
+}
+
+fn parse_datetime(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+
+    let mut parts = date.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || !(1..=12).contains(&month) || day == 0 || day > 31 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 || civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let mut parts = time.split(':');
+        let hour: u64 = parts.next()?.parse().ok()?;
+        let minute: u64 = parts.next()?.parse().ok()?;
+        let second: u64 = match parts.next() {
+            Some(second) => second.parse().ok()?,
+            None => 0,
+        };
+        if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        seconds = hour * HOUR + minute * MINUTE + second;
+    }
+    Some(days as u64 * DAY + seconds)
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+// (http://howardhinnant.github.io/date_algorithms.html)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
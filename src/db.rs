@@ -0,0 +1,210 @@
+use crate::cache::EmbeddingCache;
+use crate::device::Backend;
+use crate::embedding::Embeddor;
+use crate::error::Result;
+use crate::lock::StoreLock;
+use crate::progress::{ProgressEvent, ProgressSink};
+use crate::sources;
+use crate::vectorstore::{Document, Provenance, SearchResult, VectorStore};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+// A vector store opened for adding and searching documents from other
+// programs. Clones share the loaded store and embedding model; calls on them
+// run one at a time.
+#[derive(Clone)]
+pub struct ArrowDb {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    path: PathBuf,
+    backend: Backend,
+    store: VectorStore,
+    // Loaded on first use and kept
+    embeddor: Option<Embeddor>,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+impl ArrowDb {
+    // Open a store made with `arrow create`, running models on the CPU
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let backend = Backend::cpu();
+        let store = VectorStore::load(&path, backend.candle.clone())?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Inner {
+                path,
+                backend,
+                store,
+                embeddor: None,
+                progress: None,
+            })),
+        })
+    }
+
+    // Report the progress of `add`, and of embedding queries, to `progress`
+    pub fn with_progress(self, progress: Arc<dyn ProgressSink>) -> Self {
+        let mut inner = self.lock();
+        inner.embeddor = None;
+        inner.progress = Some(progress);
+        drop(inner);
+        self
+    }
+
+    // Add text under a source name, replacing what the source held before.
+    // Returns the number of chunks added, 0 if the text hasn't changed.
+    pub fn add(&self, source: &str, text: &str) -> Result<usize> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        // Start from the store on disk so other writers' changes aren't lost
+        let _writer = StoreLock::writer(&inner.path)?;
+        inner.store = VectorStore::load(&inner.path, inner.backend.candle.clone())?;
+        let progress = inner.progress.clone();
+        let report = |event| {
+            if let Some(progress) = &progress {
+                progress.event(event);
+            }
+        };
+
+        let hash = sources::hash_bytes(text.as_bytes());
+        if inner.store.source_record(source).is_some_and(|record| record.hash == hash) {
+            report(ProgressEvent::FileSkipped { source });
+            return Ok(0);
+        }
+        report(ProgressEvent::FileStarted {
+            source,
+            index: 0,
+            total: 1,
+        });
+        inner.store.remove_source(source);
+        if let Some(progress) = &progress {
+            inner.store.set_progress(progress.clone());
+        }
+
+        let embeddor = embeddor(
+            &mut inner.embeddor,
+            &inner.path,
+            &inner.store,
+            &inner.backend,
+            &inner.progress,
+        )?;
+        let chunks = embeddor.chunk(text, embeddor.max_chunk_tokens(), 0);
+        report(ProgressEvent::ChunksCreated {
+            source,
+            chunks: chunks.len(),
+        });
+        let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+        let embeddings = embeddor.embed_chunks(&texts)?;
+        let count = chunks.len();
+        let documents = chunks
+            .into_iter()
+            .zip(embeddings)
+            .enumerate()
+            .map(|(i, (chunk, embedding))| {
+                Document::new(embedding, chunk.text)
+                    .with_provenance(Provenance::new(source, i + 1, count))
+                    .with_span((chunk.start, chunk.end))
+                    .with_metadata(chunk.metadata)
+            })
+            .collect();
+        let chunk_ids = inner.store.add_batch(documents)?;
+        let added = chunk_ids.len();
+        inner.store.record_source(source.to_string(), hash, 0, chunk_ids);
+        inner.store.save(&inner.path)?;
+        embeddor.save_cache()?;
+        report(ProgressEvent::FileFinished {
+            source,
+            chunks: added,
+        });
+        Ok(added)
+    }
+
+    // The `top_k` passages most similar to a query, best first
+    pub fn query(&self, text: &str, top_k: usize) -> Result<Vec<SearchResult>> {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let embeddor = embeddor(
+            &mut inner.embeddor,
+            &inner.path,
+            &inner.store,
+            &inner.backend,
+            &inner.progress,
+        )?;
+        // A query without words to embed matches nothing
+        let query_embedding = match embeddor.embed_query(text)?.into_iter().next() {
+            Some(query_embedding) => query_embedding,
+            None => return Ok(Vec::new()),
+        };
+        embeddor.save_cache()?;
+        inner.store.query_with_options(&query_embedding, top_k, true)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // A panic mid-call leaves the store as last loaded or saved
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// `add` and `query` for async programs. Embedding and file IO run on tokio's
+// blocking thread pool, so they don't hold up the executor; a panic there is
+// resumed in the caller.
+#[cfg(feature = "async")]
+impl ArrowDb {
+    pub async fn open_async(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        tokio::task::spawn_blocking(move || Self::open(path))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    pub async fn add_async(
+        &self,
+        source: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Result<usize> {
+        let db = self.clone();
+        let (source, text) = (source.into(), text.into());
+        tokio::task::spawn_blocking(move || db.add(&source, &text))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    pub async fn query_async(
+        &self,
+        text: impl Into<String>,
+        top_k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let db = self.clone();
+        let text = text.into();
+        tokio::task::spawn_blocking(move || db.query(&text, top_k))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+fn embeddor<'a>(
+    embeddor: &'a mut Option<Embeddor>,
+    path: &Path,
+    store: &VectorStore,
+    backend: &Backend,
+    progress: &Option<Arc<dyn ProgressSink>>,
+) -> Result<&'a Embeddor> {
+    if embeddor.is_none() {
+        // An encrypted store's embeddings aren't written beside it in the clear
+        let cache = if store.is_encrypted() {
+            EmbeddingCache::in_memory(store.model())
+        } else {
+            EmbeddingCache::open(path, store.model())
+        };
+        let mut new = Embeddor::new(store.model(), backend)?
+            .with_cache(cache)
+            .with_dimension(store.dimension())
+            .with_prompts(store.prompts().clone());
+        if let Some(progress) = progress {
+            new = new.with_progress(progress.clone());
+        }
+        *embeddor = Some(new);
+    }
+    Ok(embeddor.as_ref().unwrap())
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use candle_core::Device;
+use std::fmt;
+use std::str::FromStr;
+
+// Hardware the embedding model and tensors run on, as given by `--device`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeviceChoice {
+    #[default]
+    Cpu,
+    // CUDA GPU with the given ordinal
+    Cuda(usize),
+    // Apple GPU through Metal (MPS in libtorch)
+    Metal,
+}
+
+impl FromStr for DeviceChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.to_lowercase();
+        match value.as_str() {
+            "cpu" => Ok(DeviceChoice::Cpu),
+            "cuda" | "gpu" => Ok(DeviceChoice::Cuda(0)),
+            "metal" | "mps" => Ok(DeviceChoice::Metal),
+            _ => match value.strip_prefix("cuda:").map(str::parse) {
+                Some(Ok(ordinal)) => Ok(DeviceChoice::Cuda(ordinal)),
+                _ => bail!("Unknown device '{}'; expected cpu, cuda, cuda:<N> or metal", value),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DeviceChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceChoice::Cpu => write!(f, "cpu"),
+            DeviceChoice::Cuda(ordinal) => write!(f, "cuda:{}", ordinal),
+            DeviceChoice::Metal => write!(f, "metal"),
+        }
+    }
+}
+
+// The same device as seen by candle (store tensors and candle models) and
+// libtorch (rust-bert models)
+#[derive(Clone, Debug)]
+pub struct Backend {
+    pub candle: Device,
+    #[cfg(feature = "torch")]
+    pub torch: tch::Device,
+}
+
+impl Backend {
+    pub fn cpu() -> Self {
+        Self {
+            candle: Device::Cpu,
+            #[cfg(feature = "torch")]
+            torch: tch::Device::Cpu,
+        }
+    }
+}
+
+// Open the chosen device. If this build or machine can't use it, returns the
+// CPU backend along with the reason.
+pub fn open(choice: DeviceChoice) -> (Backend, Option<String>) {
+    let opened = match choice {
+        DeviceChoice::Cpu => return (Backend::cpu(), None),
+        DeviceChoice::Cuda(ordinal) => open_cuda(ordinal),
+        DeviceChoice::Metal => open_metal(),
+    };
+    match opened {
+        Ok(backend) => (backend, None),
+        Err(reason) => (Backend::cpu(), Some(reason)),
+    }
+}
+
+fn open_cuda(ordinal: usize) -> std::result::Result<Backend, String> {
+    #[cfg(feature = "torch")]
+    {
+        if !tch::Cuda::is_available() {
+            return Err("libtorch was not built with CUDA or no CUDA GPU was found".to_string());
+        }
+        let count = tch::Cuda::device_count() as usize;
+        if ordinal >= count {
+            return Err(format!("cuda:{} requested but only {} CUDA GPU(s) found", ordinal, count));
+        }
+    }
+    let candle = Device::new_cuda(ordinal)
+        .map_err(|e| format!("candle can't open cuda:{} ({}); build with --features cuda", ordinal, e))?;
+    Ok(Backend {
+        candle,
+        #[cfg(feature = "torch")]
+        torch: tch::Device::Cuda(ordinal),
+    })
+}
+
+fn open_metal() -> std::result::Result<Backend, String> {
+    #[cfg(feature = "torch")]
+    if !tch::utils::has_mps() {
+        return Err("libtorch was not built with MPS support or no Metal GPU was found".to_string());
+    }
+    let candle = Device::new_metal(0)
+        .map_err(|e| format!("candle can't open Metal ({}); build with --features metal", e))?;
+    Ok(Backend {
+        candle,
+        #[cfg(feature = "torch")]
+        torch: tch::Device::Mps,
+    })
+}
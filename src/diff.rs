@@ -0,0 +1,161 @@
+use crate::sources;
+use crate::vectorstore::{DocumentOrder, VectorStore};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+// One difference between two stores
+#[derive(Serialize)]
+pub struct Difference {
+    // "parameter", "added", "removed" or "changed"
+    pub change: &'static str,
+    // Parameter name, or the document's external ID or source
+    pub name: String,
+    // Parameter values, or chunk counts of the document
+    pub old: String,
+    pub new: String,
+    // What changed in a changed document: "text", "metadata" or both
+    pub details: String,
+}
+
+pub struct StoreDiff {
+    pub differences: Vec<Difference>,
+    pub unchanged: usize,
+}
+
+impl StoreDiff {
+    pub fn count(&self, change: &str) -> usize {
+        self.differences.iter().filter(|d| d.change == change).count()
+    }
+}
+
+// A document as compared: the chunks stored under one external ID, or else
+// from one source (a file, or a record of a JSONL file)
+struct Document {
+    chunks: usize,
+    text: String,
+    metadata: String,
+}
+
+// Compare parameters, then documents. Node IDs differ between stores that were
+// built separately, so documents are matched by external ID or source and
+// compared by hashes of their text and metadata.
+pub fn diff(old: &VectorStore, new: &VectorStore) -> StoreDiff {
+    let mut differences: Vec<Difference> = parameters(old)
+        .into_iter()
+        .zip(parameters(new))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((name, old), (_, new))| Difference {
+            change: "parameter",
+            name: name.to_string(),
+            old,
+            new,
+            details: String::new(),
+        })
+        .collect();
+
+    let old_documents = documents(old);
+    let mut new_documents = documents(new);
+    let mut unchanged = 0;
+    let mut changes = Vec::new();
+    for (name, before) in old_documents {
+        let Some(after) = new_documents.remove(&name) else {
+            changes.push(Difference {
+                change: "removed",
+                name,
+                old: before.chunks.to_string(),
+                new: String::new(),
+                details: String::new(),
+            });
+            continue;
+        };
+        let mut details = Vec::new();
+        if before.text != after.text {
+            details.push("text");
+        }
+        if before.metadata != after.metadata {
+            details.push("metadata");
+        }
+        if details.is_empty() {
+            unchanged += 1;
+            continue;
+        }
+        changes.push(Difference {
+            change: "changed",
+            name,
+            old: before.chunks.to_string(),
+            new: after.chunks.to_string(),
+            details: details.join(", "),
+        });
+    }
+    changes.extend(new_documents.into_iter().map(|(name, after)| Difference {
+        change: "added",
+        name,
+        old: String::new(),
+        new: after.chunks.to_string(),
+        details: String::new(),
+    }));
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    differences.extend(changes);
+    StoreDiff {
+        differences,
+        unchanged,
+    }
+}
+
+// Settings that affect what searches return, in a fixed order
+fn parameters(store: &VectorStore) -> Vec<(&'static str, String)> {
+    let optional = |value: Option<usize>| value.map(|v| v.to_string()).unwrap_or_default();
+    vec![
+        ("model", store.model().to_string()),
+        ("dimension", optional(store.dimension())),
+        ("storage", format!("{:?}", store.storage_mode()).to_lowercase()),
+        ("max_connections", store.max_connections().to_string()),
+        ("pq_subspaces", optional(store.pq_subspaces())),
+        ("query_prompt", store.prompts().query.clone()),
+        ("passage_prompt", store.prompts().passage.clone()),
+        ("vector_fields", store.field_names().join(",")),
+        ("backend", format!("{:?}", store.engine()).to_lowercase()),
+    ]
+}
+
+fn documents(store: &VectorStore) -> HashMap<String, Document> {
+    // Chunks in source order, so the hashes don't depend on when each was added
+    let mut chunks: BTreeMap<String, Vec<(&str, String)>> = BTreeMap::new();
+    for id in store.list_ids(DocumentOrder::Source, None) {
+        let Some((text, filename)) = store.get_embedding(&id) else {
+            continue;
+        };
+        let name = match (store.external_id(&id), store.provenance(&id), filename) {
+            (Some(external_id), _, _) => format!("id:{}", external_id),
+            (None, Some(provenance), _) => match provenance.record {
+                Some(record) => format!("{}#record{}", provenance.source_path, record),
+                None => provenance.source_path.clone(),
+            },
+            (None, None, Some(filename)) => filename.clone(),
+            (None, None, None) => id.to_string(),
+        };
+        let metadata: BTreeMap<_, _> = store.get_metadata(&id).into_iter().flatten().collect();
+        let metadata = serde_json::to_string(&metadata).unwrap_or_default();
+        chunks.entry(name).or_default().push((text, metadata));
+    }
+
+    chunks
+        .into_iter()
+        .map(|(name, chunks)| {
+            let mut text = Vec::new();
+            let mut metadata = Vec::new();
+            for (chunk, meta) in &chunks {
+                text.extend_from_slice(chunk.as_bytes());
+                text.push(0);
+                metadata.extend_from_slice(meta.as_bytes());
+                metadata.push(0);
+            }
+            let document = Document {
+                chunks: chunks.len(),
+                text: sources::hash_bytes(&text),
+                metadata: sources::hash_bytes(&metadata),
+            };
+            (name, document)
+        })
+        .collect()
+}
@@ -0,0 +1,440 @@
+use crate::crypto;
+use crate::device::{self, Backend, DeviceChoice};
+use crate::embedding::Embeddor;
+use crate::format_bytes;
+use crate::models;
+use crate::provider::{ModelSpec, Provider};
+use crate::remote::{self, RemoteStore};
+use crate::vectorstore::VectorStore;
+use candle_core::Device;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Outcome of one check
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warning,
+    Failed,
+}
+
+// What a check found, and what to do about it if it isn't ok
+#[derive(Serialize, Debug)]
+pub struct Finding {
+    pub check: &'static str,
+    pub status: Status,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(check: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warning(check: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: Status::Warning,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn failed(check: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            check,
+            status: Status::Failed,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+// Store files at least this fraction of the available memory are warned about
+const MEMORY_WARN_FRACTION: f64 = 0.5;
+// Text embedded to check the model
+const PROBE_TEXT: &str = "arrow doctor";
+
+// Check the environment a command on `database` would run in: this build,
+// libtorch, the device, the database path, memory and the embedding model.
+// `fallback_model` is checked when the store's own model can't be read.
+pub fn run(
+    database: &str,
+    db_path: &str,
+    choice: DeviceChoice,
+    fallback_model: &ModelSpec,
+) -> Vec<Finding> {
+    let (device, backend) = device(choice);
+    let database = database_path(database, db_path);
+    let size = store_size(db_path);
+    let (memory, fits) = memory(size);
+    let (store, store_model) = store(db_path, size, fits);
+    let (model, dimension) = match store_model {
+        Some((model, dimension)) => (model, dimension),
+        None => (fallback_model.clone(), None),
+    };
+
+    let mut findings = vec![build(), libtorch(&model), device, database, memory];
+    findings.extend(store);
+    findings.push(model_cache(&model));
+    findings.push(embedding(&model, &backend, dimension));
+    findings
+}
+
+fn build() -> Finding {
+    let mut providers = Vec::new();
+    if cfg!(feature = "torch") {
+        providers.push("local");
+    }
+    if cfg!(feature = "candle") {
+        providers.push("candle");
+    }
+    if cfg!(feature = "onnx") {
+        providers.push("onnx");
+    }
+    providers.extend(["openai", "ollama"]);
+    let mut gpus = Vec::new();
+    if cfg!(feature = "cuda") {
+        gpus.push("cuda");
+    }
+    if cfg!(feature = "metal") {
+        gpus.push("metal");
+    }
+    let gpus = if gpus.is_empty() {
+        "none".to_string()
+    } else {
+        gpus.join(", ")
+    };
+    Finding::ok(
+        "Build",
+        format!(
+            "arrow {}; providers: {}; candle GPUs: {}",
+            env!("CARGO_PKG_VERSION"),
+            providers.join(", "),
+            gpus
+        ),
+    )
+}
+
+// libtorch is linked when the binary starts, so a missing library fails
+// before this runs; what's left to check is that it computes
+#[cfg(feature = "torch")]
+fn libtorch(_model: &ModelSpec) -> Finding {
+    let location = std::env::var("LIBTORCH").unwrap_or_else(|_| "the system library path".into());
+    let sum = std::panic::catch_unwind(|| {
+        tch::Tensor::from_slice(&[1.0f32, 2.0, 3.0])
+            .sum(tch::Kind::Float)
+            .double_value(&[])
+    });
+    match sum {
+        Ok(sum) if (sum - 6.0).abs() < 1e-6 => Finding::ok(
+            "libtorch",
+            format!(
+                "works, from {}; CUDA: {}, MPS: {}",
+                location,
+                yes_no(tch::Cuda::is_available()),
+                yes_no(tch::utils::has_mps())
+            ),
+        ),
+        _ => Finding::failed(
+            "libtorch",
+            format!("loaded from {} but failed a test computation", location),
+            "Install libtorch 2.4 and point LIBTORCH and LD_LIBRARY_PATH at it, \
+             or use --provider candle",
+        ),
+    }
+}
+
+#[cfg(not(feature = "torch"))]
+fn libtorch(model: &ModelSpec) -> Finding {
+    if model.provider() == Provider::Local {
+        return Finding::failed(
+            "libtorch",
+            "not in this build, but --provider local needs it",
+            "Rebuild with `--features torch`, or create stores with --provider candle",
+        );
+    }
+    Finding::ok("libtorch", "not in this build; only --provider local needs it")
+}
+
+fn device(choice: DeviceChoice) -> (Finding, Backend) {
+    let (backend, fallback) = device::open(choice);
+    let finding = match fallback {
+        Some(reason) => Finding::failed(
+            "Device",
+            format!("can't use {}: {}", choice, reason),
+            "Install the GPU driver and a build with its feature (`--features cuda` or \
+             `--features metal`), or choose --device cpu",
+        ),
+        None if choice == DeviceChoice::Cpu && gpu_available() => Finding::ok(
+            "Device",
+            "cpu; a GPU is also available, use it with --device cuda or --device metal",
+        ),
+        None => Finding::ok("Device", choice.to_string()),
+    };
+    (finding, backend)
+}
+
+fn gpu_available() -> bool {
+    #[cfg(feature = "torch")]
+    if tch::Cuda::is_available() || tch::utils::has_mps() {
+        return true;
+    }
+    candle_core::utils::cuda_is_available() || candle_core::utils::metal_is_available()
+}
+
+fn database_path(database: &str, db_path: &str) -> Finding {
+    if remote::is_remote(database) {
+        return match RemoteStore::open(database) {
+            Ok(store) => match writable_dir(Path::new(&store.local_path())) {
+                Ok(()) => Finding::ok(
+                    "Database",
+                    format!("{}, copied to {}", database, store.local_path()),
+                ),
+                Err(e) => Finding::failed(
+                    "Database",
+                    format!("local copy of {} can't be written: {}", database, e),
+                    "Make the cache directory writable, or set XDG_CACHE_HOME",
+                ),
+            },
+            Err(e) => Finding::failed(
+                "Database",
+                format!("{}: {:#}", database, e),
+                "Set the bucket's credentials (AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, \
+                 or GOOGLE_OAUTH_ACCESS_TOKEN)",
+            ),
+        };
+    }
+
+    let path = Path::new(db_path);
+    if !path.exists() {
+        return match writable_dir(path) {
+            Ok(()) => Finding::warning(
+                "Database",
+                format!("{} doesn't exist yet", db_path),
+                "Create it with `arrow create`, or pass --database with an existing store",
+            ),
+            Err(e) => Finding::failed(
+                "Database",
+                format!("{} can't be created: {}", db_path, e),
+                "Create the directory or make it writable, or pass --database elsewhere",
+            ),
+        };
+    }
+    let file = fs::OpenOptions::new().append(true).open(path);
+    match file.map_err(|e| e.to_string()).and_then(|_| writable_dir(path)) {
+        Ok(()) => Finding::ok("Database", format!("{} is writable", db_path)),
+        Err(e) => Finding::failed(
+            "Database",
+            format!("{} can't be written: {}", db_path, e),
+            "Fix the permissions of the store and its directory (saves write beside it), \
+             or read from a copy",
+        ),
+    }
+}
+
+// Whether files can be made beside `path`, as saves and locks do
+fn writable_dir(path: &Path) -> Result<(), String> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    if !dir.is_dir() {
+        return Err(format!("directory {} doesn't exist", dir.display()));
+    }
+    let probe = dir.join(format!(".arrow-doctor-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| format!("directory {}: {}", dir.display(), e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+// Bytes of the store's files on disk, None if it has none
+fn store_size(db_path: &str) -> Option<u64> {
+    let files = VectorStore::files(db_path).ok()?;
+    let size = files
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    Some(size).filter(|&size| size > 0)
+}
+
+// Compare the store's size with the memory available, as it is loaded whole.
+// Also returns whether it fits, assuming so when either is unknown.
+fn memory(size: Option<u64>) -> (Finding, bool) {
+    let available = match available_memory() {
+        Some(available) => available,
+        None => return (Finding::ok("Memory", "available memory unknown on this system"), true),
+    };
+    let size = match size {
+        Some(size) => size,
+        None => {
+            let detail = format!("{} available", format_bytes(available));
+            return (Finding::ok("Memory", detail), true);
+        }
+    };
+    let detail = format!(
+        "store is {} on disk, {} available",
+        format_bytes(size),
+        format_bytes(available)
+    );
+    let finding = if size >= available {
+        Finding::failed(
+            "Memory",
+            detail,
+            "Free memory, or shrink the store with `arrow create --storage int8` and \
+             re-adding, or `arrow quantize`",
+        )
+    } else if size as f64 >= available as f64 * MEMORY_WARN_FRACTION {
+        Finding::warning(
+            "Memory",
+            detail,
+            "Loading may push other programs into swap; consider --storage int8 or --mmap",
+        )
+    } else {
+        Finding::ok("Memory", detail)
+    };
+    (finding, size < available)
+}
+
+// MemAvailable from /proc/meminfo, where there is one
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+// Load the store to check it and learn its model and dimension, unless it
+// can't be read without a passphrase or wouldn't fit in memory
+#[allow(clippy::type_complexity)]
+fn store(
+    db_path: &str,
+    size: Option<u64>,
+    fits: bool,
+) -> (Option<Finding>, Option<(ModelSpec, Option<usize>)>) {
+    if size.is_none() {
+        return (None, None);
+    }
+    if crypto::is_encrypted(Path::new(db_path)) {
+        let detail = "encrypted; not opened, so the default model is checked";
+        return (Some(Finding::ok("Store", detail)), None);
+    }
+    if !fits {
+        return (None, None);
+    }
+    match VectorStore::load(db_path, Device::Cpu) {
+        Ok(store) => {
+            let finding = Finding::ok(
+                "Store",
+                format!("loads; {} documents, model {}", store.text_count(), store.model()),
+            );
+            (Some(finding), Some((store.model().clone(), store.dimension())))
+        }
+        Err(e) => {
+            let finding = Finding::failed(
+                "Store",
+                format!("doesn't load: {}", e),
+                "Run `arrow fsck --repair`, or restore a backup from beside the store",
+            );
+            (Some(finding), None)
+        }
+    }
+}
+
+// Where the model's files are downloaded to
+fn model_cache(model: &ModelSpec) -> Finding {
+    let cache = match model.provider() {
+        Provider::Local => models::rustbert_cache_dir(),
+        Provider::Candle | Provider::Onnx if Path::new(model.model()).is_dir() => {
+            return Finding::ok("Model files", format!("local directory {}", model.model()));
+        }
+        Provider::Candle | Provider::Onnx => models::hub_cache_dir(),
+        Provider::Openai | Provider::Ollama => {
+            return Finding::ok("Model files", format!("served by {}", model.provider().name()));
+        }
+    };
+    let cache = match cache {
+        Some(cache) => cache,
+        None => {
+            return Finding::failed(
+                "Model files",
+                "no cache directory",
+                "Set HOME, or --model-cache to a writable directory",
+            )
+        }
+    };
+    // The cache is made on first download, so check the nearest directory
+    // that exists
+    let existing = cache.ancestors().find(|dir| dir.is_dir()).map(Path::to_path_buf);
+    let writable = existing
+        .as_deref()
+        .is_some_and(|dir| writable_dir(&dir.join("probe")).is_ok());
+    if !writable {
+        return Finding::failed(
+            "Model files",
+            format!("cache {} isn't writable", cache.display()),
+            "Make it writable, or set --model-cache to a writable directory",
+        );
+    }
+    let state = if cache.is_dir() { "" } else { " (not created yet)" };
+    Finding::ok("Model files", format!("cached in {}{}", cache.display(), state))
+}
+
+// Load the model, downloading it if needed, and embed a short text
+fn embedding(model: &ModelSpec, backend: &Backend, dimension: Option<usize>) -> Finding {
+    let embedded = Embeddor::new(model, backend)
+        .map(|embeddor| embeddor.with_dimension(dimension))
+        .and_then(|embeddor| embeddor.embed_query(PROBE_TEXT));
+    match embedded {
+        Ok(vectors) => {
+            let dimension = vectors.first().map_or(0, |vector| vector.elem_count());
+            Finding::ok("Model", format!("{} embeds {}-dimensional vectors", model, dimension))
+        }
+        Err(e) => Finding::failed(
+            "Model",
+            format!("{}: {:#}", model, anyhow::Error::from(e)),
+            model_fix(model),
+        ),
+    }
+}
+
+fn model_fix(model: &ModelSpec) -> String {
+    match model.provider() {
+        Provider::Local => "Check network access to huggingface.co for the first download and \
+                            that libtorch works; --model-cache moves the download"
+            .to_string(),
+        Provider::Candle | Provider::Onnx => format!(
+            "Check network access to huggingface.co and that `{}` exists there, or download \
+             it and pass its directory with --model",
+            model.model()
+        ),
+        Provider::Openai => "Set OPENAI_API_KEY, or OPENAI_BASE_URL for a compatible server, \
+                             and check the model name"
+            .to_string(),
+        Provider::Ollama => format!(
+            "Start Ollama (`ollama serve`) and run `ollama pull {}`; set OLLAMA_HOST if it \
+             listens elsewhere",
+            model.model()
+        ),
+    }
+}
+
+#[cfg(feature = "torch")]
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
@@ -0,0 +1,84 @@
+use crate::vectorstore::{Metadata, Span};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use uuid::Uuid;
+
+// One document of an export, written as a line of JSON. Imported records
+// need only `text` and `vector`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DumpRecord {
+    // The node's UUID in exports; imports also accept any other string as a
+    // stable document ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: Metadata,
+    // When the document was added and last updated, in seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated: Option<u64>,
+    // When the document expires, if it was added with a TTL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    // The embedding as the model returned it, when vectors are exported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+    // Unit-length embeddings of named fields, when vectors are exported
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fields: HashMap<String, Vec<f32>>,
+}
+
+// Write records as JSON lines, returning how many were written
+pub fn write(out: &mut impl Write, records: impl Iterator<Item = DumpRecord>) -> Result<usize> {
+    let mut count = 0;
+    for record in records {
+        serde_json::to_writer(&mut *out, &record)?;
+        writeln!(out)?;
+        count += 1;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+// Read records written by `write` or by other tools, one JSON object per line
+pub fn read(input: impl BufRead) -> Result<Vec<DumpRecord>> {
+    let mut records = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.context("Failed to read import file")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => bail!("Line {}: {}", i + 1, e),
+        }
+    }
+    Ok(records)
+}
+
+impl DumpRecord {
+    // The record's `id` if it is a node UUID, as in Arrow's own exports
+    pub fn node_id(&self) -> Option<Uuid> {
+        self.id.as_deref().and_then(|id| Uuid::parse_str(id).ok())
+    }
+
+    // The stable document ID to upsert under: `external_id`, or an `id` that
+    // isn't a UUID
+    pub fn document_id(&self) -> Option<&str> {
+        match (&self.external_id, &self.id) {
+            (Some(external_id), _) => Some(external_id),
+            (None, Some(id)) if self.node_id().is_none() => Some(id),
+            _ => None,
+        }
+    }
+}
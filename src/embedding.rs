@@ -1,16 +1,136 @@
-use anyhow::Result;
+use crate::cache::EmbeddingCache;
+use crate::code::{self, Language};
+use crate::device::Backend;
+use crate::error::{ArrowError, Result};
+use crate::markdown;
+use crate::progress::{ProgressEvent, ProgressSink};
+#[cfg(feature = "torch")]
+use crate::provider::SPECIAL_TOKENS;
+use crate::provider::{self, EmbeddingProvider, ModelSpec, Prompts};
+use crate::vectorstore::Metadata;
 use candle_core::{Device, Tensor};
+use clap::ValueEnum;
+#[cfg(feature = "torch")]
 use rust_bert::pipelines::sentence_embeddings::{
     SentenceEmbeddingsBuilder, SentenceEmbeddingsModel, SentenceEmbeddingsModelType,
 };
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "torch")]
 use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(feature = "torch")]
 use std::thread;
+use tracing::{debug, debug_span, trace, trace_span};
 use uuid::Uuid;
 
+// Texts embedded between progress reports
+const PROGRESS_BATCH_SIZE: usize = 256;
+
+// Sentence embedding models a store can be built with
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmbeddingModel {
+    #[default]
+    #[serde(rename = "all-minilm-l6-v2")]
+    #[value(name = "all-minilm-l6-v2")]
+    AllMiniLmL6V2,
+    #[serde(rename = "all-minilm-l12-v2")]
+    #[value(name = "all-minilm-l12-v2")]
+    AllMiniLmL12V2,
+    #[serde(rename = "all-mpnet-base-v2")]
+    #[value(name = "all-mpnet-base-v2")]
+    AllMpnetBaseV2,
+    #[serde(rename = "all-distilroberta-v1")]
+    #[value(name = "all-distilroberta-v1")]
+    AllDistilrobertaV1,
+    #[serde(rename = "paraphrase-albert-small-v2")]
+    #[value(name = "paraphrase-albert-small-v2")]
+    ParaphraseAlbertSmallV2,
+    #[serde(rename = "distiluse-base-multilingual-cased")]
+    #[value(name = "distiluse-base-multilingual-cased")]
+    DistiluseBaseMultilingualCased,
+    #[serde(rename = "sentence-t5-base")]
+    #[value(name = "sentence-t5-base")]
+    SentenceT5Base,
+    #[serde(rename = "bert-base-nli-mean-tokens")]
+    #[value(name = "bert-base-nli-mean-tokens")]
+    BertBaseNliMeanTokens,
+}
+
+impl EmbeddingModel {
+    #[cfg(feature = "torch")]
+    pub fn model_type(self) -> SentenceEmbeddingsModelType {
+        match self {
+            EmbeddingModel::AllMiniLmL6V2 => SentenceEmbeddingsModelType::AllMiniLmL6V2,
+            EmbeddingModel::AllMiniLmL12V2 => SentenceEmbeddingsModelType::AllMiniLmL12V2,
+            EmbeddingModel::AllMpnetBaseV2 => SentenceEmbeddingsModelType::AllMpnetBaseV2,
+            EmbeddingModel::AllDistilrobertaV1 => SentenceEmbeddingsModelType::AllDistilrobertaV1,
+            EmbeddingModel::ParaphraseAlbertSmallV2 => {
+                SentenceEmbeddingsModelType::ParaphraseAlbertSmallV2
+            }
+            EmbeddingModel::DistiluseBaseMultilingualCased => {
+                SentenceEmbeddingsModelType::DistiluseBaseMultilingualCased
+            }
+            EmbeddingModel::SentenceT5Base => SentenceEmbeddingsModelType::SentenceT5Base,
+            EmbeddingModel::BertBaseNliMeanTokens => {
+                SentenceEmbeddingsModelType::BertBaseNliMeanTokens
+            }
+        }
+    }
+
+    // Name used on the command line and in saved stores
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    // Longest input in tokens; anything beyond it is silently truncated
+    pub fn max_sequence_length(self) -> usize {
+        match self {
+            EmbeddingModel::AllMiniLmL6V2 | EmbeddingModel::SentenceT5Base => 256,
+            EmbeddingModel::AllMiniLmL12V2
+            | EmbeddingModel::DistiluseBaseMultilingualCased
+            | EmbeddingModel::BertBaseNliMeanTokens => 128,
+            EmbeddingModel::AllMpnetBaseV2 => 384,
+            EmbeddingModel::AllDistilrobertaV1 => 512,
+            EmbeddingModel::ParaphraseAlbertSmallV2 => 100,
+        }
+    }
+
+    pub fn dimension(self) -> usize {
+        match self {
+            EmbeddingModel::AllMiniLmL6V2 | EmbeddingModel::AllMiniLmL12V2 => 384,
+            EmbeddingModel::DistiluseBaseMultilingualCased => 512,
+            _ => 768,
+        }
+    }
+}
+
+// A piece of a document, its byte range within the document and anything
+// the chunking strategy learned about it
+pub struct Chunk {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+    pub metadata: Metadata,
+}
+
 pub struct Embeddor {
-    model: SentenceEmbeddingsModel,
+    provider: Box<dyn EmbeddingProvider>,
     device: Device,
+    cache: Option<Mutex<EmbeddingCache>>,
+    prompts: Prompts,
+    // Vector length, from the store or else learned from the first vectors
+    dimension: OnceLock<usize>,
+    progress: Option<Arc<dyn ProgressSink>>,
+}
+
+// A rust-bert sentence embedding model running in-process on `Backend`
+#[cfg(feature = "torch")]
+pub struct LocalProvider {
+    model: SentenceEmbeddingsModel,
+    model_kind: EmbeddingModel,
+    torch_device: tch::Device,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -42,37 +162,319 @@ impl StoredEmbedding {
 }
 
 impl Embeddor {
-    pub fn new(model_type: SentenceEmbeddingsModelType) -> Result<Self> {
-        let model: SentenceEmbeddingsModel =
-            SentenceEmbeddingsBuilder::remote(model_type).create_model()?;
-        let device = Device::Cpu;
-        Ok(Self { model, device })
+    pub fn new(model: &ModelSpec, backend: &Backend) -> Result<Self> {
+        Ok(Self {
+            provider: provider::open(model, backend).map_err(ArrowError::Model)?,
+            device: backend.candle.clone(),
+            cache: None,
+            prompts: Prompts::default(),
+            dimension: OnceLock::new(),
+            progress: None,
+        })
+    }
+
+    // Chunks as `model` would without loading it, for a dry run: token counts
+    // are estimated from word lengths, and embedding fails
+    pub fn estimating(model: &ModelSpec) -> Self {
+        let estimator = provider::Estimator {
+            max_chunk_tokens: model.estimated_chunk_tokens(),
+        };
+        Self {
+            provider: Box::new(estimator),
+            device: Device::Cpu,
+            cache: None,
+            prompts: Prompts::default(),
+            dimension: OnceLock::new(),
+            progress: None,
+        }
+    }
+
+    // Require vectors of this length, such as those already in the store
+    pub fn with_dimension(self, dimension: Option<usize>) -> Self {
+        if let Some(dimension) = dimension {
+            let _ = self.dimension.set(dimension);
+        }
+        self
+    }
+
+    // Look up and record embeddings in `cache`
+    pub fn with_cache(mut self, cache: EmbeddingCache) -> Self {
+        self.cache = Some(Mutex::new(cache));
+        self
+    }
+
+    // Report how many texts are embedded to `progress`, embedding them in
+    // batches so there is something to report
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    // Put the store's prompts in front of chunks and queries
+    pub fn with_prompts(mut self, prompts: Prompts) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    // Default number of tokens per chunk, leaving room for the passage prompt
+    pub fn max_chunk_tokens(&self) -> usize {
+        let prompt = self.provider.token_starts(&self.prompts.passage).len();
+        self.provider.max_chunk_tokens().saturating_sub(prompt).max(1)
     }
 
-    pub fn chunk(&self, text: &str) -> Vec<String> {
+    // Split text into chunks of whole words holding at most `max_tokens` model
+    // tokens. Each chunk after the first starts far enough back to repeat up to
+    // `overlap` tokens of the one before, so context straddling a boundary
+    // appears in both.
+    pub fn chunk(&self, text: &str, max_tokens: usize, overlap: usize) -> Vec<Chunk> {
+        let _span = trace_span!("chunk", bytes = text.len(), max_tokens).entered();
+        let words = word_spans(text);
+        let tokens = self.word_token_counts(text, &words);
+        let chunks = pack_words(text, &words, &tokens, max_tokens, overlap);
+        trace!(words = words.len(), chunks = chunks.len(), "chunked text");
+        chunks
+    }
+
+    // Chunk a markdown document section by section, so no chunk spans a
+    // heading or code fence boundary. Each chunk records its heading path
+    // under the `section` metadata key.
+    pub fn chunk_markdown(&self, text: &str, max_tokens: usize, overlap: usize) -> Vec<Chunk> {
         let mut chunks = Vec::new();
-        let words: Vec<&str> = text.split_whitespace().collect();
-        let mut current_chunk = String::new();
-        for word in words {
-            if current_chunk.is_empty() {
-                current_chunk.push_str(word);
-            } else if current_chunk.len() + word.len() + 1 <= 512 {
-                current_chunk.push(' ');
-                current_chunk.push_str(word);
-            } else {
-                chunks.push(current_chunk);
-                current_chunk = String::new();
-                current_chunk.push_str(word);
+        for section in markdown::sections(text) {
+            let heading_path = section.heading_path();
+            for mut chunk in self.chunk(&text[section.start..section.end], max_tokens, overlap) {
+                chunk.start += section.start;
+                chunk.end += section.start;
+                if !heading_path.is_empty() {
+                    chunk.metadata.insert("section".to_string(), heading_path.clone());
+                }
+                chunks.push(chunk);
             }
         }
-        if !current_chunk.is_empty() {
-            chunks.push(current_chunk);
+        chunks
+    }
+
+    // Chunk source code along function, type and impl boundaries so each unit
+    // stays intact. Units too large for one chunk are split into the units
+    // nested inside them, then by words. Each chunk records the symbol it
+    // defines under the `symbol` metadata key.
+    pub fn chunk_code(
+        &self,
+        text: &str,
+        language: Language,
+        max_tokens: usize,
+        overlap: usize,
+    ) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        for unit in code::units(text, language) {
+            let unit_text = &text[unit.start..unit.end];
+            let tokens: usize = self
+                .word_token_counts(unit_text, &word_spans(unit_text))
+                .iter()
+                .sum();
+            let nested = code::nested_units(text, &unit, language);
+            if tokens > max_tokens && nested.len() > 1 {
+                for nested_unit in &nested {
+                    self.chunk_code_unit(text, nested_unit, max_tokens, overlap, &mut chunks);
+                }
+            } else {
+                self.chunk_code_unit(text, &unit, max_tokens, overlap, &mut chunks);
+            }
         }
         chunks
     }
 
-    pub fn embed(&self, text: &str) -> Result<Vec<Tensor>> {
-        let chunks = self.chunk(text);
+    fn chunk_code_unit(
+        &self,
+        text: &str,
+        unit: &code::Unit,
+        max_tokens: usize,
+        overlap: usize,
+        chunks: &mut Vec<Chunk>,
+    ) {
+        for mut chunk in self.chunk(&text[unit.start..unit.end], max_tokens, overlap) {
+            chunk.start += unit.start;
+            chunk.end += unit.start;
+            if let Some(symbol) = &unit.symbol {
+                chunk.metadata.insert("symbol".to_string(), symbol.clone());
+            }
+            chunks.push(chunk);
+        }
+    }
+
+    // Number of model tokens in each word, from the character offsets of the
+    // provider's tokens
+    fn word_token_counts(&self, text: &str, words: &[(usize, usize)]) -> Vec<usize> {
+        let char_to_byte: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        let mut counts = vec![0; words.len()];
+        for begin in self.provider.token_starts(text) {
+            let byte = match char_to_byte.get(begin) {
+                Some(&byte) => byte,
+                None => continue,
+            };
+            // The word starting at or before the token
+            let word = words.partition_point(|&(start, _)| start <= byte);
+            if word > 0 {
+                counts[word - 1] += 1;
+            }
+        }
+        counts
+    }
+
+    pub fn embed_query(&self, text: &str) -> Result<Vec<Tensor>> {
+        let queries: Vec<String> = self
+            .chunk(text, self.max_chunk_tokens(), 0)
+            .into_iter()
+            .map(|chunk| self.prompts.query(&chunk.text))
+            .collect();
+        self.embed_texts(&queries)
+    }
+
+    // Embed several queries in one batch, each cut to the model's input
+    // limit. None for a query with no text to embed.
+    pub fn embed_queries(&self, texts: &[String]) -> Result<Vec<Option<Tensor>>> {
+        let queries: Vec<Option<String>> = texts
+            .iter()
+            .map(|text| {
+                let chunk = self.chunk(text, self.max_chunk_tokens(), 0).into_iter().next();
+                chunk.map(|chunk| self.prompts.query(&chunk.text))
+            })
+            .collect();
+        let batch: Vec<String> = queries.iter().flatten().cloned().collect();
+        let mut embedded = self.embed_texts(&batch)?.into_iter();
+        Ok(queries
+            .iter()
+            .map(|query| query.as_ref().and_then(|_| embedded.next()))
+            .collect())
+    }
+
+    // Embed each chunk of a document
+    pub fn embed_chunks(&self, chunks: &[String]) -> Result<Vec<Tensor>> {
+        let passages: Vec<String> = chunks
+            .iter()
+            .map(|chunk| self.prompts.passage(chunk))
+            .collect();
+        self.embed_texts(&passages)
+    }
+
+    // Embed texts as given, prompts included, taking vectors from the cache
+    // where possible and caching the rest
+    fn embed_texts(&self, chunks: &[String]) -> Result<Vec<Tensor>> {
+        let mut cache = match &self.cache {
+            Some(cache) => cache.lock().expect("embedding cache lock poisoned"),
+            None => return self.convert_to_tensors(self.encode_reporting(chunks, chunks.len())?),
+        };
+
+        let mut vectors: Vec<Option<Vec<f32>>> = chunks
+            .iter()
+            .map(|chunk| cache.get(chunk).map(<[f32]>::to_vec))
+            .collect();
+        let misses: Vec<String> = chunks
+            .iter()
+            .zip(&vectors)
+            .filter(|(_, vector)| vector.is_none())
+            .map(|(chunk, _)| chunk.clone())
+            .collect();
+        debug!(texts = chunks.len(), cached = chunks.len() - misses.len(), "embedding");
+        let mut encoded = self.encode_reporting(&misses, chunks.len())?.into_iter();
+        for (chunk, slot) in chunks.iter().zip(vectors.iter_mut()) {
+            if slot.is_none() {
+                let vector = encoded.next().ok_or_else(|| {
+                    ArrowError::Model(anyhow::anyhow!("The model returned too few embeddings"))
+                })?;
+                cache.insert(chunk, vector.clone());
+                *slot = Some(vector);
+            }
+        }
+        self.convert_to_tensors(vectors.into_iter().flatten().collect())
+    }
+
+    // Write vectors embedded since the cache was opened to disk
+    pub fn save_cache(&self) -> Result<()> {
+        match &self.cache {
+            Some(cache) => Ok(cache.lock().expect("embedding cache lock poisoned").save()?),
+            None => Ok(()),
+        }
+    }
+
+    // Encode texts that are the last of `total` being embedded, the rest
+    // having come from the cache, reporting progress after every batch
+    fn encode_reporting(&self, texts: &[String], total: usize) -> Result<Vec<Vec<f32>>> {
+        let progress = match &self.progress {
+            Some(progress) if total > 0 => progress,
+            _ => return self.encode(texts),
+        };
+        let mut done = total - texts.len();
+        progress.event(ProgressEvent::EmbeddingBatchDone { done, total });
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(PROGRESS_BATCH_SIZE) {
+            vectors.extend(self.encode(batch)?);
+            done += batch.len();
+            progress.event(ProgressEvent::EmbeddingBatchDone { done, total });
+        }
+        Ok(vectors)
+    }
+
+    // Run the provider, checking every vector has the expected length so a
+    // changed model can't mix incompatible vectors into the store
+    fn encode(&self, chunks: &[String]) -> Result<Vec<Vec<f32>>> {
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let _span = debug_span!("embed_batch", texts = chunks.len()).entered();
+        let vectors = self.provider.embed(chunks).map_err(ArrowError::Model)?;
+        for vector in &vectors {
+            let dimension = *self.dimension.get_or_init(|| vector.len());
+            if vector.len() != dimension {
+                return Err(ArrowError::DimensionMismatch {
+                    expected: dimension,
+                    found: vector.len(),
+                });
+            }
+        }
+        Ok(vectors)
+    }
+
+    fn convert_to_tensors(&self, embeddings: Vec<Vec<f32>>) -> Result<Vec<Tensor>> {
+        let tensors = embeddings
+            .iter()
+            .map(|embedding| {
+                Tensor::from_vec(embedding.clone(), &[embedding.len()], &self.device)
+                    .map_err(ArrowError::from)
+            })
+            .collect::<Result<Vec<Tensor>>>()?;
+
+        Ok(tensors)
+    }
+
+    pub fn decode(&self, tensor: &Tensor) -> Result<Vec<f32>> {
+        let result = tensor.to_device(&Device::Cpu)?.to_vec1::<f32>()?;
+        Ok(result)
+    }
+
+    pub fn decode_batch(&self, tensors: &[Tensor]) -> Result<Vec<Vec<f32>>> {
+        tensors.iter().map(|tensor| self.decode(tensor)).collect()
+    }
+}
+
+#[cfg(feature = "torch")]
+impl LocalProvider {
+    pub fn new(model_kind: EmbeddingModel, backend: &Backend) -> anyhow::Result<Self> {
+        let model = SentenceEmbeddingsBuilder::remote(model_kind.model_type())
+            .with_device(backend.torch)
+            .create_model()?;
+        Ok(Self {
+            model,
+            model_kind,
+            torch_device: backend.torch,
+        })
+    }
+}
+
+#[cfg(feature = "torch")]
+impl EmbeddingProvider for LocalProvider {
+    // Run the model over the chunks, spreading them over threads on the CPU
+    fn embed(&self, chunks: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
         if chunks.is_empty() {
             return Ok(Vec::new());
         }
@@ -80,10 +482,10 @@ impl Embeddor {
         let num_chunks = chunks.len();
         let num_threads = std::cmp::min(4, num_chunks); // Cap at 4 threads
 
-        if num_threads <= 1 {
+        // A GPU batches on its own; extra model copies would only use up its memory
+        if num_threads <= 1 || self.torch_device != tch::Device::Cpu {
             // If only one chunk or one thread, process sequentially
-            let embeddings = self.model.encode(&chunks)?;
-            return self.convert_to_tensors(embeddings);
+            return Ok(self.model.encode(chunks)?);
         }
 
         // Split chunks into batches
@@ -106,11 +508,13 @@ impl Embeddor {
         for (thread_idx, batch) in chunk_batches.into_iter().enumerate() {
             // Clone the sender for each thread
             let thread_sender = sender.clone();
+            let model_type = self.model_kind.model_type();
 
             // Move batch into thread
             thread::spawn(move || {
                 // Directly call model.encode in the spawned thread
-                match SentenceEmbeddingsBuilder::remote(SentenceEmbeddingsModelType::AllMiniLmL6V2)
+                match SentenceEmbeddingsBuilder::remote(model_type)
+                    .with_device(tch::Device::Cpu)
                     .create_model()
                     .and_then(|model| model.encode(&batch))
                 {
@@ -135,7 +539,8 @@ impl Embeddor {
         drop(sender);
 
         // Collect and order results
-        let mut ordered_results: Vec<(usize, Result<Vec<Vec<f32>>>)> = receiver.iter().collect();
+        let mut ordered_results: Vec<(usize, anyhow::Result<Vec<Vec<f32>>>)> =
+            receiver.iter().collect();
         ordered_results.sort_by_key(|(idx, _)| *idx);
 
         // Process results, flatten embeddings
@@ -146,33 +551,151 @@ impl Embeddor {
                 Err(e) => return Err(e),
             }
         }
+        Ok(all_embeddings)
+    }
 
-        // Convert to tensors
-        self.convert_to_tensors(all_embeddings)
+    // Most tokens a chunk can hold without being truncated by the model
+    fn max_chunk_tokens(&self) -> usize {
+        self.model_kind.max_sequence_length() - SPECIAL_TOKENS
     }
 
-    fn convert_to_tensors(&self, embeddings: Vec<Vec<f32>>) -> Result<Vec<Tensor>> {
-        let tensors = embeddings
-            .iter()
-            .map(|embedding| {
-                Tensor::from_vec(embedding.clone(), &[embedding.len()], &self.device)
-                    .map_err(anyhow::Error::from)
-            })
-            .collect::<Result<Vec<Tensor>>>()?;
+    fn token_starts(&self, text: &str) -> Vec<usize> {
+        let tokenized = self.model.get_tokenizer().tokenize_with_offsets(text);
+        tokenized
+            .offsets
+            .into_iter()
+            .flatten()
+            .map(|offset| offset.begin as usize)
+            .collect()
+    }
+}
 
-        Ok(tensors)
+// Group the `words` of `text`, each `tokens` long, into chunks as
+// `Embeddor::chunk` describes
+fn pack_words(
+    text: &str,
+    words: &[(usize, usize)],
+    tokens: &[usize],
+    max_tokens: usize,
+    overlap: usize,
+) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut first = 0;
+    while first < words.len() {
+        let start = words[first].0;
+        let mut last = first;
+        let mut size = tokens[first];
+        while last + 1 < words.len() && size + tokens[last + 1] <= max_tokens {
+            last += 1;
+            size += tokens[last];
+        }
+        let end = words[last].1;
+        chunks.push(Chunk {
+            text: text[start..end].to_string(),
+            start,
+            end,
+            metadata: Metadata::new(),
+        });
+        if last + 1 == words.len() {
+            break;
+        }
+
+        let mut next = last + 1;
+        let mut repeated = 0;
+        while next > first + 1 && repeated + tokens[next - 1] <= overlap {
+            next -= 1;
+            repeated += tokens[next];
+        }
+        first = next;
     }
+    chunks
+}
 
-    pub fn decode(&self, tensor: &Tensor) -> Result<Vec<f32>> {
-        let result = tensor.to_device(&Device::Cpu)?.to_vec1::<f32>()?;
-        Ok(result)
+// Byte ranges of the whitespace-separated words in `text`
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut word_start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), word_start) {
+            (true, Some(start)) => {
+                spans.push((start, i));
+                word_start = None;
+            }
+            (false, None) => word_start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(start) = word_start {
+        spans.push((start, text.len()));
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "The quick brown fox jumps over the lazy dog.\n\nÜber café naïve \
+                        façade — straße   tabs\tand  spaces; antidisestablishmentarianism!";
+
+    // Stands in for a tokenizer with a token per four bytes of each word
+    fn chunk(text: &str, max_tokens: usize, overlap: usize) -> Vec<Chunk> {
+        let words = word_spans(text);
+        let tokens: Vec<usize> = words.iter().map(|(start, end)| 1 + (end - start) / 4).collect();
+        pack_words(text, &words, &tokens, max_tokens, overlap)
     }
 
-    pub fn decode_batch(&self, tensors: &[Tensor]) -> Result<Vec<Vec<f32>>> {
-        tensors.iter().map(|tensor| self.decode(tensor)).collect()
+    #[test]
+    fn chunks_are_exact_slices_of_the_text() {
+        for max_tokens in 1..12 {
+            for chunk in chunk(TEXT, max_tokens, max_tokens / 2) {
+                assert_eq!(chunk.text, &TEXT[chunk.start..chunk.end]);
+            }
+        }
+    }
+
+    // Every chunk starts past the one before, whatever the overlap, never
+    // ends before it, and the last one reaches the end of the text
+    #[test]
+    fn chunks_make_progress_for_any_overlap() {
+        for max_tokens in 1..12 {
+            for overlap in 0..16 {
+                let chunks = chunk(TEXT, max_tokens, overlap);
+                assert!(!chunks.is_empty());
+                for pair in chunks.windows(2) {
+                    assert!(pair[1].start > pair[0].start);
+                    assert!(pair[1].end >= pair[0].end);
+                }
+                assert_eq!(chunks.last().unwrap().end, TEXT.trim_end().len());
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_without_overlap_are_disjoint_and_cover_every_word() {
+        let chunks = chunk(TEXT, 5, 0);
+        for pair in chunks.windows(2) {
+            assert!(pair[1].start >= pair[0].end);
+        }
+        let words: Vec<&str> =
+            chunks.iter().flat_map(|chunk| chunk.text.split_whitespace()).collect();
+        assert_eq!(words, TEXT.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn overlapping_chunks_repeat_the_end_of_the_one_before() {
+        let text = "one two six ten red tan big low hot far";
+        let chunks = chunk(text, 4, 2);
+        assert!(chunks.len() > 1);
+        for pair in chunks.windows(2) {
+            let shared = &text[pair[1].start..pair[0].end];
+            assert!(pair[0].text.ends_with(shared) && pair[1].text.starts_with(shared));
+            assert!(shared.split_whitespace().count() <= 2);
+        }
     }
 
-    pub fn embedding_dim(&self) -> usize {
-        384
+    #[test]
+    fn blank_text_has_no_chunks() {
+        assert!(chunk(" \n\t ", 10, 2).is_empty());
     }
 }
@@ -0,0 +1,32 @@
+use std::io;
+use thiserror::Error;
+
+// Why a `VectorStore` or `Embeddor` call failed
+#[derive(Debug, Error)]
+pub enum ArrowError {
+    // Reading or writing the store's files
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    // Encoding or decoding stored records
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    // A vector whose length differs from the store's or the model's
+    #[error(
+        "Expected {expected}-dimensional vectors, got {found}; was the store's model replaced?"
+    )]
+    DimensionMismatch { expected: usize, found: usize },
+    // Loading or running an embedding model
+    #[error(transparent)]
+    Model(anyhow::Error),
+    // A document, source or field that isn't in the store
+    #[error("{0} not found")]
+    NotFound(String),
+    // Arguments that can't be used, such as field weights adding up to 0
+    #[error("{0}")]
+    Invalid(String),
+    // Converting between tensors and vectors
+    #[error(transparent)]
+    Tensor(#[from] candle_core::Error),
+}
+
+pub type Result<T, E = ArrowError> = std::result::Result<T, E>;
@@ -0,0 +1,225 @@
+use crate::loaders;
+use crate::vectorstore::{Provenance, SearchResult};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+// The lines of a source file a search result came from, with lines around it
+#[derive(Serialize)]
+pub struct Excerpt {
+    // Line numbers of the result's first and last lines, from 1
+    pub start_line: usize,
+    pub end_line: usize,
+    // Line number of the first of `lines`
+    pub first_line: usize,
+    pub lines: Vec<String>,
+    // Byte offset of each of `lines` in the source
+    #[serde(skip)]
+    pub offsets: Vec<usize>,
+}
+
+impl Excerpt {
+    // Whether the line at `number` is part of the result rather than context
+    pub fn is_match(&self, number: usize) -> bool {
+        (self.start_line..=self.end_line).contains(&number)
+    }
+}
+
+// Reads each source file once for all the results taken from it
+#[derive(Default)]
+pub struct Excerpts {
+    // Text of each source as its loader produced it when the file was indexed,
+    // or None if it can't be read
+    texts: HashMap<String, Option<String>>,
+}
+
+impl Excerpts {
+    // The region of its source a result came from, with `context` lines before
+    // and after it. None for results without a span, such as records, those
+    // read from stdin, and files that are gone or changed since they were indexed.
+    pub fn excerpt(&mut self, result: &SearchResult, context: usize) -> Option<Excerpt> {
+        let (start, end) = result.span?;
+        let source = result.provenance.as_ref()?.source_path.as_str();
+        let text = self
+            .texts
+            .entry(source.to_string())
+            .or_insert_with(|| loaders::load(Path::new(source)).ok().map(|loaded| loaded.text))
+            .as_deref()?;
+        // Spans index the loaded text, so a changed file no longer lines up
+        if text.get(start..end) != Some(result.text.as_str()) {
+            return None;
+        }
+
+        let start_line = text[..start].matches('\n').count() + 1;
+        let end_line = start_line + text[start..end].trim_end_matches('\n').matches('\n').count();
+        let first_line = start_line.saturating_sub(context).max(1);
+        let mut lines = Vec::new();
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        for (number, line) in (1..).zip(text.split_inclusive('\n')) {
+            if number > end_line + context {
+                break;
+            }
+            if number >= first_line {
+                lines.push(line.trim_end_matches(['\n', '\r']).to_string());
+                offsets.push(offset);
+            }
+            offset += line.len();
+        }
+        Some(Excerpt {
+            start_line,
+            end_line,
+            first_line,
+            lines,
+            offsets,
+        })
+    }
+}
+
+// Merge results that are overlapping chunks of the same source into passages,
+// taking them best first until one would start passage `limit + 1`. A passage
+// keeps the rank and score of its best chunk.
+pub fn stitch_overlapping(results: Vec<SearchResult>, limit: usize) -> Vec<SearchResult> {
+    let mut passages: Vec<SearchResult> = Vec::new();
+    for result in results {
+        let Some(i) = passages.iter_mut().position(|passage| stitch(passage, &result)) else {
+            if passages.len() == limit {
+                break;
+            }
+            passages.push(result);
+            continue;
+        };
+        // The chunk can bridge its passage to a later one
+        let mut j = i + 1;
+        while j < passages.len() {
+            let (head, tail) = passages.split_at_mut(j);
+            if stitch(&mut head[i], &tail[0]) {
+                passages.remove(j);
+            } else {
+                j += 1;
+            }
+        }
+    }
+    passages
+}
+
+// Whether two chunks come from the same source file
+fn same_source(a: &Option<Provenance>, b: &Option<Provenance>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.source_path == b.source_path,
+        _ => false,
+    }
+}
+
+// Extend a result with an overlapping chunk of the same source. Chunk texts are
+// exact slices of the source, so the ranges line up byte for byte.
+fn stitch(result: &mut SearchResult, other: &SearchResult) -> bool {
+    let ((current_start, current_end), (start, end)) = match (result.span, other.span) {
+        (Some(current), Some(span)) => (current, span),
+        _ => return false,
+    };
+    if !same_source(&result.provenance, &other.provenance)
+        || start >= current_end
+        || end <= current_start
+    {
+        return false;
+    }
+
+    let prefix = if start < current_start {
+        match other.text.get(..current_start - start) {
+            Some(prefix) => prefix,
+            None => return false,
+        }
+    } else {
+        ""
+    };
+    let suffix = if end > current_end {
+        match other.text.get(current_end - start..) {
+            Some(suffix) => suffix,
+            None => return false,
+        }
+    } else {
+        ""
+    };
+    result.text = format!("{}{}{}", prefix, result.text, suffix);
+    result.span = Some((start.min(current_start), end.max(current_end)));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectorstore::{Metadata, Score};
+
+    const SOURCE: &str = "Stitched chunks must line up byte for byte — even across ünïcode.";
+
+    fn result(source_path: &str, start: usize, end: usize) -> SearchResult {
+        SearchResult {
+            text: SOURCE[start..end].to_string(),
+            score: Score::cosine(0.5),
+            filename: Some(source_path.to_string()),
+            provenance: Some(Provenance::new(source_path, 0, 1)),
+            span: Some((start, end)),
+            metadata: Metadata::new(),
+            added: None,
+            updated: None,
+        }
+    }
+
+    #[test]
+    fn stitches_overlapping_spans_into_the_source_slice() {
+        let end = SOURCE.len();
+        let cases = [(0, 30, 20, end), (20, end, 0, 30), (0, end, 9, 30), (9, 30, 0, end)];
+        for (start, stop, other_start, other_stop) in cases {
+            let mut stitched = result("a.txt", start, stop);
+            assert!(stitch(&mut stitched, &result("a.txt", other_start, other_stop)));
+            let span = (start.min(other_start), stop.max(other_stop));
+            assert_eq!(stitched.span, Some(span));
+            assert_eq!(stitched.text, &SOURCE[span.0..span.1]);
+        }
+    }
+
+    #[test]
+    fn leaves_unrelated_results_alone() {
+        let mut first = result("a.txt", 0, 20);
+        assert!(!stitch(&mut first, &result("b.txt", 10, 30)));
+        assert!(!stitch(&mut first, &result("a.txt", 20, 30)));
+
+        let mut spanless = result("a.txt", 10, 30);
+        spanless.span = None;
+        assert!(!stitch(&mut first, &spanless));
+        assert_eq!(first.text, &SOURCE[..20]);
+        assert_eq!(first.span, Some((0, 20)));
+    }
+
+    #[test]
+    fn stitches_a_chunk_bridging_two_earlier_ones() {
+        let first = result("a.txt", 0, 20);
+        let second = result("a.txt", 30, 50);
+        let stitched = stitch_overlapping(vec![first, second, result("a.txt", 15, 35)], 2);
+        assert_eq!(stitched.len(), 1);
+        assert_eq!(stitched[0].text, &SOURCE[..50]);
+    }
+
+    #[test]
+    fn stops_before_a_passage_past_the_limit() {
+        let chunks = vec![
+            result("a.txt", 0, 20),
+            result("b.txt", 0, 20),
+            result("c.txt", 0, 20),
+            result("a.txt", 15, 35),
+        ];
+        let stitched = stitch_overlapping(chunks, 2);
+        let sources: Vec<_> = stitched.iter().map(|result| result.filename.as_deref()).collect();
+        assert_eq!(sources, vec![Some("a.txt"), Some("b.txt")]);
+        assert_eq!(stitched[0].span, Some((0, 20)));
+    }
+
+    #[test]
+    fn same_source_compares_source_paths() {
+        let source = Some(Provenance::new("a.txt", 0, 2));
+        assert!(same_source(&source, &Some(Provenance::new("a.txt", 1, 2))));
+        assert!(!same_source(&source, &Some(Provenance::new("b.txt", 0, 2))));
+        assert!(!same_source(&source, &None));
+    }
+}
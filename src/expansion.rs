@@ -0,0 +1,97 @@
+use crate::llm::{Llm, Message};
+use anyhow::{bail, Result};
+
+// Words that frame a question rather than say what it is about
+const FRAMING_WORDS: &[&str] = &[
+    "a", "about", "an", "any", "are", "be", "can", "could", "did", "do", "does", "for", "how",
+    "i", "in", "is", "it", "me", "my", "of", "on", "our", "please", "should", "tell", "the",
+    "there", "to", "was", "we", "what", "when", "where", "which", "who", "why", "with", "you",
+];
+
+// How a query is reworded for multi-query search
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Expansion {
+    // Fixed rewrites of the query's keywords, without a model
+    #[default]
+    Template,
+    // Paraphrases written by the chat model `ask` uses
+    Llm,
+}
+
+// Up to `count` rewrites of a query from fixed templates: its keywords alone,
+// then phrasings such as "how to ..." and "... explained" around them
+pub fn template_paraphrases(query: &str, count: usize) -> Vec<String> {
+    let words: Vec<&str> = query
+        .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '\'')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let keywords: Vec<&str> = words
+        .iter()
+        .filter(|word| !FRAMING_WORDS.contains(&word.to_lowercase().as_str()))
+        .copied()
+        .collect();
+    let topic = if keywords.is_empty() {
+        words.join(" ")
+    } else {
+        keywords.join(" ")
+    };
+    let candidates = [
+        topic.clone(),
+        format!("how to {}", topic),
+        format!("what is {}", topic),
+        format!("{} explained", topic),
+        format!("guide to {}", topic),
+        format!("{} example", topic),
+        format!("troubleshooting {}", topic),
+        format!("{} overview", topic),
+    ];
+    distinct(query, candidates.into_iter(), count)
+}
+
+// Up to `count` paraphrases of a query written by a chat model
+pub fn llm_paraphrases(llm: &Llm, query: &str, count: usize) -> Result<Vec<String>> {
+    let messages = [
+        Message::system(
+            "You rewrite search queries for a document search engine. Reply with only \
+             the rewrites, one per line, without numbering, quotes or commentary.",
+        ),
+        Message::user(format!(
+            "Write {} different rewordings of this search query that ask for the same \
+             thing in other words:\n\n{}",
+            count,
+            query.trim()
+        )),
+    ];
+    let reply = llm.chat(&messages)?;
+    // Models number or bullet their lines despite being asked not to
+    let lines = reply.lines().map(|line| {
+        line.trim()
+            .trim_start_matches(|c: char| c.is_ascii_digit() || "-*•.)".contains(c))
+            .trim()
+            .trim_matches('"')
+            .to_string()
+    });
+    let paraphrases = distinct(query, lines, count);
+    if paraphrases.is_empty() {
+        bail!("{} gave no paraphrases of the query", llm.name());
+    }
+    Ok(paraphrases)
+}
+
+// The first `count` non-empty candidates that differ from the query and each
+// other, ignoring case
+fn distinct(query: &str, candidates: impl Iterator<Item = String>, count: usize) -> Vec<String> {
+    let mut seen = vec![query.trim().to_lowercase()];
+    let mut kept = Vec::new();
+    for candidate in candidates {
+        let key = candidate.trim().to_lowercase();
+        if kept.len() == count {
+            break;
+        }
+        if !key.is_empty() && !seen.contains(&key) {
+            seen.push(key);
+            kept.push(candidate.trim().to_string());
+        }
+    }
+    kept
+}
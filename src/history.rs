@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Queries kept; older ones are dropped once the file holds twice as many
+const MAX_ENTRIES: usize = 1_000;
+
+// A past query and the best match it returned
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub query: String,
+    pub results: usize,
+    pub top_source: Option<String>,
+    pub top_score: Option<f32>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        query: &str,
+        results: usize,
+        top_source: Option<String>,
+        top_score: Option<f32>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Self {
+            timestamp,
+            query: query.to_string(),
+            results,
+            top_source,
+            top_score,
+        }
+    }
+}
+
+// Append a query to the store's history in `<database>.history`, one JSON
+// object per line
+pub fn record(store_path: &Path, entry: &HistoryEntry) -> Result<()> {
+    let path = history_path(store_path);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open query history: {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    drop(file);
+
+    let entries = read(&path)?;
+    if entries.len() > 2 * MAX_ENTRIES {
+        write(&path, &entries[entries.len() - MAX_ENTRIES..])?;
+    }
+    Ok(())
+}
+
+// The most recent queries, newest first
+pub fn recent(store_path: &Path, limit: usize) -> Result<Vec<HistoryEntry>> {
+    let mut entries = read(&history_path(store_path))?;
+    entries.reverse();
+    entries.truncate(limit);
+    Ok(entries)
+}
+
+pub fn clear(store_path: &Path) -> Result<()> {
+    let path = history_path(store_path);
+    if path.exists() {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove query history: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+// Entries oldest first. Lines that don't parse, such as one cut short by a
+// crash, are skipped.
+fn read(path: &Path) -> Result<Vec<HistoryEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read query history: {}", path.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+fn write(path: &Path, entries: &[HistoryEntry]) -> Result<()> {
+    let tmp_path = path.with_extension("history.tmp");
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to write query history: {}", path.display()))
+}
+
+fn history_path(store_path: &Path) -> PathBuf {
+    let mut path = store_path.as_os_str().to_owned();
+    path.push(".history");
+    PathBuf::from(path)
+}
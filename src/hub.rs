@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use crate::{models, offline};
+use hf_hub::api::sync::{Api, ApiBuilder, ApiRepo};
+use hf_hub::{Cache, CacheRepo};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+// Where a model's files come from
+enum Source {
+    // A directory holding the files, for machines without network access
+    Dir(PathBuf),
+    // A Hugging Face Hub repository, downloaded into the hub cache on first use
+    Hub(ApiRepo),
+    // Only what the hub cache in the directory already holds, when offline
+    Cached(CacheRepo, PathBuf),
+}
+
+// The files of a transformer model: its config, tokenizer and weights
+pub struct ModelFiles {
+    name: String,
+    source: Source,
+}
+
+// Input limit of the architecture, from `config.json`
+#[derive(Deserialize)]
+struct ModelLimits {
+    max_position_embeddings: usize,
+}
+
+// Input limit the model was trained with, from `sentence_bert_config.json`
+#[derive(Deserialize)]
+struct SentenceConfig {
+    max_seq_length: usize,
+}
+
+impl ModelFiles {
+    // A local directory if `name` is one, otherwise a hub repository such as
+    // `sentence-transformers/all-MiniLM-L6-v2`
+    pub fn open(name: &str) -> Result<Self> {
+        let source = if Path::new(name).is_dir() {
+            Source::Dir(PathBuf::from(name))
+        } else if offline::is_offline() {
+            let dir = models::hub_cache_dir()
+                .context("Can't locate the model cache; set --model-cache or HOME")?;
+            Source::Cached(Cache::new(dir.clone()).model(name.to_string()), dir)
+        } else {
+            let api = match models::hub_cache_dir() {
+                Some(dir) => ApiBuilder::from_cache(Cache::new(dir)).build(),
+                None => Api::new(),
+            };
+            let api = api.context("Failed to open the Hugging Face Hub cache")?;
+            Source::Hub(api.model(name.to_string()))
+        };
+        Ok(Self {
+            name: name.to_string(),
+            source,
+        })
+    }
+
+    pub fn get(&self, file: &str) -> Result<PathBuf> {
+        match &self.source {
+            Source::Dir(dir) => {
+                let path = dir.join(file);
+                if !path.exists() {
+                    anyhow::bail!("{} has no {}", self.name, file);
+                }
+                Ok(path)
+            }
+            Source::Hub(repo) => repo
+                .get(file)
+                .with_context(|| format!("Failed to fetch {} from {}", file, self.name)),
+            Source::Cached(repo, dir) => repo.get(file).with_context(|| {
+                format!(
+                    "{} of {} isn't in the model cache at {}, and --offline forbids downloading \
+                     it; run `arrow model download` while online",
+                    file,
+                    self.name,
+                    dir.display()
+                )
+            }),
+        }
+    }
+
+    // The first of `files` the model has
+    pub fn first_of(&self, files: &[&str]) -> Result<PathBuf> {
+        files
+            .iter()
+            .find_map(|file| self.get(file).ok())
+            .with_context(|| format!("{} has none of: {}", self.name, files.join(", ")))
+    }
+
+    // Longest input in tokens. Sentence-transformers models may be trained
+    // on shorter inputs than the architecture allows.
+    pub fn max_tokens(&self) -> Result<usize> {
+        let config = fs::read_to_string(self.get("config.json")?)?;
+        let limits: ModelLimits = serde_json::from_str(&config)
+            .with_context(|| format!("Invalid config.json in {}", self.name))?;
+        let trained = self
+            .get("sentence_bert_config.json")
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str::<SentenceConfig>(&json).ok())
+            .map(|sentence| sentence.max_seq_length);
+        Ok(trained.map_or(limits.max_position_embeddings, |trained| {
+            trained.min(limits.max_position_embeddings)
+        }))
+    }
+
+    // The model's tokenizer twice: padding and truncating batches to
+    // `max_tokens` for the model, and as-is to size chunks
+    pub fn tokenizers(&self, max_tokens: usize) -> Result<(Tokenizer, Tokenizer)> {
+        let counter =
+            Tokenizer::from_file(self.get("tokenizer.json")?).map_err(anyhow::Error::msg)?;
+        let mut tokenizer = counter.clone();
+        let padding = padding(&tokenizer);
+        tokenizer.with_padding(Some(padding));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: max_tokens,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+        Ok((tokenizer, counter))
+    }
+}
+
+// Pad with the tokenizer's own padding token: BERT's `[PAD]`, or `<pad>` in
+// the SentencePiece vocabularies of multilingual models such as
+// paraphrase-multilingual-MiniLM, where id 0 is `<s>`
+fn padding(tokenizer: &Tokenizer) -> PaddingParams {
+    ["[PAD]", "<pad>"]
+        .into_iter()
+        .find_map(|token| {
+            let pad_id = tokenizer.token_to_id(token)?;
+            Some(PaddingParams {
+                pad_id,
+                pad_token: token.to_string(),
+                ..PaddingParams::default()
+            })
+        })
+        .unwrap_or_default()
+}
+
+// Character offsets of the tokens of `text`, falling back to an estimate
+pub fn token_starts(counter: &Tokenizer, text: &str) -> Vec<usize> {
+    match counter.encode_char_offsets(text, false) {
+        Ok(encoding) => encoding
+            .get_offsets()
+            .iter()
+            .map(|&(start, _)| start)
+            .collect(),
+        Err(_) => crate::provider::estimated_token_starts(text),
+    }
+}
@@ -0,0 +1,155 @@
+use crate::pq;
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Vectors per list sampled to train the centroids
+const TRAINING_VECTORS_PER_LIST: usize = 64;
+
+// k-means iterations when training the centroids
+const ITERATIONS: usize = 10;
+
+// Inverted file index: the vectors partitioned by k-means into one list per
+// centroid. A search compares the query with the centroids and only scans
+// the lists of the nearest few, so adding or removing a vector touches one
+// list instead of relinking a graph.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InvertedFile {
+    centroids: Vec<Vec<f32>>,
+    lists: Vec<Vec<Uuid>>,
+}
+
+impl InvertedFile {
+    // Train `nlist` centroids on a sample of the vectors, then put every
+    // vector in the list of its nearest one
+    pub fn train(vectors: &[(Uuid, Vec<f32>)], nlist: usize) -> Self {
+        // k-means starts from the first points, so shuffle to pick them at random
+        let mut points: Vec<&[f32]> = vectors.iter().map(|(_, vector)| &vector[..]).collect();
+        points.shuffle(&mut rand::thread_rng());
+        points.truncate(nlist * TRAINING_VECTORS_PER_LIST);
+        let centroids = pq::kmeans(&points, nlist.min(points.len()), ITERATIONS);
+        let mut ivf = Self {
+            lists: vec![Vec::new(); centroids.len()],
+            centroids,
+        };
+        ivf.assign_all(vectors);
+        ivf
+    }
+
+    // Empty the lists and assign every vector again with the same centroids
+    pub fn assign_all(&mut self, vectors: &[(Uuid, Vec<f32>)]) {
+        let assignments: Vec<usize> = vectors
+            .par_iter()
+            .map(|(_, vector)| pq::nearest_centroid(&self.centroids, vector))
+            .collect();
+        for list in &mut self.lists {
+            list.clear();
+        }
+        for ((id, _), list) in vectors.iter().zip(assignments) {
+            self.lists[list].push(*id);
+        }
+    }
+
+    pub fn assign(&mut self, id: Uuid, vector: &[f32]) {
+        let list = pq::nearest_centroid(&self.centroids, vector);
+        self.lists[list].push(id);
+    }
+
+    // Take a vector out of the list it was assigned to
+    pub fn remove(&mut self, id: &Uuid, vector: &[f32]) {
+        let list = pq::nearest_centroid(&self.centroids, vector);
+        self.lists[list].retain(|other| other != id);
+    }
+
+    // IDs in the lists of the `nprobe` centroids nearest the query
+    pub fn probe(&self, query: &[f32], nprobe: usize) -> impl Iterator<Item = &Uuid> {
+        let mut nearest: Vec<(f32, usize)> = self
+            .centroids
+            .iter()
+            .enumerate()
+            .map(|(list, centroid)| (pq::squared_distance(query, centroid), list))
+            .collect();
+        let nprobe = nprobe.clamp(1, nearest.len().max(1));
+        if nearest.len() > nprobe {
+            nearest.select_nth_unstable_by(nprobe, |a, b| a.0.total_cmp(&b.0));
+            nearest.truncate(nprobe);
+        }
+        nearest.into_iter().flat_map(|(_, list)| &self.lists[list])
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &Uuid> {
+        self.lists.iter().flatten()
+    }
+
+    pub fn list_count(&self) -> usize {
+        self.lists.len()
+    }
+
+    pub fn largest_list(&self) -> usize {
+        self.lists.iter().map(|list| list.len()).max().unwrap_or(0)
+    }
+
+    // Rough bytes held in memory: centroids and the IDs in the lists
+    pub fn memory_bytes(&self) -> usize {
+        self.centroids.iter().map(|centroid| centroid.len() * 4).sum::<usize>()
+            + self.lists.iter().map(|list| list.len() * 16 + 24).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two well separated groups of vectors
+    fn vectors() -> Vec<(Uuid, Vec<f32>)> {
+        (0..40)
+            .map(|i| {
+                let offset = if i % 2 == 0 { 0.0 } else { 100.0 };
+                (Uuid::new_v4(), vec![offset + (i / 2) as f32 * 0.01, offset])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assigns_every_vector_to_one_list() {
+        let vectors = vectors();
+        let ivf = InvertedFile::train(&vectors, 4);
+        let mut ids: Vec<Uuid> = ivf.ids().copied().collect();
+        ids.sort();
+        let mut expected: Vec<Uuid> = vectors.iter().map(|(id, _)| *id).collect();
+        expected.sort();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn probing_every_list_finds_every_vector() {
+        let vectors = vectors();
+        let ivf = InvertedFile::train(&vectors, 4);
+        assert_eq!(ivf.probe(&[0.0, 0.0], ivf.list_count()).count(), vectors.len());
+        // More lists than there are is the same as all of them
+        assert_eq!(ivf.probe(&[0.0, 0.0], 100).count(), vectors.len());
+    }
+
+    #[test]
+    fn nearest_list_holds_the_query_vector() {
+        let vectors = vectors();
+        let ivf = InvertedFile::train(&vectors, 4);
+        for (id, vector) in &vectors {
+            assert!(ivf.probe(vector, 1).any(|probed| probed == id));
+        }
+    }
+
+    #[test]
+    fn removes_from_the_assigned_list() {
+        let vectors = vectors();
+        let mut ivf = InvertedFile::train(&vectors, 4);
+        let (id, vector) = &vectors[5];
+        ivf.remove(id, vector);
+        assert!(!ivf.ids().any(|other| other == id));
+        assert_eq!(ivf.ids().count(), vectors.len() - 1);
+
+        ivf.assign(*id, vector);
+        assert!(ivf.probe(vector, 1).any(|probed| probed == id));
+    }
+}
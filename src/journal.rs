@@ -0,0 +1,166 @@
+use crate::sources;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// One line of the journal. Sources are kept as hashes of their paths, so the
+// journal of an encrypted store doesn't reveal its file names.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    // The command the journal belongs to, always the first line
+    Job { key: String },
+    // Indexing of a file began
+    Started { source: String },
+    // A file's chunks were saved with the store
+    Done { source: String },
+}
+
+// Progress of an `add` or `sync` over many files, kept in `<path>.journal`
+// next to the store until the command finishes. Running the same command
+// again after it was interrupted skips the files saved by its checkpoints.
+pub struct Journal {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    // Files the interrupted run saved
+    done: HashSet<String>,
+    // Files the interrupted run began but didn't save, which may have left
+    // chunks behind
+    interrupted: HashSet<String>,
+    // Files finished since the last checkpoint
+    finished: Vec<String>,
+}
+
+impl Journal {
+    // Open the journal for the command identified by `job`, resuming it if the
+    // journal was left by the same command and starting afresh otherwise
+    pub fn open<P: AsRef<Path>>(store_path: P, job: &str) -> Result<Self> {
+        let path = journal_path(store_path.as_ref());
+        let key = sources::hash_bytes(job.as_bytes());
+        let entries = read_entries(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut done = HashSet::new();
+        let mut interrupted = HashSet::new();
+        let resumed = matches!(
+            entries.first(),
+            Some(Entry::Job { key: previous }) if *previous == key
+        );
+        if resumed {
+            for entry in entries {
+                match entry {
+                    Entry::Job { .. } => {}
+                    Entry::Started { source } => {
+                        interrupted.insert(source);
+                    }
+                    Entry::Done { source } => {
+                        interrupted.remove(&source);
+                        done.insert(source);
+                    }
+                }
+            }
+        }
+
+        let file = if resumed {
+            OpenOptions::new().append(true).open(&path)
+        } else {
+            File::create(&path)
+        }
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut journal = Self {
+            path,
+            writer: BufWriter::new(file),
+            done,
+            interrupted,
+            finished: Vec::new(),
+        };
+        if !resumed {
+            journal.append(&Entry::Job { key })?;
+            journal.sync()?;
+        }
+        Ok(journal)
+    }
+
+    // Number of files the interrupted run saved
+    pub fn resumed(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn is_done(&self, source: &str) -> bool {
+        self.done.contains(&hash(source))
+    }
+
+    // Whether the interrupted run was indexing `source` when it stopped
+    pub fn was_interrupted(&self, source: &str) -> bool {
+        self.interrupted.contains(&hash(source))
+    }
+
+    pub fn start(&mut self, source: &str) -> Result<()> {
+        self.append(&Entry::Started { source: hash(source) })
+    }
+
+    // Note a finished file, recorded as done at the next checkpoint
+    pub fn finish(&mut self, source: &str) {
+        self.finished.push(hash(source));
+    }
+
+    // Record the files finished since the last checkpoint as done. Call once
+    // the store holding their chunks has been saved.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        for source in std::mem::take(&mut self.finished) {
+            self.append(&Entry::Done { source })?;
+        }
+        self.sync()
+    }
+
+    // Delete the journal once the command has finished
+    pub fn remove(self) -> Result<()> {
+        drop(self.writer);
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove {}", self.path.display()))
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        self.writer.write_all(b"\n")?;
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer
+            .get_ref()
+            .sync_data()
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+// Entries of an existing journal. A torn final line ends it, as in the WAL.
+fn read_entries(path: &Path) -> std::io::Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+    }
+    Ok(entries)
+}
+
+fn hash(source: &str) -> String {
+    sources::hash_bytes(source.as_bytes())
+}
+
+fn journal_path(store_path: &Path) -> PathBuf {
+    let mut path = store_path.as_os_str().to_owned();
+    path.push(".journal");
+    PathBuf::from(path)
+}
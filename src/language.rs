@@ -0,0 +1,112 @@
+use whatlang::Lang;
+
+// whatlang's ISO 639-3 codes with the ISO 639-1 codes documents are tagged with
+const CODES: &[(&str, &str)] = &[
+    ("afr", "af"),
+    ("aka", "ak"),
+    ("amh", "am"),
+    ("ara", "ar"),
+    ("aze", "az"),
+    ("bel", "be"),
+    ("ben", "bn"),
+    ("bul", "bg"),
+    ("cat", "ca"),
+    ("ces", "cs"),
+    ("cmn", "zh"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("epo", "eo"),
+    ("est", "et"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("guj", "gu"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hrv", "hr"),
+    ("hun", "hu"),
+    ("hye", "hy"),
+    ("ind", "id"),
+    ("ita", "it"),
+    ("jav", "jv"),
+    ("jpn", "ja"),
+    ("kan", "kn"),
+    ("kat", "ka"),
+    ("khm", "km"),
+    ("kor", "ko"),
+    ("lat", "la"),
+    ("lav", "lv"),
+    ("lit", "lt"),
+    ("mal", "ml"),
+    ("mar", "mr"),
+    ("mkd", "mk"),
+    ("mya", "my"),
+    ("nep", "ne"),
+    ("nld", "nl"),
+    ("nob", "nb"),
+    ("ori", "or"),
+    ("pan", "pa"),
+    ("pes", "fa"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("sin", "si"),
+    ("slk", "sk"),
+    ("slv", "sl"),
+    ("sna", "sn"),
+    ("spa", "es"),
+    ("srp", "sr"),
+    ("swe", "sv"),
+    ("tam", "ta"),
+    ("tel", "te"),
+    ("tgl", "tl"),
+    ("tha", "th"),
+    ("tuk", "tk"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("urd", "ur"),
+    ("uzb", "uz"),
+    ("vie", "vi"),
+    ("yid", "yi"),
+    ("zul", "zu"),
+];
+
+// The language `text` is written in, as an ISO 639-1 code, when whatlang is
+// confident of it. Short or mixed texts are often not.
+pub fn detect(text: &str) -> Option<&'static str> {
+    let info = whatlang::detect(text)?;
+    if !info.is_reliable() {
+        return None;
+    }
+    let code = info.lang().code();
+    CODES.iter().find(|(long, _)| *long == code).map(|(_, short)| *short)
+}
+
+// English name of the language with an ISO 639-1 code, such as "German" for "de"
+pub fn name(code: &str) -> Option<&'static str> {
+    let (long, _) = CODES.iter().find(|(_, short)| *short == code)?;
+    Lang::from_code(*long).map(Lang::eng_name)
+}
+
+// A language given on the command line as an ISO 639-1 or 639-3 code, or its
+// English name, as the ISO 639-1 code documents are tagged with
+pub fn parse(value: &str) -> Result<String, String> {
+    let value = value.trim().to_lowercase();
+    CODES
+        .iter()
+        .find(|(long, short)| {
+            *short == value
+                || *long == value
+                || Lang::from_code(*long).is_some_and(|lang| lang.eng_name().to_lowercase() == value)
+        })
+        .map(|(_, short)| short.to_string())
+        .ok_or_else(|| {
+            format!(
+                "unknown language '{}': expected a code such as en, de or fr, or a name such as \
+                 German",
+                value
+            )
+        })
+}
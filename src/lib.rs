@@ -1,2 +1,48 @@
+#[cfg(feature = "candle")]
+mod bert;
+mod binary;
+mod cache;
+mod code;
+mod collections;
+mod columnar;
+mod config;
+mod crypto;
+mod dates;
+mod db;
+mod device;
+mod dump;
 mod embedding;
+mod error;
+mod expansion;
+mod history;
+mod ivf;
+mod llm;
+#[cfg(any(feature = "candle", feature = "onnx"))]
+mod hub;
+mod loaders;
+mod lock;
+mod markdown;
+mod markup;
+mod mcp;
+mod models;
+mod offline;
+mod ollama;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod openai;
+mod output;
+mod pq;
+mod progress;
+mod provider;
+mod records;
+mod remote;
+mod server;
+mod sources;
+mod textindex;
 mod vectorstore;
+mod wal;
+
+pub use db::ArrowDb;
+pub use error::{ArrowError, Result};
+pub use progress::{ProgressEvent, ProgressSink};
+pub use vectorstore::{Provenance, SearchResult};
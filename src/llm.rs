@@ -0,0 +1,315 @@
+use crate::{offline, ollama, openai};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
+use std::thread;
+use std::time::Duration;
+
+// Attempts per request when rate limited or the server fails
+const MAX_ATTEMPTS: u32 = 4;
+
+// Where answers are generated
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LlmProvider {
+    // An OpenAI-compatible `/v1/chat/completions` endpoint, at
+    // `OPENAI_BASE_URL` with `OPENAI_API_KEY` as for embeddings
+    #[default]
+    Openai,
+    // A local Ollama server's `/api/chat`, at `OLLAMA_HOST`
+    Ollama,
+}
+
+impl LlmProvider {
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    // Model used when only the provider is given
+    pub fn default_model(self) -> &'static str {
+        match self {
+            LlmProvider::Openai => "gpt-4o-mini",
+            LlmProvider::Ollama => "llama3.2",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Message {
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: "system",
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user",
+            content: content.into(),
+        }
+    }
+}
+
+// A chat model that answers from a list of messages
+pub struct Llm {
+    agent: ureq::Agent,
+    provider: LlmProvider,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    // Ollama streams unless told not to; OpenAI only streams when asked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponse {
+    message: ChatMessage,
+}
+
+// A piece of a streamed OpenAI reply, sent as a server-sent event
+#[derive(Deserialize)]
+struct OpenAiChunk {
+    #[serde(default)]
+    choices: Vec<OpenAiDelta>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiDelta {
+    delta: ChatDelta,
+}
+
+#[derive(Deserialize)]
+struct ChatDelta {
+    content: Option<String>,
+}
+
+// A line of a streamed Ollama reply
+#[derive(Deserialize)]
+struct OllamaChunk {
+    message: Option<ChatMessage>,
+    error: Option<String>,
+    #[serde(default)]
+    done: bool,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    #[serde(default)]
+    content: String,
+}
+
+impl Llm {
+    pub fn from_env(provider: LlmProvider, model: &str) -> Result<Self> {
+        let (url, api_key) = match provider {
+            LlmProvider::Openai => {
+                let (base_url, api_key) = openai::endpoint()?;
+                (format!("{}/chat/completions", base_url), api_key)
+            }
+            LlmProvider::Ollama => {
+                let host = ollama::host();
+                offline::check_url(&host, "Ollama")?;
+                (format!("{}/api/chat", host), None)
+            }
+        };
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(300))
+                .build(),
+            provider,
+            url,
+            api_key,
+            model: model.to_string(),
+        })
+    }
+
+    // `<provider>/<model>`, as shown to the user
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.provider.name(), self.model)
+    }
+
+    // The model's reply to the conversation
+    pub fn chat(&self, messages: &[Message]) -> Result<String> {
+        let body = ChatRequest {
+            model: &self.model,
+            messages,
+            stream: match self.provider {
+                LlmProvider::Openai => None,
+                LlmProvider::Ollama => Some(false),
+            },
+        };
+        let response = self.send(&body)?;
+        let content = match self.provider {
+            LlmProvider::Openai => {
+                let response: OpenAiResponse = response
+                    .into_json()
+                    .context("Invalid response from chat completions endpoint")?;
+                response
+                    .choices
+                    .into_iter()
+                    .next()
+                    .context("The chat completions endpoint returned no choices")?
+                    .message
+                    .content
+            }
+            LlmProvider::Ollama => {
+                let response: OllamaResponse =
+                    response.into_json().context("Invalid response from Ollama")?;
+                response.message.content
+            }
+        };
+        Ok(content.trim().to_string())
+    }
+
+    // The model's reply, passing each piece to `on_token` as it arrives
+    pub fn chat_stream(
+        &self,
+        messages: &[Message],
+        mut on_token: impl FnMut(&str) -> Result<()>,
+    ) -> Result<String> {
+        let body = ChatRequest {
+            model: &self.model,
+            messages,
+            stream: Some(true),
+        };
+        let response = self.send(&body)?;
+        let mut reply = String::new();
+        for line in std::io::BufReader::new(response.into_reader()).lines() {
+            let line = line.context("The chat stream was cut off")?;
+            let token = match self.provider {
+                LlmProvider::Openai => {
+                    // Server-sent events; other fields and comments carry nothing
+                    let data = match line.strip_prefix("data:") {
+                        Some(data) => data.trim(),
+                        None => continue,
+                    };
+                    if data == "[DONE]" {
+                        break;
+                    }
+                    let chunk: OpenAiChunk = serde_json::from_str(data)
+                        .with_context(|| format!("Invalid chat stream event: {}", data))?;
+                    chunk.choices.into_iter().find_map(|choice| choice.delta.content)
+                }
+                LlmProvider::Ollama => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let chunk: OllamaChunk = serde_json::from_str(&line)
+                        .with_context(|| format!("Invalid line in Ollama's reply: {}", line))?;
+                    if let Some(error) = chunk.error {
+                        bail!("Ollama failed: {}", error);
+                    }
+                    if chunk.done {
+                        break;
+                    }
+                    chunk.message.map(|message| message.content)
+                }
+            };
+            if let Some(token) = token.filter(|token| !token.is_empty()) {
+                // Leading whitespace some models start with isn't shown
+                let token = if reply.is_empty() { token.trim_start() } else { &token };
+                on_token(token)?;
+                reply.push_str(token);
+            }
+        }
+        Ok(reply.trim_end().to_string())
+    }
+
+    fn send(&self, body: &ChatRequest) -> Result<ureq::Response> {
+        let mut attempt = 1;
+        loop {
+            let mut request = self.agent.post(&self.url);
+            if let Some(key) = &self.api_key {
+                request = request.set("Authorization", &format!("Bearer {}", key));
+            }
+            match request.send_json(body) {
+                Ok(response) => return Ok(response),
+                // Back off and retry when rate limited or on server errors
+                Err(ureq::Error::Status(code, _))
+                    if (code == 429 || code >= 500) && attempt < MAX_ATTEMPTS =>
+                {
+                    thread::sleep(Duration::from_secs(1 << attempt));
+                    attempt += 1;
+                }
+                Err(ureq::Error::Status(code, response)) => {
+                    let detail = response.into_string().unwrap_or_default();
+                    bail!(
+                        "Chat request to {} failed with status {}: {}",
+                        self.url,
+                        code,
+                        detail.trim()
+                    );
+                }
+                Err(e) if self.provider == LlmProvider::Ollama => bail!(
+                    "Can't reach Ollama at {} ({}); is `ollama serve` running?",
+                    self.url,
+                    e
+                ),
+                Err(e) => bail!("Chat request to {} failed: {}", self.url, e),
+            }
+        }
+    }
+}
+
+// Instructions and numbered passages for answering a question from them alone
+pub fn grounded_prompt(question: &str, passages: &[(Option<&str>, &str)]) -> Vec<Message> {
+    let mut context = String::new();
+    for (i, (source, text)) in passages.iter().enumerate() {
+        context.push_str(&format!(
+            "[{}] (source: {})\n{}\n\n",
+            i + 1,
+            source.unwrap_or("unknown"),
+            text.trim()
+        ));
+    }
+    vec![
+        Message::system(
+            "Answer the question using only the numbered passages. Cite the passages \
+             that support each statement by number in square brackets, like [1] or [2][3]. \
+             If the passages don't contain the answer, say that you don't know.",
+        ),
+        Message::user(format!("Passages:\n\n{}Question: {}", context, question.trim())),
+    ]
+}
+
+// Numbers of the passages an answer cites as `[n]` or `[n, m]`, in order of
+// first citation
+pub fn citations(answer: &str, passages: usize) -> Vec<usize> {
+    let mut cited = Vec::new();
+    for part in answer.split('[').skip(1) {
+        let inside = part.split(']').next().unwrap_or_default();
+        for number in inside.split(',') {
+            if let Ok(number) = number.trim().parse::<usize>() {
+                if (1..=passages).contains(&number) && !cited.contains(&number) {
+                    cited.push(number);
+                }
+            }
+        }
+    }
+    cited
+}
@@ -0,0 +1,148 @@
+use crate::markup;
+use crate::vectorstore::Metadata;
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+// Text extracted from a file, split into regions that are chunked separately
+// and carry their own metadata (such as the page of a PDF)
+pub struct Loaded {
+    pub text: String,
+    pub regions: Vec<Region>,
+}
+
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+    pub metadata: Metadata,
+}
+
+impl Loaded {
+    // A document made of a single region with no metadata
+    pub fn from_text(text: String) -> Self {
+        let regions = vec![Region {
+            start: 0,
+            end: text.len(),
+            metadata: Metadata::new(),
+        }];
+        Self { text, regions }
+    }
+}
+
+// How much of a file `sniff` looks at
+const SNIFF_BYTES: usize = 8192;
+
+// Why a file is left out of an add
+#[derive(Clone, Copy)]
+pub enum Skip {
+    Empty,
+    Binary,
+}
+
+impl Skip {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Skip::Empty => "empty or whitespace-only file",
+            Skip::Binary => "binary or non-UTF-8 file",
+        }
+    }
+}
+
+// Extract the text of a file, picking a loader by its extension
+pub fn load(path: &Path) -> Result<Loaded> {
+    match extension(path).as_deref() {
+        Some("pdf") => load_pdf(path),
+        Some("html") | Some("htm") => load_html(path),
+        Some("docx") => load_docx(path),
+        _ => load_text(path),
+    }
+}
+
+// Look at the start of a file read as text for a reason to skip it: nothing
+// but whitespace, or a NUL byte or invalid UTF-8, which text doesn't have.
+// PDFs and Word files are binary anyway; they are empty if no text is
+// extracted from them.
+pub fn sniff(path: &Path) -> Result<Option<Skip>> {
+    if matches!(extension(path).as_deref(), Some("pdf") | Some("docx")) {
+        return Ok(None);
+    }
+    let mut sample = Vec::with_capacity(SNIFF_BYTES);
+    File::open(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?
+        .take(SNIFF_BYTES as u64)
+        .read_to_end(&mut sample)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    if sample.contains(&0) {
+        return Ok(Some(Skip::Binary));
+    }
+    let text = match std::str::from_utf8(&sample) {
+        Ok(text) => text,
+        // A character cut off at the end of the sample is still text
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&sample[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return Ok(Some(Skip::Binary)),
+    };
+    if sample.len() < SNIFF_BYTES && text.trim().is_empty() {
+        return Ok(Some(Skip::Empty));
+    }
+    Ok(None)
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+}
+
+fn load_text(path: &Path) -> Result<Loaded> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(Loaded::from_text(text))
+}
+
+// One region per page, numbered from 1 under the `page` metadata key
+fn load_pdf(path: &Path) -> Result<Loaded> {
+    let pages = pdf_extract::extract_text_by_pages(path)
+        .with_context(|| format!("Failed to extract text from PDF: {}", path.display()))?;
+
+    let mut text = String::new();
+    let mut regions = Vec::new();
+    for (i, page) in pages.iter().enumerate() {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        let start = text.len();
+        text.push_str(page);
+        let mut metadata = Metadata::new();
+        metadata.insert("page".to_string(), (i + 1).to_string());
+        regions.push(Region {
+            start,
+            end: text.len(),
+            metadata,
+        });
+    }
+    Ok(Loaded { text, regions })
+}
+
+// Headings and body text of a page, as markdown-style text
+fn load_html(path: &Path) -> Result<Loaded> {
+    let html = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(Loaded::from_text(markup::html_to_text(&html)))
+}
+
+// Paragraphs of the main document part of a Word file, as markdown-style text
+fn load_docx(path: &Path) -> Result<Loaded> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("Not a valid .docx file: {}", path.display()))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .with_context(|| format!("No document body in: {}", path.display()))?
+        .read_to_string(&mut xml)?;
+    Ok(Loaded::from_text(markup::docx_to_text(&xml)))
+}
@@ -0,0 +1,86 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Advisory locks on files beside a store, so several arrow processes can
+// share it. `<path>.lock` is held shared while a store is loaded and
+// exclusively while it is saved, so a reader never sees a half-written save.
+// `<path>.writer.lock` is held by a process that changes the store from
+// before it loads until after its last save, so two writers can't both start
+// from the same state and have the last save silently win. Locks are
+// released when dropped, or when the process exits.
+pub struct StoreLock {
+    // None when reading a store nobody can save to
+    _file: Option<File>,
+}
+
+enum Mode {
+    Shared,
+    Exclusive,
+}
+
+impl StoreLock {
+    // Shared lock for loading, waiting out any save in progress. A store in
+    // a directory we can't write to can't be saved by anyone else either, so
+    // it is read without a lock, as is a missing store, which fails to load.
+    pub fn read<P: AsRef<Path>>(store_path: P) -> io::Result<Self> {
+        let store_path = store_path.as_ref();
+        if !store_path.exists() {
+            return Ok(Self { _file: None });
+        }
+        match Self::acquire(&lock_path(store_path, ".lock"), Mode::Shared) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+                ) =>
+            {
+                Ok(Self { _file: None })
+            }
+            result => result,
+        }
+    }
+
+    // Exclusive lock for saving, waiting for readers to finish loading
+    pub fn save<P: AsRef<Path>>(store_path: P) -> io::Result<Self> {
+        Self::acquire(&lock_path(store_path.as_ref(), ".lock"), Mode::Exclusive)
+    }
+
+    // Writer lock, waiting for any other process changing the store
+    pub fn writer<P: AsRef<Path>>(store_path: P) -> io::Result<Self> {
+        Self::acquire(&lock_path(store_path.as_ref(), ".writer.lock"), Mode::Exclusive)
+    }
+
+    // Writer lock if no other process holds it, without waiting
+    pub fn try_writer<P: AsRef<Path>>(store_path: P) -> io::Result<Option<Self>> {
+        let file = open(&lock_path(store_path.as_ref(), ".writer.lock"))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: Some(file) })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    fn acquire(path: &Path, mode: Mode) -> io::Result<Self> {
+        let file = open(path)?;
+        match mode {
+            Mode::Shared => file.lock_shared()?,
+            Mode::Exclusive => file.lock()?,
+        }
+        Ok(Self { _file: Some(file) })
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+}
+
+fn lock_path(store_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = store_path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
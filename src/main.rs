@@ -1,489 +1,6216 @@
+#[cfg(feature = "candle")]
+mod bert;
+mod binary;
+mod cache;
+mod code;
+mod collections;
+mod columnar;
+mod completions;
+mod config;
+mod crypto;
+mod dates;
+mod device;
+mod diff;
+mod doctor;
+mod dump;
 mod embedding;
+mod error;
+mod excerpts;
+mod expansion;
+mod history;
+mod ivf;
+mod journal;
+mod language;
+mod llm;
+#[cfg(any(feature = "candle", feature = "onnx"))]
+mod hub;
+mod loaders;
+mod lock;
+mod manpage;
+mod markdown;
+mod markup;
+mod mcp;
+mod models;
+mod offline;
+mod ollama;
+#[cfg(feature = "onnx")]
+mod onnx;
+mod openai;
+mod output;
+mod pq;
+mod progress;
+mod provider;
+mod records;
+mod remote;
+mod server;
+mod snapshots;
+mod sources;
+mod textindex;
 mod vectorstore;
+mod wal;
 
 use anyhow::{Context, Result};
-use candle_core::Device;
-use clap::{Parser, Subcommand};
+use candle_core::{Device, Tensor};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueHint};
 use colored::*;
 use console::Term;
+use device::DeviceChoice;
 use indicatif::{ProgressBar, ProgressStyle};
-use rust_bert::pipelines::sentence_embeddings::SentenceEmbeddingsModelType;
+use output::OutputFormat;
+use progress::{ProgressEvent, ProgressSink};
+use provider::{ModelSpec, Prompts, Provider};
+use serde::Serialize;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use tabled::settings::Style;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tabled::settings::peaker::PriorityMax;
+use tabled::settings::{Style, Width};
 use tabled::{Table, Tabled};
+use vectorstore::{DocumentOrder, IndexKind, Provenance, StorageEngine, StorageMode};
 
 const DEFAULT_VECTOR_STORE: &str = "vector_store.json";
-const DEFAULT_MODEL: SentenceEmbeddingsModelType = SentenceEmbeddingsModelType::AllMiniLmL6V2;
 const DEFAULT_CONNECTIONS: usize = 16;
+// Path argument that reads a document from stdin instead of a file
+const STDIN_PATH: &str = "-";
+// Candidates fetched per requested result when results are filtered afterwards
+const FILTER_CANDIDATE_FACTOR: usize = 10;
+// Share of one of its limits a store can reach before `add` warns
+const QUOTA_WARNING: f64 = 0.9;
+// Files an add reads the start of to tell whether they're in several languages
+const LANGUAGE_SAMPLE_FILES: usize = 32;
+// Characters of each of those files whose language is detected
+const LANGUAGE_SAMPLE_CHARS: usize = 2000;
 
 /// Arrow Vector Database CLI
 #[derive(Parser, Debug)]
-#[clap(author, version, about)]
+#[clap(author, version, about, after_long_help = manpage::ARROW)]
 struct Cli {
-    /// Path to vector store file
-    #[clap(short, long, default_value = DEFAULT_VECTOR_STORE)]
-    database: String,
+    /// Path to vector store file [default: vector_store.json]
+    #[clap(short, long, env = "ARROW_DATABASE", value_hint = ValueHint::FilePath)]
+    database: Option<String>,
+
+    /// Named collection within the database (defaults to the database file itself)
+    #[clap(short, long, global = true)]
+    collection: Option<String>,
+
+    /// Output format for query, list and info
+    #[clap(long, value_enum, global = true, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+
+    /// Print only results, warnings and errors
+    #[clap(short, long, global = true)]
+    quiet: bool,
+
+    /// Don't draw spinners or progress bars
+    #[clap(long, global = true)]
+    no_progress: bool,
+
+    /// Log timings of loads, saves, chunking, embedding and searches to stderr;
+    /// repeat (-vv) to trace every graph layer searched. RUST_LOG overrides
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Device for embedding: cpu, cuda, cuda:<N> or metal [default: cpu]
+    #[clap(long, global = true, env = "ARROW_DEVICE")]
+    device: Option<DeviceChoice>,
+
+    /// File holding the key of an encrypted store, instead of a passphrase
+    #[clap(long, global = true, env = "ARROW_KEY_FILE")]
+    key_file: Option<PathBuf>,
+
+    /// Fail instead of downloading models or calling remote APIs; servers on
+    /// this machine are still used
+    #[clap(long, global = true, env = "ARROW_OFFLINE")]
+    offline: bool,
+
+    /// Directory embedding models are downloaded into and loaded from
+    /// [default: the Hugging Face and rust-bert caches]
+    #[clap(long, global = true, env = "ARROW_MODEL_CACHE", value_hint = ValueHint::DirPath)]
+    model_cache: Option<PathBuf>,
+
+    /// Search stores with fewer documents than this by scanning every vector
+    /// instead of the graph; 0 always uses the graph [default: 10000]
+    #[clap(long, global = true, env = "ARROW_EXACT_BELOW", value_name = "DOCUMENTS")]
+    exact_below: Option<usize>,
+
+    /// Number of lists an IVF store's searches scan, nearest first; more find
+    /// more of the true nearest neighbors but compare more vectors [default: 16]
+    #[clap(long, global = true, env = "ARROW_NPROBE", value_name = "LISTS")]
+    nprobe: Option<usize>,
 
     #[clap(subcommand)]
     command: Commands,
 }
 
+/// Index parameters chosen when a store or collection is created
+#[derive(Args, Debug)]
+struct StoreOptions {
+    /// Maximum connections per node
+    #[clap(short, long, default_value_t = DEFAULT_CONNECTIONS)]
+    max_connections: usize,
+
+    /// How vectors are stored (f16 is 2x smaller with almost no loss of recall;
+    /// int8 is 4x smaller but approximate)
+    #[clap(long, value_enum, default_value_t = StorageMode::F32)]
+    storage: StorageMode,
+
+    /// How nearest neighbors are found: an HNSW graph, ivf k-means lists that are
+    /// cheaper to update (trained once the store holds 8 documents per list), or flat,
+    /// an exact scan of every vector
+    #[clap(long, value_enum, default_value_t = IndexKind::Hnsw)]
+    index: IndexKind,
+
+    /// Number of lists an IVF store is partitioned into
+    #[clap(long, default_value_t = vectorstore::DEFAULT_NLIST, value_name = "LISTS")]
+    nlist: usize,
+
+    /// Where embeddings are computed [default: local]
+    #[clap(long, value_enum, env = "ARROW_PROVIDER")]
+    provider: Option<Provider>,
+
+    /// Embedding model used for documents and queries, or `multilingual` for the
+    /// provider's model for text in many languages [default: all-minilm-l6-v2 for
+    /// local, text-embedding-3-small for openai, nomic-embed-text for ollama]
+    #[clap(long, env = "ARROW_MODEL")]
+    model: Option<String>,
+
+    /// Text put in front of queries before embedding them [default: the model's
+    /// published prompt, e.g. "query: " for e5 models]
+    #[clap(long)]
+    query_prompt: Option<String>,
+
+    /// Text put in front of document chunks before embedding them [default: the
+    /// model's published prompt, e.g. "passage: " for e5 models]
+    #[clap(long)]
+    passage_prompt: Option<String>,
+
+    /// Keep vectors in a memory-mapped file next to the store
+    #[clap(long)]
+    mmap: bool,
+
+    /// How the store is kept on disk [default: sqlite for .db, .sqlite and .sqlite3
+    /// paths, otherwise json]
+    #[clap(long, value_enum)]
+    backend: Option<StorageEngine>,
+
+    /// Number of previous saves to keep as rotating .bak files
+    #[clap(short, long, default_value_t = vectorstore::DEFAULT_BACKUPS)]
+    backups: usize,
+
+    /// Encrypt the store with a passphrase (or --key-file), asked for on every load
+    #[clap(long)]
+    encrypt: bool,
+
+    #[clap(flatten)]
+    quota: QuotaOptions,
+}
+
+impl StoreOptions {
+    // Fill options not given on the command line from the config file. A
+    // configured model only applies to the provider it was chosen for.
+    fn with_config(mut self, config: &config::Config) -> Self {
+        let provider = self.provider.or(config.provider);
+        let same_provider = provider.unwrap_or_default() == config.provider.unwrap_or_default();
+        if self.model.is_none() && same_provider {
+            self.model = config.model.clone();
+        }
+        self.provider = provider;
+        self
+    }
+
+    fn model(&self) -> Result<ModelSpec> {
+        let provider = self.provider.unwrap_or_default();
+        ModelSpec::new(provider, self.model.as_deref().unwrap_or(provider.default_model()))
+    }
+
+    // The model's own prompts unless overridden
+    fn prompts(&self, model: &ModelSpec) -> Prompts {
+        let mut prompts = Prompts::for_model(model);
+        if let Some(query) = &self.query_prompt {
+            prompts.query = query.clone();
+        }
+        if let Some(passage) = &self.passage_prompt {
+            prompts.passage = passage.clone();
+        }
+        prompts
+    }
+}
+
+/// How files are turned into chunks before embedding
+#[derive(Args, Debug, Clone)]
+struct IngestOptions {
+    /// Maximum model tokens per chunk (defaults to the model's input limit)
+    #[clap(long, env = "ARROW_CHUNK_TOKENS")]
+    chunk_tokens: Option<usize>,
+
+    /// Tokens repeated from the end of each chunk at the start of the next [default: 0]
+    #[clap(long, env = "ARROW_CHUNK_OVERLAP")]
+    chunk_overlap: Option<usize>,
+
+    /// Field holding each record's text in CSV and JSONL files
+    #[clap(long, default_value = "text")]
+    text_field: String,
+
+    /// Field holding a stable ID for each record in CSV and JSONL files
+    #[clap(long)]
+    id_field: Option<String>,
+
+    /// Comma-separated fields stored as metadata for CSV and JSONL records
+    #[clap(long, value_delimiter = ',')]
+    meta_fields: Vec<String>,
+
+    /// Comma-separated fields of CSV and JSONL records embedded as named vectors
+    /// of their own, searchable with `query --field`
+    #[clap(long, value_delimiter = ',')]
+    vector_fields: Vec<String>,
+
+    /// Expire the documents this long after they are added, such as `30d` or `12h`.
+    /// Queries skip expired documents, and `vacuum` and `compact` remove them.
+    #[clap(long, value_name = "AGE", value_parser = parse_ttl)]
+    ttl: Option<u64>,
+}
+
+impl IngestOptions {
+    // Fill options not given on the command line from the config file
+    fn with_config(mut self, config: &config::Config) -> Self {
+        self.chunk_tokens = self.chunk_tokens.or(config.chunk_tokens);
+        self.chunk_overlap = self.chunk_overlap.or(config.chunk_overlap);
+        self
+    }
+
+    fn overlap(&self) -> usize {
+        self.chunk_overlap.unwrap_or(0)
+    }
+
+    fn max_tokens(&self, embeddor: &embedding::Embeddor) -> usize {
+        self.chunk_tokens
+            .unwrap_or_else(|| embeddor.max_chunk_tokens())
+            .max(1)
+    }
+
+    // When documents added now expire, given --ttl
+    fn expires(&self) -> Option<u64> {
+        self.ttl.map(|ttl| dates::now().saturating_add(ttl))
+    }
+
+    fn fields(&self) -> records::FieldMapping<'_> {
+        records::FieldMapping {
+            text: &self.text_field,
+            id: self.id_field.as_deref(),
+            metadata: &self.meta_fields,
+            vectors: &self.vector_fields,
+        }
+    }
+}
+
+/// Limits on how far `add` may grow a store
+#[derive(Args, Debug, Clone)]
+struct QuotaOptions {
+    /// Most documents the store may hold ("none" removes the limit)
+    #[clap(long, value_parser = parse_document_limit, value_name = "COUNT")]
+    max_documents: Option<usize>,
+
+    /// Most space the store's files may take up on disk, such as 500M or 2G,
+    /// not counting backups ("none" removes the limit)
+    #[clap(long, value_parser = parse_size_limit, value_name = "SIZE")]
+    max_size: Option<u64>,
+}
+
+impl QuotaOptions {
+    fn is_empty(&self) -> bool {
+        self.max_documents.is_none() && self.max_size.is_none()
+    }
+
+    // `quota` with the limits given replacing its own; 0 stands for "none"
+    fn apply(&self, mut quota: vectorstore::Quota) -> vectorstore::Quota {
+        if let Some(max) = self.max_documents {
+            quota.max_documents = (max > 0).then_some(max);
+        }
+        if let Some(max) = self.max_size {
+            quota.max_bytes = (max > 0).then_some(max);
+        }
+        quota
+    }
+}
+
+fn parse_document_limit(value: &str) -> Result<usize, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(0);
+    }
+    match value.parse() {
+        Ok(0) | Err(_) => Err(format!(
+            "invalid limit '{}': expected a positive number of documents or none",
+            value
+        )),
+        Ok(count) => Ok(count),
+    }
+}
+
+// A size in bytes, or with a K, M, G or T suffix for powers of 1024; an
+// optional B or iB after the suffix is ignored
+fn parse_size_limit(value: &str) -> Result<u64, String> {
+    if value.eq_ignore_ascii_case("none") {
+        return Ok(0);
+    }
+    let invalid = || {
+        format!(
+            "invalid size '{}': expected a number of bytes such as 500M or 2G, or none",
+            value
+        )
+    };
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (digits, shift) = match number.char_indices().last() {
+        Some((at, 'K')) => (&number[..at], 10),
+        Some((at, 'M')) => (&number[..at], 20),
+        Some((at, 'G')) => (&number[..at], 30),
+        Some((at, 'T')) => (&number[..at], 40),
+        _ => (number, 0),
+    };
+    let amount: f64 = digits.trim().parse().map_err(|_| invalid())?;
+    let bytes = amount * (1u64 << shift) as f64;
+    if !bytes.is_finite() || bytes < 1.0 || bytes > u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(bytes as u64)
+}
+
+fn parse_ttl(value: &str) -> Result<u64, String> {
+    match dates::parse_age(value) {
+        Some(0) | None => Err(format!(
+            "invalid TTL '{}': expected a span such as 45m, 12h, 30d or 2w",
+            value
+        )),
+        Some(seconds) => Ok(seconds),
+    }
+}
+
+/// The chat model that writes answers
+#[derive(Args, Debug, Clone)]
+struct LlmOptions {
+    /// Where answers are generated [default: openai]
+    #[clap(long, value_enum, env = "ARROW_LLM")]
+    llm: Option<llm::LlmProvider>,
+
+    /// Chat model [default: gpt-4o-mini for openai, llama3.2 for ollama]
+    #[clap(long, env = "ARROW_LLM_MODEL")]
+    llm_model: Option<String>,
+}
+
+impl LlmOptions {
+    // Fill options not given on the command line from the config file. A
+    // configured model only applies to the provider it was chosen for.
+    fn with_config(mut self, config: &config::Config) -> Self {
+        let provider = self.llm.or(config.llm);
+        let same_provider = provider.unwrap_or_default() == config.llm.unwrap_or_default();
+        if self.llm_model.is_none() && same_provider {
+            self.llm_model = config.llm_model.clone();
+        }
+        self.llm = provider;
+        self
+    }
+
+    fn open(&self) -> Result<llm::Llm> {
+        let provider = self.llm.unwrap_or_default();
+        let model = self.llm_model.as_deref().unwrap_or(provider.default_model());
+        llm::Llm::from_env(provider, model)
+    }
+}
+
+/// How much of each document's text tables show
+#[derive(Args, Debug, Clone, Copy)]
+struct PreviewOptions {
+    /// Characters of each document's text to show
+    #[clap(long, value_name = "CHARS")]
+    preview_len: Option<usize>,
+
+    /// Show each document's full text instead of a preview
+    #[clap(long, conflicts_with = "preview_len")]
+    full: bool,
+}
+
+impl PreviewOptions {
+    // The start of `text`, `default_len` characters long unless set otherwise,
+    // with "..." if anything was cut
+    fn preview(&self, text: &str, default_len: usize) -> String {
+        if self.full {
+            return text.to_string();
+        }
+        let len = self.preview_len.unwrap_or(default_len);
+        match text.char_indices().nth(len) {
+            Some((end, _)) => format!("{}...", &text[..end]),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// Bounds on when documents were added or last updated
+#[derive(Args, Debug, Clone)]
+struct TimeRange {
+    /// Only documents added or updated at or after this time: a date (2024-05-01),
+    /// a date and time (2024-05-01T13:45), or an age (30m, 12h, 7d, 2w)
+    #[clap(long, value_parser = dates::parse)]
+    since: Option<u64>,
+
+    /// Only documents added or updated at or before this time, in the same forms
+    #[clap(long, value_parser = dates::parse)]
+    until: Option<u64>,
+}
+
+impl TimeRange {
+    fn filter(&self) -> vectorstore::Filter {
+        vectorstore::Filter {
+            since: self.since,
+            until: self.until,
+            languages: Vec::new(),
+            sources: None,
+        }
+    }
+}
+
+/// Queries and comparison for the benchmark command
+#[derive(Args, Debug)]
+struct BenchmarkOptions {
+    /// Number of queries to run, sampled from the stored vectors
+    #[clap(long, default_value_t = 1000)]
+    queries: usize,
+
+    /// Read query texts from a file, one per line, instead of sampling
+    #[clap(long)]
+    query_file: Option<PathBuf>,
+
+    /// Number of results compared per query
+    #[clap(short, long, default_value_t = 10)]
+    top_k: usize,
+
+    /// How the exact results are computed
+    #[clap(long, value_enum, default_value_t = GroundTruth::Brute)]
+    ground_truth: GroundTruth,
+}
+
+// Where the benchmark's exact results come from
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum GroundTruth {
+    // Scan every stored vector
+    Brute,
+}
+
+// File format of exports and imports
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum FileFormat {
+    // JSON lines
+    Jsonl,
+    // Apache Arrow IPC (Feather v2), always with vectors
+    Arrow,
+    // Apache Parquet, always with vectors
+    Parquet,
+}
+
+impl FileFormat {
+    // The format named by a file's extension, JSONL by default
+    fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("arrow" | "feather" | "ipc") => FileFormat::Arrow,
+            Some("parquet") => FileFormat::Parquet,
+            _ => FileFormat::Jsonl,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Create a new vector store
+    #[clap(after_long_help = manpage::CREATE)]
     Create {
-        /// Maximum connections per node
-        #[clap(short, long, default_value_t = DEFAULT_CONNECTIONS)]
-        max_connections: usize,
+        #[clap(flatten)]
+        options: StoreOptions,
+    },
+
+    /// Manage named collections within the database
+    #[clap(after_long_help = manpage::COLLECTIONS)]
+    Collections {
+        #[clap(subcommand)]
+        command: CollectionCommands,
     },
 
     /// Add documents to the vector store
+    #[clap(after_long_help = manpage::ADD)]
     Add {
-        /// File paths to add
-        #[clap(required = true)]
+        /// File or directory paths to add, or `-` to read a document from stdin
+        #[clap(required = true, value_hint = ValueHint::AnyPath)]
         files: Vec<String>,
+
+        /// Stable document ID; adding again with the same ID replaces the document
+        #[clap(long)]
+        id: Option<String>,
+
+        /// Source name for a document read from stdin; its extension picks the chunker
+        #[clap(long, default_value = "stdin")]
+        source: String,
+
+        /// Save after this many chunks (`500`) or this long (`30s`, `5m`); running
+        /// the same add again after it was interrupted resumes from the last save
+        #[clap(long, default_value = "60s")]
+        save_every: SaveEvery,
+
+        /// Stop at the first file that can't be added instead of adding the rest
+        #[clap(long)]
+        fail_fast: bool,
+
+        /// Show the files that would be added and their estimated chunks, without
+        /// loading the model or changing the store
+        #[clap(long)]
+        dry_run: bool,
+
+        #[clap(flatten)]
+        ingest: IngestOptions,
+    },
+
+    /// Re-index changed files and drop chunks of deleted ones
+    #[clap(after_long_help = manpage::SYNC)]
+    Sync {
+        /// File or directory paths to sync
+        #[clap(required = true, value_hint = ValueHint::AnyPath)]
+        paths: Vec<String>,
+
+        /// Save after this many chunks (`500`) or this long (`30s`, `5m`); running
+        /// the same sync again after it was interrupted resumes from the last save
+        #[clap(long, default_value = "60s")]
+        save_every: SaveEvery,
+
+        /// Stop at the first file that can't be added instead of adding the rest
+        #[clap(long)]
+        fail_fast: bool,
+
+        /// Show the files that would be added and their estimated chunks, without
+        /// loading the model or changing the store
+        #[clap(long)]
+        dry_run: bool,
+
+        #[clap(flatten)]
+        ingest: IngestOptions,
+    },
+
+    /// Watch files and directories, keeping the vector store up to date
+    #[clap(after_long_help = manpage::WATCH)]
+    Watch {
+        /// File or directory paths to watch
+        #[clap(required = true, value_hint = ValueHint::AnyPath)]
+        paths: Vec<String>,
+
+        /// Milliseconds to wait for changes to settle before re-indexing
+        #[clap(long, default_value_t = 500)]
+        debounce_ms: u64,
+
+        /// Save after this many changed chunks (`500`) or this long (`30s`, `5m`)
+        /// while there are unsaved changes
+        #[clap(long, default_value = "30s")]
+        save_every: SaveEvery,
+
+        /// Seconds between saves, as `--save-every <SECS>s`
+        #[clap(long, hide = true, conflicts_with = "save_every")]
+        save_interval: Option<u64>,
+
+        #[clap(flatten)]
+        ingest: IngestOptions,
     },
 
     /// Query the vector store
+    #[clap(after_long_help = manpage::QUERY)]
     Query {
         /// The text to search for
-        #[clap(required = true)]
-        text: String,
+        #[clap(required_unless_present_any = ["stdin", "batch"])]
+        text: Option<String>,
+
+        /// Read the text to search for from stdin
+        #[clap(long, conflicts_with = "text")]
+        stdin: bool,
+
+        /// Run every query in a file, one per line ("-" for stdin), embedding
+        /// them in one batch; best with --format json or jsonl
+        #[clap(
+            long,
+            value_name = "FILE",
+            value_hint = ValueHint::FilePath,
+            conflicts_with_all = ["text", "stdin", "expand"]
+        )]
+        batch: Option<String>,
 
         /// Number of results to return
         #[clap(short, long, default_value_t = 5)]
         top_k: usize,
+
+        /// Skip re-ranking candidates found with PQ or binary codes against the
+        /// stored vectors
+        #[clap(long)]
+        no_rerank: bool,
+
+        /// Return every result at or above this cosine similarity (-1 to 1)
+        /// instead of the top k
+        #[clap(long, value_parser = parse_similarity)]
+        min_score: Option<f32>,
+
+        /// Return every result within this cosine distance (0 to 2) instead of
+        /// the top k; the same as --min-score 1-DISTANCE
+        #[clap(long, value_name = "DISTANCE", value_parser = parse_distance, conflicts_with_all = ["min_score", "diversify", "hybrid", "field", "exact", "expand", "group_by"])]
+        max_distance: Option<f32>,
+
+        /// Diversify results with Maximal Marginal Relevance
+        #[clap(long, conflicts_with = "min_score")]
+        diversify: bool,
+
+        /// MMR trade-off between relevance (1.0) and diversity (0.0)
+        #[clap(long, default_value_t = 0.5, requires = "diversify")]
+        lambda: f32,
+
+        /// Fuse BM25 keyword ranking with vector similarity
+        #[clap(long, conflicts_with_all = ["min_score", "diversify"])]
+        hybrid: bool,
+
+        /// Search a named vector field instead of the text, as NAME or NAME=WEIGHT;
+        /// repeat to rank by a weighted combination (`text` is the main vector)
+        #[clap(long, value_parser = parse_field_weight, conflicts_with_all = ["min_score", "diversify", "hybrid"])]
+        field: Vec<(String, f32)>,
+
+        /// Scan every stored vector instead of searching the graph; exact, and
+        /// often faster on small stores
+        #[clap(long, conflicts_with_all = ["no_rerank", "min_score", "diversify", "hybrid", "field"])]
+        exact: bool,
+
+        /// Also search with N rewordings of the query and fuse the rankings,
+        /// for better recall on short or vague queries
+        #[clap(long, value_name = "N", conflicts_with_all = ["min_score", "diversify", "hybrid", "field", "exact"])]
+        expand: Option<usize>,
+
+        /// How rewordings are written for --expand
+        #[clap(long, value_enum, default_value_t, requires = "expand")]
+        expand_with: expansion::Expansion,
+
+        /// Return the best chunks of each of the top-k sources instead of the
+        /// top-k chunks, so one very relevant file doesn't crowd out the others
+        #[clap(long, value_enum, conflicts_with_all = ["min_score", "diversify", "hybrid", "field", "expand"])]
+        group_by: Option<GroupBy>,
+
+        /// Chunks to return from each group with --group-by
+        #[clap(long, value_name = "N", default_value_t = 1, requires = "group_by")]
+        per_group: usize,
+
+        /// Show each result in its source file with LINES lines around it
+        /// (3 if not given), instead of a truncated preview
+        #[clap(long, value_name = "LINES", num_args = 0..=1, default_missing_value = "3", conflicts_with_all = ["batch", "preview_len", "full"])]
+        context: Option<usize>,
+
+        #[clap(flatten)]
+        preview: PreviewOptions,
+
+        /// Only documents detected to be in one of these languages, given as codes
+        /// such as en or de, or as names; repeat or separate with commas
+        #[clap(long, value_delimiter = ',', value_parser = language::parse)]
+        lang: Vec<String>,
+
+        /// Only documents from source files matching this glob, such as "docs/**" or
+        /// "*.md"; a path without wildcards matches that file or directory. Repeat
+        /// for more
+        #[clap(long = "source", value_name = "GLOB")]
+        sources: Vec<String>,
+
+        #[clap(flatten)]
+        llm: LlmOptions,
+
+        #[clap(flatten)]
+        time: TimeRange,
+    },
+
+    /// Train product quantization codebooks for faster search on large stores
+    #[clap(after_long_help = manpage::QUANTIZE)]
+    Quantize {
+        /// Number of subspaces each vector is split into
+        #[clap(short, long, default_value_t = 48)]
+        subspaces: usize,
+
+        /// Number of k-means iterations per subspace
+        #[clap(short, long, default_value_t = 10)]
+        iterations: usize,
+
+        /// Instead of codebooks, keep a 1-bit code of each vector and search by
+        /// scanning them by Hamming distance, re-ranking the best against the
+        /// stored vectors
+        #[clap(long, conflicts_with_all = ["subspaces", "iterations"])]
+        binary: bool,
     },
 
     /// List documents in the vector store
+    #[clap(after_long_help = manpage::LIST)]
     List {
         /// Maximum number of documents to list
         #[clap(short, long, default_value_t = 10)]
         limit: usize,
+
+        /// Number of documents to skip, for paging through the list
+        #[clap(long, default_value_t = 0)]
+        offset: usize,
+
+        /// Order of the documents
+        #[clap(long, value_enum, default_value_t = DocumentOrder::Added)]
+        sort: DocumentOrder,
+
+        /// Only list chunks of this source file
+        #[clap(long)]
+        source: Option<String>,
+
+        #[clap(flatten)]
+        preview: PreviewOptions,
+
+        #[clap(flatten)]
+        time: TimeRange,
+    },
+
+    /// Show a document in full: its text, source, metadata and graph links
+    #[clap(after_long_help = manpage::GET)]
+    Get {
+        /// The document's ID, or enough of its start to be unique
+        id: String,
+
+        /// Also print the stored vector
+        #[clap(long)]
+        vector: bool,
     },
 
     /// Show information about the vector store
+    #[clap(after_long_help = manpage::INFO)]
     Info,
-}
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let db_path = &cli.database;
+    /// Show index statistics: graph shape, connectivity, sizes and a summary of each source
+    #[clap(after_long_help = manpage::STATS)]
+    Stats {
+        /// Maximum number of source files to summarize
+        #[clap(short, long, default_value_t = 20)]
+        limit: usize,
 
-    match cli.command {
-        Commands::Create { max_connections } => create_vector_store(db_path, max_connections),
-        Commands::Add { files } => add_documents(db_path, files),
-        Commands::Query { text, top_k } => query_vector_store(db_path, &text, top_k),
-        Commands::List { limit } => list_documents(db_path, limit),
-        Commands::Info => show_info(db_path),
-    }
-}
+        /// Number of source files to skip, for paging through them
+        #[clap(long, default_value_t = 0)]
+        offset: usize,
+    },
 
-fn create_vector_store(db_path: &str, max_connections: usize) -> Result<()> {
-    let term = Term::stdout();
-    if Path::new(db_path).exists() {
-        term.write_line(&format!(
-            "{}",
-            "Vector store already exists".yellow().bold()
-        ))?;
-        term.write_line(&format!("  Path: {}", db_path.bright_blue()))?;
-        term.write_line(&format!(
-            "{}",
-            "Use 'add' command to add documents to the existing store".italic()
-        ))?;
-        return Ok(());
-    }
+    /// Check the index for inconsistencies, and optionally repair them
+    #[clap(after_long_help = manpage::FSCK)]
+    Fsck {
+        /// Fix the problems found, relinking unreachable nodes and dropping what can't be recovered
+        #[clap(long)]
+        repair: bool,
+    },
 
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.green} {msg}")?,
-    );
-    spinner.set_message(format!(
-        "Creating vector store with {} max connections...",
-        max_connections
-    ));
-    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    /// Rebuild the search graph (or retrain the IVF lists) from the stored vectors,
+    /// without re-embedding
+    #[clap(after_long_help = manpage::REINDEX)]
+    Reindex {
+        /// Maximum connections per node in the new graph [default: the store's current value]
+        #[clap(short, long)]
+        max_connections: Option<usize>,
+    },
 
-    let store = vectorstore::VectorStore::new(Device::Cpu, max_connections);
-    store.save(db_path).context("Failed to save vector store")?;
+    /// Drop removed documents from the search graph by rebuilding it
+    #[clap(after_long_help = manpage::VACUUM)]
+    Vacuum {
+        /// Only rebuild once at least this fraction of the graph is removed documents
+        #[clap(long, default_value_t = vectorstore::DEFAULT_VACUUM_THRESHOLD)]
+        threshold: f32,
 
-    spinner.finish_with_message(format!(
-        "{}✓{} Vector store created successfully!",
-        "[".green(),
-        "]".green()
-    ));
-    term.write_line("")?;
-    term.write_line(&format!("  {} {}", "Location:".blue(), db_path))?;
-    term.write_line(&format!(
-        "  {}",
-        "Use 'add' command to add documents".italic()
-    ))?;
+        /// Rebuild even below the threshold
+        #[clap(long)]
+        force: bool,
+    },
 
-    Ok(())
-}
+    /// Merge the segments saved since the last compaction into the store file
+    #[clap(after_long_help = manpage::COMPACT)]
+    Compact,
 
-fn add_documents(db_path: &str, files: Vec<String>) -> Result<()> {
-    let term = Term::stdout();
-    term.write_line(&format!(
-        "{}",
-        "Arrow Vector Database".bright_green().bold()
-    ))?;
-    term.write_line("")?;
+    /// Show the store's limits on documents and disk space, or change them
+    #[clap(after_long_help = manpage::QUOTA)]
+    Quota {
+        #[clap(flatten)]
+        limits: QuotaOptions,
+    },
 
-    // Load or create the vector store
-    let load_spinner = ProgressBar::new_spinner();
-    load_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.blue} {msg}")?,
-    );
-    load_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-    load_spinner.set_message("Loading vector store...");
+    /// Save named copies of the store and roll back to them
+    #[clap(after_long_help = manpage::SNAPSHOT)]
+    Snapshot {
+        #[clap(subcommand)]
+        command: SnapshotCommands,
+    },
 
-    let mut store = if Path::new(db_path).exists() {
-        let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
-            .context("Failed to load vector store")?;
-        load_spinner.finish_with_message(format!(
-            "{}✓{} Vector store loaded from {}",
-            "[".green(),
-            "]".green(),
-            db_path.bright_blue()
-        ));
-        store
-    } else {
-        load_spinner.finish_with_message(format!(
-            "{}!{} Vector store not found, creating a new one",
-            "[".yellow(),
-            "]".yellow()
-        ));
-        vectorstore::VectorStore::new(Device::Cpu, DEFAULT_CONNECTIONS)
-    };
+    /// Compare two stores or snapshots: parameters, and documents added, removed and changed
+    #[clap(after_long_help = manpage::DIFF)]
+    Diff {
+        /// Store file, or name of a snapshot of the database, to compare from
+        old: String,
 
-    // Create embedder
-    term.write_line("")?;
-    let embed_spinner = ProgressBar::new_spinner();
-    embed_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.magenta} {msg}")?,
-    );
-    embed_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+        /// Store file or snapshot to compare with (default: the database)
+        new: Option<String>,
+    },
+
+    /// Remove near-identical documents, keeping one of each group
+    #[clap(after_long_help = manpage::DEDUPE)]
+    Dedupe {
+        /// Similarity at or above which two documents count as duplicates
+        #[clap(long, default_value_t = 0.98)]
+        threshold: f32,
+
+        /// List the duplicates without removing them
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Group documents by topic with k-means and label each with its cluster
+    #[clap(after_long_help = manpage::CLUSTER)]
+    Cluster {
+        /// Number of clusters
+        #[clap(short, long, default_value_t = 20)]
+        k: usize,
+
+        /// Number of k-means iterations
+        #[clap(short, long, default_value_t = 20)]
+        iterations: usize,
+
+        /// Number of top terms shown per cluster
+        #[clap(long, default_value_t = 8)]
+        terms: usize,
+
+        /// Metadata key the cluster number is stored under
+        #[clap(long, default_value = "cluster")]
+        key: String,
+
+        /// Show the clusters without labeling documents
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Measure search recall and speed against exact results
+    #[clap(after_long_help = manpage::BENCHMARK)]
+    Benchmark {
+        #[clap(flatten)]
+        options: BenchmarkOptions,
+    },
+
+    /// Write every document to a JSONL file
+    #[clap(after_long_help = manpage::EXPORT)]
+    Export {
+        /// File to write, or `-` for stdout
+        #[clap(short, long, value_hint = ValueHint::FilePath)]
+        output: String,
+
+        /// Include each document's vectors
+        #[clap(long)]
+        include_vectors: bool,
+
+        /// File format: `jsonl`, or `arrow` (Feather) or `parquet` with vectors
+        /// [default: from the extension: .arrow, .feather, .ipc, .parquet, otherwise `jsonl`]
+        #[clap(long = "as", value_enum)]
+        file_format: Option<FileFormat>,
+    },
+
+    /// Add documents with precomputed vectors from a JSONL, Arrow or Parquet file,
+    /// without the model
+    #[clap(after_long_help = manpage::IMPORT)]
+    Import {
+        /// File to read, or `-` for JSONL on stdin
+        #[clap(value_hint = ValueHint::FilePath)]
+        input: String,
+
+        /// File format [default: from the extension, as for export]
+        #[clap(long = "as", value_enum)]
+        file_format: Option<FileFormat>,
+
+        /// Arrow or Parquet column holding each document's text
+        #[clap(long, default_value = "text")]
+        text_col: String,
+
+        /// Arrow or Parquet column holding each document's vector, a list of numbers
+        #[clap(long, default_value = "vector")]
+        vector_col: String,
+    },
+
+    /// Answer a question from the store's documents with an LLM, citing its sources
+    #[clap(after_long_help = manpage::ASK)]
+    Ask {
+        /// The question
+        question: String,
+
+        /// Number of passages retrieved to answer from
+        #[clap(short, long, default_value_t = 5)]
+        top_k: usize,
+
+        #[clap(flatten)]
+        llm: LlmOptions,
+
+        #[clap(flatten)]
+        time: TimeRange,
+    },
+
+    /// Serve the database's collections over a Qdrant-compatible REST API
+    #[clap(after_long_help = manpage::SERVE)]
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Port to listen on
+        #[clap(long, default_value_t = 6333)]
+        port: u16,
+
+        /// Index parameters and model for collections created through the API
+        #[clap(flatten)]
+        options: StoreOptions,
+    },
+
+    /// Serve the store to LLM agents as Model Context Protocol tools over stdio
+    #[clap(after_long_help = manpage::MCP)]
+    Mcp {
+        #[clap(flatten)]
+        ingest: IngestOptions,
+    },
+
+    /// Download, list and remove cached embedding models
+    #[clap(after_long_help = manpage::MODEL)]
+    Model {
+        #[clap(subcommand)]
+        command: ModelCommands,
+    },
+
+    /// Check the build, libtorch, device, database path, memory and embedding model,
+    /// and suggest fixes for what's wrong
+    #[clap(after_long_help = manpage::DOCTOR)]
+    Doctor,
+
+    /// List recent queries and their top result
+    #[clap(after_long_help = manpage::HISTORY)]
+    History {
+        /// Maximum number of queries to list
+        #[clap(short, long, default_value_t = 20)]
+        limit: usize,
+
+        /// Forget all recorded queries
+        #[clap(long)]
+        clear: bool,
+    },
+
+    /// View or change defaults in the config file
+    #[clap(after_long_help = manpage::CONFIG)]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Print a completion script for bash, zsh, fish or PowerShell
+    #[clap(after_long_help = manpage::COMPLETIONS)]
+    Completions {
+        /// Shell to complete in
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the man page, or write one for every command
+    #[clap(after_long_help = manpage::MANPAGE)]
+    Manpage {
+        /// Directory to write `arrow.1` and an `arrow-<command>.1` page per
+        /// command into, instead of printing `arrow.1`
+        #[clap(long, value_hint = ValueHint::DirPath)]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModelCommands {
+    /// Download a model ahead of use, such as while building an image
+    #[clap(after_long_help = manpage::MODEL_DOWNLOAD)]
+    Download {
+        /// Model to download [default: the configured model, or the provider's default]
+        model: Option<String>,
+
+        /// Provider the model is for [default: the configured provider]
+        #[clap(long, value_enum)]
+        provider: Option<Provider>,
+    },
+
+    /// List the models in the cache and their sizes
+    #[clap(after_long_help = manpage::MODEL_LIST)]
+    List,
+
+    /// Delete a model from the cache
+    #[clap(after_long_help = manpage::MODEL_REMOVE)]
+    Remove {
+        /// Name of the model, as listed
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Show every setting and where its value comes from
+    #[clap(after_long_help = manpage::CONFIG_SHOW)]
+    Show,
+
+    /// Print the value of a setting
+    #[clap(after_long_help = manpage::CONFIG_GET)]
+    Get {
+        /// Setting name (database, provider, model, device, chunk_tokens, chunk_overlap)
+        key: String,
+    },
+
+    /// Set a setting in the config file
+    #[clap(after_long_help = manpage::CONFIG_SET)]
+    Set {
+        /// Setting name (database, provider, model, device, chunk_tokens, chunk_overlap)
+        key: String,
+
+        /// New value
+        value: String,
+    },
+
+    /// Remove a setting from the config file
+    #[clap(after_long_help = manpage::CONFIG_UNSET)]
+    Unset {
+        /// Setting name (database, provider, model, device, chunk_tokens, chunk_overlap)
+        key: String,
+    },
+
+    /// Print the path of the config file
+    #[clap(after_long_help = manpage::CONFIG_PATH)]
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+enum CollectionCommands {
+    /// List the collections in the database
+    #[clap(after_long_help = manpage::COLLECTIONS_LIST)]
+    List {
+        /// Print only the names, one per line
+        #[clap(long)]
+        names: bool,
+    },
+
+    /// Create a new named collection
+    #[clap(after_long_help = manpage::COLLECTIONS_CREATE)]
+    Create {
+        /// Name of the collection
+        name: String,
+
+        #[clap(flatten)]
+        options: StoreOptions,
+    },
+
+    /// Delete a named collection and all of its files
+    #[clap(after_long_help = manpage::COLLECTIONS_DROP)]
+    Drop {
+        /// Name of the collection to delete
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotCommands {
+    /// Copy the store into a named snapshot
+    #[clap(after_long_help = manpage::SNAPSHOT_CREATE)]
+    Create {
+        /// Name of the snapshot
+        name: String,
+
+        /// Replace an existing snapshot of the same name
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Replace the store's contents with a snapshot's
+    #[clap(after_long_help = manpage::SNAPSHOT_RESTORE)]
+    Restore {
+        /// Name of the snapshot
+        name: String,
+    },
+
+    /// List the store's snapshots, oldest first
+    #[clap(after_long_help = manpage::SNAPSHOT_LIST)]
+    List,
+
+    /// Delete a snapshot
+    #[clap(after_long_help = manpage::SNAPSHOT_DELETE)]
+    Delete {
+        /// Name of the snapshot
+        name: String,
+    },
+}
+
+// Whether spinners and progress bars are drawn; off with --no-progress or
+// --quiet, and when output is piped or machine-readable
+static DECORATED: AtomicBool = AtomicBool::new(true);
+// Whether informational messages are suppressed (--quiet)
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(dir) = cli.model_cache.clone() {
+        models::set_cache_dir(dir);
+    }
+    offline::set_offline(cli.offline);
+    // Scripts are printed before the config is read, so a broken config can't
+    // break completion
+    if let Commands::Completions { shell } = cli.command {
+        return completions::generate(shell, &mut Cli::command(), &mut std::io::stdout())
+            .context("Failed to write completion script");
+    }
+    if let Commands::Manpage { dir } = cli.command {
+        return write_man_pages(dir.as_deref());
+    }
+    if !std::io::stdout().is_terminal() || cli.format.is_structured() {
+        colored::control::set_override(false);
+        DECORATED.store(false, Ordering::Relaxed);
+    }
+    // Log lines would be drawn over by spinners
+    let logging = init_logging(cli.verbose)?;
+    if cli.no_progress || cli.quiet || logging {
+        DECORATED.store(false, Ordering::Relaxed);
+    }
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+
+    // Flags and environment variables win over the config file
+    let config = config::Config::load()?;
+    vectorstore::set_exact_below(
+        cli.exact_below
+            .or(config.exact_below)
+            .unwrap_or(vectorstore::DEFAULT_EXACT_BELOW),
+    );
+    vectorstore::set_nprobe(
+        cli.nprobe
+            .or(config.nprobe)
+            .unwrap_or(vectorstore::DEFAULT_NPROBE),
+    );
+    if let Commands::Config { command } = cli.command {
+        return configure(config, command);
+    }
+    if let Commands::Model { command } = cli.command {
+        return match command {
+            ModelCommands::Download { model, provider } => {
+                let provider = provider.or(config.provider).unwrap_or_default();
+                // A configured model only applies to the provider it was chosen for
+                let model = model.or_else(|| {
+                    let configured = provider == config.provider.unwrap_or_default();
+                    configured.then(|| config.model.clone()).flatten()
+                });
+                let model = model.as_deref().unwrap_or(provider.default_model());
+                download_model(&ModelSpec::new(provider, model)?)
+            }
+            ModelCommands::List => list_models(cli.format),
+            ModelCommands::Remove { name } => remove_model(&name),
+        };
+    }
+    let database = cli
+        .database
+        .clone()
+        .or_else(|| config.database.clone())
+        .unwrap_or_else(|| DEFAULT_VECTOR_STORE.to_string());
+    // Run before anything that can fail on what it checks
+    if let Commands::Doctor = cli.command {
+        let device = match cli.device {
+            Some(device) => device,
+            None => config.device()?.unwrap_or_default(),
+        };
+        let provider = config.provider.unwrap_or_default();
+        let model = config.model.as_deref().unwrap_or(provider.default_model());
+        let model = ModelSpec::new(provider, model).context("Invalid model in config file")?;
+        let db_path = collections::collection_path(&database, cli.collection.as_deref())?;
+        return run_doctor(&database, &db_path, device, &model, cli.format);
+    }
+    // A store in object storage is worked on as a local copy, brought up to
+    // date first and uploaded again once the command succeeds
+    let mut remote = if remote::is_remote(&database) {
+        Some(pull_remote(&database)?)
+    } else {
+        None
+    };
+    let database = match &remote {
+        Some(remote) => remote.local_path(),
+        None => database,
+    };
+    let db_path = &collections::collection_path(&database, cli.collection.as_deref())?;
+    // Ask for an encrypted store's passphrase up front, before a spinner can
+    // draw over the prompt
+    if let Some(key_file) = cli.key_file.clone() {
+        crypto::set_key_file(key_file);
+    }
+    if crypto::is_encrypted(Path::new(db_path)) {
+        crypto::secret(Path::new(db_path), false)?;
+    }
+    let device = match cli.device {
+        Some(device) => device,
+        None => config.device()?.unwrap_or_default(),
+    };
+
+    let result = match cli.command {
+        Commands::Create { options } => create_vector_store(db_path, &options.with_config(&config)),
+        Commands::Collections { command } => match command {
+            CollectionCommands::List { names } => list_collections(&database, names),
+            CollectionCommands::Create { name, options } => create_vector_store(
+                &collections::collection_path(&database, Some(&name))?,
+                &options.with_config(&config),
+            ),
+            CollectionCommands::Drop { name } => drop_collection(&database, &name),
+        },
+        Commands::Add {
+            files,
+            id,
+            source,
+            save_every,
+            fail_fast,
+            dry_run,
+            ingest,
+        } => {
+            let ingest = ingest.with_config(&config);
+            let job = AddJob {
+                paths: files,
+                prune: false,
+                id,
+                stdin_source: source,
+                save_every,
+                fail_fast,
+            };
+            if dry_run {
+                return preview_add(db_path, &job, &ingest);
+            }
+            let backend = open_device(device)?;
+            add_documents(db_path, job, &ingest, &backend)
+        }
+        Commands::Sync {
+            paths,
+            save_every,
+            fail_fast,
+            dry_run,
+            ingest,
+        } => {
+            let ingest = ingest.with_config(&config);
+            let job = AddJob {
+                paths,
+                prune: true,
+                id: None,
+                stdin_source: "stdin".to_string(),
+                save_every,
+                fail_fast,
+            };
+            if dry_run {
+                return preview_add(db_path, &job, &ingest);
+            }
+            let backend = open_device(device)?;
+            add_documents(db_path, job, &ingest, &backend)
+        }
+        Commands::Watch {
+            paths,
+            debounce_ms,
+            save_every,
+            save_interval,
+            ingest,
+        } => {
+            let ingest = ingest.with_config(&config);
+            let backend = open_device(device)?;
+            let save_every = save_interval
+                .map(|secs| SaveEvery::Time(Duration::from_secs(secs)))
+                .unwrap_or(save_every);
+            watch_paths(db_path, paths, debounce_ms, save_every, &ingest, &backend)
+        }
+        Commands::Query {
+            text,
+            stdin,
+            batch,
+            top_k,
+            no_rerank,
+            min_score,
+            max_distance,
+            diversify,
+            lambda,
+            hybrid,
+            field,
+            exact,
+            expand,
+            expand_with,
+            group_by,
+            per_group,
+            context,
+            preview,
+            lang,
+            sources,
+            llm,
+            time,
+        } => {
+            if group_by.is_some() && per_group == 0 {
+                anyhow::bail!("--per-group must be at least 1");
+            }
+            // Both bound the same cosine distance
+            let min_score = min_score
+                .map(vectorstore::Score::cosine)
+                .or(max_distance.map(vectorstore::Score::from_distance));
+            let mode = match (min_score, diversify) {
+                _ if group_by.is_some() => SearchMode::Grouped {
+                    per_group,
+                    rerank: !no_rerank,
+                    exact,
+                },
+                _ if exact => SearchMode::Exact,
+                _ if hybrid => SearchMode::Hybrid,
+                _ if !field.is_empty() => SearchMode::Fields(field),
+                _ if expand.is_some() => SearchMode::Expanded(Vec::new()),
+                (Some(min_score), _) => SearchMode::Range(min_score),
+                (None, true) => SearchMode::Mmr(lambda),
+                (None, false) => SearchMode::TopK { rerank: !no_rerank },
+            };
+            let filter = vectorstore::Filter {
+                languages: lang,
+                sources: (!sources.is_empty())
+                    .then(|| sources::glob_set(&sources))
+                    .transpose()?,
+                ..time.filter()
+            };
+            if let Some(batch) = batch {
+                let backend = open_device(device)?;
+                let (format, filter) = (cli.format, &filter);
+                return query_batch(db_path, &batch, top_k, mode, filter, preview, format, &backend);
+            }
+            let text = match text {
+                Some(text) if !stdin => text,
+                _ => {
+                    let mut text = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut text)
+                        .context("Failed to read query from stdin")?;
+                    text.trim().to_string()
+                }
+            };
+            if text.is_empty() {
+                anyhow::bail!("Query text is empty");
+            }
+            // Rewordings depend on the query, so are only written once it's read
+            let mode = match (mode, expand) {
+                (SearchMode::Expanded(_), Some(count)) => {
+                    SearchMode::Expanded(expand_query(&text, count, expand_with, &llm, &config)?)
+                }
+                (mode, _) => mode,
+            };
+            let backend = open_device(device)?;
+            let format = cli.format;
+            let view = match context {
+                Some(context) => ResultView::Context(context),
+                None => ResultView::Table(preview),
+            };
+            query_vector_store(db_path, &text, top_k, mode, &filter, view, format, &backend)
+        }
+        Commands::Ask {
+            question,
+            top_k,
+            llm,
+            time,
+        } => {
+            if question.trim().is_empty() {
+                anyhow::bail!("Question is empty");
+            }
+            let llm = llm.with_config(&config).open()?;
+            let backend = open_device(device)?;
+            let filter = time.filter();
+            ask_question(db_path, &question, top_k, &filter, &llm, cli.format, &backend)
+        }
+        Commands::Quantize {
+            subspaces,
+            iterations,
+            binary,
+        } => quantize_vector_store(db_path, subspaces, iterations, binary),
+        Commands::List {
+            limit,
+            offset,
+            sort,
+            source,
+            preview,
+            time,
+        } => {
+            let filter = time.filter();
+            let source = source.as_deref();
+            list_documents(db_path, limit, offset, sort, source, &filter, preview, cli.format)
+        }
+        Commands::Get { id, vector } => show_document(db_path, &id, vector, cli.format),
+        Commands::Info => show_info(db_path, cli.format),
+        Commands::Stats { limit, offset } => show_stats(db_path, limit, offset, cli.format),
+        Commands::Fsck { repair } => check_store(db_path, repair, cli.format),
+        Commands::Reindex { max_connections } => reindex_store(db_path, max_connections),
+        Commands::Vacuum { threshold, force } => vacuum_store(db_path, threshold, force),
+        Commands::Compact => compact_store(db_path),
+        Commands::Quota { limits } => manage_quota(db_path, &limits, cli.format),
+        Commands::Snapshot { command } => match command {
+            SnapshotCommands::Create { name, force } => create_snapshot(db_path, &name, force),
+            SnapshotCommands::Restore { name } => restore_snapshot(db_path, &name),
+            SnapshotCommands::List => list_snapshots(db_path, cli.format),
+            SnapshotCommands::Delete { name } => delete_snapshot(db_path, &name),
+        },
+        Commands::Diff { old, new } => {
+            let old = snapshots::resolve(db_path, &old)?;
+            let new = match new {
+                Some(new) => snapshots::resolve(db_path, &new)?,
+                None => db_path.to_string(),
+            };
+            diff_stores(&old, &new, cli.format)
+        }
+        Commands::Dedupe { threshold, dry_run } => {
+            dedupe_documents(db_path, threshold, dry_run, cli.format)
+        }
+        Commands::Cluster {
+            k,
+            iterations,
+            terms,
+            key,
+            dry_run,
+        } => cluster_documents(db_path, k, iterations, terms, &key, dry_run, cli.format),
+        Commands::Benchmark { options } => {
+            let backend = open_device(device)?;
+            run_benchmark(db_path, &options, cli.format, &backend)
+        }
+        Commands::Export {
+            output,
+            include_vectors,
+            file_format,
+        } => {
+            let file_format = file_format.unwrap_or_else(|| FileFormat::from_path(&output));
+            export_documents(db_path, &output, include_vectors, file_format)
+        }
+        Commands::Import {
+            input,
+            file_format,
+            text_col,
+            vector_col,
+        } => {
+            let file_format = file_format.unwrap_or_else(|| FileFormat::from_path(&input));
+            let columns = columnar::Columns {
+                text: &text_col,
+                vector: &vector_col,
+            };
+            import_documents(db_path, &input, file_format, &columns)
+        }
+        Commands::History { limit, clear } => show_history(db_path, limit, clear, cli.format),
+        Commands::Serve {
+            host,
+            port,
+            options,
+        } => {
+            if remote.is_some() {
+                anyhow::bail!("serve needs a local database, not one in object storage");
+            }
+            serve(&database, &format!("{}:{}", host, port), options.with_config(&config))
+        }
+        Commands::Mcp { ingest } => {
+            // Stdout carries the protocol, so nothing else may be written to it
+            DECORATED.store(false, Ordering::Relaxed);
+            QUIET.store(true, Ordering::Relaxed);
+            let backend = open_device(device)?;
+            serve_mcp(db_path, ingest.with_config(&config), &backend)
+        }
+        Commands::Config { .. }
+        | Commands::Completions { .. }
+        | Commands::Manpage { .. }
+        | Commands::Model { .. }
+        | Commands::Doctor => {
+            unreachable!("handled before resolving the database")
+        }
+    };
+    if let (Ok(()), Some(remote)) = (&result, &mut remote) {
+        push_remote(remote)?;
+    }
+    result
+}
+
+// Open the local copy of a store in object storage and download what changed
+fn pull_remote(url: &str) -> Result<remote::RemoteStore> {
+    let sync_spinner = spinner("blue")?;
+    sync_spinner.set_message(format!("Downloading {}...", url));
+    let mut store = remote::RemoteStore::open(url)?;
+    let downloaded = store.pull()?;
+    sync_spinner.finish_and_clear();
+    if downloaded > 0 {
+        status(
+            &Term::stderr(),
+            &format!("  Downloaded {} files from {}", downloaded, url.bright_blue()),
+        )?;
+    }
+    Ok(store)
+}
+
+// Upload the changes a command made to the local copy of a remote store
+fn push_remote(store: &mut remote::RemoteStore) -> Result<()> {
+    let sync_spinner = spinner("blue")?;
+    sync_spinner.set_message(format!("Uploading {}...", store.url()));
+    let uploaded = store.push()?;
+    sync_spinner.finish_and_clear();
+    if uploaded > 0 {
+        status(
+            &Term::stderr(),
+            &format!("  Uploaded {} files to {}", uploaded, store.url().bright_blue()),
+        )?;
+    }
+    Ok(())
+}
+
+// Open the device for embedding, warning and falling back to the CPU when it
+// can't be used
+fn open_device(choice: DeviceChoice) -> Result<device::Backend> {
+    let (backend, fallback) = device::open(choice);
+    if let Some(reason) = fallback {
+        Term::stderr().write_line(&format!(
+            "{} Can't use {}: {}; falling back to CPU",
+            "[WARNING]".yellow().bold(),
+            choice,
+            reason
+        ))?;
+    }
+    Ok(backend)
+}
+
+// A spinner in the given color, or a hidden one when decorations are off
+fn spinner(color: &str) -> Result<ProgressBar> {
+    if !DECORATED.load(Ordering::Relaxed) {
+        return Ok(ProgressBar::hidden());
+    }
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
+            .template(&format!("{{spinner:.{}}} {{msg}}", color))?,
+    );
+    spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    Ok(spinner)
+}
+
+// A progress bar over `len` items, or a hidden one when decorations are off
+fn progress_bar(len: usize, template: &str) -> Result<ProgressBar> {
+    if !DECORATED.load(Ordering::Relaxed) {
+        return Ok(ProgressBar::hidden());
+    }
+    let bar = ProgressBar::new(len as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(template)?
+            .progress_chars("█▓▒░ "),
+    );
+    Ok(bar)
+}
+
+// The embedding cache beside a store, or one kept in memory for an
+// encrypted store
+fn open_cache(db_path: &str, store: &vectorstore::VectorStore) -> cache::EmbeddingCache {
+    if store.is_encrypted() {
+        cache::EmbeddingCache::in_memory(store.model())
+    } else {
+        cache::EmbeddingCache::open(Path::new(db_path), store.model())
+    }
+}
+
+// Take the store's writer lock, so changes made by another arrow process
+// aren't overwritten, waiting for that process to finish if it holds it
+fn lock_for_writing(db_path: &str) -> Result<lock::StoreLock> {
+    let lock = lock::StoreLock::try_writer(db_path).context("Failed to lock vector store")?;
+    if let Some(lock) = lock {
+        return Ok(lock);
+    }
+    let wait_spinner = spinner("yellow")?;
+    wait_spinner.set_message(format!(
+        "Waiting for another arrow process writing to {}...",
+        db_path
+    ));
+    let lock = lock::StoreLock::writer(db_path).context("Failed to lock vector store")?;
+    wait_spinner.finish_and_clear();
+    Ok(lock)
+}
+
+// Log spans and events to stderr for --verbose or `RUST_LOG`, each span with
+// its time when it closes. Returns whether logging is on.
+fn init_logging(verbose: u8) -> Result<bool> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(filter) if !filter.is_empty() => filter,
+        _ => match verbose {
+            0 => return Ok(false),
+            1 => "arrow=debug".to_string(),
+            _ => "arrow=trace".to_string(),
+        },
+    };
+    let filter = tracing_subscriber::EnvFilter::try_new(&filter)
+        .with_context(|| format!("Invalid log filter: {}", filter))?;
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr)
+        .init();
+    Ok(true)
+}
+
+// Write an informational line, unless --quiet was given
+fn status(term: &Term, line: &str) -> std::io::Result<()> {
+    if QUIET.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    term.write_line(line)
+}
+
+fn create_vector_store(db_path: &str, options: &StoreOptions) -> Result<()> {
+    let term = Term::stdout();
+    if Path::new(db_path).exists() {
+        status(&term, &format!(
+            "{}",
+            "Vector store already exists".yellow().bold()
+        ))?;
+        status(&term, &format!("  Path: {}", db_path.bright_blue()))?;
+        status(&term, &format!(
+            "{}",
+            "Use 'add' command to add documents to the existing store".italic()
+        ))?;
+        return Ok(());
+    }
+
+    // Any passphrase is asked for before the spinner starts
+    let mut store = new_vector_store(db_path, options)?;
+    let spinner = spinner("green")?;
+    spinner.set_message(match options.index {
+        IndexKind::Hnsw => format!(
+            "Creating vector store with {} max connections...",
+            options.max_connections
+        ),
+        IndexKind::Ivf => format!("Creating vector store with {} IVF lists...", options.nlist),
+        IndexKind::Flat => "Creating vector store without an index...".to_string(),
+    });
+    if let Some(parent) = Path::new(db_path).parent() {
+        fs::create_dir_all(parent).context("Failed to create collection directory")?;
+    }
+    store.save(db_path).context("Failed to save vector store")?;
+
+    spinner.finish_with_message(format!(
+        "{}✓{} Vector store created successfully!",
+        "[".green(),
+        "]".green()
+    ));
+    status(&term, "")?;
+    status(&term, &format!("  {} {}", "Location:".blue(), db_path))?;
+    status(&term, &format!(
+        "  {}",
+        "Use 'add' command to add documents".italic()
+    ))?;
+
+    Ok(())
+}
+
+// An empty store with the given index parameters and model, not yet saved
+fn new_vector_store(db_path: &str, options: &StoreOptions) -> Result<vectorstore::VectorStore> {
+    if options.mmap && options.storage != StorageMode::F32 {
+        anyhow::bail!("--mmap requires f32 storage");
+    }
+    if options.nlist == 0 {
+        anyhow::bail!("--nlist must be at least 1");
+    }
+    let engine = options
+        .backend
+        .unwrap_or_else(|| StorageEngine::for_path(Path::new(db_path)));
+    if options.mmap && engine != StorageEngine::Json {
+        anyhow::bail!("--mmap requires the json backend");
+    }
+    if options.encrypt && (options.mmap || engine != StorageEngine::Json) {
+        anyhow::bail!("--encrypt requires the json backend without --mmap");
+    }
+    let cipher = if options.encrypt {
+        let secret = crypto::secret(Path::new(db_path), true)?;
+        Some(crypto::Cipher::new(&secret)?)
+    } else {
+        None
+    };
+
+    let model = options.model()?;
+    let prompts = options.prompts(&model);
+    let mut store = vectorstore::VectorStore::new_with_storage(
+        Device::Cpu,
+        options.max_connections,
+        options.storage,
+    )
+    .with_index(options.index, options.nlist)
+    .with_model(model)
+    .with_prompts(prompts)
+    .with_mmap_vectors(options.mmap)
+    .with_backups(options.backups)
+    .with_quota(options.quota.apply(vectorstore::Quota::default()))
+    .with_engine(engine);
+    if let Some(cipher) = cipher {
+        store = store.with_cipher(cipher);
+    }
+    Ok(store)
+}
+
+// A document to index: a file, or text piped on stdin under a source name
+enum Input {
+    File(PathBuf),
+    Stdin { source: String, text: String },
+}
+
+impl Input {
+    // The file, or the source name of text from stdin
+    fn path(&self) -> &Path {
+        match self {
+            Input::File(file) => file,
+            Input::Stdin { source, .. } => Path::new(source.as_str()),
+        }
+    }
+}
+
+// What an `add` or `sync` indexes
+struct AddJob {
+    paths: Vec<String>,
+    // Drop tracked files under the paths that no longer exist
+    prune: bool,
+    // Stable document ID for a single input
+    id: Option<String>,
+    // Source name for a document read from stdin
+    stdin_source: String,
+    save_every: SaveEvery,
+    // Stop at the first file that fails instead of adding the rest
+    fail_fast: bool,
+}
+
+impl AddJob {
+    // Identifies the job in its journal, so only the same command resumes it
+    fn key(&self) -> String {
+        format!("{:?} {} {:?}", self.paths, self.prune, self.id)
+    }
+}
+
+// The files under `paths`, and text piped on stdin when one of them is `-`
+fn collect_inputs(term: &Term, paths: &[String], stdin_source: &str) -> Result<Vec<Input>> {
+    for path in paths {
+        if path != STDIN_PATH && !Path::new(path).exists() {
+            term.write_line(&format!(
+                "{} File not found: {}",
+                "[WARNING]".yellow().bold(),
+                path
+            ))?;
+        }
+    }
+    let mut inputs: Vec<Input> = sources::collect_files(paths)
+        .into_iter()
+        .map(Input::File)
+        .collect();
+    if paths.iter().any(|path| path == STDIN_PATH) {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read from stdin")?;
+        inputs.push(Input::Stdin {
+            source: stdin_source.to_string(),
+            text,
+        });
+    }
+    Ok(inputs)
+}
+
+fn add_documents(
+    db_path: &str,
+    job: AddJob,
+    ingest: &IngestOptions,
+    backend: &device::Backend,
+) -> Result<()> {
+    let AddJob {
+        paths,
+        prune,
+        id,
+        fail_fast,
+        ..
+    } = &job;
+    let term = Term::stdout();
+    status(&term, &format!(
+        "{}",
+        "Arrow Vector Database".bright_green().bold()
+    ))?;
+    status(&term, "")?;
+
+    // Load or create the vector store
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+
+    let mut store = if Path::new(db_path).exists() {
+        let store = vectorstore::VectorStore::load(db_path, backend.candle.clone())
+            .context("Failed to load vector store")?;
+        load_spinner.finish_with_message(format!(
+            "{}✓{} Vector store loaded from {}",
+            "[".green(),
+            "]".green(),
+            db_path.bright_blue()
+        ));
+        store
+    } else {
+        load_spinner.finish_with_message(format!(
+            "{}!{} Vector store not found, creating a new one",
+            "[".yellow(),
+            "]".yellow()
+        ));
+        // Save right away so additions are logged to the WAL
+        let mut store = vectorstore::VectorStore::new(backend.candle.clone(), DEFAULT_CONNECTIONS);
+        store.save(db_path).context("Failed to save vector store")?;
+        store
+    };
+
+    let inputs = collect_inputs(&term, paths, &job.stdin_source)?;
+    if id.is_some() && inputs.len() != 1 {
+        anyhow::bail!("--id requires exactly one file, found {}", inputs.len());
+    }
+    if id.is_some() && records::is_structured(inputs[0].path()) {
+        anyhow::bail!("--id can't be used with CSV or JSONL files; use --id-field");
+    }
+    choose_model(&term, &mut store, &inputs)?;
+
+    // Create embedder
+    status(&term, "")?;
+    let embed_spinner = spinner("magenta")?;
+
+    let progress = Arc::new(AddProgress::new()?);
+    store.set_progress(progress.clone());
+    let cache = open_cache(db_path, &store);
+    let embeddor = embedding::Embeddor::new(store.model(), backend)?
+        .with_cache(cache)
+        .with_dimension(store.dimension())
+        .with_prompts(store.prompts().clone())
+        .with_progress(progress.clone());
+    embed_spinner.finish_with_message(format!("{}✓{} Embedding model initialized", "[".green(), "]".green()));
+
+    let mut added_count = 0;
+    let mut _total_chunks = 0;
+    let mut processed_files = 0;
+    let mut skipped_files = 0;
+    let mut unusable_files = 0;
+    let mut removed_chunks = 0;
+
+    // Pick up where an interrupted run of the same command stopped
+    let mut journal = journal::Journal::open(db_path, &job.key())?;
+    if journal.resumed() > 0 {
+        status(&term, &format!(
+            "{}!{} Resuming an interrupted run; {} files were already added",
+            "[".yellow(),
+            "]".yellow(),
+            journal.resumed().to_string().bright_white()
+        ))?;
+    }
+    let mut autosave = Autosave::new(job.save_every);
+    let mut quota_warned = false;
+    warn_quota(&term, &store, db_path, &mut quota_warned)?;
+    // Set when a file would take the store past a limit, which stops the add
+    let mut quota_error = None;
+
+    progress.files.set_length(inputs.len() as u64);
+    // Files that couldn't be added, with the error each failed with
+    let mut failures: Vec<(String, anyhow::Error)> = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let file = input.path();
+        let file_path = file.to_string_lossy().to_string();
+        // Stdin may hold something else on the next run, so only files are
+        // journaled
+        let journaled = matches!(input, Input::File(_));
+
+        if journaled && journal.is_done(&file_path) {
+            skipped_files += 1;
+            progress.event(ProgressEvent::FileSkipped { source: &file_path });
+            continue;
+        }
+        if journaled && journal.was_interrupted(&file_path) {
+            removed_chunks += store.remove_untracked(&file_path);
+        }
+
+        // Skip files whose content hasn't changed since they were last indexed
+        let change = match input {
+            Input::File(_) => changed_source(&mut store, &file_path, file),
+            Input::Stdin { text, .. } => Ok(changed_text(&store, &file_path, text)),
+        };
+        let (hash, mtime) = match change {
+            Ok(Some(change)) => change,
+            Ok(None) => {
+                skipped_files += 1;
+                progress.event(ProgressEvent::FileSkipped { source: &file_path });
+                if journaled {
+                    journal.finish(&file_path);
+                }
+                continue;
+            }
+            Err(e) => {
+                fail_file(&term, &mut failures, &file_path, e, *fail_fast)?;
+                progress.event(ProgressEvent::FileSkipped { source: &file_path });
+                continue;
+            }
+        };
+        if journaled {
+            journal.start(&file_path)?;
+        }
+        // The file's chunks stay until its new ones are in, so a file that
+        // fails to re-index keeps what it had
+        let stale = store.replacing_source(&file_path);
+
+        progress.event(ProgressEvent::FileStarted {
+            source: &file_path,
+            index,
+            total: inputs.len(),
+        });
+
+        let indexed =
+            index_input(&mut store, &embeddor, &term, &progress, input, id, ingest, db_path);
+        let chunk_ids = match indexed {
+            Ok(Some(chunk_ids)) => chunk_ids,
+            Ok(None) => {
+                removed_chunks += store.remove_source(&file_path);
+                unusable_files += 1;
+                progress.event(ProgressEvent::FileSkipped { source: &file_path });
+                if journaled {
+                    journal.finish(&file_path);
+                }
+                continue;
+            }
+            // Left unfinished in the journal, so a rerun tries the file again
+            Err(e) if e.is::<QuotaExceeded>() => {
+                progress.event(ProgressEvent::FileSkipped { source: &file_path });
+                quota_error = Some(e);
+                break;
+            }
+            Err(e) => {
+                fail_file(&term, &mut failures, &file_path, e, *fail_fast)?;
+                progress.event(ProgressEvent::FileSkipped { source: &file_path });
+                continue;
+            }
+        };
+        removed_chunks += store.remove_chunks(&stale);
+        _total_chunks += chunk_ids.len();
+        added_count += chunk_ids.len();
+        progress.event(ProgressEvent::FileFinished {
+            source: &file_path,
+            chunks: chunk_ids.len(),
+        });
+        autosave.changed(chunk_ids.len());
+        store.record_source(file_path.clone(), hash, mtime, chunk_ids);
+        processed_files += 1;
+        if journaled {
+            journal.finish(&file_path);
+        }
+        warn_quota(&term, &store, db_path, &mut quota_warned)?;
+        if autosave.is_due() {
+            checkpoint(&mut store, &embeddor, &mut journal, db_path)?;
+            autosave.saved();
+        }
+    }
+    progress.finish();
+
+    // Drop tracked files under the synced paths that no longer exist
+    if *prune {
+        for source in store.source_paths() {
+            if sources::is_under_roots(&source, paths) && !Path::new(&source).exists() {
+                removed_chunks += store.remove_source(&source);
+            }
+        }
+    }
+
+    // Save the updated vector store
+    let save_spinner = spinner("green")?;
+    save_spinner.set_message("Saving vector store...");
+
+    store.save(db_path).context("Failed to save vector store")?;
+    embeddor.save_cache().context("Failed to save embedding cache")?;
+    journal.remove()?;
+    save_spinner.finish_with_message(format!(
+        "{}✓{} Vector store saved to {}",
+        "[".green(),
+        "]".green(),
+        db_path.bright_blue()
+    ));
+
+    status(&term, "")?;
+    status(&term, &format!("{}", "Summary:".bold().underline()))?;
+    status(&term, &format!(
+        "  {} {} {}",
+        "Added".green(),
+        added_count.to_string().bright_white(),
+        "chunks"
+    ))?;
+    status(&term, &format!(
+        "  {} {} {}",
+        "From".green(),
+        processed_files.to_string().bright_white(),
+        "files"
+    ))?;
+    status(&term, &format!(
+        "  {} {} {}",
+        "Skipped".green(),
+        skipped_files.to_string().bright_white(),
+        "unchanged files"
+    ))?;
+    if unusable_files > 0 {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Skipped".yellow(),
+            unusable_files.to_string().bright_white(),
+            "empty or binary files"
+        ))?;
+    }
+    if !failures.is_empty() {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Failed".red(),
+            failures.len().to_string().bright_white(),
+            "files"
+        ))?;
+    }
+    if removed_chunks > 0 {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Removed".green(),
+            removed_chunks.to_string().bright_white(),
+            "stale chunks"
+        ))?;
+    }
+    status(&term, &format!("  {} {}", "Database:".green(), db_path))?;
+    
+    // Add chunks progress bar visualization
+    let chunk_bar_width = 40;
+    let chunk_pct = if _total_chunks > 0 { (added_count as f32) / (_total_chunks as f32) } else { 1.0 };
+    let filled = (chunk_pct * chunk_bar_width as f32) as usize;
+    let empty = chunk_bar_width - filled;
+    
+    status(&term, "")?;
+    status(&term, &format!("{}", "Chunks processed:".blue().bold()))?;
+    status(&term, &format!("[{}{}] {:.1}%", 
+        "█".repeat(filled).bright_green(),
+        "▒".repeat(empty).bright_black(),
+        chunk_pct * 100.0))?;
+
+    // The files that failed were left out, and what was added is saved, so
+    // rerunning the add tries just those again
+    if !failures.is_empty() {
+        let rows: Vec<FailureRow> = failures
+            .iter()
+            .map(|(file, error)| FailureRow {
+                file: file.clone(),
+                error: format!("{:#}", error),
+            })
+            .collect();
+        let mut binding = Table::new(rows);
+        term.write_line("")?;
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+    // What was added before reaching the limit is saved; the files after it
+    // weren't tried
+    if let Some(error) = quota_error {
+        return Err(error);
+    }
+    if !failures.is_empty() {
+        anyhow::bail!("{} of {} files failed to add", failures.len(), inputs.len());
+    }
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct FailureRow {
+    #[tabled(rename = "File")]
+    file: String,
+    #[tabled(rename = "Error")]
+    error: String,
+}
+
+// What adding an input would do, as a dry run finds it
+enum Plan {
+    Unchanged,
+    Skip(loaders::Skip),
+    // The text of each chunk it would be split into
+    Chunks(Vec<String>),
+}
+
+// Show what `add` or `sync` would do with `job`: the files it would index
+// with their chunks, and roughly how much the store would grow. Chunks are
+// sized with estimated token counts, since the model isn't loaded, and the
+// store is only read to tell which files changed.
+fn preview_add(db_path: &str, job: &AddJob, ingest: &IngestOptions) -> Result<()> {
+    let term = Term::stdout();
+    let mut store = if Path::new(db_path).exists() {
+        vectorstore::VectorStore::load(db_path, Device::Cpu)
+            .context("Failed to load vector store")?
+    } else {
+        vectorstore::VectorStore::new(Device::Cpu, DEFAULT_CONNECTIONS)
+    };
+    let embeddor =
+        embedding::Embeddor::estimating(store.model()).with_prompts(store.prompts().clone());
+    let inputs = collect_inputs(&term, &job.paths, &job.stdin_source)?;
+
+    let mut rows = Vec::new();
+    let (mut files, mut chunks, mut text_bytes, mut removed) = (0, 0, 0, 0);
+    let (mut unchanged, mut unusable, mut failed) = (0, 0, 0);
+    for input in &inputs {
+        let file_path = input.path().to_string_lossy().to_string();
+        let previous = store
+            .source_record(&file_path)
+            .map_or(0, |record| record.chunk_ids.len());
+        let (change, count) = match plan_input(&mut store, &embeddor, input, ingest) {
+            Ok(Plan::Unchanged) => {
+                unchanged += 1;
+                continue;
+            }
+            Ok(Plan::Skip(skip)) => {
+                unusable += 1;
+                (format!("skip: {}", skip.describe()), "-".to_string())
+            }
+            Ok(Plan::Chunks(texts)) => {
+                files += 1;
+                chunks += texts.len();
+                text_bytes += texts.iter().map(String::len).sum::<usize>();
+                removed += previous;
+                let change = if previous > 0 { "modified" } else { "new" };
+                (change.to_string(), texts.len().to_string())
+            }
+            Err(e) if job.fail_fast => return Err(e),
+            Err(e) => {
+                failed += 1;
+                (format!("error: {:#}", e), "-".to_string())
+            }
+        };
+        rows.push(PreviewRow {
+            file: file_path,
+            change,
+            chunks: count,
+        });
+    }
+    if job.prune {
+        for source in store.source_paths() {
+            if sources::is_under_roots(&source, &job.paths) && !Path::new(&source).exists() {
+                let previous = store
+                    .source_record(&source)
+                    .map_or(0, |record| record.chunk_ids.len());
+                removed += previous;
+                rows.push(PreviewRow {
+                    file: source,
+                    change: "deleted".to_string(),
+                    chunks: "-".to_string(),
+                });
+            }
+        }
+    }
+
+    if !rows.is_empty() {
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+    status(&term, "")?;
+    status(&term, &format!("{}", "Dry run; nothing was embedded or saved:".bold().underline()))?;
+    status(&term, &format!(
+        "  {} {} {} {} {}",
+        "Would add about".green(),
+        chunks.to_string().bright_white(),
+        "chunks from",
+        files.to_string().bright_white(),
+        "files"
+    ))?;
+    if removed > 0 {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Would remove".green(),
+            removed.to_string().bright_white(),
+            "chunks of modified or deleted files"
+        ))?;
+    }
+    status(&term, &format!(
+        "  {} {} {}",
+        "Skipped".green(),
+        unchanged.to_string().bright_white(),
+        "unchanged files"
+    ))?;
+    if unusable > 0 {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Skipped".yellow(),
+            unusable.to_string().bright_white(),
+            "empty or binary files"
+        ))?;
+    }
+    if failed > 0 {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Failed".red(),
+            failed.to_string().bright_white(),
+            "files"
+        ))?;
+    }
+    let growth = match store.estimated_growth(chunks, text_bytes) {
+        Some(bytes) => format!("about {}", format_bytes(bytes as u64)),
+        None => "unknown until the store holds a vector".to_string(),
+    };
+    status(&term, &format!("  {} {}", "Store growth:".green(), growth))?;
+    for usage in quota_usage(&store, db_path, chunks, text_bytes) {
+        if usage.used > usage.max {
+            term.write_line(&format!(
+                "  {} Would take the store to about {}, past its limit of {}",
+                "[WARNING]".yellow().bold(),
+                usage.amount(usage.used),
+                usage.amount(usage.max)
+            ))?;
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} files couldn't be read", failed, inputs.len());
+    }
+    Ok(())
+}
+
+// Work out what adding an input would do without embedding it: whether it
+// changed, and if so the chunks `add` would split it into
+fn plan_input(
+    store: &mut vectorstore::VectorStore,
+    embeddor: &embedding::Embeddor,
+    input: &Input,
+    ingest: &IngestOptions,
+) -> Result<Plan> {
+    let file = input.path();
+    let file_path = file.to_string_lossy();
+    let change = match input {
+        Input::File(_) => changed_source(store, &file_path, file)?,
+        Input::Stdin { text, .. } => changed_text(store, &file_path, text),
+    };
+    if change.is_none() {
+        return Ok(Plan::Unchanged);
+    }
+    if let Some(skip) = sniff_input(input)? {
+        return Ok(Plan::Skip(skip));
+    }
+
+    let texts: Vec<String> = if records::is_structured(file) {
+        let (records, _) = match input {
+            Input::File(_) => records::load(file, &ingest.fields())?,
+            Input::Stdin { text, .. } => records::parse(file, text, &ingest.fields())?,
+        };
+        let max_tokens = ingest.max_tokens(embeddor);
+        records
+            .iter()
+            .flat_map(|record| embeddor.chunk(&record.text, max_tokens, ingest.overlap()))
+            .map(|chunk| chunk.text)
+            .collect()
+    } else {
+        let loaded = match input {
+            Input::File(_) => loaders::load(file)?,
+            Input::Stdin { text, .. } => loaders::Loaded::from_text(text.clone()),
+        };
+        let chunks = chunk_file(embeddor, file, &loaded, ingest);
+        chunks.into_iter().map(|chunk| chunk.text).collect()
+    };
+    if texts.is_empty() {
+        return Ok(Plan::Skip(loaders::Skip::Empty));
+    }
+    Ok(Plan::Chunks(texts))
+}
+
+#[derive(Tabled)]
+struct PreviewRow {
+    #[tabled(rename = "File")]
+    file: String,
+    #[tabled(rename = "Change")]
+    change: String,
+    #[tabled(rename = "Est. chunks")]
+    chunks: String,
+}
+
+// Index an input as `add` does: each record of a CSV or JSONL file as a
+// document of its own, anything else split into chunks. Returns the ids of
+// the chunks added, or None for an empty or binary file, which is warned about.
+#[allow(clippy::too_many_arguments)]
+fn index_input(
+    store: &mut vectorstore::VectorStore,
+    embeddor: &embedding::Embeddor,
+    term: &Term,
+    progress: &AddProgress,
+    input: &Input,
+    id: &Option<String>,
+    ingest: &IngestOptions,
+    db_path: &str,
+) -> Result<Option<Vec<uuid::Uuid>>> {
+    let file = input.path();
+    let file_path = file.to_string_lossy();
+
+    // Empty and binary files have nothing to index
+    if let Some(skip) = sniff_input(input)? {
+        warn_skipped(term, skip)?;
+        return Ok(None);
+    }
+
+    // Each record of a CSV or JSONL file is its own document
+    if records::is_structured(file) {
+        let (records, skipped_records) = match input {
+            Input::File(_) => records::load(file, &ingest.fields())?,
+            Input::Stdin { text, .. } => records::parse(file, text, &ingest.fields())?,
+        };
+        let chunk_ids = index_records(store, embeddor, &file_path, records, ingest, db_path)?;
+        status(term, &format!(
+            "  Indexed records into {} chunks",
+            chunk_ids.len().to_string().cyan()
+        ))?;
+        if skipped_records > 0 {
+            term.write_line(&format!(
+                "  {} Skipped {} records without a '{}' field",
+                "[WARNING]".yellow().bold(),
+                skipped_records,
+                ingest.text_field
+            ))?;
+        }
+        return Ok(Some(chunk_ids));
+    }
+
+    // Extract the file's text
+    let loaded = match input {
+        Input::File(_) => loaders::load(file)?,
+        Input::Stdin { text, .. } => loaders::Loaded::from_text(text.clone()),
+    };
+
+    // Split into chunks
+    let chunks = chunk_file(embeddor, file, &loaded, ingest);
+    if loaded.text.trim().is_empty() || chunks.is_empty() {
+        warn_skipped(term, loaders::Skip::Empty)?;
+        return Ok(None);
+    }
+    progress.event(ProgressEvent::ChunksCreated {
+        source: &file_path,
+        chunks: chunks.len(),
+    });
+
+    let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+    check_quota(store, db_path, &file_path, &texts)?;
+    let embeddings = embeddor.embed_chunks(&texts)?;
+    let batch = chunk_documents(&file_path, chunks, embeddings, ingest.expires());
+    let chunk_ids = match id {
+        Some(id) => store.upsert_chunks(id, batch, vectorstore::Metadata::new())?,
+        None => store.add_batch(batch)?,
+    };
+    Ok(Some(chunk_ids))
+}
+
+// Switch a store that is still empty, and on its provider's default model,
+// to the provider's multilingual model when the inputs are in more than one
+// language
+fn choose_model(term: &Term, store: &mut vectorstore::VectorStore, inputs: &[Input]) -> Result<()> {
+    let provider = store.model().provider();
+    let multilingual = match provider.multilingual_model() {
+        Some(multilingual)
+            if store.dimension().is_none() && store.model().model() == provider.default_model() =>
+        {
+            multilingual
+        }
+        _ => return Ok(()),
+    };
+    let languages = sample_languages(inputs);
+    if languages.len() < 2 {
+        return Ok(());
+    }
+
+    let model = ModelSpec::new(provider, multilingual)?;
+    let prompts = Prompts::for_model(&model);
+    store.set_model(model.clone(), prompts)?;
+    let names: Vec<&str> = languages
+        .iter()
+        .map(|&code| language::name(code).unwrap_or(code))
+        .collect();
+    status(term, &format!(
+        "{}!{} Found text in {}; embedding with the multilingual model {}",
+        "[".yellow(),
+        "]".yellow(),
+        names.join(", "),
+        model.to_string().bright_blue()
+    ))?;
+    Ok(())
+}
+
+// Languages the first few inputs of an add are in, most common first. Code
+// and records say little about it, and PDFs are slow to read, so they're
+// left out.
+fn sample_languages(inputs: &[Input]) -> Vec<&'static str> {
+    let sampled = inputs
+        .iter()
+        .filter(|input| {
+            let file = input.path();
+            let pdf = file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+            !pdf && !records::is_structured(file) && code::detect(file).is_none()
+        })
+        .take(LANGUAGE_SAMPLE_FILES);
+
+    let mut counts: HashMap<&'static str, usize> = HashMap::new();
+    for input in sampled {
+        let text = match input {
+            Input::File(file) => match loaders::load(file) {
+                Ok(loaded) => loaded.text,
+                Err(_) => continue,
+            },
+            Input::Stdin { text, .. } => text.clone(),
+        };
+        let sample: String = text.chars().take(LANGUAGE_SAMPLE_CHARS).collect();
+        if let Some(language) = language::detect(&sample) {
+            *counts.entry(language).or_default() += 1;
+        }
+    }
+    let mut languages: Vec<(&'static str, usize)> = counts.into_iter().collect();
+    languages.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    languages.into_iter().map(|(language, _)| language).collect()
+}
+
+// Why an input has nothing to index, if it hasn't, from the start of a file
+fn sniff_input(input: &Input) -> Result<Option<loaders::Skip>> {
+    match input {
+        Input::File(file) => loaders::sniff(file),
+        Input::Stdin { text, .. } => Ok(text.trim().is_empty().then_some(loaders::Skip::Empty)),
+    }
+}
+
+// Record a file an add couldn't index and move on to the next, or with
+// `fail_fast`, stop the add with its error
+fn fail_file(
+    term: &Term,
+    failures: &mut Vec<(String, anyhow::Error)>,
+    file_path: &str,
+    error: anyhow::Error,
+    fail_fast: bool,
+) -> Result<()> {
+    if fail_fast {
+        return Err(error);
+    }
+    term.write_line(&format!(
+        "  {} Failed to add {}: {:#}",
+        "[ERROR]".red().bold(),
+        file_path,
+        error
+    ))?;
+    failures.push((file_path.to_string(), error));
+    Ok(())
+}
+
+// Warn that a file was left out of an add, under its "Processing file" line
+fn warn_skipped(term: &Term, skip: loaders::Skip) -> Result<()> {
+    term.write_line(&format!(
+        "  {} Skipped {}",
+        "[WARNING]".yellow().bold(),
+        skip.describe()
+    ))?;
+    Ok(())
+}
+
+// An add stopped because it would take the store past one of its limits
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct QuotaExceeded(String);
+
+// One of a store's limits, and how much of it the store uses
+struct QuotaUse {
+    used: u64,
+    max: u64,
+    // Whether the limit is on disk space rather than documents
+    bytes: bool,
+}
+
+impl QuotaUse {
+    fn amount(&self, amount: u64) -> String {
+        if self.bytes {
+            format_bytes(amount)
+        } else {
+            format!("{} documents", amount)
+        }
+    }
+}
+
+// Each of the store's limits with how much of it the store would use after
+// adding `documents` with `text_bytes` of text. Disk space is what the store
+// takes up now, plus an estimate for the new documents.
+fn quota_usage(
+    store: &vectorstore::VectorStore,
+    db_path: &str,
+    documents: usize,
+    text_bytes: usize,
+) -> Vec<QuotaUse> {
+    let quota = store.quota();
+    let mut usage = Vec::new();
+    if let Some(max) = quota.max_documents {
+        usage.push(QuotaUse {
+            used: (store.text_count() + documents) as u64,
+            max: max as u64,
+            bytes: false,
+        });
+    }
+    if let Some(max) = quota.max_bytes {
+        let growth = store.estimated_growth(documents, text_bytes).unwrap_or(text_bytes);
+        usage.push(QuotaUse {
+            used: store.disk_bytes(db_path) + growth as u64,
+            max,
+            bytes: true,
+        });
+    }
+    usage
+}
+
+// Refuse to add `texts` as documents of `source` when that would take the
+// store past one of its limits. The chunks they replace aren't counted.
+fn check_quota(
+    store: &vectorstore::VectorStore,
+    db_path: &str,
+    source: &str,
+    texts: &[String],
+) -> Result<()> {
+    let text_bytes = texts.iter().map(String::len).sum();
+    let replaced = store.source_record(source).map_or(0, |record| record.chunk_ids.len());
+    let usage = quota_usage(store, db_path, texts.len().saturating_sub(replaced), text_bytes);
+    match usage.into_iter().find(|usage| usage.used > usage.max) {
+        Some(usage) => Err(QuotaExceeded(format!(
+            "Adding {} chunks would take the store to {}, past its limit of {}; \
+             raise or remove it with `arrow quota`",
+            texts.len(),
+            usage.amount(usage.used),
+            usage.amount(usage.max)
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+// Warn once the store comes within QUOTA_WARNING of one of its limits,
+// unless an earlier call already did
+fn warn_quota(
+    term: &Term,
+    store: &vectorstore::VectorStore,
+    db_path: &str,
+    warned: &mut bool,
+) -> Result<()> {
+    if *warned {
+        return Ok(());
+    }
+    for usage in quota_usage(store, db_path, 0, 0) {
+        let share = usage.used as f64 / usage.max as f64;
+        if share >= QUOTA_WARNING {
+            term.write_line(&format!(
+                "{} The store uses {:.0}% of its limit of {}",
+                "[WARNING]".yellow().bold(),
+                share * 100.0,
+                usage.amount(usage.max)
+            ))?;
+            *warned = true;
+        }
+    }
+    Ok(())
+}
+
+// The terminal output of `add`: a bar over the files, a line as each is
+// started and split into chunks, and bars over its chunks as they are
+// embedded and linked into the graph
+struct AddProgress {
+    term: Term,
+    files: ProgressBar,
+    embedding: Mutex<Option<ProgressBar>>,
+    inserting: Mutex<Option<ProgressBar>>,
+}
+
+impl AddProgress {
+    fn new() -> Result<Self> {
+        Ok(Self {
+            term: Term::stdout(),
+            files: progress_bar(
+                0,
+                "[{elapsed_precise}] {wide_bar:.cyan/blue} {pos}/{len} files processed",
+            )?,
+            embedding: Mutex::new(None),
+            inserting: Mutex::new(None),
+        })
+    }
+
+    fn finish(&self) {
+        self.files.finish();
+    }
+
+    // Move a chunk bar to `done` of `total`, drawing it on the first step and
+    // clearing it on the last
+    fn step(&self, bar: &Mutex<Option<ProgressBar>>, done: usize, total: usize, template: &str) {
+        let mut bar = bar.lock().unwrap_or_else(PoisonError::into_inner);
+        if bar.is_none() {
+            *bar = progress_bar(total, template).ok();
+        }
+        if let Some(current) = bar.as_ref() {
+            current.set_length(total as u64);
+            current.set_position(done as u64);
+        }
+        if done >= total {
+            if let Some(finished) = bar.take() {
+                finished.finish_and_clear();
+            }
+        }
+    }
+}
+
+impl ProgressSink for AddProgress {
+    fn event(&self, event: ProgressEvent) {
+        // Progress output is best effort; a closed terminal mustn't stop `add`
+        match event {
+            ProgressEvent::FileStarted { source, .. } => {
+                let _ = status(&self.term, &format!(
+                    "\n{} {}",
+                    "Processing file:".blue().bold(),
+                    source.bright_white()
+                ));
+            }
+            ProgressEvent::FileSkipped { .. } | ProgressEvent::FileFinished { .. } => {
+                self.files.inc(1);
+            }
+            ProgressEvent::ChunksCreated { chunks, .. } => {
+                let _ = status(&self.term, &format!(
+                    "  Split into {} chunks",
+                    chunks.to_string().cyan()
+                ));
+            }
+            ProgressEvent::EmbeddingBatchDone { done, total } => self.step(
+                &self.embedding,
+                done,
+                total,
+                "  Generating embeddings: [{elapsed_precise}] {bar:.green} {pos}/{len} chunks",
+            ),
+            ProgressEvent::NodeInserted { done, total } => self.step(
+                &self.inserting,
+                done,
+                total,
+                "  Adding to vector store: [{elapsed_precise}] {bar:.yellow} {pos}/{len} chunks",
+            ),
+        }
+    }
+}
+
+// Compare a file against its recorded hash and mtime. Returns the new hash and
+// mtime when the file needs (re-)indexing, or None when it is unchanged.
+fn changed_source(
+    store: &mut vectorstore::VectorStore,
+    file_path: &str,
+    file: &Path,
+) -> Result<Option<(String, u64)>> {
+    let mtime = sources::file_mtime(file);
+    let previous = store.source_record(file_path).cloned();
+    if let Some(record) = &previous {
+        if record.mtime == mtime {
+            return Ok(None);
+        }
+    }
+
+    let hash = sources::hash_file(file)?;
+    if let Some(record) = previous {
+        if record.hash == hash {
+            // Touched but not modified; remember the new mtime
+            store.record_source(file_path.to_string(), hash, mtime, record.chunk_ids);
+            return Ok(None);
+        }
+    }
+    Ok(Some((hash, mtime)))
+}
+
+// When a long-running ingestion saves: after a number of chunks or a time
+#[derive(Clone, Copy, Debug)]
+enum SaveEvery {
+    Chunks(usize),
+    Time(Duration),
+}
+
+impl std::str::FromStr for SaveEvery {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Ok(chunks) = value.parse::<usize>() {
+            return match chunks {
+                0 => Err("must be at least 1 chunk".to_string()),
+                _ => Ok(SaveEvery::Chunks(chunks)),
+            };
+        }
+        match dates::parse_age(value) {
+            Some(seconds) => Ok(SaveEvery::Time(Duration::from_secs(seconds))),
+            None => Err("use a number of chunks like 500 or a time like 30s or 5m".to_string()),
+        }
+    }
+}
+
+// Counts the chunks changed since the last save to tell when the next is due
+struct Autosave {
+    every: SaveEvery,
+    chunks: usize,
+    last_save: Instant,
+}
+
+impl Autosave {
+    fn new(every: SaveEvery) -> Self {
+        Self {
+            every,
+            chunks: 0,
+            last_save: Instant::now(),
+        }
+    }
+
+    fn changed(&mut self, chunks: usize) {
+        self.chunks += chunks;
+    }
+
+    fn is_due(&self) -> bool {
+        match self.every {
+            SaveEvery::Chunks(chunks) => self.chunks >= chunks,
+            SaveEvery::Time(interval) => self.last_save.elapsed() >= interval,
+        }
+    }
+
+    fn saved(&mut self) {
+        self.chunks = 0;
+        self.last_save = Instant::now();
+    }
+}
+
+// Save what an add has indexed so far, so a rerun after it's interrupted
+// starts from here
+fn checkpoint(
+    store: &mut vectorstore::VectorStore,
+    embeddor: &embedding::Embeddor,
+    journal: &mut journal::Journal,
+    db_path: &str,
+) -> Result<()> {
+    store.save(db_path).context("Failed to save vector store")?;
+    embeddor.save_cache().context("Failed to save embedding cache")?;
+    journal.checkpoint()
+}
+
+// Compare text read from stdin against the hash recorded for its source name.
+// Returns the new hash (with no mtime) when it needs (re-)indexing.
+fn changed_text(
+    store: &vectorstore::VectorStore,
+    source: &str,
+    text: &str,
+) -> Option<(String, u64)> {
+    let hash = sources::hash_bytes(text.as_bytes());
+    match store.source_record(source) {
+        Some(record) if record.hash == hash => None,
+        _ => Some((hash, 0)),
+    }
+}
+
+// Re-index a single file without any terminal output, returning the number of
+// chunks added and removed
+fn sync_file(
+    store: &mut vectorstore::VectorStore,
+    embeddor: &embedding::Embeddor,
+    file: &Path,
+    ingest: &IngestOptions,
+    db_path: &str,
+) -> Result<(usize, usize)> {
+    let file_path = file.to_string_lossy().to_string();
+    let (hash, mtime) = match changed_source(store, &file_path, file)? {
+        Some(change) => change,
+        None => return Ok((0, 0)),
+    };
+    // Empty and binary files have nothing to index
+    if loaders::sniff(file)?.is_some() {
+        return Ok((0, store.remove_source(&file_path)));
+    }
+    // The file's chunks stay until its new ones are in
+    let stale = store.replacing_source(&file_path);
+
+    let chunk_ids = if records::is_structured(file) {
+        let (records, _) = records::load(file, &ingest.fields())?;
+        index_records(store, embeddor, &file_path, records, ingest, db_path)?
+    } else {
+        let loaded = loaders::load(file)?;
+        let chunks = chunk_file(embeddor, file, &loaded, ingest);
+        if loaded.text.trim().is_empty() || chunks.is_empty() {
+            return Ok((0, store.remove_source(&file_path)));
+        }
+        let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+        check_quota(store, db_path, &file_path, &texts)?;
+        let embeddings = embeddor.embed_chunks(&texts)?;
+        store.add_batch(chunk_documents(&file_path, chunks, embeddings, ingest.expires()))?
+    };
+    let added = chunk_ids.len();
+    let removed = store.remove_chunks(&stale);
+    store.record_source(file_path, hash, mtime, chunk_ids);
+
+    Ok((added, removed))
+}
+
+// Index each record of a CSV or JSONL file as a document of its own, named
+// `file#recordN`. Records with an ID are upserted under it. Returns the ids of
+// the chunks added.
+fn index_records(
+    store: &mut vectorstore::VectorStore,
+    embeddor: &embedding::Embeddor,
+    file_path: &str,
+    records: Vec<records::Record>,
+    ingest: &IngestOptions,
+    db_path: &str,
+) -> Result<Vec<uuid::Uuid>> {
+    // Embed the chunks of every record together
+    let max_tokens = ingest.max_tokens(embeddor);
+    let record_chunks: Vec<Vec<embedding::Chunk>> = records
+        .iter()
+        .map(|record| embeddor.chunk(&record.text, max_tokens, ingest.overlap()))
+        .collect();
+    let texts: Vec<String> = record_chunks
+        .iter()
+        .flatten()
+        .map(|chunk| chunk.text.clone())
+        .collect();
+    check_quota(store, db_path, file_path, &texts)?;
+    let mut embeddings = embeddor.embed_chunks(&texts)?.into_iter();
+
+    // Each record's other fields are embedded whole and shared by its chunks
+    let field_texts: Vec<String> = records
+        .iter()
+        .flat_map(|record| record.vector_fields.values().cloned())
+        .collect();
+    let mut field_embeddings = embeddor.embed_chunks(&field_texts)?.into_iter();
+
+    let expires = ingest.expires();
+    let mut chunk_ids = Vec::new();
+    let mut batch = Vec::new();
+    for (mut record, chunks) in records.into_iter().zip(record_chunks) {
+        // A record's language is the same for all its chunks, unless one of
+        // its fields gives it
+        if let Some(language) = language::detect(&record.text) {
+            record
+                .metadata
+                .entry(vectorstore::LANGUAGE_KEY.to_string())
+                .or_insert_with(|| language.to_string());
+        }
+        let mut fields = Vec::with_capacity(record.vector_fields.len());
+        for name in record.vector_fields.keys() {
+            let embedding = field_embeddings.next().context("Missing embedding for field")?;
+            fields.push((name.clone(), embedding));
+        }
+
+        let count = chunks.len();
+        let mut documents = Vec::with_capacity(count);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let embedding = embeddings.next().context("Missing embedding for record")?;
+            let provenance = Provenance::new(file_path, i + 1, count).with_record(record.number);
+            let mut document = vectorstore::Document::new(embedding, chunk.text)
+                .with_provenance(provenance)
+                .with_expiry(expires);
+            for (name, embedding) in &fields {
+                document = document.with_field(name.clone(), embedding.clone());
+            }
+            documents.push(document);
+        }
+
+        match &record.id {
+            Some(id) => chunk_ids.extend(store.upsert_chunks(id, documents, record.metadata)?),
+            None => batch.extend(
+                documents
+                    .into_iter()
+                    .map(|document| document.with_metadata(record.metadata.clone())),
+            ),
+        }
+    }
+    chunk_ids.extend(store.add_batch(batch)?);
+    Ok(chunk_ids)
+}
+
+// Split each region of a loaded file with the chunking strategy for the
+// file's type. Chunks inherit their region's metadata, and are tagged with
+// the language they're in when it can be told.
+fn chunk_file(
+    embeddor: &embedding::Embeddor,
+    file: &Path,
+    loaded: &loaders::Loaded,
+    ingest: &IngestOptions,
+) -> Vec<embedding::Chunk> {
+    let _span = tracing::debug_span!("chunk_file", file = %file.display()).entered();
+    let max_tokens = ingest.max_tokens(embeddor);
+    let overlap = ingest.overlap();
+    let mut chunks = Vec::new();
+    for region in &loaded.regions {
+        let content = &loaded.text[region.start..region.end];
+        let region_chunks = match file.extension().and_then(|ext| ext.to_str()) {
+            // HTML and Word documents load as markdown-style text
+            Some("md") | Some("markdown") | Some("html") | Some("htm") | Some("docx") => {
+                embeddor.chunk_markdown(content, max_tokens, overlap)
+            }
+            _ => match code::detect(file) {
+                Some(language) => embeddor.chunk_code(content, language, max_tokens, overlap),
+                None => embeddor.chunk(content, max_tokens, overlap),
+            },
+        };
+        for mut chunk in region_chunks {
+            chunk.start += region.start;
+            chunk.end += region.start;
+            chunk.metadata.extend(region.metadata.clone());
+            chunks.push(chunk);
+        }
+    }
+    // Code is mostly keywords and identifiers, which say little about the
+    // language its comments are in
+    if code::detect(file).is_none() {
+        for chunk in &mut chunks {
+            if let Some(language) = language::detect(&chunk.text) {
+                chunk.metadata.insert(vectorstore::LANGUAGE_KEY.to_string(), language.to_string());
+            }
+        }
+    }
+    tracing::debug!(bytes = loaded.text.len(), chunks = chunks.len(), "chunked file");
+    chunks
+}
+
+// Pair a file's chunks with their embeddings, recording where each came from
+fn chunk_documents(
+    file_path: &str,
+    chunks: Vec<embedding::Chunk>,
+    embeddings: Vec<candle_core::Tensor>,
+    expires: Option<u64>,
+) -> Vec<vectorstore::Document> {
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .zip(embeddings)
+        .enumerate()
+        .map(|(i, (chunk, embedding))| {
+            vectorstore::Document::new(embedding, chunk.text)
+                .with_provenance(Provenance::new(file_path, i + 1, count))
+                .with_span((chunk.start, chunk.end))
+                .with_metadata(chunk.metadata)
+                .with_expiry(expires)
+        })
+        .collect()
+}
+
+fn watch_paths(
+    db_path: &str,
+    paths: Vec<String>,
+    debounce_ms: u64,
+    save_every: SaveEvery,
+    ingest: &IngestOptions,
+    backend: &device::Backend,
+) -> Result<()> {
+    let term = Term::stdout();
+    status(&term, &format!(
+        "{}",
+        "Arrow Vector Database".bright_green().bold()
+    ))?;
+    status(&term, "")?;
+
+    let _writer = lock_for_writing(db_path)?;
+    let mut store = if Path::new(db_path).exists() {
+        vectorstore::VectorStore::load(db_path, backend.candle.clone())
+            .context("Failed to load vector store")?
+    } else {
+        let mut store = vectorstore::VectorStore::new(backend.candle.clone(), DEFAULT_CONNECTIONS);
+        store.save(db_path).context("Failed to save vector store")?;
+        store
+    };
+
+    let embed_spinner = spinner("magenta")?;
+    let cache = open_cache(db_path, &store);
+    let embeddor = embedding::Embeddor::new(store.model(), backend)?
+        .with_cache(cache)
+        .with_dimension(store.dimension())
+        .with_prompts(store.prompts().clone());
+    embed_spinner.finish_with_message(format!(
+        "{}✓{} Embedding model initialized",
+        "[".green(),
+        "]".green()
+    ));
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).context("Failed to start file watcher")?;
+    for path in &paths {
+        watcher
+            .watch(Path::new(path), RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch path: {}", path))?;
+    }
+
+    let status = spinner("cyan")?;
+
+    // Changes to hidden entries, such as `.git`, and to the store's own files
+    // are ignored; indexing the store would change it again on every save
+    let roots: Vec<PathBuf> = paths.iter().filter_map(|path| fs::canonicalize(path).ok()).collect();
+    let watched = |path: &Path| {
+        !sources::is_hidden(path, &roots) && !sources::is_store_file(path, Path::new(db_path))
+    };
+
+    // Catch up on anything that changed while we weren't watching
+    let mut pending: HashSet<PathBuf> = sources::collect_files(&paths)
+        .into_iter()
+        .filter(|path| watched(path))
+        .collect();
+    for source in store.source_paths() {
+        if sources::is_under_roots(&source, &paths) {
+            pending.insert(PathBuf::from(source));
+        }
+    }
+
+    // Stop on Ctrl-C so pending changes get saved before exiting
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || handler_running.store(false, Ordering::SeqCst))
+        .context("Failed to install Ctrl-C handler")?;
+
+    let debounce = Duration::from_millis(debounce_ms);
+    let mut autosave = Autosave::new(save_every);
+    let mut last_event = Instant::now();
+    let mut dirty = false;
+    let mut added_count = 0;
+    let mut removed_count = 0;
+
+    while running.load(Ordering::SeqCst) {
+        status.set_message(format!(
+            "Watching {} — {} pending, {} chunks added, {} removed{}",
+            paths.join(", ").bright_white(),
+            pending.len().to_string().yellow(),
+            added_count.to_string().green(),
+            removed_count.to_string().red(),
+            if dirty { " (unsaved)" } else { "" }
+        ));
+
+        match receiver.recv_timeout(debounce) {
+            Ok(Ok(event)) => {
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    pending.extend(event.paths.into_iter().filter(|path| watched(path)));
+                    last_event = Instant::now();
+                }
+                continue;
+            }
+            Ok(Err(e)) => {
+                // Through the terminal, since the status line is hidden without progress
+                let warning = format!("{} {}", "[WARNING]".yellow().bold(), e);
+                status.suspend(|| term.write_line(&warning))?;
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        // Only process once events have been quiet for the debounce period
+        if !pending.is_empty() && last_event.elapsed() >= debounce {
+            for path in pending.drain() {
+                let file_path = path.to_string_lossy().to_string();
+                if path.is_file() {
+                    match sync_file(&mut store, &embeddor, &path, ingest, db_path) {
+                        Ok((added, removed)) => {
+                            added_count += added;
+                            removed_count += removed;
+                            autosave.changed(added + removed);
+                            dirty |= added > 0 || removed > 0;
+                        }
+                        Err(e) => {
+                            let warning =
+                                format!("{} {}: {}", "[WARNING]".yellow().bold(), file_path, e);
+                            status.suspend(|| term.write_line(&warning))?;
+                        }
+                    }
+                } else if !path.exists() {
+                    // A deleted directory removes every tracked file beneath it
+                    for source in store.source_paths() {
+                        if sources::is_under_roots(&source, std::slice::from_ref(&file_path)) {
+                            let removed = store.remove_source(&source);
+                            removed_count += removed;
+                            autosave.changed(removed);
+                            dirty |= removed > 0;
+                        }
+                    }
+                }
+            }
+        }
+
+        if dirty && autosave.is_due() {
+            store.save(db_path).context("Failed to save vector store")?;
+            embeddor.save_cache().context("Failed to save embedding cache")?;
+            autosave.saved();
+            dirty = false;
+        }
+    }
+
+    if dirty {
+        store.save(db_path).context("Failed to save vector store")?;
+        embeddor.save_cache().context("Failed to save embedding cache")?;
+    }
+    status.finish_with_message(format!("{}✓{} Watcher stopped", "[".green(), "]".green()));
+
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct QueryResult {
+    #[tabled(rename = "#")]
+    index: usize,
+    #[tabled(rename = "Score")]
+    score: String,
+    #[tabled(rename = "Distance")]
+    distance: String,
+    #[tabled(rename = "Source")]
+    source: String,
+    #[tabled(rename = "Content")]
+    content: String,
+}
+
+// A query result as printed by `--format json|jsonl|tsv`
+#[derive(Serialize)]
+struct QueryRecord<'a> {
+    rank: usize,
+    score: f32,
+    // What the score measures: "cosine" similarity, or a "fused" rank score
+    metric: vectorstore::Metric,
+    // Cosine distance to the query, for cosine scores
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<f32>,
+    source: Option<&'a str>,
+    // The source's path and the chunk's place in it, as separate fields
+    #[serde(flatten)]
+    provenance: Option<&'a Provenance>,
+    span: Option<vectorstore::Span>,
+    // Byte range within `text` of the sentence that best matches the query
+    highlight: Option<vectorstore::Span>,
+    metadata: &'a vectorstore::Metadata,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<u64>,
+    // The result's lines in its source with those around them, for `--context`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<excerpts::Excerpt>,
+}
+
+// A query's results as printed by `query --batch --format json|jsonl`
+#[derive(Serialize)]
+struct BatchRecord<'a> {
+    line: usize,
+    query: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    results: Vec<QueryRecord<'a>>,
+}
+
+// How the query command shows results
+enum ResultView {
+    // A table with a preview of each result
+    Table(PreviewOptions),
+    // Each result in its source with this many lines around it
+    Context(usize),
+}
+
+// How the query command selects results
+#[derive(Clone)]
+enum SearchMode {
+    TopK { rerank: bool },
+    // Linear scan over every stored vector
+    Exact,
+    Range(vectorstore::Score),
+    Mmr(f32),
+    Hybrid,
+    // Weighted combination of named vector fields
+    Fields(Vec<(String, f32)>),
+    // The query and these rewordings of it, fused by reciprocal rank
+    Expanded(Vec<String>),
+    // The best `per_group` chunks of each of the top-k sources
+    Grouped {
+        per_group: usize,
+        rerank: bool,
+        exact: bool,
+    },
+}
+
+// What `query --group-by` groups results by
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum GroupBy {
+    // The file, or name given on stdin, a chunk came from
+    Source,
+}
+
+// Rewordings of a query to search with alongside it
+fn expand_query(
+    text: &str,
+    count: usize,
+    expansion: expansion::Expansion,
+    llm: &LlmOptions,
+    config: &config::Config,
+) -> Result<Vec<String>> {
+    match expansion {
+        expansion::Expansion::Template => Ok(expansion::template_paraphrases(text, count)),
+        expansion::Expansion::Llm => {
+            let llm = llm.clone().with_config(config).open()?;
+            let rewrite_spinner = spinner("magenta")?;
+            rewrite_spinner.set_message(format!("Rewording the query with {}...", llm.name()));
+            let paraphrases = expansion::llm_paraphrases(&llm, text, count);
+            rewrite_spinner.finish_and_clear();
+            paraphrases
+        }
+    }
+}
+
+// Parse a `--field` value: a field name, optionally with `=WEIGHT`
+fn parse_similarity(value: &str) -> Result<f32, String> {
+    let similarity: f32 = value.parse().map_err(|_| format!("invalid score '{}'", value))?;
+    if !(-1.0..=1.0).contains(&similarity) {
+        return Err("cosine similarity is between -1 and 1".to_string());
+    }
+    Ok(similarity)
+}
+
+fn parse_distance(value: &str) -> Result<f32, String> {
+    let distance: f32 = value.parse().map_err(|_| format!("invalid distance '{}'", value))?;
+    if !(0.0..=2.0).contains(&distance) {
+        return Err("cosine distance is between 0 and 2".to_string());
+    }
+    Ok(distance)
+}
+
+fn parse_field_weight(value: &str) -> Result<(String, f32), String> {
+    let (name, weight) = match value.split_once('=') {
+        Some((name, weight)) => {
+            let weight: f32 = weight
+                .parse()
+                .map_err(|_| format!("invalid weight '{}'", weight))?;
+            (name, weight)
+        }
+        None => (value, 1.0),
+    };
+    if name.is_empty() {
+        return Err("field name is empty".to_string());
+    }
+    if weight.is_nan() || weight < 0.0 {
+        return Err(format!("weight of '{}' must not be negative", name));
+    }
+    Ok((name.to_string(), weight))
+}
+
+// Embed a query with the store's model, or take its embedding from the
+// cache. None when the model gives no embedding for it.
+fn embed_query_text(
+    db_path: &str,
+    store: &vectorstore::VectorStore,
+    query_text: &str,
+    backend: &device::Backend,
+) -> Result<Option<Tensor>> {
+    let mut embeddings = embed_query_texts(db_path, store, &[query_text.to_string()], backend)?;
+    Ok(embeddings.pop().flatten())
+}
+
+// Embed queries in one batch, like `embed_query_text`
+fn embed_query_texts(
+    db_path: &str,
+    store: &vectorstore::VectorStore,
+    texts: &[String],
+    backend: &device::Backend,
+) -> Result<Vec<Option<Tensor>>> {
+    // Repeated queries reuse their cached embeddings without loading the model
+    let mut cache = open_cache(db_path, store);
+    let keys: Vec<String> = texts.iter().map(|text| store.prompts().query(text.trim())).collect();
+    if keys.iter().all(|key| cache.get(key).is_some()) {
+        let mut embeddings = Vec::with_capacity(keys.len());
+        for key in &keys {
+            let vector = cache.get(key).context("Missing cached embedding")?.to_vec();
+            let len = vector.len();
+            embeddings.push(Some(Tensor::from_vec(vector, len, &backend.candle)?));
+            cache.touch(key);
+        }
+        if let Err(e) = cache.save() {
+            warn_not_saved("Query embeddings not cached", e)?;
+        }
+        return Ok(embeddings);
+    }
+
+    // Create embedder
+    let embed_spinner = spinner("magenta")?;
+    let embeddor = embedding::Embeddor::new(store.model(), backend)?
+        .with_cache(cache)
+        .with_dimension(store.dimension())
+        .with_prompts(store.prompts().clone());
+    embed_spinner.finish_with_message(format!(
+        "{}✓{} Embedding model ready",
+        "[".green(),
+        "]".green()
+    ));
+
+    // Generate query embeddings
+    let query_spinner = spinner("yellow")?;
+    query_spinner.set_message(match texts.len() {
+        1 => "Generating query embedding...".to_string(),
+        count => format!("Generating {} query embeddings...", count),
+    });
+    let embeddings = embeddor
+        .embed_queries(texts)
+        .context("Failed to generate query embedding")?;
+    if embeddings.iter().all(Option::is_none) {
+        query_spinner.finish_with_message(format!(
+            "{}✗{} Failed to generate embedding",
+            "[".red(),
+            "]".red()
+        ));
+        return Ok(embeddings);
+    }
+    query_spinner.finish_with_message(format!(
+        "{}✓{} Query embedding generated",
+        "[".green(),
+        "]".green()
+    ));
+    if let Err(e) = embeddor.save_cache() {
+        warn_not_saved("Query embeddings not cached", e)?;
+    }
+    Ok(embeddings)
+}
+
+// Warn that something a query only keeps for later couldn't be written, as in
+// a directory the user can't write to; the query itself goes on
+fn warn_not_saved(what: &str, error: impl std::fmt::Display) -> Result<()> {
+    Term::stderr().write_line(&format!("{} {}: {:#}", "[WARNING]".yellow().bold(), what, error))?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn query_vector_store(
+    db_path: &str,
+    query_text: &str,
+    top_k: usize,
+    mode: SearchMode,
+    filter: &vectorstore::Filter,
+    view: ResultView,
+    format: OutputFormat,
+    backend: &device::Backend,
+) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        if format.is_structured() {
+            anyhow::bail!("Vector store not found: {}", db_path);
+        }
+        term.write_line(&format!("{}", "Vector store not found".red().bold()))?;
+        term.write_line(&format!("  Expected at: {}", db_path))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'create' command to create a new vector store".italic()
+        ))?;
+        return Ok(());
+    }
+
+    // Show banner
+    if !format.is_structured() {
+        status(&term, &format!("{}", "Arrow Vector Search".bright_green().bold()))?;
+        status(&term, "")?;
+    }
+
+    // Load vector store
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+
+    let store = vectorstore::VectorStore::load(db_path, backend.candle.clone())
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    // Rewordings are embedded in the same batch as the query
+    let mut texts = vec![query_text.to_string()];
+    if let SearchMode::Expanded(paraphrases) = &mode {
+        texts.extend(paraphrases.iter().cloned());
+    }
+    let mut embeddings = embed_query_texts(db_path, &store, &texts, backend)?;
+    let variants: Vec<_> = embeddings.split_off(1).into_iter().flatten().collect();
+    let query_embedding = match embeddings.pop().flatten() {
+        Some(query_embedding) => query_embedding,
+        None => {
+            if format.is_structured() {
+                anyhow::bail!("Query embedding could not be generated. Try a longer query.");
+            }
+            term.write_line(&format!(
+                "{}",
+                "Query embedding could not be generated. Try a longer query.".yellow()
+            ))?;
+            return Ok(());
+        }
+    };
+
+    // Display query
+    if !format.is_structured() {
+        status(&term, "")?;
+        status(&term, &format!(
+            "{} {}",
+            "Query:".blue().bold(),
+            query_text.bright_white()
+        ))?;
+        if let SearchMode::Expanded(paraphrases) = &mode {
+            for paraphrase in paraphrases {
+                status(&term, &format!("{} {}", "Also searched:".blue(), paraphrase))?;
+            }
+        }
+    }
+
+    let search_spinner = spinner("cyan")?;
+    search_spinner.set_message(match mode {
+        SearchMode::Range(min_score) => {
+            format!("Searching for matches scoring at least {}...", min_score)
+        }
+        _ => format!("Searching for top {} matches...", top_k),
+    });
+
+    let results =
+        search_passages(&store, mode, query_text, &query_embedding, variants, top_k, filter)?;
+    search_spinner.finish_with_message(format!("{}✓{} Search complete", "[".green(), "]".green()));
+
+    if let Err(e) = record_history(&store, db_path, query_text, &results) {
+        warn_not_saved("Query not added to the history", e)?;
+    }
+
+    let mut excerpts = excerpts::Excerpts::default();
+    if format.is_structured() {
+        let mut rows = query_records(&store, query_text, &results);
+        if let ResultView::Context(context) = view {
+            for (row, result) in rows.iter_mut().zip(&results) {
+                row.context = excerpts.excerpt(result, context);
+            }
+        }
+        return output::write_rows(format, &rows, &["rank", "score", "source", "text"], |row| {
+            vec![
+                row.rank.to_string(),
+                row.score.to_string(),
+                row.source.unwrap_or_default().to_string(),
+                row.text.to_string(),
+            ]
+        });
+    }
+
+    if results.is_empty() {
+        term.write_line(&format!("{}", "\nNo results found.".yellow().bold()))?;
+    } else {
+        status(&term, &format!("{}", "\nResults:".green().bold()))?;
+        match view {
+            ResultView::Context(context) => {
+                print_excerpts(&term, &store, query_text, &results, &mut excerpts, context)?
+            }
+            ResultView::Table(preview) => {
+                term.write_line(&results_table(&store, query_text, &results, preview))?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn query_batch(
+    db_path: &str,
+    path: &str,
+    top_k: usize,
+    mode: SearchMode,
+    filter: &vectorstore::Filter,
+    preview: PreviewOptions,
+    format: OutputFormat,
+    backend: &device::Backend,
+) -> Result<()> {
+    let term = Term::stdout();
+    let input = if path == STDIN_PATH {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("Failed to read queries from stdin")?;
+        input
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read queries from {}", path))?
+    };
+    // Blank lines are skipped; results keep the line number they came from
+    let (lines, queries): (Vec<usize>, Vec<String>) = input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .unzip();
+    if queries.is_empty() {
+        anyhow::bail!("No queries in {}", path);
+    }
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let store = vectorstore::VectorStore::load(db_path, backend.candle.clone())
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    let embeddings = embed_query_texts(db_path, &store, &queries, backend)?;
+
+    let search_progress = progress_bar(
+        queries.len(),
+        "[{elapsed_precise}] {wide_bar:.cyan/blue} {pos}/{len} queries searched",
+    )?;
+    let mut all_results = Vec::with_capacity(queries.len());
+    for (query, embedding) in queries.iter().zip(&embeddings) {
+        let results = match embedding {
+            Some(embedding) => {
+                search_passages(&store, mode.clone(), query, embedding, Vec::new(), top_k, filter)?
+            }
+            None => Vec::new(),
+        };
+        all_results.push(results);
+        search_progress.inc(1);
+    }
+    search_progress.finish_and_clear();
+    for (query, results) in queries.iter().zip(&all_results) {
+        if let Err(e) = record_history(&store, db_path, query, results) {
+            warn_not_saved("Queries not added to the history", e)?;
+            break;
+        }
+    }
+
+    let failed = "Query embedding could not be generated";
+    if format == OutputFormat::Tsv {
+        // One row per result, led by the query it answers
+        let rows: Vec<(&str, QueryRecord)> = queries
+            .iter()
+            .zip(&all_results)
+            .flat_map(|(query, results)| {
+                let rows = query_records(&store, query, results);
+                rows.into_iter().map(move |row| (query.as_str(), row))
+            })
+            .collect();
+        let header = ["query", "rank", "score", "source", "text"];
+        return output::write_rows(format, &rows, &header, |(query, row)| {
+            vec![
+                query.to_string(),
+                row.rank.to_string(),
+                row.score.to_string(),
+                row.source.unwrap_or_default().to_string(),
+                row.text.to_string(),
+            ]
+        });
+    }
+    if format.is_structured() {
+        let records: Vec<BatchRecord> = lines
+            .iter()
+            .zip(&queries)
+            .zip(embeddings.iter().zip(&all_results))
+            .map(|((&line, query), (embedding, results))| BatchRecord {
+                line,
+                query,
+                error: embedding.is_none().then_some(failed),
+                results: query_records(&store, query, results),
+            })
+            .collect();
+        return output::write_rows(format, &records, &[], |_| Vec::new());
+    }
+
+    for ((query, embedding), results) in queries.iter().zip(&embeddings).zip(&all_results) {
+        status(&term, "")?;
+        status(&term, &format!("{} {}", "Query:".blue().bold(), query.bright_white()))?;
+        if embedding.is_none() {
+            term.write_line(&format!("{}", format!("{}.", failed).yellow()))?;
+        } else if results.is_empty() {
+            term.write_line(&format!("{}", "No results found.".yellow().bold()))?;
+        } else {
+            term.write_line(&results_table(&store, query, results, preview))?;
+        }
+    }
+    Ok(())
+}
+
+// Run a search, stitching overlapping chunks of the same source into one
+// passage. Ranked searches fetch more chunks until they make `top_k` passages
+// or the store runs out; range and grouped results are kept as found.
+fn search_passages(
+    store: &vectorstore::VectorStore,
+    mode: SearchMode,
+    query_text: &str,
+    query_embedding: &Tensor,
+    variants: Vec<Tensor>,
+    top_k: usize,
+    filter: &vectorstore::Filter,
+) -> Result<Vec<vectorstore::SearchResult>> {
+    if matches!(mode, SearchMode::Range(_) | SearchMode::Grouped { .. }) {
+        return search_store(store, mode, query_text, query_embedding, variants, top_k, filter);
+    }
+    let mut fetched = top_k;
+    loop {
+        let results = search_store(
+            store,
+            mode.clone(),
+            query_text,
+            query_embedding,
+            variants.clone(),
+            fetched,
+            filter,
+        )?;
+        let found = results.len();
+        let passages = excerpts::stitch_overlapping(results, top_k);
+        if passages.len() == top_k || found < fetched {
+            return Ok(passages);
+        }
+        fetched *= 2;
+    }
+}
+
+// Run a search in the given mode. Top k and exact searches apply the filter
+// as they search; the other modes over-fetch, then drop what it rejects.
+fn search_store(
+    store: &vectorstore::VectorStore,
+    mode: SearchMode,
+    query_text: &str,
+    query_embedding: &Tensor,
+    variants: Vec<Tensor>,
+    top_k: usize,
+    filter: &vectorstore::Filter,
+) -> Result<Vec<vectorstore::SearchResult>> {
+    // Range and grouped results aren't cut to the top k
+    let ranked = !matches!(mode, SearchMode::Range(_) | SearchMode::Grouped { .. });
+    let pushed_down = matches!(
+        mode,
+        SearchMode::TopK { .. } | SearchMode::Exact | SearchMode::Grouped { .. }
+    );
+    let k = if filter.is_empty() || pushed_down {
+        top_k
+    } else {
+        top_k * FILTER_CANDIDATE_FACTOR
+    };
+    let mut results = match mode {
+        SearchMode::TopK { rerank } => store.query_filtered(query_embedding, k, rerank, filter)?,
+        SearchMode::Exact => store.query_exact(query_embedding, k, filter)?,
+        SearchMode::Grouped {
+            per_group,
+            rerank,
+            exact,
+        } => store.query_grouped(query_embedding, k, per_group, rerank, exact, filter)?,
+        SearchMode::Range(min_score) => store.query_range(query_embedding, min_score)?,
+        SearchMode::Mmr(lambda) => store.query_mmr(query_embedding, k, lambda)?,
+        SearchMode::Hybrid => store.query_hybrid(query_embedding, query_text, k)?,
+        SearchMode::Fields(weights) => {
+            let known = store.field_names();
+            for (field, _) in &weights {
+                if field != vectorstore::TEXT_FIELD && !known.contains(field) {
+                    anyhow::bail!(
+                        "No document has a '{}' vector field; add records with --vector-fields {}",
+                        field,
+                        field
+                    );
+                }
+            }
+            store.query_fields(query_embedding, &weights, k)?
+        }
+        SearchMode::Expanded(_) => {
+            let mut queries = vec![query_embedding.clone()];
+            queries.extend(variants);
+            store.query_fused(&queries, k)?
+        }
+    };
+    if !filter.is_empty() {
+        results.retain(|result| {
+            let language = result.metadata.get(vectorstore::LANGUAGE_KEY);
+            let source = result.provenance.as_ref().map(|p| p.source_path.as_str());
+            filter.admits(result.added, result.updated, language.map(String::as_str), source)
+        });
+        if ranked {
+            results.truncate(top_k);
+        }
+    }
+    Ok(results)
+}
+
+fn query_records<'a>(
+    store: &vectorstore::VectorStore,
+    query_text: &str,
+    results: &'a [vectorstore::SearchResult],
+) -> Vec<QueryRecord<'a>> {
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| QueryRecord {
+            rank: i + 1,
+            score: result.score.value,
+            metric: result.score.metric,
+            distance: result.score.distance(),
+            source: result.filename.as_deref(),
+            provenance: result.provenance.as_ref(),
+            span: result.span,
+            highlight: store.best_sentence(&result.text, query_text),
+            metadata: &result.metadata,
+            text: &result.text,
+            added: result.added,
+            updated: result.updated,
+            context: None,
+        })
+        .collect()
+}
+
+// Add a query and its top result to the store's history. Queries to an
+// encrypted store would be kept in the clear, so they aren't recorded.
+fn record_history(
+    store: &vectorstore::VectorStore,
+    db_path: &str,
+    query: &str,
+    results: &[vectorstore::SearchResult],
+) -> Result<()> {
+    if store.is_encrypted() {
+        return Ok(());
+    }
+    let top = results.first();
+    let entry = history::HistoryEntry::new(
+        query,
+        results.len(),
+        top.and_then(|result| result.filename.clone()),
+        top.map(|result| result.score.value),
+    );
+    history::record(Path::new(db_path), &entry)
+}
+
+// Where a result came from, as tables show it
+fn source_label(result: &vectorstore::SearchResult) -> String {
+    match (&result.provenance, &result.filename) {
+        (Some(provenance), _) => provenance.to_string(),
+        (None, Some(filename)) => filename.clone(),
+        (None, None) => "Unknown".to_string(),
+    }
+}
+
+fn results_table(
+    store: &vectorstore::VectorStore,
+    query_text: &str,
+    results: &[vectorstore::SearchResult],
+    preview: PreviewOptions,
+) -> String {
+    let table_results = results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            let mut source = source_label(result);
+            // Show which part of the file the result came from
+            if let Some(location) = result_location(&result.metadata) {
+                source = format!("{}\n{}", source, location);
+            }
+            // Preview from the sentence that best matches the query
+            let content = match store.best_sentence(&result.text, query_text) {
+                Some((start, _)) if start > 0 && !preview.full => {
+                    format!("...{}", preview.preview(&result.text[start..], 100))
+                }
+                _ => preview.preview(&result.text, 100),
+            };
+            QueryResult {
+                index: i + 1,
+                score: result.score.to_string(),
+                distance: match result.score.distance() {
+                    Some(distance) => format!("{:.4}", distance),
+                    None => "-".to_string(),
+                },
+                source,
+                content,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut binding = Table::new(table_results);
+    fit_to_terminal(binding.with(Style::modern().to_owned())).to_string()
+}
+
+// Print each result as the lines it spans in its source, numbered, with the
+// lines around it dimmed and the sentence best matching the query highlighted.
+// Results whose source can't be matched up are printed whole instead.
+fn print_excerpts(
+    term: &Term,
+    store: &vectorstore::VectorStore,
+    query_text: &str,
+    results: &[vectorstore::SearchResult],
+    excerpts: &mut excerpts::Excerpts,
+    context: usize,
+) -> Result<()> {
+    for (i, result) in results.iter().enumerate() {
+        let source = source_label(result);
+        let excerpt = excerpts.excerpt(result, context);
+        let mut heading = format!("{} {}", format!("{}.", i + 1).bold(), source.cyan());
+        if let Some(excerpt) = &excerpt {
+            heading.push_str(&format!(" lines {}-{}", excerpt.start_line, excerpt.end_line));
+        } else if let Some(location) = result_location(&result.metadata) {
+            heading.push_str(&format!(" {}", location));
+        }
+        term.write_line("")?;
+        term.write_line(&format!("{} {}", heading, format!("({})", result.score).dimmed()))?;
+
+        let highlight = store.best_sentence(&result.text, query_text);
+        let Some(excerpt) = excerpt else {
+            // Offsets into the chunk's own text
+            let mut offset = 0;
+            for line in result.text.split_inclusive('\n') {
+                let text = line.trim_end_matches(['\n', '\r']);
+                term.write_line(&format!("  {}", highlight_line(text, offset, highlight)))?;
+                offset += line.len();
+            }
+            continue;
+        };
+        // Offsets into the source, where the chunk starts at its span
+        let start = result.span.map_or(0, |(start, _)| start);
+        let highlight = highlight.map(|(from, to)| (start + from, start + to));
+        let width = (excerpt.first_line + excerpt.lines.len()).to_string().len();
+        let lines = excerpt.lines.iter().zip(&excerpt.offsets);
+        for (number, (line, &offset)) in (excerpt.first_line..).zip(lines) {
+            let gutter = format!("{:>width$} |", number, width = width);
+            if excerpt.is_match(number) {
+                let line = highlight_line(line, offset, highlight);
+                term.write_line(&format!("{} {}", gutter.green(), line))?;
+            } else {
+                term.write_line(&format!("{} {}", gutter.dimmed(), line.dimmed()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A line starting at byte `offset`, with the part of it inside `highlight`
+// in bold yellow
+fn highlight_line(line: &str, offset: usize, highlight: Option<vectorstore::Span>) -> String {
+    let Some((from, to)) = highlight else {
+        return line.to_string();
+    };
+    let start = from.saturating_sub(offset).min(line.len());
+    let end = to.saturating_sub(offset).min(line.len());
+    match (line.get(..start), line.get(start..end), line.get(end..)) {
+        (Some(before), Some(matched), Some(after)) if start < end => {
+            format!("{}{}{}", before, matched.yellow().bold(), after)
+        }
+        _ => line.to_string(),
+    }
+}
+
+#[derive(Tabled)]
+struct Citation {
+    #[tabled(rename = "#")]
+    number: usize,
+    #[tabled(rename = "Score")]
+    score: String,
+    #[tabled(rename = "Source")]
+    source: String,
+}
+
+// An answer as printed by `ask --format json|jsonl|tsv`
+#[derive(Serialize)]
+struct AnswerRecord<'a> {
+    question: &'a str,
+    answer: &'a str,
+    model: String,
+    sources: Vec<PassageRecord<'a>>,
+}
+
+// A passage the answer was written from, numbered as the model saw it
+#[derive(Serialize)]
+struct PassageRecord<'a> {
+    number: usize,
+    cited: bool,
+    score: f32,
+    source: Option<&'a str>,
+    span: Option<vectorstore::Span>,
+    text: &'a str,
+}
+
+// Retrieve the passages closest to a question and have the LLM answer from
+// them, citing them by number
+fn ask_question(
+    db_path: &str,
+    question: &str,
+    top_k: usize,
+    filter: &vectorstore::Filter,
+    llm: &llm::Llm,
+    format: OutputFormat,
+    backend: &device::Backend,
+) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let store = vectorstore::VectorStore::load(db_path, backend.candle.clone())
+        .context("Failed to load vector store")?;
+    load_spinner.finish_and_clear();
+
+    let query_embedding = embed_query_text(db_path, &store, question, backend)?
+        .context("Query embedding could not be generated. Try a longer question.")?;
+    let results = store.query_filtered(&query_embedding, top_k, true, filter)?;
+    if results.is_empty() {
+        anyhow::bail!("No documents to answer from in {}", db_path);
+    }
+
+    let answer_spinner = spinner("cyan")?;
+    answer_spinner.set_message(format!("Asking {}...", llm.name()));
+    let passages: Vec<(Option<&str>, &str)> = results
+        .iter()
+        .map(|result| (result.filename.as_deref(), result.text.as_str()))
+        .collect();
+    let messages = llm::grounded_prompt(question, &passages);
+    let answer = if format.is_structured() {
+        llm.chat(&messages)?
+    } else {
+        // Show the answer as it is written
+        let answer = llm.chat_stream(&messages, |token| {
+            answer_spinner.finish_and_clear();
+            term.write_str(token)?;
+            Ok(())
+        })?;
+        term.write_line("")?;
+        answer
+    };
+    answer_spinner.finish_and_clear();
+    let cited = llm::citations(&answer, results.len());
+
+    if format.is_structured() {
+        let record = AnswerRecord {
+            question,
+            answer: &answer,
+            model: llm.name(),
+            sources: results
+                .iter()
+                .enumerate()
+                .map(|(i, result)| PassageRecord {
+                    number: i + 1,
+                    cited: cited.contains(&(i + 1)),
+                    score: result.score.value,
+                    source: result.filename.as_deref(),
+                    span: result.span,
+                    text: &result.text,
+                })
+                .collect(),
+        };
+        return output::write_object(format, &record, |record| {
+            let sources: Vec<String> = cited
+                .iter()
+                .map(|&number| {
+                    let source = record.sources[number - 1].source;
+                    format!("[{}] {}", number, source.unwrap_or("Unknown"))
+                })
+                .collect();
+            vec![
+                ("question".to_string(), record.question.to_string()),
+                ("answer".to_string(), record.answer.to_string()),
+                ("model".to_string(), record.model.clone()),
+                ("sources".to_string(), sources.join("; ")),
+            ]
+        });
+    }
+
+    // An answer that cites nothing may still draw on what was retrieved
+    let (heading, numbers) = if cited.is_empty() {
+        ("Retrieved passages:", (1..=results.len()).collect())
+    } else {
+        ("Sources:", cited)
+    };
+    let citations: Vec<Citation> = numbers
+        .into_iter()
+        .map(|number| {
+            let result = &results[number - 1];
+            let mut source = source_label(result);
+            if let Some(location) = result_location(&result.metadata) {
+                source = format!("{}\n{}", source, location);
+            }
+            Citation {
+                number,
+                score: result.score.to_string(),
+                source,
+            }
+        })
+        .collect();
+    status(&term, "")?;
+    status(&term, &format!("{}", heading.green().bold()))?;
+    let mut binding = Table::new(citations);
+    status(&term, &format!("{}", binding.with(Style::modern().to_owned())))?;
+    Ok(())
+}
+
+// A document as printed by `get --format json|jsonl|tsv`
+#[derive(Serialize)]
+struct DocumentDetails {
+    #[serde(flatten)]
+    record: dump::DumpRecord,
+    // Links from the node on each layer it is in, from the base layer up
+    neighbors: Vec<usize>,
+}
+
+fn show_document(db_path: &str, id: &str, vector: bool, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_and_clear();
+
+    // `list` shows IDs cut short with a trailing "..."
+    let prefix = id.trim().trim_end_matches("...");
+    if prefix.is_empty() {
+        anyhow::bail!("Document ID is empty");
+    }
+    let id = match store.find_ids(prefix).as_slice() {
+        [] => anyhow::bail!("No document ID starts with '{}'", prefix),
+        [id] => *id,
+        ids => anyhow::bail!(
+            "'{}' matches {} documents; give more of the ID",
+            prefix,
+            ids.len()
+        ),
+    };
+    let details = DocumentDetails {
+        record: store.export(&id, vector).context("Document not found")?,
+        neighbors: store.neighbor_counts(&id),
+    };
+    let record = &details.record;
+
+    if format.is_structured() {
+        return output::write_object(format, &details, |details| {
+            let record = &details.record;
+            let mut fields = vec![("id".to_string(), id.to_string())];
+            if let Some(filename) = &record.filename {
+                fields.push(("source".to_string(), filename.clone()));
+            }
+            if let Some(external_id) = &record.external_id {
+                fields.push(("external_id".to_string(), external_id.clone()));
+            }
+            if let Some((start, end)) = record.span {
+                fields.push(("span".to_string(), format!("{}..{}", start, end)));
+            }
+            if let Some(added) = record.added {
+                fields.push(("added".to_string(), added.to_string()));
+            }
+            if let Some(updated) = record.updated {
+                fields.push(("updated".to_string(), updated.to_string()));
+            }
+            if let Some(expires) = record.expires {
+                fields.push(("expires".to_string(), expires.to_string()));
+            }
+            let mut metadata: Vec<_> = record.metadata.iter().collect();
+            metadata.sort();
+            for (key, value) in metadata {
+                fields.push((format!("metadata:{}", key), value.clone()));
+            }
+            for (level, count) in details.neighbors.iter().enumerate() {
+                fields.push((format!("neighbors:layer{}", level), count.to_string()));
+            }
+            fields.push(("text".to_string(), record.text.clone()));
+            if let Some(vector) = &record.vector {
+                fields.push(("vector".to_string(), join_vector(vector)));
+            }
+            let mut names: Vec<_> = record.fields.keys().collect();
+            names.sort();
+            for name in names {
+                fields.push((format!("field:{}", name), join_vector(&record.fields[name])));
+            }
+            fields
+        });
+    }
+
+    let mut lines = vec![("ID".to_string(), id.to_string())];
+    if let Some(filename) = &record.filename {
+        let mut source = filename.clone();
+        if let Some((start, end)) = record.span {
+            source = format!("{} (bytes {}..{})", source, start, end);
+        }
+        lines.push(("Source".to_string(), source));
+    }
+    if let Some(external_id) = &record.external_id {
+        lines.push(("Document ID".to_string(), external_id.clone()));
+    }
+    if let Some(added) = record.added {
+        let added = format!("{} ({})", dates::format(added), time_ago(added));
+        lines.push(("Added".to_string(), added));
+    }
+    if let Some(updated) = record.updated {
+        let updated = format!("{} ({})", dates::format(updated), time_ago(updated));
+        lines.push(("Updated".to_string(), updated));
+    }
+    if let Some(expires) = record.expires {
+        let mut expires_line = dates::format(expires);
+        if expires <= dates::now() {
+            expires_line.push_str(" (expired)");
+        }
+        lines.push(("Expires".to_string(), expires_line));
+    }
+    let mut metadata: Vec<_> = record.metadata.iter().collect();
+    metadata.sort();
+    for (key, value) in metadata {
+        lines.push((format!("Metadata {}", key), value.clone()));
+    }
+    let neighbors: Vec<String> = details
+        .neighbors
+        .iter()
+        .enumerate()
+        .map(|(level, count)| format!("{} on layer {}", count, level))
+        .collect();
+    lines.push(("Neighbors".to_string(), neighbors.join(", ")));
+
+    for (label, value) in lines {
+        term.write_line(&format!("  {} {}", format!("{}:", label).green(), value.bright_white()))?;
+    }
+    term.write_line("")?;
+    term.write_line(&record.text)?;
+
+    if let Some(vector) = &record.vector {
+        term.write_line("")?;
+        term.write_line(&format!("{} ({} dimensions)", "Vector:".blue().bold(), vector.len()))?;
+        term.write_line(&join_vector(vector))?;
+        let mut names: Vec<_> = record.fields.keys().collect();
+        names.sort();
+        for name in names {
+            term.write_line(&format!("{}", format!("Field {}:", name).blue().bold()))?;
+            term.write_line(&join_vector(&record.fields[name]))?;
+        }
+    }
+    Ok(())
+}
+
+fn join_vector(vector: &[f32]) -> String {
+    vector.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",")
+}
+
+// Where in its file a result was found: page, heading path and/or symbol
+fn result_location(metadata: &vectorstore::Metadata) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(page) = metadata.get("page") {
+        parts.push(format!("page {}", page));
+    }
+    for key in ["section", "symbol"] {
+        if let Some(value) = metadata.get(key) {
+            parts.push(value.clone());
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(", "))
+    }
+}
+
+fn quantize_vector_store(
+    db_path: &str,
+    subspaces: usize,
+    iterations: usize,
+    binary: bool,
+) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        term.write_line(&format!("{}", "Vector store not found".red().bold()))?;
+        term.write_line(&format!("  Expected at: {}", db_path))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'create' command to create a new vector store".italic()
+        ))?;
+        return Ok(());
+    }
+
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    if binary {
+        let encode_spinner = spinner("magenta")?;
+        encode_spinner.set_message(format!(
+            "Encoding {} vectors as binary codes...",
+            store.text_count()
+        ));
+        store.enable_binary();
+        encode_spinner.finish_and_clear();
+        store.save(db_path).context("Failed to save vector store")?;
+        status(&term, &format!("{}✓{} Binary codes encoded", "[".green(), "]".green()))?;
+        let bits = store.dimension().unwrap_or_default();
+        status(&term, &format!(
+            "  {} {} bits, {} bytes per vector",
+            "Codes:".blue(),
+            bits,
+            bits.div_ceil(64) * 8
+        ))?;
+        return Ok(());
+    }
+
+    let train_spinner = spinner("magenta")?;
+    train_spinner.set_message(format!(
+        "Training {} codebooks over {} vectors...",
+        subspaces,
+        store.text_count()
+    ));
+
+    store
+        .train_pq(subspaces, iterations)
+        .context("Failed to train product quantizer")?;
+    train_spinner.finish_with_message(format!(
+        "{}✓{} Product quantizer trained",
+        "[".green(),
+        "]".green()
+    ));
+
+    store.save(db_path).context("Failed to save vector store")?;
+    status(&term, &format!(
+        "  {} {} subspaces, {} bytes per vector",
+        "Codebooks:".blue(),
+        subspaces,
+        subspaces
+    ))?;
+
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct ModelRow {
+    #[tabled(rename = "Model")]
+    name: String,
+    #[tabled(rename = "Cache")]
+    cache: String,
+    #[tabled(rename = "Size")]
+    size: String,
+    #[tabled(rename = "Path")]
+    path: String,
+}
+
+// Load a model once so its files are downloaded into the cache
+fn download_model(model: &ModelSpec) -> Result<()> {
+    let term = Term::stdout();
+    match model.provider() {
+        Provider::Openai => {
+            anyhow::bail!("{} is served by the API; there is nothing to download", model)
+        }
+        Provider::Ollama => anyhow::bail!(
+            "{} is served by Ollama; download it with `ollama pull {}`",
+            model,
+            model.model()
+        ),
+        _ => {}
+    }
+
+    offline::check(&format!("Downloading {}", model))?;
+    let download_spinner = spinner("blue")?;
+    download_spinner.set_message(format!("Downloading {}...", model));
+    embedding::Embeddor::new(model, &device::Backend::cpu())
+        .with_context(|| format!("Failed to download {}", model))?;
+    download_spinner.finish_and_clear();
+
+    let cache = match model.provider() {
+        Provider::Local => models::rustbert_cache_dir(),
+        _ if Path::new(model.model()).is_dir() => Some(PathBuf::from(model.model())),
+        _ => models::hub_cache_dir(),
+    };
+    status(&term, &format!(
+        "{}✓{} {} is ready in {}",
+        "[".green(),
+        "]".green(),
+        model.to_string().bright_blue(),
+        cache.map_or_else(|| "the cache".to_string(), |dir| dir.display().to_string())
+    ))?;
+    Ok(())
+}
+
+fn list_models(format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    let cached = models::list()?;
+    if format.is_structured() {
+        return output::write_rows(format, &cached, &["name", "cache", "bytes", "path"], |model| {
+            vec![
+                model.name.clone(),
+                model.cache.to_string(),
+                model.bytes.to_string(),
+                model.path.display().to_string(),
+            ]
+        });
+    }
+    if cached.is_empty() {
+        term.write_line(&format!("{}", "No models downloaded".yellow().bold()))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'model download' to fetch one ahead of use".italic()
+        ))?;
+        return Ok(());
+    }
+
+    let rows: Vec<ModelRow> = cached
+        .iter()
+        .map(|model| ModelRow {
+            name: model.name.clone(),
+            cache: model.cache.to_string(),
+            size: format_bytes(model.bytes),
+            path: model.path.display().to_string(),
+        })
+        .collect();
+    let mut binding = Table::new(rows);
+    term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    Ok(())
+}
+
+fn remove_model(name: &str) -> Result<()> {
+    let term = Term::stdout();
+    for model in models::remove(name)? {
+        status(&term, &format!(
+            "{}✓{} Removed {} ({}) from {}",
+            "[".green(),
+            "]".green(),
+            model.name.bright_blue(),
+            format_bytes(model.bytes),
+            model.path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct CollectionRow {
+    #[tabled(rename = "Collection")]
+    name: String,
+    #[tabled(rename = "Documents")]
+    documents: usize,
+    #[tabled(rename = "Model")]
+    model: String,
+    #[tabled(rename = "Storage")]
+    storage: String,
+}
+
+fn list_collections(database: &str, names_only: bool) -> Result<()> {
+    let term = Term::stdout();
+    let names = collections::list(database)?;
+    if names_only {
+        for name in names {
+            term.write_line(&name)?;
+        }
+        return Ok(());
+    }
+    if names.is_empty() {
+        term.write_line(&format!("{}", "No collections found".yellow().bold()))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'collections create <name>' to create one".italic()
+        ))?;
+        return Ok(());
+    }
+
+    status(&term, &format!(
+        "{} {}",
+        "Collections in".blue().bold(),
+        database.bright_white()
+    ))?;
+
+    let mut rows = Vec::new();
+    for name in names {
+        let path = collections::collection_path(database, Some(&name))?;
+        let store = vectorstore::VectorStore::load(&path, Device::Cpu)
+            .with_context(|| format!("Failed to load collection: {}", name))?;
+        rows.push(CollectionRow {
+            name,
+            documents: store.text_count(),
+            model: store.model().to_string(),
+            storage: format!("{:?}", store.storage_mode()).to_lowercase(),
+        });
+    }
+
+    let mut binding = Table::new(rows);
+    let table = binding.with(Style::modern().to_owned());
+    term.write_line(&format!("{}", table))?;
+
+    Ok(())
+}
+
+fn drop_collection(database: &str, name: &str) -> Result<()> {
+    let term = Term::stdout();
+    if name == collections::DEFAULT_COLLECTION {
+        anyhow::bail!("The default collection can't be dropped; delete the database file instead");
+    }
+
+    let path = collections::collection_path(database, Some(name))?;
+    if !Path::new(&path).exists() {
+        term.write_line(&format!("{}", "Collection not found".red().bold()))?;
+        term.write_line(&format!("  Name: {}", name))?;
+        return Ok(());
+    }
+
+    vectorstore::VectorStore::delete_files(&path).context("Failed to delete collection")?;
+    status(&term, &format!(
+        "{}✓{} Collection {} dropped",
+        "[".green(),
+        "]".green(),
+        name.bright_blue()
+    ))?;
+
+    Ok(())
+}
+
+// Print the man page, or write a page per command into `dir`
+fn write_man_pages(dir: Option<&Path>) -> Result<()> {
+    let dir = match dir {
+        Some(dir) => dir,
+        None => {
+            return manpage::render(Cli::command(), &mut std::io::stdout())
+                .context("Failed to write man page");
+        }
+    };
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let written = manpage::generate_to(Cli::command(), dir)
+        .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+    let term = Term::stdout();
+    status(&term, &format!(
+        "{} {} man pages to {}",
+        "Wrote".green().bold(),
+        written.len(),
+        dir.display().to_string().bright_white()
+    ))?;
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct Document {
+    #[tabled(rename = "#")]
+    index: usize,
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "Source")]
+    source: String,
+    #[tabled(rename = "Added")]
+    added: String,
+    #[tabled(rename = "Preview")]
+    preview: String,
+}
+
+// A document as printed by `list --format json|jsonl|tsv`
+#[derive(Serialize)]
+struct DocumentRecord<'a> {
+    id: String,
+    source: Option<&'a str>,
+    #[serde(flatten)]
+    provenance: Option<&'a Provenance>,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<u64>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn list_documents(
+    db_path: &str,
+    limit: usize,
+    offset: usize,
+    sort: DocumentOrder,
+    source: Option<&str>,
+    filter: &vectorstore::Filter,
+    preview: PreviewOptions,
+    format: OutputFormat,
+) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        if format.is_structured() {
+            anyhow::bail!("Vector store not found: {}", db_path);
+        }
+        term.write_line(&format!("{}", "Vector store not found".red().bold()))?;
+        term.write_line(&format!("  Expected at: {}", db_path))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'create' command to create a new vector store".italic()
+        ))?;
+        return Ok(());
+    }
+
+    // Show banner
+    if !format.is_structured() {
+        status(&term, &format!(
+            "{}",
+            "Arrow Vector Database".bright_green().bold()
+        ))?;
+        status(&term, "")?;
+    }
+
+    // Load vector store
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+
+    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    let mut ids = store.list_ids(sort, source);
+    ids.retain(|id| store.admitted(filter, id));
+    let page: Vec<&uuid::Uuid> = ids.iter().skip(offset).take(limit).collect();
+    if format.is_structured() {
+        let rows: Vec<DocumentRecord> = page
+            .iter()
+            .filter_map(|id| {
+                let (text, filename) = store.get_embedding(id)?;
+                Some(DocumentRecord {
+                    id: id.to_string(),
+                    source: filename.map(String::as_str),
+                    provenance: store.provenance(id),
+                    text,
+                    added: store.added_at(id),
+                    updated: store.updated_at(id),
+                })
+            })
+            .collect();
+        let columns = ["id", "source", "text", "added", "updated"];
+        return output::write_rows(format, &rows, &columns, |row| {
+            vec![
+                row.id.clone(),
+                row.source.unwrap_or_default().to_string(),
+                row.text.to_string(),
+                row.added.map(|added| added.to_string()).unwrap_or_default(),
+                row.updated.map(|updated| updated.to_string()).unwrap_or_default(),
+            ]
+        });
+    }
+    if ids.is_empty() {
+        if let Some(source) = source {
+            anyhow::bail!("No documents from {}", source);
+        }
+        if !filter.is_empty() {
+            term.write_line(&format!("{}", "\nNo documents in that time range".yellow().bold()))?;
+            return Ok(());
+        }
+        term.write_line(&format!("{}", "\nVector store is empty".yellow().bold()))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'add' command to add documents".italic()
+        ))?;
+        return Ok(());
+    }
+
+    status(&term, "")?;
+    status(&term, &format!(
+        "{} {} {} {}",
+        "Documents in the vector store".blue().bold(),
+        if page.is_empty() {
+            format!("(none past offset {} of {})", offset, ids.len())
+        } else {
+            format!("(showing {}-{} of {})", offset + 1, offset + page.len(), ids.len())
+        }
+        .bright_black(),
+        "db:".blue().bold(),
+        db_path.bright_white()
+    ))?;
+
+    let mut documents = Vec::new();
+
+    for (i, id) in page.into_iter().enumerate() {
+        if let Some((text, filename)) = store.get_embedding(id) {
+            documents.push(Document {
+                index: offset + i + 1,
+                id: id.to_string().chars().take(8).collect::<String>() + "...",
+                source: match (store.provenance(id), filename) {
+                    (Some(provenance), _) => provenance.to_string(),
+                    (None, Some(f)) => f.clone(),
+                    (None, None) => "Unknown".to_string(),
+                },
+                added: match (store.added_at(id), store.updated_at(id)) {
+                    (Some(added), Some(updated)) => {
+                        format!("{}\nupdated {}", time_ago(added), time_ago(updated))
+                    }
+                    (Some(added), None) => time_ago(added),
+                    (None, _) => "-".to_string(),
+                },
+                preview: preview.preview(text, 60),
+            });
+        }
+    }
+
+    let mut binding = Table::new(documents);
+    let table = fit_to_terminal(binding.with(Style::modern().to_owned()));
+
+    term.write_line(&format!("{}", table))?;
+
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct SourceFile {
+    #[tabled(rename = "#")]
+    index: usize,
+    #[tabled(rename = "File")]
+    filename: String,
+}
+
+#[derive(Tabled)]
+struct LanguageRow {
+    #[tabled(rename = "Language")]
+    language: String,
+    #[tabled(rename = "Documents")]
+    documents: usize,
+    #[tabled(rename = "Share")]
+    share: String,
+}
+
+// Store details as printed by `info --format json|jsonl|tsv`
+#[derive(Serialize)]
+struct StoreInfo<'a> {
+    location: &'a str,
+    documents: usize,
+    storage: StorageMode,
+    index: IndexKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nlist: Option<usize>,
+    mmap: bool,
+    backend: StorageEngine,
+    encrypted: bool,
+    model: String,
+    #[serde(skip_serializing_if = "Prompts::is_empty")]
+    prompts: Prompts,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    vector_fields: Vec<String>,
+    backups: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_documents: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_bytes: Option<u64>,
+    pq_subspaces: Option<usize>,
+    binary: bool,
+    cached_embeddings: usize,
+    // Documents per ISO 639-1 code, with `und` for those without one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    languages: Vec<LanguageCount>,
+    sources: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct LanguageCount {
+    language: String,
+    documents: usize,
+}
+
+// Documents per detected language, most common first, then those without
+// one (code, text too short to tell, or added before languages were
+// detected) as `None`. Empty when no document has a language.
+fn language_histogram(store: &vectorstore::VectorStore) -> Vec<(Option<String>, usize)> {
+    let mut counts: HashMap<Option<&str>, usize> = HashMap::new();
+    for id in store.get_all_ids() {
+        *counts.entry(store.language_of(&id)).or_default() += 1;
+    }
+    let unknown = counts.remove(&None);
+    if counts.is_empty() {
+        return Vec::new();
+    }
+    let mut histogram: Vec<(Option<String>, usize)> = counts
+        .into_iter()
+        .map(|(language, count)| (language.map(str::to_string), count))
+        .collect();
+    histogram.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    histogram.extend(unknown.map(|count| (None, count)));
+    histogram
+}
+
+// Write every document as a line of JSON, ordered by source and position.
+// Progress goes to stderr when the documents go to stdout.
+fn export_documents(
+    db_path: &str,
+    output: &str,
+    include_vectors: bool,
+    file_format: FileFormat,
+) -> Result<()> {
+    let to_stdout = output == STDIN_PATH;
+    let term = if to_stdout { Term::stderr() } else { Term::stdout() };
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    let include_vectors = include_vectors || file_format != FileFormat::Jsonl;
+    let mut records: Vec<dump::DumpRecord> = store
+        .get_all_ids()
+        .iter()
+        .filter_map(|id| store.export(id, include_vectors))
+        .collect();
+    records.sort_by(|a, b| {
+        (&a.filename, a.span.map(|span| span.0), &a.id)
+            .cmp(&(&b.filename, b.span.map(|span| span.0), &b.id))
+    });
+
+    let mut out: Box<dyn Write + Send> = if to_stdout {
+        Box::new(std::io::BufWriter::new(std::io::stdout()))
+    } else {
+        let file = fs::File::create(output)
+            .with_context(|| format!("Failed to create export file: {}", output))?;
+        Box::new(std::io::BufWriter::new(file))
+    };
+    let count = match file_format {
+        FileFormat::Jsonl => dump::write(&mut out, records.into_iter())?,
+        FileFormat::Arrow => columnar::write_ipc(&mut out, &records)?,
+        FileFormat::Parquet => columnar::write_parquet(&mut out, &records)?,
+    };
+    out.flush().context("Failed to write export file")?;
+
+    status(&term, &format!(
+        "{}✓{} Exported {} documents{} to {}",
+        "[".green(),
+        "]".green(),
+        count.to_string().bright_white(),
+        if include_vectors { " with vectors" } else { "" },
+        if to_stdout { "stdout" } else { output }.bright_blue()
+    ))?;
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct LayerRow {
+    #[tabled(rename = "Layer")]
+    level: usize,
+    #[tabled(rename = "Nodes")]
+    nodes: usize,
+    #[tabled(rename = "Links")]
+    edges: usize,
+    #[tabled(rename = "Mean degree")]
+    mean_degree: String,
+    #[tabled(rename = "Median")]
+    median_degree: usize,
+    #[tabled(rename = "P90")]
+    p90_degree: usize,
+    #[tabled(rename = "Max")]
+    max_degree: usize,
+    #[tabled(rename = "Orphans")]
+    orphans: usize,
+    #[tabled(rename = "Unreachable")]
+    unreachable: usize,
+}
+
+// What the store holds of one source file. Characters and the time it was
+// indexed come from its source record, or for files added before they were
+// recorded (and for imports, which have none) from its chunks.
+#[derive(Serialize)]
+struct SourceSummary {
+    source: String,
+    chunks: usize,
+    chars: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    indexed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+#[derive(Tabled)]
+struct SourceRow {
+    #[tabled(rename = "#")]
+    index: usize,
+    #[tabled(rename = "Source")]
+    source: String,
+    #[tabled(rename = "Chunks")]
+    chunks: usize,
+    #[tabled(rename = "Characters")]
+    chars: usize,
+    #[tabled(rename = "Indexed")]
+    indexed: String,
+    #[tabled(rename = "SHA-256")]
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct FileSize {
+    file: String,
+    bytes: u64,
+}
+
+#[derive(Serialize)]
+struct StatsReport {
+    location: String,
+    documents: usize,
+    #[serde(flatten)]
+    stats: vectorstore::StoreStats,
+    disk_bytes: u64,
+    files: Vec<FileSize>,
+    source_count: usize,
+    sources: Vec<SourceSummary>,
+}
+
+fn show_stats(db_path: &str, limit: usize, offset: usize, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    let files: Vec<FileSize> = vectorstore::VectorStore::files(db_path)?
+        .into_iter()
+        .map(|path| FileSize {
+            bytes: fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0),
+            file: path.to_string_lossy().to_string(),
+        })
+        .collect();
+
+    // Source files, most chunks first, a page at a time
+    let mut per_source: HashMap<String, SourceSummary> = HashMap::new();
+    for id in store.get_all_ids() {
+        let Some((text, _)) = store.get_embedding(&id) else {
+            continue;
+        };
+        let source = match store.provenance(&id) {
+            Some(provenance) => provenance.source_path.clone(),
+            None => "(no source)".to_string(),
+        };
+        let summary = per_source.entry(source.clone()).or_insert_with(|| SourceSummary {
+            source,
+            chunks: 0,
+            chars: 0,
+            indexed: None,
+            hash: None,
+        });
+        summary.chunks += 1;
+        summary.chars += text.chars().count();
+        if let Some(added) = store.added_at(&id) {
+            summary.indexed = Some(summary.indexed.map_or(added, |indexed| indexed.min(added)));
+        }
+    }
+    let mut sources: Vec<SourceSummary> = per_source
+        .into_values()
+        .map(|mut summary| {
+            if let Some(record) = store.source_record(&summary.source) {
+                summary.chars = record.chars.unwrap_or(summary.chars);
+                summary.indexed = record.indexed.or(summary.indexed);
+                summary.hash = Some(record.hash.clone());
+            }
+            summary
+        })
+        .collect();
+    sources.sort_by(|a, b| b.chunks.cmp(&a.chunks).then_with(|| a.source.cmp(&b.source)));
+    let source_count = sources.len();
+    let sources: Vec<SourceSummary> = sources.into_iter().skip(offset).take(limit).collect();
+
+    let report = StatsReport {
+        location: db_path.to_string(),
+        documents: store.text_count(),
+        stats: store.stats(),
+        disk_bytes: files.iter().map(|file| file.bytes).sum(),
+        files,
+        source_count,
+        sources,
+    };
+
+    if format.is_structured() {
+        return output::write_object(format, &report, |report| {
+            let mut fields = vec![
+                ("location".to_string(), report.location.clone()),
+                ("documents".to_string(), report.documents.to_string()),
+                (
+                    "dimension".to_string(),
+                    report.stats.dimension.map(|d| d.to_string()).unwrap_or_default(),
+                ),
+                ("max_connections".to_string(), report.stats.max_connections.to_string()),
+                ("index".to_string(), format!("{:?}", report.stats.index).to_lowercase()),
+                ("ivf_lists".to_string(), report.stats.ivf_lists.to_string()),
+                ("largest_list".to_string(), report.stats.largest_list.to_string()),
+                ("memory_bytes".to_string(), report.stats.memory_bytes.to_string()),
+                ("mapped_bytes".to_string(), report.stats.mapped_bytes.to_string()),
+                ("tombstones".to_string(), report.stats.tombstones.to_string()),
+                ("segments".to_string(), report.stats.segments.to_string()),
+                ("disk_bytes".to_string(), report.disk_bytes.to_string()),
+            ];
+            for layer in &report.stats.layers {
+                let prefix = format!("layer{}", layer.level);
+                fields.extend([
+                    (format!("{}_nodes", prefix), layer.nodes.to_string()),
+                    (format!("{}_edges", prefix), layer.edges.to_string()),
+                    (format!("{}_mean_degree", prefix), layer.mean_degree.to_string()),
+                    (format!("{}_median_degree", prefix), layer.median_degree.to_string()),
+                    (format!("{}_p90_degree", prefix), layer.p90_degree.to_string()),
+                    (format!("{}_max_degree", prefix), layer.max_degree.to_string()),
+                    (format!("{}_orphans", prefix), layer.orphans.to_string()),
+                    (format!("{}_unreachable", prefix), layer.unreachable.to_string()),
+                ]);
+            }
+            for file in &report.files {
+                fields.push((format!("disk:{}", file.file), file.bytes.to_string()));
+            }
+            fields.push(("source_count".to_string(), report.source_count.to_string()));
+            for source in &report.sources {
+                fields.push((format!("chunks:{}", source.source), source.chunks.to_string()));
+                fields.push((format!("chars:{}", source.source), source.chars.to_string()));
+                if let Some(indexed) = source.indexed {
+                    fields.push((format!("indexed:{}", source.source), indexed.to_string()));
+                }
+                if let Some(hash) = &source.hash {
+                    fields.push((format!("hash:{}", source.source), hash.clone()));
+                }
+            }
+            fields
+        });
+    }
+
+    if !report.stats.layers.is_empty() {
+        status(&term, "")?;
+        status(&term, &format!("{}", "Graph layers:".blue().bold()))?;
+    }
+    let rows: Vec<LayerRow> = report
+        .stats
+        .layers
+        .iter()
+        .map(|layer| LayerRow {
+            level: layer.level,
+            nodes: layer.nodes,
+            edges: layer.edges,
+            mean_degree: format!("{:.2}", layer.mean_degree),
+            median_degree: layer.median_degree,
+            p90_degree: layer.p90_degree,
+            max_degree: layer.max_degree,
+            orphans: layer.orphans,
+            unreachable: layer.unreachable,
+        })
+        .collect();
+    if !rows.is_empty() {
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+
+    let stats = &report.stats;
+    let index = match stats.index {
+        IndexKind::Hnsw => ("Max connections", stats.max_connections.to_string()),
+        IndexKind::Ivf if stats.ivf_lists == 0 => ("IVF lists", "untrained".to_string()),
+        IndexKind::Ivf => (
+            "IVF lists",
+            format!("{} (largest {})", stats.ivf_lists, stats.largest_list),
+        ),
+        IndexKind::Flat => ("Search", "exact, scanning every vector".to_string()),
+    };
+    let lines = [
+        ("Documents", report.documents.to_string()),
+        (
+            "Dimension",
+            stats.dimension.map(|d| d.to_string()).unwrap_or_else(|| "-".to_string()),
+        ),
+        ("Index", format!("{:?}", stats.index).to_lowercase()),
+        index,
+        ("Memory (approx.)", format_bytes(stats.memory_bytes as u64)),
+        ("Mapped vectors", format_bytes(stats.mapped_bytes as u64)),
+        ("Tombstones", stats.tombstones.to_string()),
+        ("Segments", stats.segments.to_string()),
+        (
+            "On disk",
+            format!("{} in {} files", format_bytes(report.disk_bytes), report.files.len()),
+        ),
+    ];
+    term.write_line("")?;
+    for (label, value) in lines {
+        term.write_line(&format!("  {} {}", format!("{}:", label).green(), value.bright_white()))?;
+    }
+    for file in &report.files {
+        term.write_line(&format!("    {:>10}  {}", format_bytes(file.bytes), file.file))?;
+    }
+
+    let unreachable: usize = stats.layers.iter().map(|layer| layer.unreachable).sum();
+    if unreachable > 0 {
+        term.write_line(&format!(
+            "\n  {} Searches can't reach {} nodes, which lowers recall; see `arrow fsck`",
+            "[WARNING]".yellow().bold(),
+            unreachable
+        ))?;
+    }
+    if store.tombstone_ratio() >= vectorstore::DEFAULT_VACUUM_THRESHOLD {
+        term.write_line(&format!(
+            "\n  {} {:.0}% of the graph is removed documents; run `arrow vacuum` to drop them",
+            "[WARNING]".yellow().bold(),
+            store.tombstone_ratio() * 100.0
+        ))?;
+    }
+
+    if report.source_count > 0 {
+        term.write_line("")?;
+        term.write_line(&format!(
+            "{} {}",
+            "Sources:".blue().bold(),
+            if report.sources.is_empty() {
+                format!("(none past offset {} of {})", offset, report.source_count)
+            } else {
+                format!(
+                    "(showing {}-{} of {})",
+                    offset + 1,
+                    offset + report.sources.len(),
+                    report.source_count
+                )
+            }
+            .bright_black()
+        ))?;
+        let rows: Vec<SourceRow> = report
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| SourceRow {
+                index: offset + i + 1,
+                source: source.source.clone(),
+                chunks: source.chunks,
+                chars: source.chars,
+                indexed: source.indexed.map_or_else(|| "-".to_string(), dates::format),
+                // Enough of the hash to tell versions of a file apart
+                hash: source
+                    .hash
+                    .as_deref()
+                    .map_or("-", |hash| &hash[..hash.len().min(12)])
+                    .to_string(),
+            })
+            .collect();
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", fit_to_terminal(binding.with(Style::psql().to_owned()))))?;
+        if offset + report.sources.len() < report.source_count {
+            term.write_line(&format!(
+                "{}",
+                format!("  Use --offset {} for more", offset + report.sources.len()).italic()
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn reindex_store(db_path: &str, max_connections: Option<usize>) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+    if max_connections.is_some_and(|m| m < 2) {
+        anyhow::bail!("--max-connections must be at least 2");
+    }
+
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    if store.index_kind() == IndexKind::Flat {
+        anyhow::bail!(
+            "Flat stores have no index to rebuild; `arrow vacuum` drops removed documents"
+        );
+    }
+    if store.index_kind() == IndexKind::Ivf {
+        if max_connections.is_some() {
+            anyhow::bail!("--max-connections only applies to stores with an hnsw index");
+        }
+        let build_spinner = spinner("magenta")?;
+        build_spinner.set_message(format!(
+            "Training IVF lists over {} vectors...",
+            store.text_count()
+        ));
+        store.reindex(store.max_connections());
+        store.save(db_path).context("Failed to save vector store")?;
+        build_spinner.finish_with_message(format!(
+            "{}✓{} IVF lists rebuilt",
+            "[".green(),
+            "]".green()
+        ));
+        let lists = match store.stats().ivf_lists {
+            0 => format!("untrained, too few documents for {} lists", store.nlist()),
+            lists => lists.to_string(),
+        };
+        status(&term, &format!("  {} {}", "Lists:".blue(), lists))?;
+        return Ok(());
+    }
+
+    let previous = store.max_connections();
+    let max_connections = max_connections.unwrap_or(previous);
+    let build_spinner = spinner("magenta")?;
+    build_spinner.set_message(format!(
+        "Rebuilding graph over {} vectors...",
+        store.text_count()
+    ));
+    store.reindex(max_connections);
+    store.save(db_path).context("Failed to save vector store")?;
+    build_spinner.finish_with_message(format!(
+        "{}✓{} Graph rebuilt",
+        "[".green(),
+        "]".green()
+    ));
+
+    let layers = store.stats().layers.len();
+    let connections = if previous == max_connections {
+        max_connections.to_string()
+    } else {
+        format!("{} (was {})", max_connections, previous)
+    };
+    status(&term, &format!("  {} {}", "Max connections:".blue(), connections))?;
+    status(&term, &format!("  {} {}", "Layers:".blue(), layers))?;
+
+    Ok(())
+}
+
+fn vacuum_store(db_path: &str, threshold: f32, force: bool) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+    if threshold.is_nan() || !(0.0..=1.0).contains(&threshold) {
+        anyhow::bail!("--threshold must be between 0 and 1");
+    }
+
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    let expired = store.remove_expired();
+    if expired > 0 {
+        status(&term, &format!(
+            "{}✓{} Removed {} expired documents",
+            "[".green(),
+            "]".green(),
+            expired
+        ))?;
+    }
+
+    let tombstones = store.tombstone_count();
+    let ratio = store.tombstone_ratio();
+    if tombstones == 0 || (!force && ratio < threshold) {
+        if expired > 0 {
+            store.save(db_path).context("Failed to save vector store")?;
+        }
+        status(
+            &term,
+            &format!(
+                "{}✓{} {} removed documents in the graph ({:.1}%), below the {:.0}% threshold; \
+                 nothing to vacuum",
+                "[".green(),
+                "]".green(),
+                tombstones,
+                ratio * 100.0,
+                threshold * 100.0
+            ),
+        )?;
+        return Ok(());
+    }
+
+    let build_spinner = spinner("magenta")?;
+    build_spinner.set_message(format!(
+        "Rebuilding graph over {} vectors...",
+        store.text_count()
+    ));
+    let dropped = store.vacuum();
+    store.save(db_path).context("Failed to save vector store")?;
+    build_spinner.finish_with_message(format!(
+        "{}✓{} Dropped {} removed documents from the graph",
+        "[".green(),
+        "]".green(),
+        dropped
+    ));
+    Ok(())
+}
+
+fn compact_store(db_path: &str) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+
+    let expired = store.remove_expired();
+    if expired > 0 {
+        status(&term, &format!(
+            "{}✓{} Removed {} expired documents",
+            "[".green(),
+            "]".green(),
+            expired
+        ))?;
+    }
+
+    let segments = store.segment_count();
+    let write_spinner = spinner("magenta")?;
+    write_spinner.set_message(format!("Writing {} documents...", store.text_count()));
+    store.compact(db_path).context("Failed to save vector store")?;
+    write_spinner.finish_with_message(format!(
+        "{}✓{} Merged {} segments into {}",
+        "[".green(),
+        "]".green(),
+        segments,
+        db_path
+    ));
+
+    let size = fs::metadata(db_path).map(|meta| meta.len()).unwrap_or(0);
+    status(&term, &format!("  {} {}", "Store file:".blue(), format_bytes(size)))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct QuotaInfo {
+    documents: usize,
+    max_documents: Option<usize>,
+    bytes: u64,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Tabled)]
+struct QuotaRow {
+    #[tabled(rename = "Limit")]
+    limit: &'static str,
+    #[tabled(rename = "Used")]
+    used: String,
+    #[tabled(rename = "Max")]
+    max: String,
+    #[tabled(rename = "Use")]
+    share: String,
+}
+
+impl QuotaRow {
+    fn new(limit: &'static str, used: u64, max: Option<u64>, amount: fn(u64) -> String) -> Self {
+        Self {
+            limit,
+            used: amount(used),
+            max: max.map_or_else(|| "none".to_string(), amount),
+            share: max.map_or_else(
+                || "-".to_string(),
+                |max| format!("{:.0}%", used as f64 / max as f64 * 100.0),
+            ),
+        }
+    }
+}
+
+// Change the limits given, then show how much of each the store uses
+fn manage_quota(db_path: &str, limits: &QuotaOptions, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let store = if limits.is_empty() {
+        vectorstore::VectorStore::load(db_path, Device::Cpu)
+            .context("Failed to load vector store")?
+    } else {
+        let _writer = lock_for_writing(db_path)?;
+        let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+            .context("Failed to load vector store")?;
+        store.set_quota(limits.apply(store.quota()));
+        store.save(db_path).context("Failed to save vector store")?;
+        if !format.is_structured() {
+            status(&term, &format!(
+                "{}✓{} Limits saved to {}",
+                "[".green(),
+                "]".green(),
+                db_path.bright_blue()
+            ))?;
+        }
+        store
+    };
+
+    let quota = store.quota();
+    let info = QuotaInfo {
+        documents: store.text_count(),
+        max_documents: quota.max_documents,
+        bytes: store.disk_bytes(db_path),
+        max_bytes: quota.max_bytes,
+    };
+    if format.is_structured() {
+        return output::write_object(format, &info, |info| {
+            let limit = |max: Option<u64>| max.map_or("none".to_string(), |max| max.to_string());
+            vec![
+                ("documents".to_string(), info.documents.to_string()),
+                ("max_documents".to_string(), limit(info.max_documents.map(|max| max as u64))),
+                ("bytes".to_string(), info.bytes.to_string()),
+                ("max_bytes".to_string(), limit(info.max_bytes)),
+            ]
+        });
+    }
+
+    let rows = vec![
+        QuotaRow::new(
+            "Documents",
+            info.documents as u64,
+            info.max_documents.map(|max| max as u64),
+            |count| count.to_string(),
+        ),
+        QuotaRow::new("Disk space", info.bytes, info.max_bytes, format_bytes),
+    ];
+    let mut binding = Table::new(rows);
+    term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    Ok(())
+}
+
+fn create_snapshot(db_path: &str, name: &str, force: bool) -> Result<()> {
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    // Held so no other process changes the store while it's copied
+    let _writer = lock_for_writing(db_path)?;
+    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    let copy_spinner = spinner("blue")?;
+    copy_spinner.set_message(format!("Copying {} documents...", store.text_count()));
+    let manifest = snapshots::create(
+        db_path,
+        name,
+        store.text_count(),
+        store.model().to_string(),
+        force,
+    )?;
+    copy_spinner.finish_and_clear();
+    status(&Term::stdout(), &format!(
+        "{}✓{} Snapshot {} created with {} documents ({})",
+        "[".green(),
+        "]".green(),
+        manifest.name.bright_blue(),
+        manifest.documents,
+        format_bytes(manifest.bytes)
+    ))?;
+    Ok(())
+}
+
+fn restore_snapshot(db_path: &str, name: &str) -> Result<()> {
+    let _writer = lock_for_writing(db_path)?;
+    let copy_spinner = spinner("blue")?;
+    copy_spinner.set_message(format!("Restoring snapshot {}...", name));
+    let manifest = snapshots::restore(db_path, name)?;
+    copy_spinner.finish_and_clear();
+    status(&Term::stdout(), &format!(
+        "{}✓{} Restored snapshot {} from {} with {} documents",
+        "[".green(),
+        "]".green(),
+        manifest.name.bright_blue(),
+        dates::format(manifest.created),
+        manifest.documents
+    ))?;
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct SnapshotRow {
+    #[tabled(rename = "Snapshot")]
+    name: String,
+    #[tabled(rename = "Created")]
+    created: String,
+    #[tabled(rename = "Documents")]
+    documents: usize,
+    #[tabled(rename = "Model")]
+    model: String,
+    #[tabled(rename = "Size")]
+    size: String,
+}
+
+fn list_snapshots(db_path: &str, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    let manifests = snapshots::list(db_path)?;
+    if format.is_structured() {
+        let columns = ["name", "created", "documents", "model", "bytes"];
+        return output::write_rows(format, &manifests, &columns, |manifest| {
+            vec![
+                manifest.name.clone(),
+                manifest.created.to_string(),
+                manifest.documents.to_string(),
+                manifest.model.clone(),
+                manifest.bytes.to_string(),
+            ]
+        });
+    }
+    if manifests.is_empty() {
+        term.write_line(&format!("{}", "No snapshots found".yellow().bold()))?;
+        term.write_line(&format!(
+            "{}",
+            "Use 'snapshot create <name>' to save one".italic()
+        ))?;
+        return Ok(());
+    }
+
+    let rows: Vec<SnapshotRow> = manifests
+        .into_iter()
+        .map(|manifest| SnapshotRow {
+            name: manifest.name,
+            created: dates::format(manifest.created),
+            documents: manifest.documents,
+            model: manifest.model,
+            size: format_bytes(manifest.bytes),
+        })
+        .collect();
+    let mut binding = Table::new(rows);
+    term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    Ok(())
+}
+
+fn delete_snapshot(db_path: &str, name: &str) -> Result<()> {
+    let term = Term::stdout();
+    let manifest = snapshots::delete(db_path, name)?;
+    status(&term, &format!(
+        "{}✓{} Snapshot {} deleted ({})",
+        "[".green(),
+        "]".green(),
+        manifest.name.bright_blue(),
+        format_bytes(manifest.bytes)
+    ))?;
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct ParameterRow {
+    #[tabled(rename = "Parameter")]
+    name: String,
+    #[tabled(rename = "Old")]
+    old: String,
+    #[tabled(rename = "New")]
+    new: String,
+}
+
+#[derive(Tabled)]
+struct DocumentChangeRow {
+    #[tabled(rename = "Change")]
+    change: String,
+    #[tabled(rename = "Document")]
+    name: String,
+    #[tabled(rename = "Chunks")]
+    chunks: String,
+    #[tabled(rename = "Details")]
+    details: String,
+}
+
+fn diff_stores(old_path: &str, new_path: &str, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector stores...");
+    let old = vectorstore::VectorStore::load(old_path, Device::Cpu)
+        .with_context(|| format!("Failed to load vector store: {}", old_path))?;
+    let new = vectorstore::VectorStore::load(new_path, Device::Cpu)
+        .with_context(|| format!("Failed to load vector store: {}", new_path))?;
+    load_spinner.finish_and_clear();
+
+    let diff = diff::diff(&old, &new);
+    if format.is_structured() {
+        let columns = ["change", "name", "old", "new", "details"];
+        return output::write_rows(format, &diff.differences, &columns, |difference| {
+            vec![
+                difference.change.to_string(),
+                difference.name.clone(),
+                difference.old.clone(),
+                difference.new.clone(),
+                difference.details.clone(),
+            ]
+        });
+    }
+
+    status(&term, &format!(
+        "{} {} {} {}",
+        "Comparing".blue().bold(),
+        old_path.bright_white(),
+        "with".blue().bold(),
+        new_path.bright_white()
+    ))?;
+    if diff.differences.is_empty() {
+        status(&term, &format!(
+            "{}✓{} No differences in {} documents",
+            "[".green(),
+            "]".green(),
+            diff.unchanged
+        ))?;
+        return Ok(());
+    }
+
+    let counts = format!(
+        "  {} {}  {} {}  {} {}  {} {}",
+        "Added:".green(),
+        diff.count("added"),
+        "Removed:".red(),
+        diff.count("removed"),
+        "Changed:".yellow(),
+        diff.count("changed"),
+        "Unchanged:".blue(),
+        diff.unchanged
+    );
+    let (parameters, documents): (Vec<_>, Vec<_>) = diff
+        .differences
+        .into_iter()
+        .partition(|difference| difference.change == "parameter");
+    if !parameters.is_empty() {
+        let rows = parameters.into_iter().map(|difference| ParameterRow {
+            name: difference.name,
+            old: difference.old,
+            new: difference.new,
+        });
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+
+    status(&term, &counts)?;
+    if !documents.is_empty() {
+        let rows = documents.into_iter().map(|difference| DocumentChangeRow {
+            chunks: match difference.change {
+                "added" => difference.new,
+                "removed" => difference.old,
+                _ if difference.old == difference.new => difference.new,
+                _ => format!("{} → {}", difference.old, difference.new),
+            },
+            change: difference.change.to_string(),
+            name: difference.name,
+            details: difference.details,
+        });
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct DuplicateGroup {
+    #[tabled(rename = "Kept")]
+    source: String,
+    #[tabled(rename = "Duplicates")]
+    duplicates: usize,
+    #[tabled(rename = "Preview")]
+    preview: String,
+}
+
+// A removed document as printed by `dedupe --format json|jsonl|tsv`
+#[derive(Serialize)]
+struct DuplicateRecord {
+    kept: String,
+    duplicate: String,
+    source: String,
+}
 
-    let embeddor = embedding::Embeddor::new(DEFAULT_MODEL)?;
-    embed_spinner.finish_with_message(format!("{}✓{} Embedding model initialized", "[".green(), "]".green()));
+fn dedupe_documents(
+    db_path: &str,
+    threshold: f32,
+    dry_run: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+    if threshold.is_nan() || threshold <= 0.0 || threshold > 1.0 {
+        anyhow::bail!("--threshold must be greater than 0 and at most 1");
+    }
 
-    let mut added_count = 0;
-    let mut _total_chunks = 0;
-    let mut processed_files = 0;
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
 
-    // Create multi-file progress bar
-    let files_progress = ProgressBar::new(files.len() as u64);
-    files_progress.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {wide_bar:.cyan/blue} {pos}/{len} files processed")?
-            .progress_chars("█▓▒░ "),
-    );
+    let scan_spinner = spinner("cyan")?;
+    scan_spinner.set_message(format!("Comparing {} documents...", store.text_count()));
+    let groups = store.duplicates(threshold);
+    scan_spinner.finish_and_clear();
 
-    for file_path in &files {
-        let path = Path::new(&file_path);
-        if !path.exists() {
-            term.write_line(&format!(
-                "{} File not found: {}",
-                "[WARNING]".yellow().bold(),
-                file_path
-            ))?;
-            files_progress.inc(1);
-            continue;
+    let source = |id: &uuid::Uuid| match store.get_embedding(id) {
+        Some((_, Some(filename))) => filename.clone(),
+        _ => id.to_string(),
+    };
+    if format.is_structured() {
+        let records: Vec<DuplicateRecord> = groups
+            .iter()
+            .flat_map(|(kept, duplicates)| {
+                duplicates.iter().map(|duplicate| DuplicateRecord {
+                    kept: kept.to_string(),
+                    duplicate: duplicate.to_string(),
+                    source: source(duplicate),
+                })
+            })
+            .collect();
+        let columns = ["kept", "duplicate", "source"];
+        output::write_rows(format, &records, &columns, |record| {
+            vec![record.kept.clone(), record.duplicate.clone(), record.source.clone()]
+        })?;
+    } else if !groups.is_empty() {
+        let mut rows: Vec<DuplicateGroup> = groups
+            .iter()
+            .map(|(kept, duplicates)| DuplicateGroup {
+                source: source(kept),
+                duplicates: duplicates.len(),
+                preview: store
+                    .get_embedding(kept)
+                    .map(|(text, _)| {
+                        text.replace('\n', " ").chars().take(60).collect::<String>() + "..."
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect();
+        rows.sort_by(|a, b| b.duplicates.cmp(&a.duplicates).then_with(|| a.source.cmp(&b.source)));
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+
+    let documents = store.text_count();
+    let duplicates: usize = groups.iter().map(|(_, duplicates)| duplicates.len()).sum();
+    if duplicates > 0 && !dry_run {
+        for (_, ids) in &groups {
+            for id in ids {
+                store.remove(id);
+            }
         }
+        store.save(db_path).context("Failed to save vector store")?;
+    }
+    if format.is_structured() {
+        return Ok(());
+    }
 
-        term.write_line(&format!(
-            "\n{} {}",
-            "Processing file:".blue().bold(),
-            file_path.bright_white()
+    let share = 100.0 * duplicates as f64 / documents as f64;
+    if duplicates == 0 {
+        status(&term, &format!(
+            "{}✓{} No documents are {} or more similar",
+            "[".green(),
+            "]".green(),
+            threshold
         ))?;
+    } else if dry_run {
+        status(&term, &format!(
+            "  {} {} duplicates in {} groups ({:.1}% of {} documents); nothing removed",
+            "Found:".blue(),
+            duplicates,
+            groups.len(),
+            share,
+            documents
+        ))?;
+    } else {
+        status(&term, &format!(
+            "{}✓{} Removed {} duplicates in {} groups ({:.1}% of {} documents)",
+            "[".green(),
+            "]".green(),
+            duplicates,
+            groups.len(),
+            share,
+            documents
+        ))?;
+    }
+    Ok(())
+}
 
-        // Read file content
-        let content = fs::read_to_string(&file_path)
-            .with_context(|| format!("Failed to read file: {}", file_path))?;
-
-        // Split into chunks
-        let chunks = embeddor.chunk(&content);
-        term.write_line(&format!(
-            "  Split into {} chunks",
-            chunks.len().to_string().cyan()
-        ))?;
-        _total_chunks += chunks.len();
-
-        // Generate embeddings
-        let embedding_progress = ProgressBar::new(chunks.len() as u64);
-        embedding_progress.set_style(
-            ProgressStyle::default_bar()
-                .template(
-                    "  Generating embeddings: [{elapsed_precise}] {bar:.green} {pos}/{len} chunks",
-                )?
-                .progress_chars("█▓▒░ "),
-        );
-
-        let embeddings = embeddor.embed(&content)?;
-        embedding_progress.finish_and_clear();
+#[derive(Tabled)]
+struct ClusterRow {
+    #[tabled(rename = "Cluster")]
+    cluster: usize,
+    #[tabled(rename = "Documents")]
+    documents: usize,
+    #[tabled(rename = "Top terms")]
+    terms: String,
+}
 
-        // Add to vector store with progress
-        let store_progress = ProgressBar::new(chunks.len() as u64);
-        store_progress.set_style(ProgressStyle::default_bar()
-            .template("  Adding to vector store: [{elapsed_precise}] {bar:.yellow} {pos}/{len} chunks")?
-            .progress_chars("█▓▒░ "));
+// A cluster as printed by `cluster --format json|jsonl|tsv`
+#[derive(Serialize)]
+struct ClusterRecord<'a> {
+    cluster: usize,
+    documents: usize,
+    terms: &'a [String],
+}
 
-        for (i, (chunk, embedding)) in chunks.into_iter().zip(embeddings.into_iter()).enumerate() {
-            let chunk_filename = format!("{}#chunk{}", file_path, i + 1);
-            store.add_with_filename(embedding, chunk, Some(chunk_filename))?;
-            added_count += 1;
-            store_progress.inc(1);
-        }
-        store_progress.finish_and_clear();
-        processed_files += 1;
-        files_progress.inc(1);
+fn cluster_documents(
+    db_path: &str,
+    k: usize,
+    iterations: usize,
+    terms: usize,
+    key: &str,
+    dry_run: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+    if k == 0 {
+        anyhow::bail!("--k must be at least 1");
     }
-    files_progress.finish();
 
-    // Save the updated vector store
-    let save_spinner = ProgressBar::new_spinner();
-    save_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.green} {msg}")?,
-    );
-    save_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-    save_spinner.set_message("Saving vector store...");
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
+        "[".green(),
+        "]".green()
+    ));
+    if store.text_count() == 0 {
+        anyhow::bail!("The vector store is empty");
+    }
 
-    store.save(db_path).context("Failed to save vector store")?;
-    save_spinner.finish_with_message(format!(
-        "{}✓{} Vector store saved to {}",
+    let cluster_spinner = spinner("magenta")?;
+    cluster_spinner.set_message(format!(
+        "Clustering {} documents into {} groups...",
+        store.text_count(),
+        k
+    ));
+    let clusters = store.cluster(k, iterations, terms);
+    cluster_spinner.finish_with_message(format!(
+        "{}✓{} Found {} clusters",
         "[".green(),
         "]".green(),
-        db_path.bright_blue()
+        clusters.len()
     ));
 
-    term.write_line("")?;
-    term.write_line(&format!("{}", "Summary:".bold().underline()))?;
-    term.write_line(&format!(
-        "  {} {} {}",
-        "Added".green(),
-        added_count.to_string().bright_white(),
-        "chunks"
-    ))?;
-    term.write_line(&format!(
-        "  {} {} {}",
-        "From".green(),
-        processed_files.to_string().bright_white(),
-        "files"
-    ))?;
-    term.write_line(&format!("  {} {}", "Database:".green(), db_path))?;
-    
-    // Add chunks progress bar visualization
-    let chunk_bar_width = 40;
-    let chunk_pct = if _total_chunks > 0 { (added_count as f32) / (_total_chunks as f32) } else { 1.0 };
-    let filled = (chunk_pct * chunk_bar_width as f32) as usize;
-    let empty = chunk_bar_width - filled;
-    
-    term.write_line("")?;
-    term.write_line(&format!("{}", "Chunks processed:".blue().bold()))?;
-    term.write_line(&format!("[{}{}] {:.1}%", 
-        "█".repeat(filled).bright_green(),
-        "▒".repeat(empty).bright_black(),
-        chunk_pct * 100.0))?;
+    // Clusters are numbered from the largest down
+    if !dry_run {
+        for (i, cluster) in clusters.iter().enumerate() {
+            for id in &cluster.members {
+                store.set_metadata(id, key, Some(i.to_string()));
+            }
+        }
+        store.save(db_path).context("Failed to save vector store")?;
+    }
 
+    if format.is_structured() {
+        let records: Vec<ClusterRecord> = clusters
+            .iter()
+            .enumerate()
+            .map(|(i, cluster)| ClusterRecord {
+                cluster: i,
+                documents: cluster.members.len(),
+                terms: &cluster.terms,
+            })
+            .collect();
+        return output::write_rows(format, &records, &["cluster", "documents", "terms"], |record| {
+            vec![
+                record.cluster.to_string(),
+                record.documents.to_string(),
+                record.terms.join(" "),
+            ]
+        });
+    }
+
+    let rows: Vec<ClusterRow> = clusters
+        .iter()
+        .enumerate()
+        .map(|(i, cluster)| ClusterRow {
+            cluster: i,
+            documents: cluster.members.len(),
+            terms: cluster.terms.join(", "),
+        })
+        .collect();
+    let mut binding = Table::new(rows);
+    term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    if !dry_run {
+        status(&term, &format!(
+            "  {} Each document's cluster is in its `{}` metadata",
+            "Labels:".blue(),
+            key
+        ))?;
+    }
     Ok(())
 }
 
-#[derive(Tabled)]
-struct QueryResult {
-    #[tabled(rename = "#")]
-    index: usize,
-    #[tabled(rename = "Score")]
-    score: String,
-    #[tabled(rename = "Source")]
-    source: String,
-    #[tabled(rename = "Content")]
-    content: String,
+#[derive(Serialize)]
+struct BenchmarkReport {
+    queries: usize,
+    top_k: usize,
+    recall: f64,
+    qps: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+    exact_qps: f64,
 }
 
-fn query_vector_store(db_path: &str, query_text: &str, top_k: usize) -> Result<()> {
+fn run_benchmark(
+    db_path: &str,
+    benchmark: &BenchmarkOptions,
+    format: OutputFormat,
+    backend: &device::Backend,
+) -> Result<()> {
     let term = Term::stdout();
     if !Path::new(db_path).exists() {
-        term.write_line(&format!("{}", "Vector store not found".red().bold()))?;
-        term.write_line(&format!("  Expected at: {}", db_path))?;
-        term.write_line(&format!(
-            "{}",
-            "Use 'create' command to create a new vector store".italic()
-        ))?;
-        return Ok(());
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+    if benchmark.top_k == 0 {
+        anyhow::bail!("--top-k must be at least 1");
     }
 
-    // Show banner
-    term.write_line(&format!("{}", "Arrow Vector Search".bright_green().bold()))?;
-    term.write_line("")?;
-
-    // Load vector store
-    let load_spinner = ProgressBar::new_spinner();
-    load_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.blue} {msg}")?,
-    );
-    load_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    let load_spinner = spinner("blue")?;
     load_spinner.set_message("Loading vector store...");
-
-    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+    let store = vectorstore::VectorStore::load(db_path, backend.candle.clone())
         .context("Failed to load vector store")?;
     load_spinner.finish_with_message(format!(
         "{}✓{} Vector store loaded",
         "[".green(),
         "]".green()
     ));
+    if store.text_count() == 0 {
+        anyhow::bail!("The vector store is empty");
+    }
 
-    // Create embedder
-    let embed_spinner = ProgressBar::new_spinner();
-    embed_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.magenta} {msg}")?,
-    );
-    embed_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    let queries = match &benchmark.query_file {
+        Some(path) => {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let texts: Vec<&str> = contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .take(benchmark.queries)
+                .collect();
+            if texts.is_empty() {
+                anyhow::bail!("No queries in {}", path.display());
+            }
 
-    let embeddor = embedding::Embeddor::new(DEFAULT_MODEL)?;
-    embed_spinner.finish_with_message(format!(
-        "{}✓{} Embedding model ready",
-        "[".green(),
-        "]".green()
+            let embeddor = embedding::Embeddor::new(store.model(), backend)?
+                .with_dimension(store.dimension())
+                .with_prompts(store.prompts().clone());
+            let bar = progress_bar(
+                texts.len(),
+                "  Embedding queries: [{elapsed_precise}] {bar:.green} {pos}/{len} queries",
+            )?;
+            let mut queries = Vec::with_capacity(texts.len());
+            for text in texts {
+                let embedding = embeddor
+                    .embed_query(text)
+                    .context("Failed to generate query embedding")?
+                    .into_iter()
+                    .next()
+                    .with_context(|| format!("No embedding for query: {}", text))?;
+                queries.push(embedding.to_vec1::<f32>()?);
+                bar.inc(1);
+            }
+            bar.finish_and_clear();
+            queries
+        }
+        None => store.sample_vectors(benchmark.queries),
+    };
+
+    let exact_spinner = spinner("cyan")?;
+    exact_spinner.set_message(format!(
+        "Computing exact top {} for {} queries...",
+        benchmark.top_k,
+        queries.len()
     ));
+    let exact_start = Instant::now();
+    let exact: Vec<HashSet<uuid::Uuid>> = match benchmark.ground_truth {
+        GroundTruth::Brute => queries
+            .iter()
+            .map(|query| {
+                store
+                    .exact_nearest(query, benchmark.top_k)
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect()
+            })
+            .collect(),
+    };
+    let exact_elapsed = exact_start.elapsed();
+    exact_spinner.finish_and_clear();
 
-    // Generate query embedding
-    let query_spinner = ProgressBar::new_spinner();
-    query_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.yellow} {msg}")?,
-    );
-    query_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-    query_spinner.set_message("Generating query embedding...");
+    let bar = progress_bar(
+        queries.len(),
+        "  Searching: [{elapsed_precise}] {bar:.yellow} {pos}/{len} queries",
+    )?;
+    // Measure the graph itself, even on a store small enough to be scanned
+    vectorstore::set_exact_below(0);
+    let mut latencies = Vec::with_capacity(queries.len());
+    let mut found = 0;
+    let mut expected = 0;
+    for (query, truth) in queries.iter().zip(&exact) {
+        let start = Instant::now();
+        let results = store.nearest(query, benchmark.top_k);
+        latencies.push(start.elapsed());
+        found += results.iter().filter(|(id, _)| truth.contains(id)).count();
+        expected += truth.len();
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
 
-    let query_embeddings = embeddor
-        .embed(query_text)
-        .context("Failed to generate query embedding")?;
+    let total: Duration = latencies.iter().sum();
+    latencies.sort();
+    let percentile = |p: f64| {
+        let index = ((latencies.len() - 1) as f64 * p).round() as usize;
+        latencies[index].as_secs_f64() * 1000.0
+    };
+    let report = BenchmarkReport {
+        queries: queries.len(),
+        top_k: benchmark.top_k,
+        recall: found as f64 / expected.max(1) as f64,
+        qps: queries.len() as f64 / total.as_secs_f64().max(f64::EPSILON),
+        p50_ms: percentile(0.5),
+        p95_ms: percentile(0.95),
+        p99_ms: percentile(0.99),
+        max_ms: percentile(1.0),
+        exact_qps: queries.len() as f64 / exact_elapsed.as_secs_f64().max(f64::EPSILON),
+    };
 
-    if query_embeddings.is_empty() {
-        query_spinner.finish_with_message(format!(
-            "{}✗{} Failed to generate embedding",
-            "[".red(),
-            "]".red()
-        ));
-        term.write_line(&format!(
-            "{}",
-            "Query embedding could not be generated. Try a longer query.".yellow()
-        ))?;
-        return Ok(());
+    if format.is_structured() {
+        return output::write_object(format, &report, |report| {
+            vec![
+                ("queries".to_string(), report.queries.to_string()),
+                ("top_k".to_string(), report.top_k.to_string()),
+                ("recall".to_string(), report.recall.to_string()),
+                ("qps".to_string(), report.qps.to_string()),
+                ("p50_ms".to_string(), report.p50_ms.to_string()),
+                ("p95_ms".to_string(), report.p95_ms.to_string()),
+                ("p99_ms".to_string(), report.p99_ms.to_string()),
+                ("max_ms".to_string(), report.max_ms.to_string()),
+                ("exact_qps".to_string(), report.exact_qps.to_string()),
+            ]
+        });
     }
-    query_spinner.finish_with_message(format!(
-        "{}✓{} Query embedding generated",
+
+    let lines = [
+        ("Queries".to_string(), report.queries.to_string()),
+        (format!("Recall@{}", report.top_k), format!("{:.4}", report.recall)),
+        ("Queries/sec".to_string(), format!("{:.1}", report.qps)),
+        (
+            "Latency".to_string(),
+            format!(
+                "p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+                report.p50_ms, report.p95_ms, report.p99_ms, report.max_ms
+            ),
+        ),
+        ("Exact queries/sec".to_string(), format!("{:.1}", report.exact_qps)),
+    ];
+    term.write_line("")?;
+    for (label, value) in lines {
+        term.write_line(&format!("  {} {}", format!("{}:", label).green(), value.bright_white()))?;
+    }
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct ProblemRow {
+    #[tabled(rename = "Layer")]
+    layer: String,
+    #[tabled(rename = "Node")]
+    id: String,
+    #[tabled(rename = "Problem")]
+    issue: String,
+}
+
+// Verify the graph and payloads agree. With `repair`, fix what was found,
+// save, and check again. Fails while problems remain, so scripts can tell.
+fn check_store(db_path: &str, repair: bool, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    if !Path::new(db_path).exists() {
+        anyhow::bail!("Vector store not found: {}", db_path);
+    }
+
+    let _writer = repair.then(|| lock_for_writing(db_path)).transpose()?;
+    let load_spinner = spinner("blue")?;
+    load_spinner.set_message("Loading vector store...");
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+        .context("Failed to load vector store")?;
+    load_spinner.finish_with_message(format!(
+        "{}✓{} Vector store loaded",
         "[".green(),
         "]".green()
     ));
 
-    // Display query
-    term.write_line("")?;
-    term.write_line(&format!(
-        "{} {}",
-        "Query:".blue().bold(),
-        query_text.bright_white()
-    ))?;
-
-    // Use the first embedding for the query
-    let search_spinner = ProgressBar::new_spinner();
-    search_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.cyan} {msg}")?,
-    );
-    search_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
-    search_spinner.set_message(format!("Searching for top {} matches...", top_k));
+    let check_spinner = spinner("cyan")?;
+    check_spinner.set_message("Checking index...");
+    let mut problems = store.check();
+    check_spinner.finish_and_clear();
 
-    let query_embedding = &query_embeddings[0];
-    let results = store.query(query_embedding, top_k)?;
-    search_spinner.finish_with_message(format!("{}✓{} Search complete", "[".green(), "]".green()));
+    let found = problems.len();
+    if repair && found > 0 {
+        let repair_spinner = spinner("yellow")?;
+        repair_spinner.set_message(format!("Repairing {} problems...", found));
+        let dropped = store.repair();
+        store.save(db_path).context("Failed to save vector store")?;
+        problems = store.check();
+        repair_spinner.finish_with_message(format!(
+            "{}✓{} Repaired {} problems",
+            "[".green(),
+            "]".green(),
+            found - problems.len()
+        ));
+        if dropped > 0 {
+            term.write_line(&format!(
+                "  {} Dropped {} documents with no vector; re-add their sources",
+                "[WARNING]".yellow().bold(),
+                dropped
+            ))?;
+        }
+    }
 
-    if results.is_empty() {
-        term.write_line(&format!("{}", "\nNo results found.".yellow().bold()))?;
+    if format.is_structured() {
+        output::write_rows(format, &problems, &["layer", "id", "issue"], |problem| {
+            vec![
+                problem.layer.map(|layer| layer.to_string()).unwrap_or_default(),
+                problem.id.to_string(),
+                problem.issue.clone(),
+            ]
+        })?;
+    } else if problems.is_empty() {
+        status(&term, &format!(
+            "{}✓{} No problems found in {} documents",
+            "[".green(),
+            "]".green(),
+            store.text_count()
+        ))?;
     } else {
-        term.write_line(&format!("{}", "\nResults:".green().bold()))?;
-
-        let table_results = results
+        let rows: Vec<ProblemRow> = problems
             .iter()
-            .enumerate()
-            .map(|(i, (text, score, filename))| QueryResult {
-                index: i + 1,
-                score: format!("{:.4}", score),
-                source: match filename {
-                    Some(f) => f.clone(),
-                    None => "Unknown".to_string(),
-                },
-                content: text.chars().take(100).collect::<String>() + "...",
+            .map(|problem| ProblemRow {
+                layer: problem
+                    .layer
+                    .map(|layer| layer.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                id: problem.id.to_string(),
+                issue: problem.issue.clone(),
             })
-            .collect::<Vec<_>>();
+            .collect();
+        let mut binding = Table::new(rows);
+        term.write_line(&format!("{}", binding.with(Style::modern().to_owned())))?;
+    }
+
+    if !problems.is_empty() {
+        if repair {
+            anyhow::bail!("{} problems remain after repair", problems.len());
+        }
+        anyhow::bail!(
+            "{} problems found; run `arrow fsck --repair` to fix them",
+            problems.len()
+        );
+    }
+    Ok(())
+}
 
-        let mut binding = Table::new(table_results);
-        let table = binding.with(Style::modern().to_owned());
+// Print what `doctor` found. Fails if any check did, so scripts can tell.
+fn run_doctor(
+    database: &str,
+    db_path: &str,
+    device: DeviceChoice,
+    model: &ModelSpec,
+    format: OutputFormat,
+) -> Result<()> {
+    let term = Term::stdout();
+    let check_spinner = spinner("cyan")?;
+    check_spinner.set_message("Checking environment...");
+    let findings = doctor::run(database, db_path, device, model);
+    check_spinner.finish_and_clear();
 
-        term.write_line(&format!("{}", table))?;
+    if format.is_structured() {
+        output::write_rows(format, &findings, &["check", "status", "detail", "fix"], |finding| {
+            vec![
+                finding.check.to_string(),
+                format!("{:?}", finding.status).to_lowercase(),
+                finding.detail.clone(),
+                finding.fix.clone().unwrap_or_default(),
+            ]
+        })?;
+    } else {
+        let width = findings.iter().map(|finding| finding.check.len()).max().unwrap_or(0);
+        for finding in &findings {
+            let mark = match finding.status {
+                doctor::Status::Ok => "✓".green(),
+                doctor::Status::Warning => "!".yellow(),
+                doctor::Status::Failed => "✗".red(),
+            };
+            term.write_line(&format!(
+                "[{}] {:width$}  {}",
+                mark,
+                finding.check.bold(),
+                finding.detail,
+                width = width
+            ))?;
+            if let Some(fix) = &finding.fix {
+                term.write_line(&format!("    {} {}", "Fix:".italic(), fix))?;
+            }
+        }
     }
 
+    let failed = findings
+        .iter()
+        .filter(|finding| finding.status == doctor::Status::Failed)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, findings.len());
+    }
     Ok(())
 }
 
-#[derive(Tabled)]
-struct Document {
-    #[tabled(rename = "#")]
-    index: usize,
-    #[tabled(rename = "ID")]
-    id: String,
-    #[tabled(rename = "Source")]
-    source: String,
-    #[tabled(rename = "Preview")]
-    preview: String,
+// A byte count in the largest binary unit that keeps it above 1
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
 }
 
-fn list_documents(db_path: &str, limit: usize) -> Result<()> {
+// Add documents whose vectors were computed elsewhere, such as by `export`.
+// Every vector is checked against the store's dimension before anything is
+// added, since the store can't tell which model produced them.
+fn import_documents(
+    db_path: &str,
+    input: &str,
+    file_format: FileFormat,
+    columns: &columnar::Columns,
+) -> Result<()> {
     let term = Term::stdout();
-    if !Path::new(db_path).exists() {
-        term.write_line(&format!("{}", "Vector store not found".red().bold()))?;
-        term.write_line(&format!("  Expected at: {}", db_path))?;
-        term.write_line(&format!(
-            "{}",
-            "Use 'create' command to create a new vector store".italic()
-        ))?;
-        return Ok(());
+    if !Path::new(db_path).exists() {
+        anyhow::bail!(
+            "Vector store not found: {}; create it first with the model that produced the vectors",
+            db_path
+        );
     }
 
-    // Show banner
-    term.write_line(&format!(
-        "{}",
-        "Arrow Vector Database".bright_green().bold()
-    ))?;
-    term.write_line("")?;
+    let records = match file_format {
+        FileFormat::Jsonl if input == STDIN_PATH => dump::read(std::io::stdin().lock())?,
+        FileFormat::Jsonl => {
+            let file = fs::File::open(input)
+                .with_context(|| format!("Failed to open import file: {}", input))?;
+            dump::read(std::io::BufReader::new(file))
+                .with_context(|| format!("Invalid import file: {}", input))?
+        }
+        // Arrow and Parquet readers seek, so need a file
+        _ if input == STDIN_PATH => {
+            anyhow::bail!("Arrow and Parquet imports must be read from a file, not stdin")
+        }
+        FileFormat::Arrow => columnar::read_ipc(Path::new(input), columns)
+            .with_context(|| format!("Invalid import file: {}", input))?,
+        FileFormat::Parquet => columnar::read_parquet(Path::new(input), columns)
+            .with_context(|| format!("Invalid import file: {}", input))?,
+    };
 
-    // Load vector store
-    let load_spinner = ProgressBar::new_spinner();
-    load_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.blue} {msg}")?,
-    );
-    load_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    let _writer = lock_for_writing(db_path)?;
+    let load_spinner = spinner("blue")?;
     load_spinner.set_message("Loading vector store...");
-
-    let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
+    let mut store = vectorstore::VectorStore::load(db_path, Device::Cpu)
         .context("Failed to load vector store")?;
     load_spinner.finish_with_message(format!(
         "{}✓{} Vector store loaded",
@@ -491,65 +6218,130 @@ fn list_documents(db_path: &str, limit: usize) -> Result<()> {
         "]".green()
     ));
 
-    let ids = store.get_all_ids();
-    if ids.is_empty() {
-        term.write_line(&format!("{}", "\nVector store is empty".yellow().bold()))?;
-        term.write_line(&format!(
-            "{}",
-            "Use 'add' command to add documents".italic()
-        ))?;
-        return Ok(());
+    // The store's vectors, else the model's known size, else the first record
+    let expected = store
+        .dimension()
+        .or_else(|| store.model().dimension())
+        .or_else(|| records.first().and_then(|record| record.vector.as_ref()).map(Vec::len));
+    let mut node_ids = HashSet::new();
+    for (i, record) in records.iter().enumerate() {
+        let vector = record
+            .vector
+            .as_ref()
+            .with_context(|| format!("Record {} has no vector", i + 1))?;
+        for (name, vector) in std::iter::once(("vector", vector))
+            .chain(record.fields.iter().map(|(name, vector)| (name.as_str(), vector)))
+        {
+            let what = match name {
+                "vector" => name.to_string(),
+                _ => format!("'{}' field", name),
+            };
+            if Some(vector.len()) != expected {
+                anyhow::bail!(
+                    "Record {} has a {}-dimensional {}, but {} holds {}-dimensional vectors ({})",
+                    i + 1,
+                    vector.len(),
+                    what,
+                    db_path,
+                    expected.unwrap_or_default(),
+                    store.model()
+                );
+            }
+            // NaN and infinities can't be ranked against other distances
+            if let Some(position) = vector.iter().position(|x| !x.is_finite()) {
+                anyhow::bail!(
+                    "Record {} has a non-finite {} in its {} at position {}",
+                    i + 1,
+                    vector[position],
+                    what,
+                    position
+                );
+            }
+        }
+        if let Some(id) = record.node_id() {
+            if !node_ids.insert(id) {
+                anyhow::bail!("Record {} repeats id {}", i + 1, id);
+            }
+        }
     }
 
-    term.write_line("")?;
-    term.write_line(&format!(
-        "{} {} {} {}",
-        "Documents in the vector store".blue().bold(),
-        format!(
-            "(showing {} of {})",
-            std::cmp::min(limit, ids.len()),
-            ids.len()
-        )
-        .bright_black(),
-        "db:".blue().bold(),
-        db_path.bright_white()
-    ))?;
+    let import_spinner = spinner("yellow")?;
+    import_spinner.set_message(format!("Importing {} documents...", records.len()));
 
-    let mut documents = Vec::new();
+    // Records sharing a document ID are upserted together, as its chunks
+    let mut documents: Vec<(String, Vec<vectorstore::Document>)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut batch = Vec::new();
+    let mut replaced = 0;
+    for record in records {
+        let vector = record.vector.as_deref().unwrap_or_default();
+        let mut document =
+            vectorstore::Document::new(store.vector_to_tensor(vector)?, record.text.clone())
+                .with_metadata(record.metadata.clone())
+                .with_expiry(record.expires);
+        if let Some(filename) = &record.filename {
+            document = document.with_filename(filename.clone());
+        }
+        if let Some(span) = record.span {
+            document = document.with_span(span);
+        }
+        if let Some(added) = record.added {
+            document = document.with_timestamps(added, record.updated);
+        }
+        for (name, vector) in &record.fields {
+            document = document.with_field(name.clone(), store.vector_to_tensor(vector)?);
+        }
+        if let Some(id) = record.node_id() {
+            // Importing a document again replaces it
+            if store.remove(&id) {
+                replaced += 1;
+            }
+            document = document.with_id(id);
+        }
 
-    for (i, id) in ids.iter().enumerate().take(limit) {
-        if let Some((text, filename)) = store.get_embedding(id) {
-            documents.push(Document {
-                index: i + 1,
-                id: id.to_string().chars().take(8).collect::<String>() + "...",
-                source: match filename {
-                    Some(f) => f.clone(),
-                    None => "Unknown".to_string(),
-                },
-                preview: text.chars().take(60).collect::<String>() + "...",
-            });
+        match record.document_id() {
+            Some(document_id) => match positions.get(document_id) {
+                Some(&position) => documents[position].1.push(document),
+                None => {
+                    positions.insert(document_id.to_string(), documents.len());
+                    documents.push((document_id.to_string(), vec![document]));
+                }
+            },
+            None => batch.push(document),
         }
     }
 
-    let mut binding = Table::new(documents);
-    let table = binding.with(Style::modern().to_owned());
-
-    term.write_line(&format!("{}", table))?;
-
+    let mut added = store.add_batch(batch)?.len();
+    for (document_id, chunks) in documents {
+        added += store
+            .upsert_chunks(&document_id, chunks, vectorstore::Metadata::new())?
+            .len();
+    }
+    store.save(db_path).context("Failed to save vector store")?;
+    import_spinner.finish_with_message(format!(
+        "{}✓{} Imported {} documents into {}",
+        "[".green(),
+        "]".green(),
+        added.to_string().bright_white(),
+        db_path.bright_blue()
+    ));
+    if replaced > 0 {
+        status(&term, &format!(
+            "  {} {} {}",
+            "Replaced".green(),
+            replaced.to_string().bright_white(),
+            "existing documents"
+        ))?;
+    }
     Ok(())
 }
 
-#[derive(Tabled)]
-struct SourceFile {
-    #[tabled(rename = "#")]
-    index: usize,
-    #[tabled(rename = "File")]
-    filename: String,
-}
-
-fn show_info(db_path: &str) -> Result<()> {
+fn show_info(db_path: &str, format: OutputFormat) -> Result<()> {
     let term = Term::stdout();
     if !Path::new(db_path).exists() {
+        if format.is_structured() {
+            anyhow::bail!("Vector store not found: {}", db_path);
+        }
         term.write_line(&format!("{}", "Vector store not found".red().bold()))?;
         term.write_line(&format!("  Expected at: {}", db_path))?;
         term.write_line(&format!(
@@ -560,20 +6352,16 @@ fn show_info(db_path: &str) -> Result<()> {
     }
 
     // Show banner
-    term.write_line(&format!(
-        "{}",
-        "Arrow Vector Database".bright_green().bold()
-    ))?;
-    term.write_line("")?;
+    if !format.is_structured() {
+        status(&term, &format!(
+            "{}",
+            "Arrow Vector Database".bright_green().bold()
+        ))?;
+        status(&term, "")?;
+    }
 
     // Load vector store
-    let load_spinner = ProgressBar::new_spinner();
-    load_spinner.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"])
-            .template("{spinner:.blue} {msg}")?,
-    );
-    load_spinner.enable_steady_tick(std::time::Duration::from_millis(100));
+    let load_spinner = spinner("blue")?;
     load_spinner.set_message("Loading vector store...");
 
     let store = vectorstore::VectorStore::load(db_path, Device::Cpu)
@@ -584,6 +6372,91 @@ fn show_info(db_path: &str) -> Result<()> {
         "]".green()
     ));
 
+    // Get source file stats
+    let ids = store.get_all_ids();
+    let mut unique_files = HashSet::new();
+
+    for id in &ids {
+        if let Some(provenance) = store.provenance(id) {
+            unique_files.insert(provenance.source_path.clone());
+        }
+    }
+
+    let cached_embeddings = open_cache(db_path, &store).len();
+    let languages = language_histogram(&store);
+
+    if format.is_structured() {
+        let mut sources: Vec<String> = unique_files.into_iter().collect();
+        sources.sort();
+        let info = StoreInfo {
+            location: db_path,
+            documents: store.text_count(),
+            storage: store.storage_mode(),
+            index: store.index_kind(),
+            nlist: (store.index_kind() == IndexKind::Ivf).then(|| store.nlist()),
+            mmap: store.uses_mmap_vectors(),
+            backend: store.engine(),
+            encrypted: store.is_encrypted(),
+            model: store.model().to_string(),
+            prompts: store.prompts().clone(),
+            vector_fields: store.field_names(),
+            backups: store.backups(),
+            max_documents: store.quota().max_documents,
+            max_bytes: store.quota().max_bytes,
+            pq_subspaces: store.pq_subspaces(),
+            binary: store.is_binary(),
+            cached_embeddings,
+            languages: languages
+                .into_iter()
+                .map(|(language, documents)| LanguageCount {
+                    language: language.unwrap_or_else(|| "und".to_string()),
+                    documents,
+                })
+                .collect(),
+            sources,
+        };
+        return output::write_object(format, &info, |info| {
+            let mut fields = vec![
+                ("location".to_string(), info.location.to_string()),
+                ("documents".to_string(), info.documents.to_string()),
+                ("storage".to_string(), format!("{:?}", info.storage).to_lowercase()),
+                ("index".to_string(), format!("{:?}", info.index).to_lowercase()),
+            ];
+            if let Some(nlist) = info.nlist {
+                fields.push(("nlist".to_string(), nlist.to_string()));
+            }
+            fields.extend([
+                ("mmap".to_string(), info.mmap.to_string()),
+                ("backend".to_string(), format!("{:?}", info.backend).to_lowercase()),
+                ("encrypted".to_string(), info.encrypted.to_string()),
+                ("model".to_string(), info.model.clone()),
+                ("query_prompt".to_string(), info.prompts.query.clone()),
+                ("passage_prompt".to_string(), info.prompts.passage.clone()),
+                ("vector_fields".to_string(), info.vector_fields.join(",")),
+                ("backups".to_string(), info.backups.to_string()),
+            ]);
+            if let Some(max) = info.max_documents {
+                fields.push(("max_documents".to_string(), max.to_string()));
+            }
+            if let Some(max) = info.max_bytes {
+                fields.push(("max_bytes".to_string(), max.to_string()));
+            }
+            if let Some(subspaces) = info.pq_subspaces {
+                fields.push(("pq_subspaces".to_string(), subspaces.to_string()));
+            }
+            fields.push(("binary".to_string(), info.binary.to_string()));
+            fields.push(("cached_embeddings".to_string(), info.cached_embeddings.to_string()));
+            for count in &info.languages {
+                let name = format!("language.{}", count.language);
+                fields.push((name, count.documents.to_string()));
+            }
+            for source in &info.sources {
+                fields.push(("source".to_string(), source.clone()));
+            }
+            fields
+        });
+    }
+
     // Display info box
     term.write_line("")?;
     let border = "╔═══════════════════════════════════════════╗".bright_blue();
@@ -627,18 +6500,137 @@ fn show_info(db_path: &str) -> Result<()> {
         ),
         "║".bright_blue()
     ))?;
-
-    // Get source file stats
-    let ids = store.get_all_ids();
-    let mut unique_files = std::collections::HashSet::new();
-
-    for id in &ids {
-        if let Some((_, Some(filename))) = store.get_embedding(id) {
-            if let Some(base_filename) = filename.split('#').next() {
-                unique_files.insert(base_filename.to_string());
-            }
+    term.write_line(&format!(
+        "{} {:<40} {}",
+        "║".bright_blue(),
+        format!(
+            "  {}: {}",
+            "Storage".green(),
+            format!(
+                "{:?}{}",
+                store.storage_mode(),
+                if store.uses_mmap_vectors() { " (mmap)" } else { "" }
+            )
+            .to_lowercase()
+            .bright_white()
+        ),
+        "║".bright_blue()
+    ))?;
+    let index = match store.index_kind() {
+        IndexKind::Hnsw => "hnsw".to_string(),
+        IndexKind::Ivf if store.is_ivf_trained() => format!("ivf ({} lists)", store.nlist()),
+        IndexKind::Ivf => format!("ivf ({} lists, untrained)", store.nlist()),
+        IndexKind::Flat => "flat (exact scan)".to_string(),
+    };
+    term.write_line(&format!(
+        "{} {:<40} {}",
+        "║".bright_blue(),
+        format!("  {}: {}", "Index".green(), index.bright_white()),
+        "║".bright_blue()
+    ))?;
+    term.write_line(&format!(
+        "{} {:<40} {}",
+        "║".bright_blue(),
+        format!(
+            "  {}: {}",
+            "Backend".green(),
+            format!(
+                "{:?}{}",
+                store.engine(),
+                if store.is_encrypted() { " (encrypted)" } else { "" }
+            )
+            .to_lowercase()
+            .bright_white()
+        ),
+        "║".bright_blue()
+    ))?;
+    term.write_line(&format!(
+        "{} {:<40} {}",
+        "║".bright_blue(),
+        format!(
+            "  {}: {}",
+            "Model".green(),
+            store.model().to_string().bright_white()
+        ),
+        "║".bright_blue()
+    ))?;
+    let fields = store.field_names();
+    if !fields.is_empty() {
+        term.write_line(&format!(
+            "{} {:<40} {}",
+            "║".bright_blue(),
+            format!("  {}: {}", "Vector fields".green(), fields.join(", ").bright_white()),
+            "║".bright_blue()
+        ))?;
+    }
+    for (label, prompt) in [
+        ("Query prompt", &store.prompts().query),
+        ("Passage prompt", &store.prompts().passage),
+    ] {
+        if !prompt.is_empty() {
+            term.write_line(&format!(
+                "{} {:<40} {}",
+                "║".bright_blue(),
+                format!("  {}: {:?}", label.green(), prompt),
+                "║".bright_blue()
+            ))?;
         }
     }
+    term.write_line(&format!(
+        "{} {:<40} {}",
+        "║".bright_blue(),
+        format!(
+            "  {}: {}",
+            "Backups kept".green(),
+            store.backups().to_string().bright_white()
+        ),
+        "║".bright_blue()
+    ))?;
+    let quota = store.quota();
+    let limits = [
+        ("Max documents", quota.max_documents.map(|max| max.to_string())),
+        ("Max disk space", quota.max_bytes.map(format_bytes)),
+    ];
+    for (label, limit) in limits {
+        if let Some(limit) = limit {
+            term.write_line(&format!(
+                "{} {:<40} {}",
+                "║".bright_blue(),
+                format!("  {}: {}", label.green(), limit.bright_white()),
+                "║".bright_blue()
+            ))?;
+        }
+    }
+    term.write_line(&format!(
+        "{} {:<40} {}",
+        "║".bright_blue(),
+        format!(
+            "  {}: {}",
+            "Cached embeddings".green(),
+            cached_embeddings.to_string().bright_white()
+        ),
+        "║".bright_blue()
+    ))?;
+    if let Some(subspaces) = store.pq_subspaces() {
+        term.write_line(&format!(
+            "{} {:<40} {}",
+            "║".bright_blue(),
+            format!(
+                "  {}: {} subspaces",
+                "PQ codebooks".green(),
+                subspaces.to_string().bright_white()
+            ),
+            "║".bright_blue()
+        ))?;
+    }
+    if store.is_binary() {
+        term.write_line(&format!(
+            "{} {:<40} {}",
+            "║".bright_blue(),
+            format!("  {}: {}", "Binary codes".green(), "Hamming pre-filter".bright_white()),
+            "║".bright_blue()
+        ))?;
+    }
 
     term.write_line(&format!(
         "{} {:<40} {}",
@@ -677,5 +6669,354 @@ fn show_info(db_path: &str) -> Result<()> {
         term.write_line(&format!("{}", table))?;
     }
 
+    if !languages.is_empty() {
+        let total = store.text_count().max(1) as f32;
+        let rows: Vec<LanguageRow> = languages
+            .into_iter()
+            .map(|(code, documents)| {
+                let share = documents as f32 / total;
+                LanguageRow {
+                    language: match code {
+                        Some(code) => match language::name(&code) {
+                            Some(name) => format!("{} ({})", name, code),
+                            None => code,
+                        },
+                        None => "Unknown".to_string(),
+                    },
+                    documents,
+                    share: format!(
+                        "{:<20} {:>5.1}%",
+                        "█".repeat((share * 20.0).round() as usize),
+                        share * 100.0
+                    ),
+                }
+            })
+            .collect();
+        term.write_line("")?;
+        term.write_line(&format!("{}", "Languages:".blue().bold()))?;
+        term.write_line(&format!("{}", Table::new(rows).with(Style::psql())))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Tabled)]
+struct PastQuery {
+    #[tabled(rename = "#")]
+    index: usize,
+    #[tabled(rename = "When")]
+    when: String,
+    #[tabled(rename = "Query")]
+    query: String,
+    #[tabled(rename = "Top result")]
+    top_result: String,
+    #[tabled(rename = "Score")]
+    score: String,
+}
+
+fn serve(database: &str, addr: &str, options: StoreOptions) -> Result<()> {
+    let term = Term::stdout();
+    if options.encrypt {
+        // Ask now, not when a client first creates a collection
+        crypto::secret(Path::new(database), true)?;
+    }
+    let new_store = Box::new(move |path: &str| new_vector_store(path, &options));
+    let server = server::Server::bind(addr, database, new_store)?;
+    let url = match server.addr() {
+        Some(addr) => format!("http://{}", addr),
+        None => addr.to_string(),
+    };
+    status(&term, &format!(
+        "{}✓{} Serving {} at {}",
+        "[".green(),
+        "]".green(),
+        database.bright_blue(),
+        url.bright_white()
+    ))?;
+    status(&term, &format!("  {}", "Point Qdrant clients at this URL; Ctrl-C stops".italic()))?;
+    server.run();
+    Ok(())
+}
+
+fn serve_mcp(db_path: &str, ingest: IngestOptions, backend: &device::Backend) -> Result<()> {
+    Term::stderr().write_line(&format!("Serving {} over MCP on stdio", db_path))?;
+    let mut tools = McpTools {
+        db_path,
+        ingest,
+        backend,
+        embeddor: None,
+    };
+    mcp::serve(&mut tools, std::io::stdin().lock(), std::io::stdout().lock())
+        .context("MCP connection failed")
+}
+
+// The MCP tools over a store. The store is loaded for every call, so changes
+// made by other arrow processes show up; the embedding model is loaded on
+// first use and kept.
+struct McpTools<'a> {
+    db_path: &'a str,
+    ingest: IngestOptions,
+    backend: &'a device::Backend,
+    embeddor: Option<embedding::Embeddor>,
+}
+
+impl McpTools<'_> {
+    fn load(&self) -> Result<vectorstore::VectorStore> {
+        if !Path::new(self.db_path).exists() {
+            anyhow::bail!("Vector store not found: {}", self.db_path);
+        }
+        vectorstore::VectorStore::load(self.db_path, self.backend.candle.clone())
+            .context("Failed to load vector store")
+    }
+
+    fn embeddor(&mut self, store: &vectorstore::VectorStore) -> Result<&embedding::Embeddor> {
+        if self.embeddor.is_none() {
+            let embeddor = embedding::Embeddor::new(store.model(), self.backend)?
+                .with_cache(open_cache(self.db_path, store))
+                .with_dimension(store.dimension())
+                .with_prompts(store.prompts().clone());
+            self.embeddor = Some(embeddor);
+        }
+        Ok(self.embeddor.as_ref().unwrap())
+    }
+}
+
+impl mcp::Tools for McpTools<'_> {
+    fn search_documents(&mut self, query: &str, top_k: usize) -> Result<serde_json::Value> {
+        let store = self.load()?;
+        let embeddor = self.embeddor(&store)?;
+        let query_embedding = embeddor
+            .embed_query(query)
+            .context("Failed to generate query embedding")?
+            .into_iter()
+            .next()
+            .context("Query embedding could not be generated. Try a longer query.")?;
+        embeddor.save_cache().context("Failed to save embedding cache")?;
+
+        let results = store.query_with_options(&query_embedding, top_k, true)?;
+        let results: Vec<serde_json::Value> = results
+            .into_iter()
+            .map(|result| {
+                serde_json::json!({
+                    "text": result.text,
+                    "score": result.score.value,
+                    "source": result.filename,
+                    "metadata": result.metadata,
+                })
+            })
+            .collect();
+        Ok(serde_json::json!(results))
+    }
+
+    // Added like text piped to `add -`, under the given source name or one
+    // made from the text's hash, so adding the same text twice does nothing
+    fn add_document(&mut self, text: &str, source: Option<&str>) -> Result<serde_json::Value> {
+        let db_path = self.db_path;
+        let _writer = lock_for_writing(db_path)?;
+        let mut store = if Path::new(db_path).exists() {
+            self.load()?
+        } else {
+            let mut store =
+                vectorstore::VectorStore::new(self.backend.candle.clone(), DEFAULT_CONNECTIONS);
+            store.save(db_path).context("Failed to save vector store")?;
+            store
+        };
+        let source = match source {
+            Some(source) => source.to_string(),
+            None => format!("mcp-{}", &sources::hash_bytes(text.as_bytes())[..12]),
+        };
+        let (hash, mtime) = match changed_text(&store, &source, text) {
+            Some(change) => change,
+            None => {
+                return Ok(serde_json::json!({"source": source, "chunks": 0, "unchanged": true}))
+            }
+        };
+        let removed = store.remove_source(&source);
+
+        let ingest = self.ingest.clone();
+        let embeddor = self.embeddor(&store)?;
+        let loaded = loaders::Loaded::from_text(text.to_string());
+        let chunks = chunk_file(embeddor, Path::new(&source), &loaded, &ingest);
+        let texts: Vec<String> = chunks.iter().map(|chunk| chunk.text.clone()).collect();
+        let embeddings = embeddor.embed_chunks(&texts)?;
+        let batch = chunk_documents(&source, chunks, embeddings, ingest.expires());
+        let chunk_ids = store.add_batch(batch)?;
+        let added = chunk_ids.len();
+        store.record_source(source.clone(), hash, mtime, chunk_ids);
+        store.save(db_path).context("Failed to save vector store")?;
+        embeddor.save_cache().context("Failed to save embedding cache")?;
+        Ok(serde_json::json!({"source": source, "chunks": added, "replaced": removed}))
+    }
+
+    fn list_sources(&mut self) -> Result<serde_json::Value> {
+        let store = self.load()?;
+        let mut sources = store.source_paths();
+        sources.sort();
+        let sources: Vec<serde_json::Value> = sources
+            .into_iter()
+            .map(|source| {
+                let chunks = store.source_record(&source).map_or(0, |r| r.chunk_ids.len());
+                serde_json::json!({"source": source, "chunks": chunks})
+            })
+            .collect();
+        Ok(serde_json::json!(sources))
+    }
+}
+
+fn show_history(db_path: &str, limit: usize, clear: bool, format: OutputFormat) -> Result<()> {
+    let term = Term::stdout();
+    if clear {
+        history::clear(Path::new(db_path))?;
+        status(&term, &format!("{}✓{} Query history cleared", "[".green(), "]".green()))?;
+        return Ok(());
+    }
+
+    let entries = history::recent(Path::new(db_path), limit)?;
+    if format.is_structured() {
+        return output::write_rows(
+            format,
+            &entries,
+            &["timestamp", "query", "results", "top_source", "top_score"],
+            |entry| {
+                vec![
+                    entry.timestamp.to_string(),
+                    entry.query.clone(),
+                    entry.results.to_string(),
+                    entry.top_source.clone().unwrap_or_default(),
+                    entry.top_score.map(|score| score.to_string()).unwrap_or_default(),
+                ]
+            },
+        );
+    }
+    if entries.is_empty() {
+        term.write_line(&format!("{}", "No queries recorded".yellow().bold()))?;
+        return Ok(());
+    }
+
+    let rows = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| PastQuery {
+            index: i + 1,
+            when: time_ago(entry.timestamp),
+            query: entry.query.chars().take(60).collect(),
+            top_result: entry.top_source.clone().unwrap_or_else(|| "-".to_string()),
+            score: entry
+                .top_score
+                .map(|score| format!("{:.4}", score))
+                .unwrap_or_else(|| "-".to_string()),
+        })
+        .collect::<Vec<_>>();
+
+    let mut binding = Table::new(rows);
+    let table = binding.with(Style::modern().to_owned());
+    term.write_line(&format!("{}", table))?;
+
+    Ok(())
+}
+
+// Wrap the widest columns of a table at word boundaries so it fits the
+// terminal. Output that isn't to a terminal is left as it is.
+fn fit_to_terminal(table: &mut Table) -> &mut Table {
+    match Term::stdout().size_checked() {
+        Some((_, width)) if width > 0 => {
+            table.with(Width::wrap(width as usize).keep_words().priority::<PriorityMax>())
+        }
+        _ => table,
+    }
+}
+
+// How long ago a Unix timestamp was, in the largest whole unit
+fn time_ago(timestamp: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let seconds = now.saturating_sub(timestamp);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+#[derive(Tabled)]
+struct Setting {
+    #[tabled(rename = "Setting")]
+    key: String,
+    #[tabled(rename = "Value")]
+    value: String,
+    #[tabled(rename = "From")]
+    origin: String,
+}
+
+// Value used when a setting is given nowhere
+fn default_setting(key: &str) -> String {
+    match key {
+        "database" => DEFAULT_VECTOR_STORE.to_string(),
+        "provider" => Provider::default().name(),
+        "model" => "provider's default".to_string(),
+        "device" => DeviceChoice::default().to_string(),
+        "chunk_tokens" => "model's input limit".to_string(),
+        "llm" => llm::LlmProvider::default().name(),
+        "llm_model" => "LLM provider's default".to_string(),
+        _ => "0".to_string(),
+    }
+}
+
+fn configure(mut config: config::Config, command: ConfigCommands) -> Result<()> {
+    let term = Term::stdout();
+    match command {
+        ConfigCommands::Show => {
+            let rows = config::KEYS
+                .iter()
+                .map(|&key| {
+                    let env_var = format!("ARROW_{}", key.to_uppercase());
+                    let (value, origin) = match (std::env::var(&env_var), config.get(key)?) {
+                        (Ok(value), _) => (value, env_var),
+                        (Err(_), Some(value)) => (value, "config file".to_string()),
+                        (Err(_), None) => (default_setting(key), "default".to_string()),
+                    };
+                    Ok(Setting {
+                        key: key.to_string(),
+                        value,
+                        origin,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if let Some(path) = config::path() {
+                status(&term, &format!("{} {}", "Config file:".blue().bold(), path.display()))?;
+            }
+            let mut binding = Table::new(rows);
+            let table = binding.with(Style::modern().to_owned());
+            term.write_line(&format!("{}", table))?;
+        }
+        ConfigCommands::Get { key } => {
+            let value = config.get(&key)?.unwrap_or_else(|| default_setting(&key));
+            term.write_line(&value)?;
+        }
+        ConfigCommands::Set { key, value } => {
+            config.set(&key, Some(&value))?;
+            config.save()?;
+            status(&term, &format!(
+                "{}✓{} Set {} to {}",
+                "[".green(),
+                "]".green(),
+                key.bright_blue(),
+                value.bright_white()
+            ))?;
+        }
+        ConfigCommands::Unset { key } => {
+            config.set(&key, None)?;
+            config.save()?;
+            status(&term, &format!("{}✓{} Unset {}", "[".green(), "]".green(), key.bright_blue()))?;
+        }
+        ConfigCommands::Path => {
+            let path = config::path().context("Can't locate the config file; set ARROW_CONFIG or HOME")?;
+            term.write_line(&path.to_string_lossy())?;
+        }
+    }
     Ok(())
 }
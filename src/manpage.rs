@@ -0,0 +1,375 @@
+use clap::Command;
+use clap_mangen::Man;
+use std::io::Write;
+use std::path::Path;
+
+// Examples shown at the end of `--help` for each command, and as the EXAMPLES
+// section of its man page. Each starts with an `Examples:` heading, then a
+// comment line before each command.
+
+pub const ARROW: &str = "Examples:
+  # Create a store, add a folder to it and search it
+  arrow create
+  arrow add notes/
+  arrow query \"how do I rotate the API key?\"
+
+  # Work on another database file
+  arrow --database docs.db add ./docs";
+
+pub const CREATE: &str = "Examples:
+  # A store using the default local model
+  arrow create
+
+  # A store embedding with OpenAI, kept in SQLite
+  arrow --database docs.db create --provider openai --model text-embedding-3-small
+
+  # A store for documents in many languages
+  arrow create --provider onnx --model multilingual
+
+  # Half-precision vectors, for half the memory and disk
+  arrow --database notes.db create --storage f16
+
+  # An encrypted store with int8 vectors
+  arrow --database private.json create --encrypt --storage int8
+
+  # An IVF index, cheaper to update than the graph, searched 32 lists at a time
+  arrow --database logs.db create --index ivf --nlist 256
+  arrow --database logs.db --nprobe 32 query \"disk full\"
+
+  # No index: every query scans all the vectors, for exact results
+  arrow --database notes.json create --index flat
+
+  # A shared store that add refuses to grow past 50,000 documents or 1 GiB
+  arrow --database team.db create --max-documents 50000 --max-size 1G";
+
+pub const COLLECTIONS: &str = "Examples:
+  arrow collections list
+  arrow collections create code --model all-mpnet-base-v2
+  arrow --collection code add src/
+  arrow collections drop code";
+
+pub const COLLECTIONS_LIST: &str = "Examples:
+  arrow collections list
+  arrow collections list --names";
+
+pub const COLLECTIONS_CREATE: &str = "Examples:
+  arrow collections create code --model all-mpnet-base-v2
+  arrow collections create papers --provider openai --model text-embedding-3-small";
+
+pub const COLLECTIONS_DROP: &str = "Examples:
+  arrow collections drop code";
+
+pub const ADD: &str = "Examples:
+  # Add files and everything under a directory
+  arrow add document1.txt document2.txt notes/
+
+  # Add records of a JSONL file, keeping some fields as metadata
+  arrow add data.jsonl --text-field body --id-field doc_id --meta-fields title,url
+
+  # Add a document from stdin under a stable ID
+  curl -s https://example.com/page.md | arrow add - --source page.md --id page
+
+  # Save every 1000 chunks; if interrupted, the same command resumes
+  arrow add corpus/ --save-every 1000
+
+  # Stop at the first file that fails instead of reporting it and going on
+  arrow add corpus/ --fail-fast
+
+  # See what a large directory would add before embedding any of it
+  arrow add corpus/ --dry-run
+
+  # Support tickets that age out of results after 30 days
+  arrow add tickets.jsonl --id-field ticket_id --ttl 30d";
+
+pub const SYNC: &str = "Examples:
+  # Re-index changed files and drop chunks of deleted ones
+  arrow sync notes/
+
+  # Quietly, from cron
+  arrow --quiet sync notes/ docs/
+
+  # List what changed since the last sync without indexing it
+  arrow sync notes/ --dry-run";
+
+pub const WATCH: &str = "Examples:
+  arrow watch ./notes
+  arrow watch ./notes ./docs --debounce-ms 2000 --save-every 1m";
+
+pub const QUERY: &str = "Examples:
+  arrow query \"What is a monopoly business?\" --top-k 3
+
+  # Sources of the results, for scripts
+  arrow query \"error handling\" --format jsonl | jq -r .source
+
+  # Only documents from the last week, fused with keyword ranking
+  arrow query \"release checklist\" --since 7d --hybrid
+
+  # Only German and French documents
+  arrow query \"Kündigungsfrist\" --lang de,fr
+
+  # Only chunks of markdown files under docs/
+  arrow query \"rate limits\" --source \"docs/**/*.md\"
+
+  # The two best chunks of each of the five best-matching files
+  arrow query \"refund policy\" --group-by source --per-group 2
+
+  # Each match in its file, with five lines either side
+  arrow query \"retry backoff\" --context 5
+
+  # Every query in a file, embedded in one batch
+  arrow query --batch queries.txt --format jsonl --top-k 10 > results.jsonl";
+
+pub const QUANTIZE: &str = "Examples:
+  arrow quantize
+  arrow quantize --subspaces 96 --iterations 20
+
+  # Search by Hamming distance over 1-bit codes, re-ranking the best
+  arrow quantize --binary";
+
+pub const LIST: &str = "Examples:
+  arrow list --limit 20
+  arrow list --sort source --source notes/todo.md --offset 20 --limit 20
+  arrow list --since 2024-05-01 --until 2024-06-01
+
+  # Every chunk of a file, in order, with its whole text
+  arrow list --sort source --source notes/todo.md --full
+
+  # Each chunk's file and position, as fields
+  arrow list --format jsonl | jq -r '[.source_path, .chunk_index, .chunk_count] | @tsv'";
+
+pub const GET: &str = "Examples:
+  # A document by the start of its ID, as listed by `arrow list`
+  arrow get 3f2a9c
+  arrow get 3f2a9c --vector --format json";
+
+pub const INFO: &str = "Examples:
+  arrow info
+  arrow --collection code info --format json";
+
+pub const STATS: &str = "Examples:
+  arrow stats
+  arrow stats --format json
+
+  # The next page of source files
+  arrow stats --limit 50 --offset 50";
+
+pub const FSCK: &str = "Examples:
+  arrow fsck
+  arrow fsck --repair";
+
+pub const REINDEX: &str = "Examples:
+  arrow reindex
+  arrow reindex --max-connections 32
+
+  # Retrain the lists of an IVF store after it has grown
+  arrow --database logs.db reindex";
+
+pub const VACUUM: &str = "Examples:
+  arrow vacuum
+  arrow vacuum --threshold 0.05
+  arrow vacuum --force";
+
+pub const COMPACT: &str = "Examples:
+  arrow compact";
+
+pub const QUOTA: &str = "Examples:
+  # How much of its limits the store uses
+  arrow quota
+
+  # Keep a shared store under 100,000 documents and 2 GiB on disk
+  arrow quota --max-documents 100000 --max-size 2G
+
+  # Remove the document limit
+  arrow quota --max-documents none";
+
+pub const SNAPSHOT: &str = "Examples:
+  # Try a change, and roll back if results get worse
+  arrow snapshot create before-dedupe
+  arrow dedupe --threshold 0.95
+  arrow snapshot restore before-dedupe";
+
+pub const SNAPSHOT_CREATE: &str = "Examples:
+  arrow snapshot create v1
+  arrow snapshot create v1 --force";
+
+pub const SNAPSHOT_RESTORE: &str = "Examples:
+  arrow snapshot restore v1";
+
+pub const SNAPSHOT_LIST: &str = "Examples:
+  arrow snapshot list
+  arrow snapshot list --format json";
+
+pub const SNAPSHOT_DELETE: &str = "Examples:
+  arrow snapshot delete v1";
+
+pub const DIFF: &str = "Examples:
+  # What a reindex or migration changed, against a snapshot taken before it
+  arrow snapshot create before
+  arrow reindex --max-connections 32
+  arrow diff before
+
+  # Two store files
+  arrow diff old.json new.db --format jsonl";
+
+pub const DEDUPE: &str = "Examples:
+  # See what would be removed first
+  arrow dedupe --threshold 0.98 --dry-run
+  arrow dedupe --threshold 0.98";
+
+pub const CLUSTER: &str = "Examples:
+  arrow cluster -k 10 --dry-run
+  arrow cluster -k 10 --key topic";
+
+pub const BENCHMARK: &str = "Examples:
+  arrow benchmark
+  arrow benchmark --queries 200 --top-k 20
+  arrow benchmark --query-file queries.txt";
+
+pub const EXPORT: &str = "Examples:
+  arrow export --output dump.jsonl --include-vectors
+  arrow export --output docs.parquet
+  arrow export --output - | gzip > dump.jsonl.gz";
+
+pub const IMPORT: &str = "Examples:
+  arrow import embeddings.jsonl
+  arrow import embeddings.parquet --vector-col embedding --text-col text";
+
+pub const ASK: &str = "Examples:
+  arrow ask \"how do I rotate the API key?\"
+  arrow ask \"what changed in the 2.0 release?\" --llm ollama --llm-model qwen2.5";
+
+pub const SERVE: &str = "Examples:
+  arrow serve
+  arrow serve --host 0.0.0.0 --port 8080 --provider openai --model text-embedding-3-small";
+
+pub const MCP: &str = "Examples:
+  # Command for an MCP client to launch
+  arrow --database /home/me/notes.json mcp";
+
+pub const MODEL: &str = "Examples:
+  # Fetch the model while building an image, so containers start offline
+  arrow --model-cache /opt/models model download --provider candle
+  arrow --model-cache /opt/models model list
+  arrow model remove sentence-transformers/all-MiniLM-L6-v2";
+
+pub const MODEL_DOWNLOAD: &str = "Examples:
+  arrow model download
+  arrow model download BAAI/bge-small-en-v1.5 --provider candle
+  ARROW_MODEL_CACHE=/opt/models arrow model download --provider onnx";
+
+pub const MODEL_LIST: &str = "Examples:
+  arrow model list
+  arrow model list --format json";
+
+pub const MODEL_REMOVE: &str = "Examples:
+  arrow model remove BAAI/bge-small-en-v1.5";
+
+pub const DOCTOR: &str = "Examples:
+  arrow doctor
+  arrow --database docs.db --device cuda doctor
+
+  # Only the checks that need attention
+  arrow doctor --format jsonl | jq -c 'select(.status != \"ok\")'";
+
+pub const HISTORY: &str = "Examples:
+  arrow history
+  arrow history --limit 50
+  arrow history --clear";
+
+pub const CONFIG: &str = "Examples:
+  arrow config show
+  arrow config set database ~/notes.json
+  arrow config get provider
+  arrow config unset model";
+
+pub const CONFIG_SHOW: &str = "Examples:
+  arrow config show";
+
+pub const CONFIG_GET: &str = "Examples:
+  arrow config get database";
+
+pub const CONFIG_SET: &str = "Examples:
+  arrow config set provider ollama
+  arrow config set chunk_tokens 256";
+
+pub const CONFIG_UNSET: &str = "Examples:
+  arrow config unset chunk_tokens";
+
+pub const CONFIG_PATH: &str = "Examples:
+  $EDITOR \"$(arrow config path)\"";
+
+pub const COMPLETIONS: &str = "Examples:
+  arrow completions bash > ~/.local/share/bash-completion/completions/arrow
+  arrow completions zsh > \"${fpath[1]}/_arrow\"
+  arrow completions fish > ~/.config/fish/completions/arrow.fish";
+
+pub const MANPAGE: &str = "Examples:
+  # Read the page for a command without installing
+  arrow manpage | man -l -
+
+  # Install a page for every command
+  arrow manpage --dir ~/.local/share/man/man1";
+
+// Write the man page of the top-level command
+pub fn render(command: Command, out: &mut dyn Write) -> std::io::Result<()> {
+    let mut command = command.disable_help_subcommand(true);
+    command.build();
+    render_page(&command, out)
+}
+
+// Write `arrow.1` and `arrow-<command>.1` for every subcommand into `dir`,
+// returning the paths written
+pub fn generate_to(command: Command, dir: &Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut command = command.disable_help_subcommand(true);
+    command.build();
+    let mut written = Vec::new();
+    let mut pending = vec![command];
+    while let Some(command) = pending.pop() {
+        pending.extend(command.get_subcommands().filter(|s| !s.is_hide_set()).cloned());
+        let path = dir.join(Man::new(command.clone()).get_filename());
+        let mut file = std::fs::File::create(&path)?;
+        render_page(&command, &mut file)?;
+        file.flush()?;
+        written.push(path);
+    }
+    written.sort();
+    Ok(written)
+}
+
+// clap_mangen's page, with the examples as their own section instead of run
+// together as a paragraph
+fn render_page(command: &Command, out: &mut dyn Write) -> std::io::Result<()> {
+    let man = Man::new(command.clone());
+    man.render_title(out)?;
+    man.render_name_section(out)?;
+    man.render_synopsis_section(out)?;
+    man.render_description_section(out)?;
+    if command.get_arguments().any(|arg| !arg.is_hide_set()) {
+        man.render_options_section(out)?;
+    }
+    if command.has_subcommands() {
+        man.render_subcommands_section(out)?;
+    }
+    if let Some(examples) = command.get_after_long_help() {
+        render_examples(&examples.to_string(), out)?;
+    }
+    if command.get_version().is_some() {
+        man.render_version_section(out)?;
+    }
+    if command.get_author().is_some() {
+        man.render_authors_section(out)?;
+    }
+    Ok(())
+}
+
+fn render_examples(examples: &str, out: &mut dyn Write) -> std::io::Result<()> {
+    writeln!(out, ".SH EXAMPLES")?;
+    writeln!(out, ".nf")?;
+    // Past the `Examples:` heading, unindented; `\&` keeps a line from being
+    // read as a request
+    for line in examples.lines().skip(1) {
+        let line = line.trim_start().replace('\\', "\\e").replace('-', "\\-");
+        writeln!(out, "\\&{}", line)?;
+    }
+    writeln!(out, ".fi")
+}
@@ -0,0 +1,102 @@
+// A byte range of a markdown document holding one heading's text, or one
+// fenced code block, along with the headings it sits under
+pub struct Section {
+    pub start: usize,
+    pub end: usize,
+    pub headings: Vec<String>,
+}
+
+impl Section {
+    // Heading path such as `Install > Linux`
+    pub fn heading_path(&self) -> String {
+        self.headings.join(" > ")
+    }
+}
+
+// Split a markdown document along its heading hierarchy, keeping each fenced
+// code block in a section of its own. Headings inside code blocks are ignored.
+pub fn sections(text: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut fence: Option<&str> = None;
+    let mut section_start = 0;
+    let mut line_start = 0;
+
+    for line in text.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        let trimmed = line.trim_start();
+
+        match fence {
+            Some(marker) => {
+                if trimmed.trim_end().starts_with(marker) {
+                    push_section(&mut sections, text, section_start, line_end, &headings);
+                    section_start = line_end;
+                    fence = None;
+                }
+            }
+            None => {
+                if let Some(marker) = fence_marker(trimmed) {
+                    push_section(&mut sections, text, section_start, line_start, &headings);
+                    section_start = line_start;
+                    fence = Some(marker);
+                } else if let Some((level, title)) = heading(line) {
+                    push_section(&mut sections, text, section_start, line_start, &headings);
+                    section_start = line_start;
+                    while headings.last().is_some_and(|(last, _)| *last >= level) {
+                        headings.pop();
+                    }
+                    headings.push((level, title));
+                }
+            }
+        }
+        line_start = line_end;
+    }
+    push_section(&mut sections, text, section_start, text.len(), &headings);
+    sections
+}
+
+fn push_section(
+    sections: &mut Vec<Section>,
+    text: &str,
+    start: usize,
+    end: usize,
+    headings: &[(usize, String)],
+) {
+    if text[start..end].trim().is_empty() {
+        return;
+    }
+    sections.push(Section {
+        start,
+        end,
+        headings: headings.iter().map(|(_, title)| title.clone()).collect(),
+    });
+}
+
+fn fence_marker(line: &str) -> Option<&'static str> {
+    if line.starts_with("```") {
+        Some("```")
+    } else if line.starts_with("~~~") {
+        Some("~~~")
+    } else {
+        None
+    }
+}
+
+// Level and title of an ATX heading (`## Title`), indented by at most 3 spaces
+fn heading(line: &str) -> Option<(usize, String)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let line = line.trim();
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    let title = rest.trim().trim_end_matches('#').trim_end();
+    Some((level, title.to_string()))
+}
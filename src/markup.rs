@@ -0,0 +1,257 @@
+// Conversion of HTML and Office XML into plain text with markdown-style
+// headings, so the markdown chunker can split it by section
+
+// Elements whose content is navigation, scripting or other boilerplate
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "head", "script", "style", "noscript", "nav", "footer", "aside", "form", "svg",
+    "template", "iframe", "button",
+];
+
+// Elements that start a new paragraph
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "section", "article", "main", "li", "ul", "ol", "dl", "dt", "dd", "tr",
+    "table", "blockquote", "pre", "figcaption", "hr",
+];
+
+enum Token<'a> {
+    Text(&'a str),
+    Tag {
+        name: String,
+        closing: bool,
+        self_closing: bool,
+        attributes: &'a str,
+    },
+}
+
+// Split markup into tags and the text between them, dropping comments,
+// doctypes and processing instructions
+fn tokenize(markup: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = markup;
+    while !rest.is_empty() {
+        let open = match rest.find('<') {
+            Some(open) => open,
+            None => {
+                tokens.push(Token::Text(rest));
+                break;
+            }
+        };
+        if open > 0 {
+            tokens.push(Token::Text(&rest[..open]));
+        }
+        rest = &rest[open..];
+
+        let end_marker = if rest.starts_with("<!--") { "-->" } else { ">" };
+        let close = match rest.find(end_marker) {
+            Some(close) => close + end_marker.len(),
+            None => break,
+        };
+        let tag = &rest[1..close - 1];
+        rest = &rest[close..];
+        if tag.starts_with('!') || tag.starts_with('?') {
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let tag = tag.trim_start_matches('/');
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        tokens.push(Token::Tag {
+            name: tag[..name_end].to_lowercase(),
+            closing,
+            self_closing,
+            attributes: &tag[name_end..],
+        });
+    }
+    tokens
+}
+
+// Readable text of an HTML page: headings become `#` lines and paragraphs are
+// separated by blank lines, while scripts, styles and navigation are dropped
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut skipping: Option<(String, usize)> = None;
+    let mut preformatted = false;
+
+    for token in tokenize(html) {
+        match token {
+            Token::Tag {
+                name,
+                closing,
+                self_closing,
+                ..
+            } => {
+                if let Some((skipped, depth)) = &mut skipping {
+                    if name == *skipped && !self_closing {
+                        if closing {
+                            *depth -= 1;
+                        } else {
+                            *depth += 1;
+                        }
+                        if *depth == 0 {
+                            skipping = None;
+                        }
+                    }
+                    continue;
+                }
+
+                if SKIPPED_ELEMENTS.contains(&name.as_str()) && !closing && !self_closing {
+                    skipping = Some((name, 1));
+                } else if let Some(level) = heading_level(&name) {
+                    paragraph_break(&mut out);
+                    if !closing {
+                        out.push_str(&"#".repeat(level));
+                        out.push(' ');
+                    }
+                } else if name == "br" {
+                    out.push('\n');
+                } else if BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    if name == "pre" {
+                        preformatted = !closing;
+                    }
+                    paragraph_break(&mut out);
+                } else if name == "td" || name == "th" {
+                    out.push(' ');
+                }
+            }
+            Token::Text(text) => {
+                if skipping.is_some() {
+                    continue;
+                }
+                let text = decode_entities(text);
+                if preformatted {
+                    out.push_str(&text);
+                } else {
+                    push_collapsed(&mut out, &text);
+                }
+            }
+        }
+    }
+    out.trim().to_string()
+}
+
+// Text of a Word `document.xml`: one paragraph per `<w:p>`, with paragraphs
+// styled as headings turned into `#` lines
+pub fn docx_to_text(xml: &str) -> String {
+    let mut out = String::new();
+    let mut paragraph = String::new();
+    let mut heading: Option<usize> = None;
+    let mut in_text = false;
+
+    for token in tokenize(xml) {
+        match token {
+            Token::Tag {
+                name,
+                closing,
+                self_closing,
+                attributes,
+            } => match name.as_str() {
+                "w:p" if closing => {
+                    let text = paragraph.trim();
+                    if !text.is_empty() {
+                        paragraph_break(&mut out);
+                        if let Some(level) = heading {
+                            out.push_str(&"#".repeat(level));
+                            out.push(' ');
+                        }
+                        out.push_str(text);
+                    }
+                    paragraph.clear();
+                    heading = None;
+                }
+                "w:pstyle" => heading = docx_heading_level(attributes),
+                "w:t" => in_text = !closing && !self_closing,
+                "w:tab" => paragraph.push('\t'),
+                "w:br" | "w:cr" => paragraph.push('\n'),
+                _ => {}
+            },
+            Token::Text(text) if in_text => paragraph.push_str(&decode_entities(text)),
+            Token::Text(_) => {}
+        }
+    }
+    out
+}
+
+fn heading_level(name: &str) -> Option<usize> {
+    let level = name.strip_prefix('h')?.parse::<usize>().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
+
+// Heading level from a paragraph style such as `w:val="Heading2"` or `"Title"`
+fn docx_heading_level(attributes: &str) -> Option<usize> {
+    let value = attributes.split("w:val=\"").nth(1)?.split('"').next()?;
+    if value == "Title" {
+        return Some(1);
+    }
+    let level = value.strip_prefix("Heading")?.parse::<usize>().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
+
+// End the current paragraph with a blank line
+fn paragraph_break(out: &mut String) {
+    let trimmed = out.trim_end_matches([' ', '\t', '\n']).len();
+    out.truncate(trimmed);
+    if !out.is_empty() {
+        out.push_str("\n\n");
+    }
+}
+
+// Append text with runs of whitespace collapsed to single spaces, as browsers
+// render it
+fn push_collapsed(out: &mut String, text: &str) {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let needs_space = !out.is_empty() && !out.ends_with(char::is_whitespace);
+    if text.starts_with(char::is_whitespace) && needs_space {
+        out.push(' ');
+    }
+    out.push_str(&words.join(" "));
+    if text.ends_with(char::is_whitespace) && !words.is_empty() {
+        out.push(' ');
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity_end = rest.find(';').filter(|&end| end <= 10);
+        let decoded = entity_end.and_then(|end| decode_entity(&rest[1..end]));
+        match (entity_end, decoded) {
+            (Some(end), Some(c)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => entity.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+// Protocol revisions we can speak, newest first
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// Results a search returns when the agent doesn't ask for a number
+pub const DEFAULT_TOP_K: usize = 5;
+
+// What the MCP tools do with the store. Each returns JSON for the agent, or
+// an error that is reported to it as a failed tool call.
+pub trait Tools {
+    fn search_documents(&mut self, query: &str, top_k: usize) -> anyhow::Result<Value>;
+    fn add_document(&mut self, text: &str, source: Option<&str>) -> anyhow::Result<Value>;
+    fn list_sources(&mut self) -> anyhow::Result<Value>;
+}
+
+// Serve the tools over the Model Context Protocol: JSON-RPC messages, one per
+// line, read from `input` and answered on `output`, until `input` closes
+pub fn serve(tools: &mut dyn Tools, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => handle(tools, &message),
+            Err(e) => Some(error(Value::Null, PARSE_ERROR, &format!("Parse error: {}", e))),
+        };
+        if let Some(response) = response {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// The response to a message, or None for a notification
+fn handle(tools: &mut dyn Tools, message: &Value) -> Option<Value> {
+    let method = match message.get("method").and_then(Value::as_str) {
+        Some(method) => method,
+        // A response to a request of ours; we make none
+        None if message.get("result").is_some() || message.get("error").is_some() => return None,
+        None => return Some(error(Value::Null, INVALID_REQUEST, "Invalid request")),
+    };
+    let id = message.get("id")?.clone();
+    let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+
+    let result = match method {
+        "initialize" => {
+            let requested = params.get("protocolVersion").and_then(Value::as_str);
+            let version = PROTOCOL_VERSIONS
+                .iter()
+                .find(|version| Some(**version) == requested)
+                .unwrap_or(&PROTOCOL_VERSIONS[0]);
+            json!({
+                "protocolVersion": version,
+                "capabilities": {"tools": {"listChanged": false}},
+                "serverInfo": {"name": "arrow", "version": env!("CARGO_PKG_VERSION")},
+                "instructions": "Search and add to a local Arrow vector store of documents.",
+            })
+        }
+        "ping" => json!({}),
+        "tools/list" => json!({"tools": definitions()}),
+        "tools/call" => match call(tools, &params) {
+            Ok(result) => result,
+            Err(message) => return Some(error(id, INVALID_PARAMS, &message)),
+        },
+        _ => return Some(error(id, METHOD_NOT_FOUND, &format!("Unknown method: {}", method))),
+    };
+    Some(json!({"jsonrpc": "2.0", "id": id, "result": result}))
+}
+
+// Run a tool. Bad arguments are a protocol error; a tool that fails is a
+// result with `isError`, so the agent sees why.
+fn call(tools: &mut dyn Tools, params: &Value) -> Result<Value, String> {
+    let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+    let arguments = params.get("arguments").cloned().unwrap_or_else(|| json!({}));
+    let string = |key: &str| arguments.get(key).and_then(Value::as_str);
+
+    let result = match name {
+        "search_documents" => {
+            let query = string("query").ok_or("search_documents needs a `query` string")?;
+            let top_k = match arguments.get("top_k") {
+                None | Some(Value::Null) => DEFAULT_TOP_K,
+                Some(value) => value
+                    .as_u64()
+                    .filter(|&k| k > 0)
+                    .ok_or("`top_k` must be a positive integer")? as usize,
+            };
+            tools.search_documents(query, top_k)
+        }
+        "add_document" => {
+            let text = string("text").ok_or("add_document needs a `text` string")?;
+            tools.add_document(text, string("source"))
+        }
+        "list_sources" => tools.list_sources(),
+        _ => return Err(format!("Unknown tool: {}", name)),
+    };
+    Ok(match result {
+        Ok(value) => json!({
+            "content": [{"type": "text", "text": serde_json::to_string_pretty(&value).unwrap()}],
+            "structuredContent": {"result": value},
+            "isError": false,
+        }),
+        Err(e) => json!({
+            "content": [{"type": "text", "text": format!("{:#}", e)}],
+            "isError": true,
+        }),
+    })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn definitions() -> Value {
+    json!([
+        {
+            "name": "search_documents",
+            "description": "Find the passages in the document store most similar in meaning \
+                            to a query, best first, with their source and score.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "query": {"type": "string", "description": "What to search for"},
+                    "top_k": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": format!("Number of results [default: {}]", DEFAULT_TOP_K),
+                    },
+                },
+                "required": ["query"],
+            },
+        },
+        {
+            "name": "add_document",
+            "description": "Add a document to the store, split into chunks and embedded. \
+                            Adding different text under an existing source replaces it.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "text": {"type": "string", "description": "The document's text"},
+                    "source": {
+                        "type": "string",
+                        "description": "Name of the document, such as a file name or URL \
+                                        [default: one derived from the text]",
+                    },
+                },
+                "required": ["text"],
+            },
+        },
+        {
+            "name": "list_sources",
+            "description": "List the files and other sources in the store, with the number \
+                            of chunks each was split into.",
+            "inputSchema": {"type": "object", "properties": {}},
+        },
+    ])
+}
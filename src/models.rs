@@ -0,0 +1,141 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Directory models are downloaded into, set from --model-cache
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Keep downloaded models under `dir`: Hugging Face Hub models (candle and
+// onnx) in `dir/hub` and rust-bert models (local) in `dir/rustbert`. Must be
+// called before a model is loaded, as rust-bert reads its cache location once.
+pub fn set_cache_dir(dir: PathBuf) {
+    std::env::set_var("RUSTBERT_CACHE", dir.join("rustbert"));
+    let _ = CACHE_DIR.set(dir);
+}
+
+// Where Hugging Face Hub models are cached: the pinned directory, else
+// `$HF_HOME/hub` or `~/.cache/huggingface/hub` as the hub's own tools use
+pub fn hub_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = CACHE_DIR.get() {
+        return Some(dir.join("hub"));
+    }
+    match std::env::var_os("HF_HOME") {
+        Some(home) if !home.is_empty() => Some(PathBuf::from(home).join("hub")),
+        _ => Some(user_cache_dir()?.join("huggingface").join("hub")),
+    }
+}
+
+// Where rust-bert models are cached: the pinned directory, else
+// `$RUSTBERT_CACHE` or `~/.cache/.rustbert`
+pub fn rustbert_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = CACHE_DIR.get() {
+        return Some(dir.join("rustbert"));
+    }
+    match std::env::var_os("RUSTBERT_CACHE") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(user_cache_dir()?.join(".rustbert")),
+    }
+}
+
+fn user_cache_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(PathBuf::from(std::env::var_os("HOME")?).join(".cache")),
+    }
+}
+
+// A model found in one of the caches
+#[derive(Serialize, Debug)]
+pub struct CachedModel {
+    // Hub repository such as `sentence-transformers/all-MiniLM-L6-v2`, or
+    // rust-bert's directory for the model
+    pub name: String,
+    // `hub` or `rustbert`
+    pub cache: &'static str,
+    pub bytes: u64,
+    pub path: PathBuf,
+}
+
+// Every model in the caches, hub models first, each sorted by name
+pub fn list() -> Result<Vec<CachedModel>> {
+    let mut models = Vec::new();
+    if let Some(dir) = hub_cache_dir() {
+        // The hub keeps `models--<org>--<name>` next to datasets and spaces
+        for (entry, path) in subdirs(&dir)? {
+            if let Some(name) = entry.strip_prefix("models--") {
+                models.push(CachedModel {
+                    name: name.replace("--", "/"),
+                    cache: "hub",
+                    bytes: dir_size(&path),
+                    path,
+                });
+            }
+        }
+    }
+    if let Some(dir) = rustbert_cache_dir() {
+        for (name, path) in subdirs(&dir)? {
+            models.push(CachedModel {
+                name,
+                cache: "rustbert",
+                bytes: dir_size(&path),
+                path,
+            });
+        }
+    }
+    Ok(models)
+}
+
+// Delete a model from the caches by the name `list` gives it, returning what
+// was removed
+pub fn remove(name: &str) -> Result<Vec<CachedModel>> {
+    let matching: Vec<CachedModel> =
+        list()?.into_iter().filter(|model| model.name == name).collect();
+    if matching.is_empty() {
+        bail!("No cached model named '{}'; see `arrow model list`", name);
+    }
+    for model in &matching {
+        fs::remove_dir_all(&model.path)
+            .with_context(|| format!("Failed to remove {}", model.path.display()))?;
+    }
+    Ok(matching)
+}
+
+// Directories in `dir` by name, sorted; none if `dir` doesn't exist yet
+fn subdirs(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    let mut subdirs: Vec<(String, PathBuf)> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .map(|entry| (entry.file_name().to_string_lossy().to_string(), entry.path()))
+        .collect();
+    subdirs.sort();
+    Ok(subdirs)
+}
+
+// Bytes of the files under `dir`. Links aren't followed, so the hub's
+// snapshots, which link to its blobs, aren't counted twice.
+fn dir_size(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?)))
+        .map(|(path, kind)| {
+            if kind.is_dir() {
+                dir_size(&path)
+            } else if kind.is_file() {
+                fs::metadata(&path).map_or(0, |metadata| metadata.len())
+            } else {
+                0
+            }
+        })
+        .sum()
+}
@@ -0,0 +1,48 @@
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether network access is forbidden, set from --offline
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+// Fail when offline, as `what` would need the network
+pub fn check(what: &str) -> Result<()> {
+    if is_offline() {
+        bail!("{} needs network access, which --offline forbids", what);
+    }
+    Ok(())
+}
+
+// Fail when offline unless `url` is on this machine, so a model or LLM
+// server running locally can still be used
+pub fn check_url(url: &str, what: &str) -> Result<()> {
+    if !is_offline() || is_loopback(url) {
+        return Ok(());
+    }
+    bail!(
+        "{} at {} isn't on this machine, and --offline forbids network access",
+        what,
+        url
+    );
+}
+
+fn is_loopback(url: &str) -> bool {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    // Brackets hold an IPv6 address; otherwise a port follows the last colon
+    let host = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or(bracketed),
+        None => authority.split(':').next().unwrap_or(authority),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
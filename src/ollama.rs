@@ -0,0 +1,104 @@
+use crate::offline;
+use crate::provider::EmbeddingProvider;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const DEFAULT_HOST: &str = "http://127.0.0.1:11434";
+// Fits models with a 512-token context, such as mxbai-embed-large
+pub const CHUNK_TOKENS: usize = 500;
+
+// Embeddings from a local Ollama server's `/api/embeddings` endpoint, at
+// `OLLAMA_HOST` like the `ollama` command itself
+pub struct OllamaProvider {
+    agent: ureq::Agent,
+    url: String,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+// The server's URL from `OLLAMA_HOST`, shared with chat
+pub fn host() -> String {
+    let host = std::env::var("OLLAMA_HOST")
+        .ok()
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| DEFAULT_HOST.to_string());
+    // OLLAMA_HOST is often given without a scheme, e.g. `0.0.0.0:11434`
+    let host = if host.contains("://") {
+        host
+    } else {
+        format!("http://{}", host)
+    };
+    host.trim_end_matches('/').to_string()
+}
+
+impl OllamaProvider {
+    pub fn from_env(model: &str) -> Result<Self> {
+        offline::check_url(&host(), "Ollama")?;
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(300))
+                .build(),
+            url: format!("{}/api/embeddings", host()),
+            model: model.to_string(),
+        })
+    }
+
+    fn embed_one(&self, text: &str) -> Result<Vec<f32>> {
+        let body = EmbeddingRequest {
+            model: &self.model,
+            prompt: text,
+        };
+        let response = match self.agent.post(&self.url).send_json(&body) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, response)) => {
+                let detail = response.into_string().unwrap_or_default();
+                let detail = serde_json::from_str::<ErrorResponse>(&detail)
+                    .map(|error| error.error)
+                    .unwrap_or(detail);
+                bail!("Ollama returned status {}: {}", code, detail.trim());
+            }
+            Err(e) => bail!(
+                "Can't reach Ollama at {} ({}); is `ollama serve` running?",
+                self.url,
+                e
+            ),
+        };
+
+        let response: EmbeddingResponse = response
+            .into_json()
+            .context("Invalid response from Ollama")?;
+        if response.embedding.is_empty() {
+            bail!(
+                "Ollama returned an empty embedding; is '{}' an embedding model?",
+                self.model
+            );
+        }
+        Ok(response.embedding)
+    }
+}
+
+impl EmbeddingProvider for OllamaProvider {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        texts.iter().map(|text| self.embed_one(text)).collect()
+    }
+
+    fn max_chunk_tokens(&self) -> usize {
+        CHUNK_TOKENS
+    }
+}
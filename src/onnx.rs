@@ -0,0 +1,145 @@
+use crate::hub::{self, ModelFiles};
+use crate::provider::{EmbeddingProvider, SPECIAL_TOKENS};
+use anyhow::{bail, Result};
+use ort::session::builder::GraphOptimizationLevel;
+use ort::session::Session;
+use ort::value::Tensor;
+use tokenizers::{Encoding, Tokenizer};
+
+// Texts run through the model at once
+const BATCH_SIZE: usize = 32;
+// Where exports keep the model; sentence-transformers repositories use `onnx/`
+const MODEL_FILES: &[&str] = &["onnx/model.onnx", "model.onnx"];
+
+// An ONNX export of a BERT-family sentence embedding model (MiniLM, e5,
+// bge, ...) run on the CPU with ONNX Runtime. The model returns token
+// states; pooling and normalization happen here.
+pub struct OnnxProvider {
+    session: Session,
+    // Pads and truncates batches for the model
+    tokenizer: Tokenizer,
+    // Tokenizes whole texts to size chunks
+    counter: Tokenizer,
+    // Whether the export takes `token_type_ids`; some drop the input
+    token_types: bool,
+    max_tokens: usize,
+}
+
+impl OnnxProvider {
+    // Load a hub repository with an ONNX export, such as
+    // `sentence-transformers/all-MiniLM-L6-v2`, or a directory holding
+    // `model.onnx`, `tokenizer.json` and `config.json`
+    pub fn new(name: &str) -> Result<Self> {
+        let files = ModelFiles::open(name)?;
+        let max_tokens = files.max_tokens()?;
+        let (tokenizer, counter) = files.tokenizers(max_tokens)?;
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .commit_from_file(files.first_of(MODEL_FILES)?)?;
+        let token_types = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+        Ok(Self {
+            session,
+            tokenizer,
+            counter,
+            token_types,
+            max_tokens,
+        })
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(anyhow::Error::msg)?;
+        let tokens = encodings.first().map_or(0, |encoding| encoding.len());
+        let shape = [encodings.len(), tokens];
+        let flatten = |field: fn(&Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|encoding| field(encoding).iter().map(|&value| value as i64))
+                .collect()
+        };
+        let mask = flatten(Encoding::get_attention_mask);
+
+        let ids = Tensor::from_array((shape, flatten(Encoding::get_ids)))?;
+        let attention = Tensor::from_array((shape, mask.clone()))?;
+        let outputs = if self.token_types {
+            let types = Tensor::from_array((shape, flatten(Encoding::get_type_ids)))?;
+            self.session.run(ort::inputs![
+                "input_ids" => ids,
+                "attention_mask" => attention,
+                "token_type_ids" => types,
+            ]?)?
+        } else {
+            self.session.run(ort::inputs![
+                "input_ids" => ids,
+                "attention_mask" => attention,
+            ]?)?
+        };
+
+        // Token states of shape (texts, tokens, hidden)
+        let (dims, states) = outputs[0].try_extract_raw_tensor::<f32>()?;
+        if dims.len() != 3 || dims[0] as usize != texts.len() || dims[1] as usize != tokens {
+            bail!(
+                "Unexpected ONNX output shape {:?}; expected token states",
+                dims
+            );
+        }
+        let hidden = dims[2] as usize;
+        Ok(states
+            .chunks(tokens * hidden)
+            .zip(mask.chunks(tokens))
+            .map(|(states, mask)| normalize(mean_pool(states, mask, hidden)))
+            .collect())
+    }
+}
+
+impl EmbeddingProvider for OnnxProvider {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(self.embed_batch(batch)?);
+        }
+        Ok(vectors)
+    }
+
+    // Most tokens a chunk can hold without being truncated by the model
+    fn max_chunk_tokens(&self) -> usize {
+        self.max_tokens - SPECIAL_TOKENS
+    }
+
+    fn token_starts(&self, text: &str) -> Vec<usize> {
+        hub::token_starts(&self.counter, text)
+    }
+}
+
+// Average of the token states that aren't padding
+fn mean_pool(states: &[f32], mask: &[i64], hidden: usize) -> Vec<f32> {
+    let mut sum = vec![0.0; hidden];
+    let mut count = 0.0;
+    for (state, _) in states
+        .chunks(hidden)
+        .zip(mask)
+        .filter(|(_, &mask)| mask != 0)
+    {
+        for (total, value) in sum.iter_mut().zip(state) {
+            *total += value;
+        }
+        count += 1.0;
+    }
+    if count > 0.0 {
+        sum.iter_mut().for_each(|total| *total /= count);
+    }
+    sum
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+    vector
+}
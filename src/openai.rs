@@ -0,0 +1,138 @@
+use crate::offline;
+use crate::provider::EmbeddingProvider;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+// Inputs sent per request; the API accepts up to 2048
+const BATCH_SIZE: usize = 96;
+// Attempts per batch when rate limited or the server fails
+const MAX_ATTEMPTS: u32 = 4;
+// The API accepts 8191 tokens per input, but smaller chunks retrieve better
+pub const CHUNK_TOKENS: usize = 512;
+
+// Embeddings from an OpenAI-compatible `/v1/embeddings` endpoint. The base URL
+// and key come from `OPENAI_BASE_URL` and `OPENAI_API_KEY`, so any compatible
+// server (Azure, vLLM, LiteLLM, ...) can stand in for OpenAI.
+pub struct OpenAiProvider {
+    agent: ureq::Agent,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+// The API's base URL and key from `OPENAI_BASE_URL` and `OPENAI_API_KEY`,
+// shared with chat completions
+pub fn endpoint() -> Result<(String, Option<String>)> {
+    let base_url = std::env::var("OPENAI_BASE_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+    let api_key = std::env::var("OPENAI_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty());
+    offline::check_url(&base_url, "The OpenAI API")?;
+    // Only the official API is sure to need a key; local servers often don't
+    if api_key.is_none() && base_url == DEFAULT_BASE_URL {
+        bail!("OPENAI_API_KEY is not set");
+    }
+    Ok((base_url.trim_end_matches('/').to_string(), api_key))
+}
+
+impl OpenAiProvider {
+    pub fn from_env(model: &str) -> Result<Self> {
+        let (base_url, api_key) = endpoint()?;
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(120))
+                .build(),
+            url: format!("{}/embeddings", base_url),
+            api_key,
+            model: model.to_string(),
+        })
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let body = EmbeddingRequest {
+            model: &self.model,
+            input: texts,
+        };
+        let mut attempt = 1;
+        let response = loop {
+            let mut request = self.agent.post(&self.url);
+            if let Some(key) = &self.api_key {
+                request = request.set("Authorization", &format!("Bearer {}", key));
+            }
+            match request.send_json(&body) {
+                Ok(response) => break response,
+                // Back off and retry when rate limited or on server errors
+                Err(ureq::Error::Status(code, _))
+                    if (code == 429 || code >= 500) && attempt < MAX_ATTEMPTS =>
+                {
+                    thread::sleep(Duration::from_secs(1 << attempt));
+                    attempt += 1;
+                }
+                Err(ureq::Error::Status(code, response)) => {
+                    let detail = response.into_string().unwrap_or_default();
+                    bail!(
+                        "Embedding request to {} failed with status {}: {}",
+                        self.url,
+                        code,
+                        detail.trim()
+                    );
+                }
+                Err(e) => bail!("Embedding request to {} failed: {}", self.url, e),
+            }
+        };
+
+        let mut response: EmbeddingResponse = response
+            .into_json()
+            .context("Invalid response from embeddings endpoint")?;
+        if response.data.len() != texts.len() {
+            bail!(
+                "Embeddings endpoint returned {} vectors for {} inputs",
+                response.data.len(),
+                texts.len()
+            );
+        }
+        response.data.sort_by_key(|data| data.index);
+        Ok(response
+            .data
+            .into_iter()
+            .map(|data| data.embedding)
+            .collect())
+    }
+}
+
+impl EmbeddingProvider for OpenAiProvider {
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(self.embed_batch(batch)?);
+        }
+        Ok(vectors)
+    }
+
+    fn max_chunk_tokens(&self) -> usize {
+        CHUNK_TOKENS
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io::{self, Write};
+
+// How command results are printed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    // Colored tables for people
+    #[default]
+    Table,
+    // A single JSON document
+    Json,
+    // One JSON object per line
+    Jsonl,
+    // Tab-separated values with a header row
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn is_structured(self) -> bool {
+        self != OutputFormat::Table
+    }
+}
+
+// Print rows in a structured format: a JSON array, one JSON object per line,
+// or TSV with `header` and the cells returned by `cells`
+pub fn write_rows<T: Serialize>(
+    format: OutputFormat,
+    rows: &[T],
+    header: &[&str],
+    cells: impl Fn(&T) -> Vec<String>,
+) -> Result<()> {
+    let mut out = io::stdout().lock();
+    match format {
+        OutputFormat::Table | OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, rows)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Jsonl => {
+            for row in rows {
+                serde_json::to_writer(&mut out, row)?;
+                writeln!(out)?;
+            }
+        }
+        OutputFormat::Tsv => {
+            writeln!(out, "{}", header.join("\t"))?;
+            for row in rows {
+                let cells: Vec<String> = cells(row).iter().map(|cell| tsv_escape(cell)).collect();
+                writeln!(out, "{}", cells.join("\t"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Print a single object in a structured format. TSV has one `key\tvalue` row
+// per field as returned by `fields`.
+pub fn write_object<T: Serialize>(
+    format: OutputFormat,
+    object: &T,
+    fields: impl Fn(&T) -> Vec<(String, String)>,
+) -> Result<()> {
+    let mut out = io::stdout().lock();
+    match format {
+        OutputFormat::Table | OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, object)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Jsonl => {
+            serde_json::to_writer(&mut out, object)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Tsv => {
+            for (key, value) in fields(object) {
+                writeln!(out, "{}\t{}", key, tsv_escape(&value))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Escape the characters that would break a TSV cell
+fn tsv_escape(cell: &str) -> String {
+    cell.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
@@ -0,0 +1,206 @@
+use rand::seq::SliceRandom;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// Centroids per subspace, so every code fits in a u8
+const NUM_CENTROIDS: usize = 256;
+
+// Upper bound on the number of vectors used to train the codebooks
+const MAX_TRAINING_VECTORS: usize = 50_000;
+
+// Product quantizer splitting each vector into equal subspaces, each encoded
+// as the index of its nearest codebook centroid
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProductQuantizer {
+    dim: usize,
+    subspaces: usize,
+    // codebooks[subspace][centroid] is a sub-vector of length dim / subspaces
+    codebooks: Vec<Vec<Vec<f32>>>,
+}
+
+impl ProductQuantizer {
+    // Train codebooks with k-means over each subspace
+    pub fn train(vectors: &[Vec<f32>], subspaces: usize, iterations: usize) -> Result<Self, String> {
+        let dim = vectors.first().map(|v| v.len()).ok_or("No vectors to train on")?;
+        if subspaces == 0 || dim % subspaces != 0 {
+            return Err(format!(
+                "Vector dimension {} is not divisible into {} subspaces",
+                dim, subspaces
+            ));
+        }
+
+        let mut sample: Vec<&Vec<f32>> = vectors.iter().collect();
+        sample.shuffle(&mut rand::thread_rng());
+        sample.truncate(MAX_TRAINING_VECTORS);
+
+        let sub_dim = dim / subspaces;
+        let codebooks = (0..subspaces)
+            .into_par_iter()
+            .map(|s| {
+                let points: Vec<&[f32]> = sample
+                    .iter()
+                    .map(|v| &v[s * sub_dim..(s + 1) * sub_dim])
+                    .collect();
+                kmeans(&points, NUM_CENTROIDS.min(points.len()), iterations)
+            })
+            .collect();
+
+        Ok(Self {
+            dim,
+            subspaces,
+            codebooks,
+        })
+    }
+
+    pub fn subspaces(&self) -> usize {
+        self.subspaces
+    }
+
+    pub fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        let sub_dim = self.dim / self.subspaces;
+        self.codebooks
+            .iter()
+            .enumerate()
+            .map(|(s, codebook)| {
+                nearest_centroid(codebook, &vector[s * sub_dim..(s + 1) * sub_dim]) as u8
+            })
+            .collect()
+    }
+
+    // Precompute query/centroid dot products for asymmetric distance computation
+    pub fn distance_table(&self, query: &[f32]) -> Vec<Vec<f32>> {
+        let sub_dim = self.dim / self.subspaces;
+        self.codebooks
+            .iter()
+            .enumerate()
+            .map(|(s, codebook)| {
+                let sub_query = &query[s * sub_dim..(s + 1) * sub_dim];
+                codebook.iter().map(|centroid| dot(sub_query, centroid)).collect()
+            })
+            .collect()
+    }
+
+    // Cosine distance approximated from a distance table and a node's codes
+    pub fn adc_distance(table: &[Vec<f32>], codes: &[u8]) -> f32 {
+        let dot: f32 = table
+            .iter()
+            .zip(codes)
+            .map(|(row, &code)| row[code as usize])
+            .sum();
+        1.0 - dot.clamp(-1.0, 1.0)
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+pub fn nearest_centroid(centroids: &[Vec<f32>], point: &[f32]) -> usize {
+    let mut best = 0;
+    let mut best_dist = f32::MAX;
+    for (i, centroid) in centroids.iter().enumerate() {
+        let dist = squared_distance(point, centroid);
+        if dist < best_dist {
+            best = i;
+            best_dist = dist;
+        }
+    }
+    best
+}
+
+// Lloyd's k-means, initialized from the first k (already shuffled) points
+pub fn kmeans(points: &[&[f32]], k: usize, iterations: usize) -> Vec<Vec<f32>> {
+    let sub_dim = points[0].len();
+    let mut centroids: Vec<Vec<f32>> = points.iter().take(k).map(|p| p.to_vec()).collect();
+
+    for _ in 0..iterations {
+        let assignments: Vec<usize> = points
+            .par_iter()
+            .map(|point| nearest_centroid(&centroids, point))
+            .collect();
+        let mut sums = vec![vec![0.0f32; sub_dim]; k];
+        let mut counts = vec![0usize; k];
+        for (point, c) in points.iter().zip(assignments) {
+            counts[c] += 1;
+            for (sum, x) in sums[c].iter_mut().zip(point.iter()) {
+                *sum += x;
+            }
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            // Empty clusters keep their previous centroid
+            if count > 0 {
+                *centroid = sum.into_iter().map(|x| x / count as f32).collect();
+            }
+        }
+    }
+
+    centroids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit vectors spread over four dimensions
+    fn vectors() -> Vec<Vec<f32>> {
+        (0..8)
+            .map(|i| {
+                let angle = i as f32 * 0.7;
+                let v = [angle.cos(), angle.sin(), (angle * 0.5).cos(), (angle * 0.5).sin()];
+                let norm = dot(&v, &v).sqrt();
+                v.iter().map(|x| x / norm).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rejects_dimensions_not_divisible_into_subspaces() {
+        assert!(ProductQuantizer::train(&vectors(), 3, 5).is_err());
+        assert!(ProductQuantizer::train(&vectors(), 0, 5).is_err());
+        assert!(ProductQuantizer::train(&[], 2, 5).is_err());
+    }
+
+    #[test]
+    fn encodes_one_code_per_subspace() {
+        let pq = ProductQuantizer::train(&vectors(), 2, 5).unwrap();
+        assert_eq!(pq.subspaces(), 2);
+        for vector in vectors() {
+            assert_eq!(pq.encode(&vector).len(), 2);
+        }
+    }
+
+    // With no more vectors than centroids each vector is a centroid, so the
+    // lookup table gives the exact cosine distance
+    #[test]
+    fn adc_matches_cosine_distance_of_training_vectors() {
+        let vectors = vectors();
+        let pq = ProductQuantizer::train(&vectors, 2, 5).unwrap();
+        let query = &vectors[3];
+        let table = pq.distance_table(query);
+        for vector in &vectors {
+            let expected = 1.0 - dot(query, vector);
+            let distance = ProductQuantizer::adc_distance(&table, &pq.encode(vector));
+            assert!((distance - expected).abs() < 1e-5, "{} != {}", distance, expected);
+        }
+    }
+
+    #[test]
+    fn kmeans_separates_clusters() {
+        let points: Vec<Vec<f32>> = vec![
+            vec![0.0, 0.1],
+            vec![10.0, 10.0],
+            vec![0.1, 0.0],
+            vec![10.1, 9.9],
+        ];
+        let points: Vec<&[f32]> = points.iter().map(|p| &p[..]).collect();
+        let centroids = kmeans(&points, 2, 10);
+        let nearest = |point: &[f32]| nearest_centroid(&centroids, point);
+        assert_eq!(nearest(points[0]), nearest(points[2]));
+        assert_eq!(nearest(points[1]), nearest(points[3]));
+        assert_ne!(nearest(points[0]), nearest(points[1]));
+    }
+}
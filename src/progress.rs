@@ -0,0 +1,29 @@
+// Something that happened while adding documents, as reported to a
+// `ProgressSink`. Sources are files, or names given to text added directly.
+#[derive(Clone, Copy, Debug)]
+pub enum ProgressEvent<'a> {
+    // Work on the `index`th of `total` sources began
+    FileStarted {
+        source: &'a str,
+        index: usize,
+        total: usize,
+    },
+    // A source was left alone because it hasn't changed
+    FileSkipped { source: &'a str },
+    // A source was split into this many chunks
+    ChunksCreated { source: &'a str, chunks: usize },
+    // `done` of the `total` texts in a call are embedded, counting those
+    // taken from the cache
+    EmbeddingBatchDone { done: usize, total: usize },
+    // `done` of the `total` documents being added are linked into the graph
+    NodeInserted { done: usize, total: usize },
+    // A source was added as this many chunks
+    FileFinished { source: &'a str, chunks: usize },
+}
+
+// Receives progress while documents are added, to show it however the
+// program likes. Events arrive on the thread doing the work, so a sink should
+// return quickly.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: ProgressEvent);
+}
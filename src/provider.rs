@@ -0,0 +1,325 @@
+#[cfg(feature = "candle")]
+use crate::bert::BertProvider;
+use crate::device::Backend;
+use crate::embedding::EmbeddingModel;
+#[cfg(feature = "torch")]
+use crate::embedding::LocalProvider;
+#[cfg(feature = "torch")]
+use crate::offline;
+use crate::ollama::{self, OllamaProvider};
+#[cfg(feature = "onnx")]
+use crate::onnx::OnnxProvider;
+use crate::openai::{self, OpenAiProvider};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Characters per token assumed when a provider can't tokenize locally
+const CHARS_PER_TOKEN: usize = 4;
+// Tokens a BERT-family model takes, unless its config says otherwise
+const BERT_MAX_TOKENS: usize = 512;
+// Tokens a local model adds around every input ([CLS] and [SEP])
+pub const SPECIAL_TOKENS: usize = 2;
+
+// A source of sentence embeddings: a local model or a remote service
+pub trait EmbeddingProvider: Send {
+    // One vector per text, in the same order
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    // Default chunk size in tokens, small enough for the model to see all of it
+    fn max_chunk_tokens(&self) -> usize;
+
+    // Character offsets at which the tokens of `text` begin, used to size
+    // chunks
+    fn token_starts(&self, text: &str) -> Vec<usize> {
+        estimated_token_starts(text)
+    }
+}
+
+// Stands in for a provider that isn't loaded, such as for a dry run: tokens
+// are estimated, and nothing can be embedded
+pub struct Estimator {
+    pub max_chunk_tokens: usize,
+}
+
+impl EmbeddingProvider for Estimator {
+    fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        bail!("No model is loaded to embed with")
+    }
+
+    fn max_chunk_tokens(&self) -> usize {
+        self.max_chunk_tokens
+    }
+}
+
+// Token starts for providers without a local tokenizer: every few characters
+// of a word count as one token
+pub fn estimated_token_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut run = 0;
+    for (i, c) in text.chars().enumerate() {
+        if c.is_whitespace() {
+            run = 0;
+            continue;
+        }
+        if run % CHARS_PER_TOKEN == 0 {
+            starts.push(i);
+        }
+        run += 1;
+    }
+    starts
+}
+
+// Name accepted by --model for the provider's multilingual model
+pub const MULTILINGUAL: &str = "multilingual";
+
+// Where embeddings are computed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    // rust-bert models run in-process on libtorch
+    #[cfg_attr(feature = "torch", default)]
+    Local,
+    // BERT-family models from the Hugging Face Hub run in-process on candle
+    #[cfg_attr(not(feature = "torch"), default)]
+    Candle,
+    // ONNX exports of BERT-family models run on ONNX Runtime
+    Onnx,
+    // An OpenAI-compatible `/v1/embeddings` endpoint
+    Openai,
+    // A local Ollama server
+    Ollama,
+}
+
+impl Provider {
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    // Model used when only the provider is given
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Provider::Local => "all-minilm-l6-v2",
+            Provider::Candle | Provider::Onnx => "sentence-transformers/all-MiniLM-L6-v2",
+            Provider::Openai => "text-embedding-3-small",
+            Provider::Ollama => "nomic-embed-text",
+        }
+    }
+
+    // Model to use instead of the default for text in many languages, for
+    // providers whose default is trained on English. OpenAI's models are
+    // multilingual already.
+    pub fn multilingual_model(self) -> Option<&'static str> {
+        match self {
+            Provider::Local => Some("distiluse-base-multilingual-cased"),
+            Provider::Candle | Provider::Onnx => {
+                Some("sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2")
+            }
+            Provider::Openai => None,
+            Provider::Ollama => Some("paraphrase-multilingual"),
+        }
+    }
+}
+
+// The provider and model a store's embeddings come from. Saved as the bare
+// model name for local models, which is how older stores recorded them, and
+// as `<provider>/<model>` otherwise.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModelSpec {
+    provider: Provider,
+    model: String,
+}
+
+impl ModelSpec {
+    // Check that `model` names a model the provider can serve. `multilingual`
+    // stands for the provider's multilingual model.
+    pub fn new(provider: Provider, model: &str) -> Result<Self> {
+        if model.trim().is_empty() {
+            bail!("Model name is empty");
+        }
+        let model = if model.eq_ignore_ascii_case(MULTILINGUAL) {
+            provider.multilingual_model().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{}'s default model {} is multilingual already; leave out --model",
+                    provider.name(),
+                    provider.default_model()
+                )
+            })?
+        } else {
+            model
+        };
+        let model = match provider {
+            Provider::Local => EmbeddingModel::from_str(model, true)
+                .map_err(|e| anyhow::anyhow!("Invalid local model: {}", e))?
+                .name(),
+            _ => model.to_string(),
+        };
+        Ok(Self { provider, model })
+    }
+
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    // The model's name as the provider knows it
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    // The rust-bert model, for local specs
+    pub fn local_model(&self) -> Option<EmbeddingModel> {
+        match self.provider {
+            Provider::Local => EmbeddingModel::from_str(&self.model, true).ok(),
+            _ => None,
+        }
+    }
+
+    // Vector length, if known without calling the model
+    pub fn dimension(&self) -> Option<usize> {
+        self.local_model().map(EmbeddingModel::dimension)
+    }
+
+    // The provider's default chunk size in tokens, without loading the model.
+    // Models from the hub are assumed to take as many tokens as BERT.
+    pub fn estimated_chunk_tokens(&self) -> usize {
+        match self.provider {
+            Provider::Local => {
+                self.local_model()
+                    .map_or(BERT_MAX_TOKENS, EmbeddingModel::max_sequence_length)
+                    - SPECIAL_TOKENS
+            }
+            Provider::Candle | Provider::Onnx => BERT_MAX_TOKENS - SPECIAL_TOKENS,
+            Provider::Openai => openai::CHUNK_TOKENS,
+            Provider::Ollama => ollama::CHUNK_TOKENS,
+        }
+    }
+}
+
+// The model stores were built with before they recorded one
+impl Default for ModelSpec {
+    fn default() -> Self {
+        Self {
+            provider: Provider::Local,
+            model: EmbeddingModel::default().name(),
+        }
+    }
+}
+
+impl fmt::Display for ModelSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.provider {
+            Provider::Local => write!(f, "{}", self.model),
+            provider => write!(f, "{}/{}", provider.name(), self.model),
+        }
+    }
+}
+
+impl TryFrom<String> for ModelSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        if let Some((prefix, model)) = value.split_once('/') {
+            if let Ok(provider) = Provider::from_str(prefix, true) {
+                if provider != Provider::Local {
+                    return Self::new(provider, model);
+                }
+            }
+        }
+        Self::new(Provider::Local, &value)
+    }
+}
+
+impl From<ModelSpec> for String {
+    fn from(spec: ModelSpec) -> Self {
+        spec.to_string()
+    }
+}
+
+// Instructions some models were trained to expect in front of their inputs,
+// such as e5's `query: ` and `passage: `. Chosen when a store is created and
+// kept with it, since vectors embedded with and without them don't mix.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Prompts {
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub passage: String,
+}
+
+impl Prompts {
+    // The prompts published with well-known model families, or none
+    pub fn for_model(spec: &ModelSpec) -> Self {
+        let name = spec.model.to_lowercase();
+        let name = name.rsplit('/').next().unwrap_or(&name);
+        let (query, passage) = if name.contains("e5-") && !name.contains("instruct") {
+            ("query: ", "passage: ")
+        } else if name.starts_with("bge-") && !name.starts_with("bge-m3") {
+            // bge only instructs short queries looking for longer passages
+            ("Represent this sentence for searching relevant passages: ", "")
+        } else if name.starts_with("nomic-embed-text") {
+            ("search_query: ", "search_document: ")
+        } else {
+            ("", "")
+        };
+        Self {
+            query: query.to_string(),
+            passage: passage.to_string(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.query.is_empty() && self.passage.is_empty()
+    }
+
+    pub fn query(&self, text: &str) -> String {
+        format!("{}{}", self.query, text)
+    }
+
+    pub fn passage(&self, text: &str) -> String {
+        format!("{}{}", self.passage, text)
+    }
+}
+
+// Connect to the provider serving `spec`, loading the model if it is local
+#[cfg_attr(
+    not(any(feature = "torch", feature = "candle")),
+    allow(unused_variables)
+)]
+pub fn open(spec: &ModelSpec, backend: &Backend) -> Result<Box<dyn EmbeddingProvider>> {
+    Ok(match spec.provider {
+        // rust-bert asks the hub whether its cached files are current on every load
+        #[cfg(feature = "torch")]
+        Provider::Local if offline::is_offline() => bail!(
+            "{} checks huggingface.co for updates each time it loads, which --offline forbids; \
+             use --provider candle or onnx",
+            spec
+        ),
+        #[cfg(feature = "torch")]
+        Provider::Local => {
+            let model = spec
+                .local_model()
+                .ok_or_else(|| anyhow::anyhow!("Unknown local model '{}'", spec.model))?;
+            Box::new(LocalProvider::new(model, backend)?)
+        }
+        #[cfg(not(feature = "torch"))]
+        Provider::Local => bail!(
+            "{} needs libtorch, but this build has no `torch` feature; rebuild with it or use \
+             --provider candle",
+            spec
+        ),
+        #[cfg(feature = "candle")]
+        Provider::Candle => Box::new(BertProvider::new(&spec.model, backend)?),
+        #[cfg(not(feature = "candle"))]
+        Provider::Candle => bail!("{} needs a build with the `candle` feature", spec),
+        #[cfg(feature = "onnx")]
+        Provider::Onnx => Box::new(OnnxProvider::new(&spec.model)?),
+        #[cfg(not(feature = "onnx"))]
+        Provider::Onnx => bail!("{} needs a build with the `onnx` feature", spec),
+        Provider::Openai => Box::new(OpenAiProvider::from_env(&spec.model)?),
+        Provider::Ollama => Box::new(OllamaProvider::from_env(&spec.model)?),
+    })
+}
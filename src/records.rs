@@ -0,0 +1,164 @@
+use crate::vectorstore::Metadata;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Which fields of a structured record hold its text, ID, metadata and
+// separately embedded fields
+pub struct FieldMapping<'a> {
+    pub text: &'a str,
+    pub id: Option<&'a str>,
+    pub metadata: &'a [String],
+    pub vectors: &'a [String],
+}
+
+// One row of a CSV or JSONL file, mapped to a document
+pub struct Record {
+    // 1-based position of the record in its file
+    pub number: usize,
+    pub id: Option<String>,
+    pub text: String,
+    pub metadata: Metadata,
+    // Text of each field embedded as a vector of its own
+    pub vector_fields: HashMap<String, String>,
+}
+
+// Whether a file holds one record per row rather than free text
+pub fn is_structured(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("jsonl") | Some("ndjson") | Some("csv")
+    )
+}
+
+// Read the records of a CSV (with a header row) or JSONL file. Records without
+// the text field are skipped and counted.
+pub fn load(path: &Path, fields: &FieldMapping) -> Result<(Vec<Record>, usize)> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    parse(path, &content, fields)
+}
+
+// Records of CSV or JSONL content, the format judged by `path`'s extension
+pub fn parse(path: &Path, content: &str, fields: &FieldMapping) -> Result<(Vec<Record>, usize)> {
+    let rows = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => csv_rows(content),
+        _ => jsonl_rows(content)
+            .with_context(|| format!("Invalid JSONL file: {}", path.display()))?,
+    };
+
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for (i, row) in rows.into_iter().enumerate() {
+        let text = match row.get(fields.text) {
+            Some(text) if !text.trim().is_empty() => text.clone(),
+            _ => {
+                skipped += 1;
+                continue;
+            }
+        };
+        let metadata = fields
+            .metadata
+            .iter()
+            .filter_map(|field| Some((field.clone(), row.get(field)?.clone())))
+            .collect();
+        let vector_fields = fields
+            .vectors
+            .iter()
+            .filter_map(|field| Some((field.clone(), row.get(field)?.clone())))
+            .filter(|(_, text)| !text.trim().is_empty())
+            .collect();
+        records.push(Record {
+            number: i + 1,
+            id: fields.id.and_then(|field| row.get(field).cloned()),
+            text,
+            metadata,
+            vector_fields,
+        });
+    }
+    Ok((records, skipped))
+}
+
+// Top-level fields of each JSON object, with strings unquoted and null dropped
+fn jsonl_rows(content: &str) -> Result<Vec<HashMap<String, String>>> {
+    let mut rows = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object = match serde_json::from_str(line) {
+            Ok(Value::Object(object)) => object,
+            Ok(_) => bail!("Line {} is not a JSON object", i + 1),
+            Err(e) => bail!("Line {}: {}", i + 1, e),
+        };
+        let row = object
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                Value::Null => None,
+                Value::String(s) => Some((key, s)),
+                other => Some((key, other.to_string())),
+            })
+            .collect();
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// Rows of a CSV file keyed by the header row. Empty cells are dropped.
+fn csv_rows(content: &str) -> Vec<HashMap<String, String>> {
+    let mut rows = parse_csv(content).into_iter();
+    let header = match rows.next() {
+        Some(header) => header,
+        None => return Vec::new(),
+    };
+    rows.map(|row| {
+        header
+            .iter()
+            .cloned()
+            .zip(row)
+            .filter(|(_, value)| !value.is_empty())
+            .collect()
+    })
+    .collect()
+}
+
+// RFC 4180 fields: comma separated, optionally double-quoted, with `""` as an
+// escaped quote and line breaks allowed inside quotes
+fn parse_csv(content: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                if !(row.len() == 1 && row[0].is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
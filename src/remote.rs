@@ -0,0 +1,850 @@
+use crate::dates;
+use crate::lock::StoreLock;
+use crate::offline;
+use crate::sources;
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// Files larger than this are uploaded in parts of this size
+const PART_SIZE: usize = 16 * 1024 * 1024;
+const MANIFEST: &str = "manifest.json";
+// Record of the last sync, kept in the local copy
+const STATE_FILE: &str = ".remote.json";
+
+// A store kept in object storage at `s3://bucket/prefix` or `gs://bucket/prefix`.
+// Commands work on a local copy under `~/.cache/arrow/remote`, which is
+// brought up to date before the command runs and written back after it
+// succeeds. Files are uploaded as objects named by their SHA-256 under
+// `<prefix>/objects/`, and `<prefix>/manifest.json` maps the store's file
+// names to them. The manifest is only replaced if its ETag (its generation
+// on GCS) is still the one the local copy was made from, so of two
+// concurrent writers the second fails instead of overwriting the first.
+pub struct RemoteStore {
+    url: String,
+    client: Client,
+    prefix: String,
+    dir: PathBuf,
+    name: String,
+    state: SyncState,
+    // Held from download to upload, so two local processes don't sync at once
+    _lock: StoreLock,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SyncState {
+    // Version of the manifest the local copy was made from, or None if the
+    // store hasn't been uploaded yet
+    version: Option<String>,
+    manifest: Manifest,
+    // Size and modification time (ns) of each local file as last synced, so
+    // unchanged files aren't hashed again
+    stats: BTreeMap<String, (u64, u128)>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    // Incremented by every upload
+    generation: u64,
+    // By path relative to the local copy, `/`-separated
+    files: BTreeMap<String, RemoteFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct RemoteFile {
+    // Key relative to the prefix
+    object: String,
+    size: u64,
+    sha256: String,
+    etag: String,
+}
+
+pub fn is_remote(database: &str) -> bool {
+    database.starts_with("s3://") || database.starts_with("gs://")
+}
+
+impl RemoteStore {
+    pub fn open(url: &str) -> Result<Self> {
+        let (service, rest) = match url.split_once("://") {
+            Some(("s3", rest)) => (Service::S3, rest),
+            Some(("gs", rest)) => (Service::Gcs, rest),
+            _ => bail!("Unsupported database URL: {}", url),
+        };
+        let (bucket, prefix) = match rest.split_once('/') {
+            Some((bucket, prefix)) if !bucket.is_empty() => (bucket, prefix.trim_matches('/')),
+            _ => ("", ""),
+        };
+        if prefix.is_empty() {
+            bail!("Give a prefix within the bucket, e.g. s3://bucket/stores/docs");
+        }
+        // The store file is named after the prefix's last component, so
+        // `s3://bucket/stores/docs.db` is a SQLite store
+        let name = prefix.rsplit('/').next().unwrap_or(prefix).to_string();
+
+        let dir = cache_dir()
+            .context("Can't locate a directory for the local copy; set XDG_CACHE_HOME or HOME")?
+            .join(service.scheme())
+            .join(bucket)
+            .join(prefix);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        let lock = StoreLock::writer(dir.join(STATE_FILE)).context("Failed to lock local copy")?;
+        let state = match fs::read_to_string(dir.join(STATE_FILE)) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => SyncState::default(),
+        };
+
+        Ok(Self {
+            url: url.to_string(),
+            client: Client::from_env(service, bucket)?,
+            prefix: prefix.to_string(),
+            dir,
+            name,
+            state,
+            _lock: lock,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // Path of the local copy of the store
+    pub fn local_path(&self) -> String {
+        self.dir.join(&self.name).to_string_lossy().to_string()
+    }
+
+    // Make the local copy match the uploaded store, downloading the files
+    // that differ and deleting any the store no longer has. Returns the
+    // number of files downloaded.
+    pub fn pull(&mut self) -> Result<usize> {
+        let (manifest, version) = match self.client.get_manifest(&self.key(MANIFEST))? {
+            Some((manifest, version)) => (manifest, Some(version)),
+            None => (Manifest::default(), None),
+        };
+        if let Some(name) = manifest.files.keys().find(|name| !is_local_name(name)) {
+            bail!("The manifest of {} lists a file outside the store: {}", self.url, name);
+        }
+
+        let local = self.local_files()?;
+        let mut downloaded = 0;
+        for (name, file) in &manifest.files {
+            let unchanged = local.contains_key(name)
+                && local.get(name) == self.state.stats.get(name)
+                && self.state.manifest.files.get(name).map(|old| &old.sha256) == Some(&file.sha256);
+            if unchanged {
+                continue;
+            }
+            let path = self.local_file(name);
+            self.client
+                .download(&self.key(&file.object), file, &path)
+                .with_context(|| format!("Failed to download {} from {}", name, self.url))?;
+            downloaded += 1;
+        }
+        for name in local.keys() {
+            if !manifest.files.contains_key(name) {
+                fs::remove_file(self.local_file(name))?;
+            }
+        }
+
+        self.state = SyncState {
+            version,
+            manifest,
+            stats: self.local_files()?,
+        };
+        self.save_state()?;
+        Ok(downloaded)
+    }
+
+    // Upload the files of the local copy that changed since it was synced
+    // and replace the manifest, failing if another writer replaced it first.
+    // Returns the number of files uploaded.
+    pub fn push(&mut self) -> Result<usize> {
+        let stats = self.local_files()?;
+        let mut hashes = BTreeMap::new();
+        for (name, stat) in &stats {
+            let known = match self.state.manifest.files.get(name) {
+                Some(file) if self.state.stats.get(name) == Some(stat) => Some(file.sha256.clone()),
+                _ => None,
+            };
+            let sha256 = match known {
+                Some(sha256) => sha256,
+                None => sources::hash_file(self.local_file(name))?,
+            };
+            hashes.insert(name.clone(), sha256);
+        }
+
+        let previous = &self.state.manifest;
+        let unchanged = hashes.len() == previous.files.len()
+            && hashes
+                .iter()
+                .all(|(name, sha256)| previous.files.get(name).map(|f| &f.sha256) == Some(sha256));
+        if unchanged {
+            self.state.stats = stats;
+            self.save_state()?;
+            return Ok(0);
+        }
+
+        // Fail before uploading anything if another writer got there first
+        let current = self.client.head_version(&self.key(MANIFEST))?;
+        if current != self.state.version {
+            bail!(self.conflict());
+        }
+
+        let uploaded_objects: HashMap<&str, &RemoteFile> = previous
+            .files
+            .values()
+            .map(|file| (file.sha256.as_str(), file))
+            .collect();
+        let mut manifest = Manifest {
+            generation: previous.generation + 1,
+            files: BTreeMap::new(),
+        };
+        let mut uploaded = 0;
+        for (name, sha256) in hashes {
+            let file = match uploaded_objects.get(sha256.as_str()) {
+                Some(file) => (*file).clone(),
+                None => {
+                    let path = self.local_file(&name);
+                    let object = format!("objects/{}", sha256);
+                    let etag = self
+                        .client
+                        .upload(&self.key(&object), &path)
+                        .with_context(|| format!("Failed to upload {} to {}", name, self.url))?;
+                    uploaded += 1;
+                    RemoteFile {
+                        object,
+                        size: fs::metadata(&path)?.len(),
+                        sha256,
+                        etag,
+                    }
+                }
+            };
+            manifest.files.insert(name, file);
+        }
+
+        let version = self.client.put_manifest(
+            &self.key(MANIFEST),
+            &manifest,
+            self.state.version.as_deref(),
+        )?;
+        let version = match version {
+            Some(version) => version,
+            None => bail!(self.conflict()),
+        };
+
+        // Objects only the previous manifest used. A reader still downloading
+        // them fails and can simply retry.
+        let live: HashSet<&str> = manifest.files.values().map(|f| f.object.as_str()).collect();
+        for file in previous.files.values() {
+            if !live.contains(file.object.as_str()) {
+                let _ = self.client.delete(&self.key(&file.object));
+            }
+        }
+
+        self.state = SyncState {
+            version: Some(version),
+            manifest,
+            stats,
+        };
+        self.save_state()?;
+        Ok(uploaded)
+    }
+
+    fn conflict(&self) -> String {
+        format!(
+            "{} was changed by another writer since it was downloaded, so these changes \
+             weren't uploaded; run the command again to apply them to the latest version",
+            self.url
+        )
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}/{}", self.prefix, name)
+    }
+
+    fn local_file(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    // Size and modification time of every synced file in the local copy, by
+    // `/`-separated path relative to it
+    fn local_files(&self) -> Result<BTreeMap<String, (u64, u128)>> {
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)?.flatten() {
+                let path = entry.path();
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let name = match path.strip_prefix(&self.dir).ok().and_then(|p| p.to_str()) {
+                    Some(name) => name.replace(std::path::MAIN_SEPARATOR, "/"),
+                    None => continue,
+                };
+                if is_synced(&name) {
+                    let modified = metadata
+                        .modified()?
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |elapsed| elapsed.as_nanos());
+                    files.insert(name, (metadata.len(), modified));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn save_state(&self) -> Result<()> {
+        let path = self.dir.join(STATE_FILE);
+        fs::write(&path, serde_json::to_vec(&self.state)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+// Whether a file of the local copy is part of the store. Locks, temporary
+// files, the WAL (empty after every save) and backups aren't, and neither
+// are the embedding cache and query history, which are kept per machine.
+fn is_synced(name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    let local_suffixes = [".lock", ".tmp", ".wal", ".embeddings", ".history"];
+    !file_name.starts_with('.')
+        && !local_suffixes
+            .iter()
+            .any(|suffix| file_name.ends_with(suffix))
+        && !file_name.contains(".bak.")
+}
+
+// Whether a manifest's file name is one the local copy can hold: a synced
+// file at a relative path of plain components, so a manifest can't write
+// outside the local copy or over the files kept per machine
+fn is_local_name(name: &str) -> bool {
+    !name.is_empty()
+        && Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        && is_synced(name)
+}
+
+// `$XDG_CACHE_HOME/arrow/remote` or `~/.cache/arrow/remote`
+fn cache_dir() -> Option<PathBuf> {
+    let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_home.join("arrow").join("remote"))
+}
+
+#[derive(Clone, Copy)]
+enum Service {
+    S3,
+    Gcs,
+}
+
+impl Service {
+    fn scheme(self) -> &'static str {
+        match self {
+            Service::S3 => "s3",
+            Service::Gcs => "gs",
+        }
+    }
+}
+
+enum Auth {
+    // AWS Signature Version 4
+    Aws {
+        access_key: String,
+        secret_key: String,
+        session_token: Option<String>,
+        region: String,
+    },
+    // An OAuth access token
+    Bearer(String),
+}
+
+// Requests to a bucket through the S3 API, which the GCS XML API also speaks,
+// multipart uploads included
+struct Client {
+    agent: ureq::Agent,
+    service: Service,
+    host: String,
+    // Everything before the key, e.g. `https://bucket.s3.us-east-1.amazonaws.com`
+    base_url: String,
+    // The path part of `base_url`, empty or starting with `/`
+    base_path: String,
+    auth: Auth,
+}
+
+impl Client {
+    // S3 credentials come from the usual AWS_* variables; AWS_ENDPOINT_URL
+    // points at S3-compatible storage such as MinIO, addressed path-style.
+    // GCS takes an access token, e.g. from `gcloud auth print-access-token`.
+    fn from_env(service: Service, bucket: &str) -> Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let endpoint = match service {
+            Service::S3 => var("AWS_ENDPOINT_URL_S3").or_else(|| var("AWS_ENDPOINT_URL")),
+            Service::Gcs => None,
+        };
+        // Checked before credentials, which offline there'd be no use for
+        let storage = format!("{}://{}", service.scheme(), bucket);
+        offline::check_url(endpoint.as_deref().unwrap_or(&storage), "Object storage")?;
+        let (base_url, auth) = match service {
+            Service::S3 => {
+                let (access_key, secret_key) =
+                    match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+                        (Some(access_key), Some(secret_key)) => (access_key, secret_key),
+                        _ => bail!(
+                            "Set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY to use s3:// databases"
+                        ),
+                    };
+                let region = var("AWS_REGION")
+                    .or_else(|| var("AWS_DEFAULT_REGION"))
+                    .unwrap_or_else(|| "us-east-1".to_string());
+                let base_url = match endpoint {
+                    Some(endpoint) => format!("{}/{}", endpoint.trim_end_matches('/'), bucket),
+                    None => format!("https://{}.s3.{}.amazonaws.com", bucket, region),
+                };
+                let auth = Auth::Aws {
+                    access_key,
+                    secret_key,
+                    session_token: var("AWS_SESSION_TOKEN"),
+                    region,
+                };
+                (base_url, auth)
+            }
+            Service::Gcs => {
+                let token = var("GOOGLE_OAUTH_ACCESS_TOKEN").context(
+                    "Set GOOGLE_OAUTH_ACCESS_TOKEN (e.g. to the output of \
+                     `gcloud auth print-access-token`) to use gs:// databases",
+                )?;
+                let base_url = format!("https://storage.googleapis.com/{}", bucket);
+                (base_url, Auth::Bearer(token))
+            }
+        };
+
+        let authority = base_url
+            .split_once("://")
+            .map_or(base_url.as_str(), |(_, rest)| rest);
+        let (host, base_path) = match authority.split_once('/') {
+            Some((host, path)) => (host.to_string(), format!("/{}", path)),
+            None => (authority.to_string(), String::new()),
+        };
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .timeout_read(Duration::from_secs(300))
+                .build(),
+            service,
+            host,
+            base_url,
+            base_path,
+            auth,
+        })
+    }
+
+    // The manifest and its version, or None if there is none
+    fn get_manifest(&self, key: &str) -> Result<Option<(Manifest, String)>> {
+        let response = match self.request("GET", key, &[], &[], &[]) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(self.error("GET", key, e)),
+        };
+        let version = self.version(&response)?;
+        let manifest = serde_json::from_reader(response.into_reader())
+            .with_context(|| format!("Invalid manifest: {}", key))?;
+        Ok(Some((manifest, version)))
+    }
+
+    fn head_version(&self, key: &str) -> Result<Option<String>> {
+        match self.request("HEAD", key, &[], &[], &[]) {
+            Ok(response) => Ok(Some(self.version(&response)?)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(self.error("HEAD", key, e)),
+        }
+    }
+
+    // Write the manifest if its version is still `expected` (None: if there
+    // is none), returning the new version, or None if it has changed
+    fn put_manifest(
+        &self,
+        key: &str,
+        manifest: &Manifest,
+        expected: Option<&str>,
+    ) -> Result<Option<String>> {
+        let body = serde_json::to_vec_pretty(manifest)?;
+        let condition = match (self.service, expected) {
+            (Service::S3, Some(etag)) => ("If-Match", etag),
+            (Service::S3, None) => ("If-None-Match", "*"),
+            (Service::Gcs, Some(generation)) => ("x-goog-if-generation-match", generation),
+            (Service::Gcs, None) => ("x-goog-if-generation-match", "0"),
+        };
+        let headers = [condition, ("Content-Type", "application/json")];
+        match self.request("PUT", key, &[], &headers, &body) {
+            Ok(response) => Ok(Some(self.version(&response)?)),
+            Err(ureq::Error::Status(409 | 412, _)) => Ok(None),
+            Err(e) => Err(self.error("PUT", key, e)),
+        }
+    }
+
+    // Download an object to `path`, checking it is the one the manifest lists
+    fn download(&self, key: &str, file: &RemoteFile, path: &Path) -> Result<()> {
+        let response = self.call("GET", key, &[], &[("If-Match", &file.etag)], &[])?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut reader = response.into_reader();
+        let mut writer = File::create(&tmp_path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            writer.write_all(&buffer[..read])?;
+        }
+        writer.sync_all()?;
+        drop(writer);
+
+        if hex(&hasher.finalize()) != file.sha256 {
+            let _ = fs::remove_file(&tmp_path);
+            bail!("{} doesn't match the checksum in the manifest", key);
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    // Upload a file, in parts if it is large, returning the object's ETag
+    fn upload(&self, key: &str, path: &Path) -> Result<String> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() <= PART_SIZE as u64 {
+            let mut body = Vec::new();
+            file.read_to_end(&mut body)?;
+            let response = self.call("PUT", key, &[], &[], &body)?;
+            return response
+                .header("ETag")
+                .map(str::to_string)
+                .context("No ETag in response");
+        }
+
+        let response = self.call("POST", key, &[("uploads", "")], &[], &[])?;
+        let upload_id =
+            xml_value(&response.into_string()?, "UploadId").context("No UploadId in response")?;
+        let result = self.upload_parts(key, &upload_id, file);
+        if result.is_err() {
+            let _ = self.request("DELETE", key, &[("uploadId", &upload_id)], &[], &[]);
+        }
+        result
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, mut file: File) -> Result<String> {
+        let mut etags = Vec::new();
+        let mut buffer = vec![0; PART_SIZE];
+        loop {
+            let read = read_full(&mut file, &mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            let number = (etags.len() + 1).to_string();
+            let query = [("partNumber", number.as_str()), ("uploadId", upload_id)];
+            let response = self.call("PUT", key, &query, &[], &buffer[..read])?;
+            let etag = response.header("ETag").context("No ETag in response")?;
+            etags.push(etag.to_string());
+            if read < PART_SIZE {
+                break;
+            }
+        }
+
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                i + 1,
+                etag
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let query = [("uploadId", upload_id)];
+        let response = self.call("POST", key, &query, &[], body.as_bytes())?;
+        // Completing can fail after the status line has been sent
+        let response = response.into_string()?;
+        if response.contains("<Error>") {
+            bail!(
+                "Multipart upload failed: {}",
+                xml_value(&response, "Message").unwrap_or(response)
+            );
+        }
+        xml_value(&response, "ETag")
+            .map(|etag| etag.replace("&quot;", "\""))
+            .context("No ETag in response")
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.call("DELETE", key, &[], &[], &[])?;
+        Ok(())
+    }
+
+    // ETag, or generation on GCS, of the object a response is about
+    fn version(&self, response: &ureq::Response) -> Result<String> {
+        let header = match self.service {
+            Service::S3 => "ETag",
+            Service::Gcs => "x-goog-generation",
+        };
+        response
+            .header(header)
+            .map(str::to_string)
+            .with_context(|| format!("No {} in response", header))
+    }
+
+    fn call(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<ureq::Response> {
+        self.request(method, key, query, headers, body)
+            .map_err(|e| self.error(method, key, e))
+    }
+
+    fn error(&self, method: &str, key: &str, error: ureq::Error) -> anyhow::Error {
+        match error {
+            ureq::Error::Status(code, response) => {
+                let detail = response.into_string().unwrap_or_default();
+                let detail = xml_value(&detail, "Message").unwrap_or(detail);
+                anyhow::anyhow!(
+                    "{} {} returned status {}: {}",
+                    method,
+                    key,
+                    code,
+                    detail.trim()
+                )
+            }
+            ureq::Error::Transport(e) => {
+                anyhow::anyhow!("Can't reach {} ({})", self.base_url, e)
+            }
+        }
+    }
+
+    // ureq's error holds the whole response, which callers need for its status
+    #[allow(clippy::result_large_err)]
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<ureq::Response, ureq::Error> {
+        let key = uri_encode(key, false);
+        let path = format!("{}/{}", self.base_path, key);
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let mut url = format!("{}/{}", self.base_url, key);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+        let mut request = self.agent.request(method, &url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+
+        match &self.auth {
+            Auth::Bearer(token) => {
+                request = request.set("Authorization", &format!("Bearer {}", token));
+            }
+            Auth::Aws {
+                access_key,
+                secret_key,
+                session_token,
+                region,
+            } => {
+                let timestamp = dates::format_basic(dates::now());
+                let payload_hash = hex(&Sha256::digest(body));
+                let mut canonical_headers = format!(
+                    "host:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n",
+                    self.host, payload_hash, timestamp
+                );
+                let mut signed_headers = "host;x-amz-content-sha256;x-amz-date".to_string();
+                if let Some(token) = session_token {
+                    canonical_headers.push_str(&format!("x-amz-security-token:{}\n", token));
+                    signed_headers.push_str(";x-amz-security-token");
+                    request = request.set("x-amz-security-token", token);
+                }
+                let canonical_request = format!(
+                    "{}\n{}\n{}\n{}\n{}\n{}",
+                    method, path, query, canonical_headers, signed_headers, payload_hash
+                );
+                let (scope, signature) =
+                    sign_v4(secret_key, region, &timestamp, &canonical_request);
+
+                request = request
+                    .set("x-amz-date", &timestamp)
+                    .set("x-amz-content-sha256", &payload_hash)
+                    .set(
+                        "Authorization",
+                        &format!(
+                            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                            access_key, scope, signed_headers, signature
+                        ),
+                    );
+            }
+        }
+
+        if body.is_empty() && method != "PUT" && method != "POST" {
+            request.call()
+        } else {
+            request.send_bytes(body)
+        }
+    }
+}
+
+// Credential scope and signature of an S3 request under AWS Signature
+// Version 4, made at `timestamp` (`YYYYMMDDTHHMMSSZ`)
+fn sign_v4(
+    secret_key: &str,
+    region: &str,
+    timestamp: &str,
+    canonical_request: &str,
+) -> (String, String) {
+    let date = &timestamp[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        timestamp,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut signing_key = hmac(format!("AWS4{}", secret_key).as_bytes(), date);
+    for part in [region, "s3", "aws4_request"] {
+        signing_key = hmac(&signing_key, part);
+    }
+    (scope, hex(&hmac(&signing_key, &string_to_sign)))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Percent-encode all but the unreserved characters, as S3 signatures
+// require; `/` is kept in keys but encoded in query parameters
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Text of the first `<tag>` element of an XML response
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{}>", tag))? + start;
+    Some(xml[start..end].to_string())
+}
+
+// Read until `buffer` is full or the reader is exhausted
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The GET Object example from the Signature Version 4 documentation
+    #[test]
+    fn signs_the_documented_example_request() {
+        let empty = hex(&Sha256::digest(b""));
+        let canonical_request = format!(
+            "GET\n/test.txt\n\nhost:examplebucket.s3.amazonaws.com\nrange:bytes=0-9\n\
+             x-amz-content-sha256:{}\nx-amz-date:20130524T000000Z\n\n\
+             host;range;x-amz-content-sha256;x-amz-date\n{}",
+            empty, empty
+        );
+        let secret_key = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+        let (scope, signature) =
+            sign_v4(secret_key, "us-east-1", "20130524T000000Z", &canonical_request);
+        assert_eq!(scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(
+            signature,
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    #[test]
+    fn encodes_uris_as_signing_expects() {
+        assert_eq!(uri_encode("stores/docs.json", false), "stores/docs.json");
+        assert_eq!(uri_encode("stores/docs.json", true), "stores%2Fdocs.json");
+        assert_eq!(uri_encode("a b+c~é", true), "a%20b%2Bc~%C3%A9");
+    }
+
+    #[test]
+    fn reads_xml_values() {
+        let xml = "<Result><UploadId>abc</UploadId><ETag>&quot;x&quot;</ETag></Result>";
+        assert_eq!(xml_value(xml, "UploadId").as_deref(), Some("abc"));
+        assert_eq!(xml_value(xml, "ETag").as_deref(), Some("&quot;x&quot;"));
+        assert_eq!(xml_value(xml, "Message"), None);
+    }
+
+    #[test]
+    fn local_names_stay_inside_the_local_copy() {
+        for name in ["docs.json", "docs.json.vectors", "docs.json.collections/notes.json"] {
+            assert!(is_local_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            "../../.bashrc",
+            "docs.json.collections/../../escape",
+            "/etc/passwd",
+            "./docs.json",
+            "docs.json.wal",
+            "docs.json.bak.1",
+            ".remote.json",
+        ] {
+            assert!(!is_local_name(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,913 @@
+use crate::collections;
+use crate::error::ArrowError;
+use crate::lock::StoreLock;
+use crate::vectorstore::{Document, DocumentOrder, Metadata, Score, VectorStore};
+use candle_core::Device;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response};
+use uuid::Uuid;
+
+// Payload keys a point's text is taken from, in order, as LangChain,
+// LlamaIndex and others name it
+const TEXT_KEYS: [&str; 4] = ["page_content", "text", "document", "content"];
+
+// Results returned by a search or scroll that doesn't give a limit
+const DEFAULT_LIMIT: usize = 10;
+
+// Makes the store for a collection created through the API, with the index
+// parameters and model `serve` was started with
+pub type NewStore = Box<dyn Fn(&str) -> anyhow::Result<VectorStore>>;
+
+// A subset of Qdrant's REST API over a database's collections, so Qdrant
+// clients and the LangChain and LlamaIndex integrations built on them can use
+// arrow as it is. Points carry precomputed vectors, like `import`; the text of
+// a point is the first of `TEXT_KEYS` in its payload. Each request loads the
+// collection, and changes are saved before responding, so the CLI can work on
+// the same database while it is served.
+pub struct Server {
+    http: tiny_http::Server,
+    database: String,
+    new_store: NewStore,
+}
+
+// An error response: the HTTP status and a message for the client
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+type ApiResult = Result<Value, ApiError>;
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError {
+        status: 400,
+        message: message.into(),
+    }
+}
+
+fn not_found(message: impl Into<String>) -> ApiError {
+    ApiError {
+        status: 404,
+        message: message.into(),
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError {
+            status: 500,
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+// Vectors of the wrong length and unusable arguments are the client's fault
+impl From<ArrowError> for ApiError {
+    fn from(e: ArrowError) -> Self {
+        match e {
+            ArrowError::DimensionMismatch { .. } | ArrowError::Invalid(_) => {
+                bad_request(e.to_string())
+            }
+            ArrowError::NotFound(_) => not_found(e.to_string()),
+            e => anyhow::Error::from(e).into(),
+        }
+    }
+}
+
+impl Server {
+    pub fn bind(addr: &str, database: &str, new_store: NewStore) -> anyhow::Result<Self> {
+        let http = tiny_http::Server::http(addr)
+            .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+        Ok(Self {
+            http,
+            database: database.to_string(),
+            new_store,
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    // Answer requests one at a time, until the process is stopped
+    pub fn run(&self) {
+        for mut request in self.http.incoming_requests() {
+            let start = Instant::now();
+            let result = self.handle(&mut request);
+            let elapsed = start.elapsed().as_secs_f64();
+            let (status, body) = match result {
+                Ok(result) => (200, json!({"result": result, "status": "ok", "time": elapsed})),
+                Err(e) => (e.status, json!({"status": {"error": e.message}, "time": elapsed})),
+            };
+            let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+            let response = Response::from_data(body.to_string())
+                .with_status_code(status)
+                .with_header(header);
+            // The client may have gone away; there's no one to tell
+            let _ = request.respond(response);
+        }
+    }
+
+    fn handle(&self, request: &mut Request) -> ApiResult {
+        let url = request.url().to_string();
+        let path = url.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body)?;
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, []) => Ok(json!({
+                "title": "arrow - Qdrant-compatible REST API",
+                "version": env!("CARGO_PKG_VERSION"),
+            })),
+            (Method::Get, ["collections"]) => self.list_collections(),
+            (Method::Get, ["collections", name]) => self.collection_info(name),
+            (Method::Put, ["collections", name]) => self.create_collection(name, parse(&body)?),
+            (Method::Delete, ["collections", name]) => self.delete_collection(name),
+            (Method::Get, ["collections", name, "exists"]) => {
+                Ok(json!({"exists": Path::new(&self.path(name)?).exists()}))
+            }
+            (Method::Put, ["collections", name, "points"]) => self.upsert(name, parse(&body)?),
+            (Method::Post, ["collections", name, "points"]) => self.retrieve(name, parse(&body)?),
+            (Method::Get, ["collections", name, "points", id]) => self.get_point(name, id),
+            (Method::Post, ["collections", name, "points", "search"]) => {
+                self.search(name, parse(&body)?)
+            }
+            (Method::Post, ["collections", name, "points", "scroll"]) => {
+                self.scroll(name, parse(&body)?)
+            }
+            (Method::Post, ["collections", name, "points", "count"]) => {
+                self.count(name, parse(&body)?)
+            }
+            (Method::Post, ["collections", name, "points", "delete"]) => {
+                self.delete_points(name, parse(&body)?)
+            }
+            _ => Err(not_found(format!("No route for {} {}", request.method(), path))),
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<String, ApiError> {
+        collections::collection_path(&self.database, Some(name))
+            .map_err(|e| bad_request(e.to_string()))
+    }
+
+    // The path of a collection that exists
+    fn existing_path(&self, name: &str) -> Result<String, ApiError> {
+        let path = self.path(name)?;
+        if !Path::new(&path).exists() {
+            return Err(not_found(format!("Collection `{}` doesn't exist", name)));
+        }
+        Ok(path)
+    }
+
+    fn load(&self, name: &str) -> Result<(String, VectorStore), ApiError> {
+        let path = self.existing_path(name)?;
+        let store = VectorStore::load(&path, Device::Cpu)?;
+        Ok((path, store))
+    }
+
+    // Load a collection to change it, holding its writer lock until the
+    // returned guard is dropped after saving. The lock is taken only once the
+    // collection is known to exist, so a missing one leaves no lock file.
+    fn load_for_writing(
+        &self,
+        name: &str,
+    ) -> Result<(String, VectorStore, StoreLock), ApiError> {
+        let path = self.existing_path(name)?;
+        let writer = StoreLock::writer(&path)?;
+        let (path, store) = self.load(name)?;
+        Ok((path, store, writer))
+    }
+
+    fn list_collections(&self) -> ApiResult {
+        let names = collections::list(&self.database)?;
+        let collections: Vec<Value> = names.iter().map(|name| json!({"name": name})).collect();
+        Ok(json!({"collections": collections}))
+    }
+
+    fn collection_info(&self, name: &str) -> ApiResult {
+        let (_, store) = self.load(name)?;
+        let count = store.text_count();
+        let size = store.dimension().or_else(|| store.model().dimension());
+        Ok(json!({
+            "status": "green",
+            "optimizer_status": "ok",
+            "vectors_count": count,
+            "indexed_vectors_count": count,
+            "points_count": count,
+            "segments_count": 1,
+            "config": {"params": {"vectors": {"size": size, "distance": "Cosine"}}},
+            "payload_schema": {},
+        }))
+    }
+
+    fn create_collection(&self, name: &str, request: CreateCollection) -> ApiResult {
+        let path = self.path(name)?;
+        if Path::new(&path).exists() {
+            return Err(ApiError {
+                status: 409,
+                message: format!("Collection `{}` already exists", name),
+            });
+        }
+        if request.vectors.distance != "Cosine" {
+            return Err(bad_request(format!(
+                "Distance {} isn't supported; arrow collections use Cosine",
+                request.vectors.distance
+            )));
+        }
+
+        let mut store = (self.new_store)(&path)?;
+        if let Some(dimension) = store.model().dimension() {
+            if dimension != request.vectors.size {
+                return Err(bad_request(format!(
+                    "Collections are created for {}, which gives {}-dimensional vectors, not {}; \
+                     start serve with the --provider and --model that produced them",
+                    store.model(),
+                    dimension,
+                    request.vectors.size
+                )));
+            }
+        }
+        if let Some(parent) = Path::new(&path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        store.save(&path)?;
+        Ok(json!(true))
+    }
+
+    fn delete_collection(&self, name: &str) -> ApiResult {
+        if name == collections::DEFAULT_COLLECTION {
+            return Err(bad_request(
+                "The default collection can't be deleted; delete the database file instead",
+            ));
+        }
+        let path = self.path(name)?;
+        if !Path::new(&path).exists() {
+            return Ok(json!(false));
+        }
+        let _writer = StoreLock::writer(&path)?;
+        VectorStore::delete_files(&path)?;
+        Ok(json!(true))
+    }
+
+    fn upsert(&self, name: &str, request: UpsertPoints) -> ApiResult {
+        let points = request.into_points()?;
+        let (path, mut store, _writer) = self.load_for_writing(name)?;
+
+        // A point given twice is written as last given
+        let mut latest: HashMap<Uuid, usize> = HashMap::new();
+        for (i, point) in points.iter().enumerate() {
+            latest.insert(point.id.uuid(), i);
+        }
+        let mut expected = store.dimension().or_else(|| store.model().dimension());
+        let mut documents = Vec::new();
+        for (i, point) in points.into_iter().enumerate() {
+            let id = point.id.uuid();
+            if latest[&id] != i {
+                continue;
+            }
+            let dimension = *expected.get_or_insert(point.vector.len());
+            if point.vector.len() != dimension {
+                return Err(bad_request(format!(
+                    "Point {} has a {}-dimensional vector, but the collection holds \
+                     {}-dimensional vectors",
+                    point.id,
+                    point.vector.len(),
+                    dimension
+                )));
+            }
+            let payload = point.payload.unwrap_or_default();
+            let text = TEXT_KEYS
+                .iter()
+                .find_map(|key| payload.get(*key).and_then(Value::as_str))
+                .unwrap_or_default()
+                .to_string();
+            let document = Document::new(store.vector_to_tensor(&point.vector)?, text)
+                .with_metadata(to_metadata(&payload))
+                .with_id(id);
+            documents.push(document);
+        }
+
+        for document in &documents {
+            store.remove(&document.id.unwrap());
+        }
+        store.add_batch(documents)?;
+        store.save(&path)?;
+        Ok(json!({"operation_id": 0, "status": "completed"}))
+    }
+
+    fn search(&self, name: &str, request: SearchRequest) -> ApiResult {
+        let vector = match request.vector {
+            QueryVector::Plain(vector) => vector,
+            QueryVector::Named { name, .. } => {
+                return Err(bad_request(format!("Named vectors aren't supported: {}", name)))
+            }
+        };
+        let (_, store) = self.load(name)?;
+        if let Some(dimension) = store.dimension() {
+            if vector.len() != dimension {
+                return Err(bad_request(format!(
+                    "The query vector has {} dimensions, but the collection holds {}",
+                    vector.len(),
+                    dimension
+                )));
+            }
+        }
+
+        // Filtered searches skip the points the filter rejects as they go;
+        // exact searches score every point
+        let wanted = request.offset.saturating_add(request.limit).min(store.text_count());
+        let candidates = match &request.filter {
+            _ if request.params.exact => store.exact_nearest(&vector, store.text_count()),
+            Some(filter) => store.nearest_where(&vector, wanted, &|id: &Uuid| {
+                filter.matches(id, &payload_of(&store, id))
+            }),
+            None => store.nearest(&vector, wanted),
+        };
+        let with_payload = request.with_payload.unwrap_or(WithPayload::Enabled(false));
+        let mut points = Vec::new();
+        for (id, distance) in candidates {
+            let score = Score::from_distance(distance).value;
+            if request.score_threshold.is_some_and(|threshold| score < threshold) {
+                break;
+            }
+            if let Some(filter) = &request.filter {
+                if !filter.matches(&id, &payload_of(&store, &id)) {
+                    continue;
+                }
+            }
+            points.push((id, score));
+            if points.len() == wanted {
+                break;
+            }
+        }
+
+        let results: Vec<Value> = points
+            .into_iter()
+            .skip(request.offset)
+            .map(|(id, score)| {
+                let mut point = point_json(&store, &id, &with_payload, request.with_vector);
+                point["score"] = json!(score);
+                point
+            })
+            .collect();
+        Ok(json!(results))
+    }
+
+    fn retrieve(&self, name: &str, request: RetrieveRequest) -> ApiResult {
+        let (_, store) = self.load(name)?;
+        let with_payload = request.with_payload.unwrap_or(WithPayload::Enabled(true));
+        let points: Vec<Value> = request
+            .ids
+            .iter()
+            .map(PointId::uuid)
+            .filter(|id| store.get_embedding(id).is_some())
+            .map(|id| point_json(&store, &id, &with_payload, request.with_vector))
+            .collect();
+        Ok(json!(points))
+    }
+
+    fn get_point(&self, name: &str, id: &str) -> ApiResult {
+        let id = match id.parse::<u64>() {
+            Ok(number) => PointId::Number(number),
+            Err(_) => PointId::Uuid(
+                Uuid::parse_str(id).map_err(|_| bad_request(format!("Invalid point id: {}", id)))?,
+            ),
+        };
+        let (_, store) = self.load(name)?;
+        if store.get_embedding(&id.uuid()).is_none() {
+            return Err(not_found(format!("No point with id {} found", id)));
+        }
+        Ok(point_json(&store, &id.uuid(), &WithPayload::Enabled(true), true))
+    }
+
+    // Points in id order, a page at a time from an optional starting id
+    fn scroll(&self, name: &str, request: ScrollRequest) -> ApiResult {
+        let (_, store) = self.load(name)?;
+        let with_payload = request.with_payload.unwrap_or(WithPayload::Enabled(true));
+        let start = request.offset.as_ref().map(PointId::uuid);
+        let mut ids = store
+            .list_ids(DocumentOrder::Id, None)
+            .into_iter()
+            .filter(|id| start.is_none_or(|start| *id >= start))
+            .filter(|id| match &request.filter {
+                Some(filter) => filter.matches(id, &payload_of(&store, id)),
+                None => true,
+            });
+        let page: Vec<Value> = ids
+            .by_ref()
+            .take(request.limit)
+            .map(|id| point_json(&store, &id, &with_payload, request.with_vector))
+            .collect();
+        let next = ids.next().map(|id| point_id_json(&id));
+        Ok(json!({"points": page, "next_page_offset": next}))
+    }
+
+    fn count(&self, name: &str, request: CountRequest) -> ApiResult {
+        let (_, store) = self.load(name)?;
+        let count = match &request.filter {
+            Some(filter) => store
+                .get_all_ids()
+                .iter()
+                .filter(|id| filter.matches(id, &payload_of(&store, id)))
+                .count(),
+            None => store.text_count(),
+        };
+        Ok(json!({"count": count}))
+    }
+
+    fn delete_points(&self, name: &str, request: DeletePoints) -> ApiResult {
+        let (path, mut store, _writer) = self.load_for_writing(name)?;
+        let ids: Vec<Uuid> = match request {
+            DeletePoints::Ids { points } => points.iter().map(PointId::uuid).collect(),
+            DeletePoints::Filter { filter } => store
+                .get_all_ids()
+                .into_iter()
+                .filter(|id| filter.matches(id, &payload_of(&store, id)))
+                .collect(),
+        };
+        let mut removed = false;
+        for id in &ids {
+            removed |= store.remove(id);
+        }
+        if removed {
+            store.save(&path)?;
+        }
+        Ok(json!({"operation_id": 0, "status": "completed"}))
+    }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, ApiError> {
+    // Some clients send no body where every field is optional
+    let body = if body.trim().is_empty() { "{}" } else { body };
+    serde_json::from_str(body).map_err(|e| bad_request(format!("Format error in JSON body: {}", e)))
+}
+
+// A point as Qdrant returns it, with its payload and vector if asked for
+fn point_json(store: &VectorStore, id: &Uuid, with_payload: &WithPayload, vector: bool) -> Value {
+    let mut point = json!({"id": point_id_json(id), "version": 0});
+    let payload = with_payload.select(payload_of(store, id));
+    if let Some(payload) = payload {
+        point["payload"] = Value::Object(payload);
+    }
+    if vector {
+        let record = store.export(id, true);
+        point["vector"] = json!(record.and_then(|record| record.vector));
+    }
+    point
+}
+
+// Integer ids are kept as UUIDs with the upper half zero, and given back as
+// integers
+fn point_id_json(id: &Uuid) -> Value {
+    let value = id.as_u128();
+    if value >> 64 == 0 {
+        json!(value as u64)
+    } else {
+        json!(id.to_string())
+    }
+}
+
+// Payload values are kept as metadata strings: strings as they are, unless
+// they would read as JSON, and everything else as JSON, so payloads come back
+// as they were given
+fn to_metadata(payload: &Map<String, Value>) -> Metadata {
+    payload
+        .iter()
+        .map(|(key, value)| {
+            let text = match value {
+                Value::String(s) if serde_json::from_str::<Value>(s).is_err() => s.clone(),
+                value => value.to_string(),
+            };
+            (key.clone(), text)
+        })
+        .collect()
+}
+
+fn payload_of(store: &VectorStore, id: &Uuid) -> Map<String, Value> {
+    let metadata = match store.get_metadata(id) {
+        Some(metadata) => metadata,
+        None => return Map::new(),
+    };
+    metadata
+        .iter()
+        .map(|(key, text)| {
+            let value = serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone()));
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+// A point id: an unsigned integer or a UUID
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum PointId {
+    Number(u64),
+    Uuid(Uuid),
+}
+
+impl PointId {
+    fn uuid(&self) -> Uuid {
+        match self {
+            PointId::Number(number) => Uuid::from_u128(*number as u128),
+            PointId::Uuid(uuid) => *uuid,
+        }
+    }
+}
+
+impl std::fmt::Display for PointId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PointId::Number(number) => write!(f, "{}", number),
+            PointId::Uuid(uuid) => write!(f, "{}", uuid),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateCollection {
+    vectors: VectorParams,
+}
+
+#[derive(Deserialize)]
+struct VectorParams {
+    size: usize,
+    distance: String,
+}
+
+#[derive(Deserialize)]
+struct Point {
+    id: PointId,
+    vector: Vec<f32>,
+    payload: Option<Map<String, Value>>,
+}
+
+// Points as a list, or as parallel lists of ids, vectors and payloads
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UpsertPoints {
+    List { points: Vec<Point> },
+    Batch { batch: Batch },
+}
+
+#[derive(Deserialize)]
+struct Batch {
+    ids: Vec<PointId>,
+    vectors: Vec<Vec<f32>>,
+    payloads: Option<Vec<Option<Map<String, Value>>>>,
+}
+
+impl UpsertPoints {
+    fn into_points(self) -> Result<Vec<Point>, ApiError> {
+        let batch = match self {
+            UpsertPoints::List { points } => return Ok(points),
+            UpsertPoints::Batch { batch } => batch,
+        };
+        let payloads = batch.payloads.unwrap_or_default();
+        if batch.vectors.len() != batch.ids.len()
+            || (!payloads.is_empty() && payloads.len() != batch.ids.len())
+        {
+            return Err(bad_request("The batch's ids, vectors and payloads differ in length"));
+        }
+        let mut payloads = payloads.into_iter();
+        Ok(batch
+            .ids
+            .into_iter()
+            .zip(batch.vectors)
+            .map(|(id, vector)| Point {
+                id,
+                vector,
+                payload: payloads.next().flatten(),
+            })
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QueryVector {
+    Plain(Vec<f32>),
+    Named {
+        name: String,
+        #[allow(dead_code)]
+        vector: Vec<f32>,
+    },
+}
+
+#[derive(Deserialize, Default)]
+struct SearchParams {
+    #[serde(default)]
+    exact: bool,
+}
+
+#[derive(Deserialize)]
+struct SearchRequest {
+    vector: QueryVector,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+    filter: Option<Filter>,
+    with_payload: Option<WithPayload>,
+    #[serde(default)]
+    with_vector: bool,
+    score_threshold: Option<f32>,
+    #[serde(default)]
+    params: SearchParams,
+}
+
+#[derive(Deserialize)]
+struct RetrieveRequest {
+    ids: Vec<PointId>,
+    with_payload: Option<WithPayload>,
+    #[serde(default)]
+    with_vector: bool,
+}
+
+#[derive(Deserialize)]
+struct ScrollRequest {
+    offset: Option<PointId>,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    filter: Option<Filter>,
+    with_payload: Option<WithPayload>,
+    #[serde(default)]
+    with_vector: bool,
+}
+
+#[derive(Deserialize)]
+struct CountRequest {
+    filter: Option<Filter>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DeletePoints {
+    Ids { points: Vec<PointId> },
+    Filter { filter: Filter },
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+// Which payload keys to return: all or none, only some, or all but some
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WithPayload {
+    Enabled(bool),
+    Keys(Vec<String>),
+    Selector {
+        include: Option<Vec<String>>,
+        exclude: Option<Vec<String>>,
+    },
+}
+
+impl WithPayload {
+    fn select(&self, mut payload: Map<String, Value>) -> Option<Map<String, Value>> {
+        match self {
+            WithPayload::Enabled(false) => return None,
+            WithPayload::Enabled(true) => {}
+            WithPayload::Keys(keys) | WithPayload::Selector { include: Some(keys), .. } => {
+                payload.retain(|key, _| keys.contains(key));
+            }
+            WithPayload::Selector { exclude, .. } => {
+                for key in exclude.iter().flatten() {
+                    payload.remove(key);
+                }
+            }
+        }
+        Some(payload)
+    }
+}
+
+// A Qdrant filter: every `must` condition holds, at least one `should` does
+// if any are given, and no `must_not` does
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Filter {
+    must: Option<Vec<Condition>>,
+    should: Option<Vec<Condition>>,
+    must_not: Option<Vec<Condition>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Condition {
+    Field(FieldCondition),
+    HasId { has_id: Vec<PointId> },
+    IsEmpty { is_empty: KeyRef },
+    IsNull { is_null: KeyRef },
+    Nested(Filter),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldCondition {
+    key: String,
+    #[serde(rename = "match")]
+    matches: Option<Match>,
+    range: Option<Range>,
+}
+
+#[derive(Deserialize)]
+struct KeyRef {
+    key: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Match {
+    Value { value: Value },
+    Any { any: Vec<Value> },
+    Except { except: Vec<Value> },
+    Text { text: String },
+}
+
+#[derive(Deserialize)]
+struct Range {
+    gt: Option<f64>,
+    gte: Option<f64>,
+    lt: Option<f64>,
+    lte: Option<f64>,
+}
+
+impl Filter {
+    fn matches(&self, id: &Uuid, payload: &Map<String, Value>) -> bool {
+        let holds = |condition: &Condition| condition.holds(id, payload);
+        self.must.iter().flatten().all(holds)
+            && self.must_not.iter().flatten().all(|condition| !holds(condition))
+            && match &self.should {
+                Some(should) if !should.is_empty() => should.iter().any(holds),
+                _ => true,
+            }
+    }
+}
+
+impl Condition {
+    fn holds(&self, id: &Uuid, payload: &Map<String, Value>) -> bool {
+        match self {
+            Condition::Field(field) => {
+                let values = values_at(payload, &field.key);
+                let matched = field.matches.as_ref().is_none_or(|m| m.holds(&values));
+                let in_range = field.range.as_ref().is_none_or(|range| {
+                    values.iter().filter_map(|v| v.as_f64()).any(|x| range.holds(x))
+                });
+                matched && in_range
+            }
+            Condition::HasId { has_id } => has_id.iter().any(|point| point.uuid() == *id),
+            Condition::IsEmpty { is_empty } => values_at(payload, &is_empty.key)
+                .iter()
+                .all(|value| value.is_null()),
+            Condition::IsNull { is_null } => match lookup(payload, &is_null.key) {
+                Some(value) => value.is_null(),
+                None => false,
+            },
+            Condition::Nested(filter) => filter.matches(id, payload),
+        }
+    }
+}
+
+impl Match {
+    fn holds(&self, values: &[&Value]) -> bool {
+        match self {
+            Match::Value { value } => values.contains(&value),
+            Match::Any { any } => values.iter().any(|value| any.contains(value)),
+            Match::Except { except } => values.iter().any(|value| !except.contains(value)),
+            Match::Text { text } => values
+                .iter()
+                .filter_map(|value| value.as_str())
+                .any(|value| value.contains(text.as_str())),
+        }
+    }
+}
+
+impl Range {
+    fn holds(&self, x: f64) -> bool {
+        self.gt.is_none_or(|bound| x > bound)
+            && self.gte.is_none_or(|bound| x >= bound)
+            && self.lt.is_none_or(|bound| x < bound)
+            && self.lte.is_none_or(|bound| x <= bound)
+    }
+}
+
+// The value at a dotted key like `metadata.source`
+fn lookup<'a>(payload: &'a Map<String, Value>, key: &str) -> Option<&'a Value> {
+    let mut parts = key.split('.');
+    let mut value = payload.get(parts.next()?)?;
+    for part in parts {
+        value = value.get(part)?;
+    }
+    Some(value)
+}
+
+// Every value at a dotted key, looking into arrays along the way and at its
+// end, as Qdrant does; `[]` after a part of the key is allowed and ignored
+fn values_at<'a>(payload: &'a Map<String, Value>, key: &str) -> Vec<&'a Value> {
+    let mut values: Vec<&Value> = Vec::new();
+    for (i, part) in key.split('.').enumerate() {
+        let part = part.trim_end_matches("[]");
+        values = if i == 0 {
+            payload.get(part).into_iter().collect()
+        } else {
+            values.iter().filter_map(|value| value.get(part)).collect()
+        };
+        values = values.into_iter().flat_map(flatten).collect();
+    }
+    values
+}
+
+fn flatten(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(items) => items.iter().collect(),
+        value => vec![value],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload() -> Map<String, Value> {
+        let payload = json!({
+            "page_content": "how to rotate the signing keys",
+            "metadata": {"source": "docs/keys.md", "page": 3, "tags": ["security", "ops"]},
+            "chunks": [{"lang": "en"}, {"lang": "de"}],
+            "owner": null,
+            "reviewers": [],
+        });
+        payload.as_object().unwrap().clone()
+    }
+
+    // Whether the filter given as JSON matches point 7 with `payload()`
+    fn matches(filter: Value) -> bool {
+        let filter: Filter = serde_json::from_value(filter).unwrap();
+        filter.matches(&Uuid::from_u128(7), &payload())
+    }
+
+    fn must(condition: Value) -> bool {
+        matches(json!({"must": [condition]}))
+    }
+
+    #[test]
+    fn matches_values_at_dotted_keys_and_inside_arrays() {
+        assert!(must(json!({"key": "metadata.source", "match": {"value": "docs/keys.md"}})));
+        assert!(must(json!({"key": "metadata.tags", "match": {"value": "ops"}})));
+        assert!(must(json!({"key": "chunks[].lang", "match": {"any": ["fr", "de"]}})));
+        assert!(!must(json!({"key": "metadata.tags", "match": {"value": "dev"}})));
+        assert!(!must(json!({"key": "missing", "match": {"value": 1}})));
+        assert!(must(json!({"key": "page_content", "match": {"text": "rotate"}})));
+        assert!(must(json!({"key": "metadata.tags", "match": {"except": ["ops"]}})));
+        assert!(!must(json!({"key": "chunks.lang", "match": {"except": ["en", "de"]}})));
+    }
+
+    #[test]
+    fn matches_ranges() {
+        assert!(must(json!({"key": "metadata.page", "range": {"gte": 3, "lt": 4}})));
+        assert!(!must(json!({"key": "metadata.page", "range": {"gt": 3}})));
+        // Strings aren't numbers, so no value falls in any range
+        assert!(!must(json!({"key": "metadata.source", "range": {"gte": 0}})));
+    }
+
+    #[test]
+    fn combines_must_should_and_must_not() {
+        let source = json!({"key": "metadata.source", "match": {"value": "docs/keys.md"}});
+        let dev = json!({"key": "metadata.tags", "match": {"value": "dev"}});
+        assert!(matches(json!({})));
+        assert!(matches(json!({"should": [dev, source]})));
+        assert!(!matches(json!({"should": [dev]})));
+        assert!(matches(json!({"should": []})));
+        assert!(!matches(json!({"must": [source], "must_not": [source]})));
+        assert!(matches(json!({"must_not": [{"should": [dev]}]})));
+    }
+
+    #[test]
+    fn matches_ids_and_empty_or_null_fields() {
+        assert!(must(json!({"has_id": [3, 7]})));
+        assert!(!must(json!({"has_id": [Uuid::from_u128(8).to_string()]})));
+        assert!(must(json!({"is_empty": {"key": "reviewers"}})));
+        assert!(must(json!({"is_empty": {"key": "missing"}})));
+        assert!(must(json!({"is_empty": {"key": "owner"}})));
+        assert!(!must(json!({"is_empty": {"key": "metadata.tags"}})));
+        assert!(must(json!({"is_null": {"key": "owner"}})));
+        assert!(!must(json!({"is_null": {"key": "missing"}})));
+    }
+
+    #[test]
+    fn rejects_unknown_filter_clauses() {
+        assert!(serde_json::from_value::<Filter>(json!({"min_should": {}})).is_err());
+        let condition = json!({"must": [{"key": "page", "geo_radius": {}}]});
+        assert!(serde_json::from_value::<Filter>(condition).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::lock::StoreLock;
+use crate::vectorstore::VectorStore;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+
+// Name the store's files are copied under within a snapshot, followed by
+// their suffix
+const STORE_FILE: &str = "store";
+
+// What a snapshot holds, kept in its `manifest.json`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub name: String,
+    // Seconds since the Unix epoch
+    pub created: u64,
+    pub documents: usize,
+    pub model: String,
+    pub bytes: u64,
+    // Suffixes of the copied files after the store's name: "" for the store
+    // file itself, then its WAL, segments, vectors and embedding cache
+    pub files: Vec<String>,
+}
+
+// Snapshots of a store live in `<path>.snapshots/<name>/`
+pub fn snapshots_dir(store_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.snapshots", store_path))
+}
+
+pub fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !name.starts_with('.');
+    if !valid {
+        bail!(
+            "Invalid snapshot name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        );
+    }
+    Ok(())
+}
+
+// Copy the store into a snapshot. The copy is made in a hidden directory and
+// renamed into place, so an interrupted snapshot never looks complete.
+pub fn create(
+    store_path: &str,
+    name: &str,
+    documents: usize,
+    model: String,
+    replace: bool,
+) -> Result<Manifest> {
+    validate_name(name)?;
+    let dir = snapshots_dir(store_path).join(name);
+    if dir.exists() && !replace {
+        bail!("Snapshot '{}' already exists; pass --force to replace it", name);
+    }
+    let partial = snapshots_dir(store_path).join(format!(".{}.partial", name));
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+
+    // Wait out any save in progress, so the files are copied as one state
+    let _lock = StoreLock::read(store_path)?;
+    let mut files = Vec::new();
+    let mut bytes = 0;
+    for (suffix, path) in content_files(store_path)? {
+        let copy = partial.join(format!("{}{}", STORE_FILE, suffix));
+        bytes += fs::copy(&path, &copy)
+            .with_context(|| format!("Failed to copy {}", path.display()))?;
+        files.push(suffix);
+    }
+    let manifest = Manifest {
+        name: name.to_string(),
+        created: crate::dates::now(),
+        documents,
+        model,
+        bytes,
+        files,
+    };
+    fs::write(partial.join(MANIFEST), serde_json::to_vec_pretty(&manifest)?)?;
+
+    if dir.exists() {
+        fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to replace {}", dir.display()))?;
+    }
+    fs::rename(&partial, &dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(manifest)
+}
+
+// Path of a store file given either as a path or as the name of one of the
+// store's snapshots, so commands that read a store can open a snapshot
+pub fn resolve(store_path: &str, path_or_name: &str) -> Result<String> {
+    if Path::new(path_or_name).exists() {
+        return Ok(path_or_name.to_string());
+    }
+    let snapshot = snapshots_dir(store_path).join(path_or_name).join(STORE_FILE);
+    if validate_name(path_or_name).is_ok() && snapshot.is_file() {
+        return Ok(snapshot.to_string_lossy().to_string());
+    }
+    bail!("No store or snapshot named '{}'", path_or_name);
+}
+
+// Every snapshot of the store, oldest first
+pub fn list(store_path: &str) -> Result<Vec<Manifest>> {
+    let dir = snapshots_dir(store_path);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut manifests = Vec::new();
+    for entry in fs::read_dir(&dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if validate_name(&name).is_ok() && entry.path().join(MANIFEST).is_file() {
+            manifests.push(read_manifest(store_path, &name)?);
+        }
+    }
+    manifests.sort_by(|a, b| (a.created, &a.name).cmp(&(b.created, &b.name)));
+    Ok(manifests)
+}
+
+// Replace the store's contents with a snapshot's. The snapshot's files are
+// copied beside the store first, then swapped in while readers are held off,
+// and files the snapshot doesn't have (such as newer segments) are removed.
+pub fn restore(store_path: &str, name: &str) -> Result<Manifest> {
+    let manifest = read_manifest(store_path, name)?;
+    let dir = snapshots_dir(store_path).join(name);
+    let mut staged = Vec::new();
+    for suffix in &manifest.files {
+        let copy = dir.join(format!("{}{}", STORE_FILE, suffix));
+        let target = format!("{}{}", store_path, suffix);
+        let staging = PathBuf::from(format!("{}.restore", target));
+        fs::copy(&copy, &staging)
+            .with_context(|| format!("Failed to copy {}", copy.display()))?;
+        staged.push((staging, target));
+    }
+
+    let _lock = StoreLock::save(store_path)?;
+    for (suffix, path) in content_files(store_path)? {
+        if !manifest.files.contains(&suffix) {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    for (staging, target) in staged {
+        fs::rename(&staging, &target).with_context(|| format!("Failed to restore {}", target))?;
+    }
+    Ok(manifest)
+}
+
+pub fn delete(store_path: &str, name: &str) -> Result<Manifest> {
+    let manifest = read_manifest(store_path, name)?;
+    let dir = snapshots_dir(store_path).join(name);
+    fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+    Ok(manifest)
+}
+
+fn read_manifest(store_path: &str, name: &str) -> Result<Manifest> {
+    validate_name(name)?;
+    let path = snapshots_dir(store_path).join(name).join(MANIFEST);
+    if !path.is_file() {
+        bail!("No snapshot named '{}'; see `arrow snapshot list`", name);
+    }
+    let json = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&json).with_context(|| format!("Invalid manifest: {}", path.display()))
+}
+
+// The store's files that hold its contents, by suffix. Locks, backups of
+// earlier saves, query history and an unfinished add's journal stay behind.
+fn content_files(store_path: &str) -> Result<Vec<(String, PathBuf)>> {
+    let name = Path::new(store_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut files = Vec::new();
+    for path in VectorStore::files(store_path)? {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let suffix = file_name.strip_prefix(&name).unwrap_or_default().to_string();
+        let skipped = matches!(suffix.as_str(), ".lock" | ".writer.lock" | ".history" | ".journal")
+            || suffix.starts_with(".bak.")
+            || suffix.ends_with(".tmp")
+            || suffix.ends_with(".restore");
+        if !skipped {
+            files.push((suffix, path));
+        }
+    }
+    Ok(files)
+}
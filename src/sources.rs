@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Hex-encoded SHA-256 of the file contents
+pub fn hash_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let bytes = fs::read(path.as_ref())
+        .with_context(|| format!("Failed to read file: {}", path.as_ref().display()))?;
+    Ok(hash_bytes(&bytes))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    let digest = Sha256::digest(bytes);
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Modification time in seconds since the Unix epoch (0 if unavailable)
+pub fn file_mtime<P: AsRef<Path>>(path: P) -> u64 {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// Expand a list of files and directories into the regular files they contain.
+// Directories are walked recursively, skipping hidden entries.
+pub fn collect_files(paths: &[String]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        walk(Path::new(path), &mut files);
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+fn walk(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return;
+    }
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let entry_path = entry.path();
+        let hidden = entry_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.starts_with('.'))
+            .unwrap_or(false);
+        if !hidden {
+            walk(&entry_path, files);
+        }
+    }
+}
+
+// Whether `path` is below a hidden entry of the root it's under, which
+// `collect_files` would skip. Roots are canonical, as are the paths file
+// events carry.
+pub fn is_hidden(path: &Path, roots: &[PathBuf]) -> bool {
+    let relative = roots
+        .iter()
+        .find_map(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path);
+    relative.components().any(|component| match component {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => false,
+    })
+}
+
+// Whether `path` is one of the files of the store at `db_path`: the store
+// itself, or a sidecar named after it such as its WAL, lock, backups and
+// segments
+pub fn is_store_file(path: &Path, db_path: &Path) -> bool {
+    let (Some(name), Some(store_name)) = (path.file_name(), db_path.file_name()) else {
+        return false;
+    };
+    let (name, store_name) = (name.to_string_lossy(), store_name.to_string_lossy());
+    if name != store_name && !name.starts_with(&format!("{}.", store_name)) {
+        return false;
+    }
+    let directory = |path: &Path| {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+        fs::canonicalize(parent.unwrap_or(Path::new("."))).ok()
+    };
+    directory(path) == directory(db_path)
+}
+
+// Whether `source` lives at or below one of the given roots
+pub fn is_under_roots(source: &str, roots: &[String]) -> bool {
+    let source = Path::new(source);
+    roots.iter().any(|root| source.starts_with(Path::new(root)))
+}
+
+// A source path as --source patterns see it: without a leading `./`, and
+// with `/` separators
+pub fn normalize(path: &str) -> String {
+    let source = path.replace('\\', "/");
+    let mut source = source.as_str();
+    while let Some(rest) = source.strip_prefix("./") {
+        source = rest;
+    }
+    source.to_string()
+}
+
+// Match source paths against glob patterns such as `docs/**` or `*.md`.
+// `*` stays within a directory and `**` crosses them. As in .gitignore, a
+// pattern without a `/` matches at any depth, and one without wildcards
+// matches that file or anything under that directory.
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = normalize(pattern);
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = if pattern.contains('/') {
+            pattern.to_string()
+        } else {
+            format!("**/{}", pattern)
+        };
+        let mut globs = vec![anchored.clone()];
+        if !pattern.contains(['*', '?', '[', '{']) {
+            globs.push(format!("{}/**", anchored));
+        }
+        for glob in globs {
+            let glob = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid source pattern '{}'", pattern))?;
+            set.add(glob);
+        }
+    }
+    set.build().context("Invalid source patterns")
+}
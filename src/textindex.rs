@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// BM25 parameters
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+// Inverted index over chunk text for BM25 keyword scoring
+#[derive(Default)]
+pub struct TextIndex {
+    // Map from term to the documents containing it and the term's frequency
+    postings: HashMap<String, HashMap<Uuid, u32>>,
+    // Map from document to its length in terms
+    doc_lengths: HashMap<Uuid, u32>,
+    total_length: u64,
+}
+
+impl TextIndex {
+    pub fn add(&mut self, id: Uuid, text: &str) {
+        let terms = tokenize(text);
+        let length = terms.len() as u32;
+        for term in terms {
+            *self.postings.entry(term).or_default().entry(id).or_default() += 1;
+        }
+        if let Some(previous) = self.doc_lengths.insert(id, length) {
+            self.total_length -= previous as u64;
+        }
+        self.total_length += length as u64;
+    }
+
+    pub fn remove(&mut self, id: &Uuid, text: &str) {
+        for term in tokenize(text) {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(id);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        if let Some(length) = self.doc_lengths.remove(id) {
+            self.total_length -= length as u64;
+        }
+    }
+
+    // Top `k` documents by BM25 score, best first
+    pub fn search(&self, query: &str, k: usize) -> Vec<(Uuid, f32)> {
+        let doc_count = self.doc_lengths.len() as f32;
+        if doc_count == 0.0 {
+            return Vec::new();
+        }
+        let avg_length = self.total_length as f32 / doc_count;
+
+        let mut scores: HashMap<Uuid, f32> = HashMap::new();
+        let mut query_terms = tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+        for term in query_terms {
+            let docs = match self.postings.get(&term) {
+                Some(docs) => docs,
+                None => continue,
+            };
+            let df = docs.len() as f32;
+            let idf = ((doc_count - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (id, &tf) in docs {
+                let tf = tf as f32;
+                let length = self.doc_lengths[id] as f32;
+                let norm = K1 * (1.0 - B + B * length / avg_length);
+                *scores.entry(*id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut ranked: Vec<(Uuid, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(k);
+        ranked
+    }
+
+    // The `n` terms that best set a group of documents apart from the rest:
+    // found in many of the group's texts, and rare across the index
+    pub fn top_terms<'a>(&self, texts: impl Iterator<Item = &'a str>, n: usize) -> Vec<String> {
+        let doc_count = self.doc_lengths.len() as f32;
+        let mut group_df: HashMap<String, u32> = HashMap::new();
+        for text in texts {
+            let mut terms = tokenize(text);
+            terms.sort();
+            terms.dedup();
+            for term in terms {
+                // Skip short tokens and bare numbers, which rarely name a topic
+                if term.chars().count() < 3 || term.chars().all(|c| c.is_ascii_digit()) {
+                    continue;
+                }
+                *group_df.entry(term).or_default() += 1;
+            }
+        }
+
+        let mut scored: Vec<(String, f32)> = group_df
+            .into_iter()
+            .map(|(term, count)| {
+                let df = self.postings.get(&term).map_or(1, |docs| docs.len()) as f32;
+                let idf = (doc_count / df).ln() + 1.0;
+                (term, count as f32 * idf)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scored.into_iter().take(n).map(|(term, _)| term).collect()
+    }
+
+    // Byte range of the sentence of `text` sharing the most with the query,
+    // weighting each query term by its rarity. None if no term appears.
+    pub fn best_sentence(&self, text: &str, query: &str) -> Option<(usize, usize)> {
+        let doc_count = self.doc_lengths.len().max(1) as f32;
+        let mut query_terms = tokenize(query);
+        query_terms.sort();
+        query_terms.dedup();
+
+        let mut best = None;
+        let mut best_score = 0.0;
+        for (start, end) in sentences(text) {
+            let mut terms = tokenize(&text[start..end]);
+            terms.sort();
+            terms.dedup();
+            let score: f32 = query_terms
+                .iter()
+                .filter(|term| terms.binary_search(term).is_ok())
+                .map(|term| {
+                    let df = self.postings.get(term).map_or(1, |docs| docs.len()) as f32;
+                    (doc_count / df).ln() + 1.0
+                })
+                .sum();
+            if score > best_score {
+                best_score = score;
+                best = Some((start, end));
+            }
+        }
+        best
+    }
+}
+
+// Byte ranges of the sentences of `text`, trimmed: runs ending at '.', '!' or
+// '?' followed by whitespace, or at a blank line
+fn sentences(text: &str) -> Vec<(usize, usize)> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let end = match (c, next) {
+            ('.' | '!' | '?', Some(next)) if next.is_whitespace() => i + 1,
+            ('\n', Some('\n')) => i,
+            _ => continue,
+        };
+        sentences.push((start, end));
+        start = end;
+    }
+    sentences.push((start, text.len()));
+
+    sentences
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let sentence = &text[start..end];
+            let trimmed = sentence.trim_start();
+            let start = start + sentence.len() - trimmed.len();
+            let end = start + trimmed.trim_end().len();
+            (start < end).then_some((start, end))
+        })
+        .collect()
+}
+
+// Lowercased runs of alphanumerics and underscores, so identifiers and error
+// codes like `E0425` or `ERR_CONN_RESET` stay whole
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(texts: &[&str]) -> (TextIndex, Vec<Uuid>) {
+        let mut index = TextIndex::default();
+        let ids: Vec<Uuid> = texts.iter().map(|_| Uuid::new_v4()).collect();
+        for (id, text) in ids.iter().zip(texts) {
+            index.add(*id, text);
+        }
+        (index, ids)
+    }
+
+    #[test]
+    fn ranks_rare_terms_above_common_ones() {
+        let (index, ids) = index(&[
+            "the cache is warm",
+            "the cache is cold",
+            "the connection was reset with ERR_CONN_RESET",
+        ]);
+        let results = index.search("the err_conn_reset", 3);
+        assert_eq!(results[0].0, ids[2]);
+        assert!(results[0].1 > results[1].1);
+    }
+
+    #[test]
+    fn favors_shorter_documents_for_the_same_matches() {
+        let (index, ids) = index(&["parser error", "parser error in a longer text than the first"]);
+        let results = index.search("parser", 2);
+        assert_eq!(results.iter().map(|r| r.0).collect::<Vec<_>>(), vec![ids[0], ids[1]]);
+    }
+
+    #[test]
+    fn forgets_removed_documents() {
+        let (mut index, ids) = index(&["apples and pears", "plums"]);
+        index.remove(&ids[0], "apples and pears");
+        assert!(index.search("apples", 5).is_empty());
+        assert!(!index.postings.contains_key("pears"));
+        assert_eq!(index.total_length, 1);
+        assert_eq!(index.search("plums", 5)[0].0, ids[1]);
+    }
+
+    #[test]
+    fn keeps_identifiers_whole() {
+        assert_eq!(tokenize("Fix E0425 in foo_bar()!"), vec!["fix", "e0425", "in", "foo_bar"]);
+    }
+
+    #[test]
+    fn picks_the_sentence_matching_the_query() {
+        let (index, _) = index(&["retry the upload", "upload"]);
+        let text = "Uploads are slow.  Retry the upload on timeout!\n\nThen stop.";
+        let (start, end) = index.best_sentence(text, "retry timeout").unwrap();
+        assert_eq!(&text[start..end], "Retry the upload on timeout!");
+        assert_eq!(index.best_sentence(text, "unrelated"), None);
+    }
+
+    #[test]
+    fn splits_sentences_at_punctuation_and_blank_lines() {
+        let text = " One. Two?\n\nThree v1.2 ";
+        let parts: Vec<&str> = sentences(text).into_iter().map(|(s, e)| &text[s..e]).collect();
+        assert_eq!(parts, vec!["One.", "Two?", "Three v1.2"]);
+    }
+}
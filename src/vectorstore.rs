@@ -1,226 +1,2909 @@
+use crate::binary;
+use crate::crypto::Cipher;
+use crate::dates;
+use crate::dump::DumpRecord;
+use crate::lock::StoreLock;
 use crate::embedding::StoredEmbedding;
-use candle_core::{Device, Result, Tensor};
+use crate::error::{ArrowError, Result};
+use crate::ivf::InvertedFile;
+use crate::pq::{self, ProductQuantizer};
+use crate::progress::{ProgressEvent, ProgressSink};
+use crate::provider::{ModelSpec, Prompts};
+use crate::sources;
+use crate::textindex::TextIndex;
+use crate::wal::{self, Wal, WalEntry};
+use candle_core::{Device, Tensor};
+use globset::GlobSet;
+use half::f16;
+use rand::seq::SliceRandom;
 use rand::Rng;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use memmap2::Mmap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{debug, debug_span, trace};
 use uuid::Uuid;
 
+mod backend;
+mod graph;
+mod json;
+mod sqlite;
+
+pub use backend::StorageEngine;
+use graph::{reachable, Layer, NodeId, Visited, ABSENT};
+
+// Number of nodes whose neighbor candidates are searched in parallel before
+// being linked into the graph
+const BUILD_BATCH_SIZE: usize = 1024;
+
+// How many candidates per requested result are re-ranked once PQ codebooks
+// are trained
+const RERANK_FACTOR: usize = 4;
+
+// How many candidates per requested result the Hamming pre-filter passes on
+// to be re-ranked against the stored vectors
+const BINARY_RERANK_FACTOR: usize = 10;
+
+// How many candidates per requested result MMR chooses from
+const MMR_CANDIDATE_FACTOR: usize = 4;
+
+// How many candidates per requested result each hybrid ranking contributes
+const HYBRID_CANDIDATE_FACTOR: usize = 4;
+
+// How many candidates per requested result a grouped query starts from, and
+// how much it widens the search each time that isn't enough to fill the groups
+const GROUP_CANDIDATE_FACTOR: usize = 4;
+
+// Reciprocal rank fusion constant; dampens the weight of top ranks
+const RRF_K: f32 = 60.0;
+
+// Nearest nodes used as starting points for a range search
+const RANGE_SEEDS: usize = 16;
+
+// Extra candidates fetched on top of those expected to be tombstones, so a
+// search still fills its top k when several deleted nodes are nearby
+const TOMBSTONE_SLACK: usize = 8;
+
+// Stores with fewer documents than this are searched by scanning every
+// vector, which at that size is faster than the graph and never misses
+pub const DEFAULT_EXACT_BELOW: usize = 10_000;
+
+static EXACT_BELOW: AtomicUsize = AtomicUsize::new(DEFAULT_EXACT_BELOW);
+
+// Scan stores with fewer than `documents` documents instead of searching their
+// graph; 0 always searches the graph
+pub fn set_exact_below(documents: usize) {
+    EXACT_BELOW.store(documents, Ordering::Relaxed);
+}
+
+// Lists an IVF store is partitioned into unless told otherwise
+pub const DEFAULT_NLIST: usize = 1024;
+
+// IVF lists are trained once the store holds this many documents per list;
+// until then it is scanned
+const IVF_TRAIN_FACTOR: usize = 8;
+
+// Lists of an IVF store searched by default, nearest centroids first
+pub const DEFAULT_NPROBE: usize = 16;
+
+static NPROBE: AtomicUsize = AtomicUsize::new(DEFAULT_NPROBE);
+
+// Scan the `lists` nearest lists of IVF stores; more lists find more of the
+// true nearest neighbors at the cost of comparing more vectors
+pub fn set_nprobe(lists: usize) {
+    NPROBE.store(lists, Ordering::Relaxed);
+}
+
+// Nodes sampled to estimate how much of the store a filter admits
+const FILTER_SAMPLE: usize = 512;
+
+// Closest nodes a filtered search keeps expanding, at least, for each
+// admitted one it needs
+const FILTER_EF: usize = 64;
+
+// Fraction of the graph that may be tombstones before `vacuum` rebuilds it
+pub const DEFAULT_VACUUM_THRESHOLD: f32 = 0.2;
+
+// Number of `.bak` generations kept by default
+pub const DEFAULT_BACKUPS: usize = 2;
+
+fn default_backups() -> usize {
+    DEFAULT_BACKUPS
+}
+
+// How vectors are held in the graph
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageMode {
+    // Full-precision f32 vectors
+    #[default]
+    F32,
+    // Half-precision floats, 2x smaller than f32 with almost the same recall
+    F16,
+    // Per-vector scaled int8 codes, 4x smaller than f32
+    Int8,
+}
+
+// How a store finds the nearest vectors to a query
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    // Hierarchical navigable small world graph
+    #[default]
+    Hnsw,
+    // Inverted file: k-means lists of vectors, of which the nearest few are scanned
+    Ivf,
+    // No index: every vector is compared with the query, in parallel
+    Flat,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Node {
     id: Uuid,
+    // Unit-length copy of the embedding
     vector: Vec<f32>,
+    // Norm of the embedding before normalization
+    #[serde(default)]
+    norm: f32,
+    // Bits of the f16 vector, used instead of `vector` in f16 mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    halves: Vec<u16>,
+    // Int8 codes and their scale, used instead of `vector` in int8 mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    codes: Vec<i8>,
+    #[serde(default)]
+    scale: f32,
+    // Product quantization codes, present once codebooks have been trained
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pq_codes: Vec<u8>,
+    // Binary code of the vector in a store searched with a Hamming
+    // pre-filter. Cheap to compute, so made on load instead of saved.
+    #[serde(skip)]
+    bits: Vec<u64>,
+    // Row in the memory-mapped vectors file, used when `vector` is empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    row: Option<usize>,
+    // Unit-length vectors of other fields of the document, such as its title,
+    // kept in f32
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    fields: HashMap<String, Vec<f32>>,
+}
+
+// Free-form key/value metadata attached to a document
+pub type Metadata = HashMap<String, String>;
+
+// Order in which `list_ids` returns documents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DocumentOrder {
+    // By source file, then by position within it
+    Source,
+    // Oldest first; documents from before timestamps were kept come first
+    #[default]
+    Added,
+    // By UUID
+    Id,
+}
+
+// Byte range of a chunk within its source text
+pub type Span = (usize, usize);
+
+// Where a document came from: the source file it was chunked from, the
+// record for CSV and JSONL files, and its place among the chunks the file
+// (or record) was split into, counted from 1
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    pub source_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<usize>,
+    pub chunk_index: usize,
+    pub chunk_count: usize,
+}
+
+impl Provenance {
+    pub fn new(source_path: &str, chunk_index: usize, chunk_count: usize) -> Self {
+        Self {
+            source_path: source_path.to_string(),
+            record: None,
+            chunk_index,
+            chunk_count,
+        }
+    }
+
+    pub fn with_record(mut self, record: usize) -> Self {
+        self.record = Some(record);
+        self
+    }
+
+    // The filename the document is shown and exported under: `notes.md#chunk3`,
+    // `data.jsonl#record2`, or `data.jsonl#record2#chunk1` for a record split
+    // into several chunks
+    pub fn label(&self) -> String {
+        let mut label = self.source_path.clone();
+        if let Some(record) = self.record {
+            label.push_str(&format!("#record{}", record));
+        }
+        if self.record.is_none() || self.chunk_count > 1 {
+            label.push_str(&format!("#chunk{}", self.chunk_index));
+        }
+        label
+    }
+
+    // Read back a label, for documents stored or exported before provenance
+    // was recorded. The chunk count is left at 1 for the caller to fill in.
+    fn from_label(label: &str) -> Self {
+        let number = |label: &str, marker: &str| -> Option<(String, usize)> {
+            let (rest, number) = label.rsplit_once(marker)?;
+            Some((rest.to_string(), number.parse().ok()?))
+        };
+        let (rest, chunk) = number(label, "#chunk").unzip();
+        let rest = rest.unwrap_or_else(|| label.to_string());
+        let (source_path, record) = number(&rest, "#record").unzip();
+        Self {
+            source_path: source_path.unwrap_or(rest),
+            record,
+            chunk_index: chunk.unwrap_or(1),
+            chunk_count: 1,
+        }
+    }
+}
+
+// As tables show it: `notes.md (chunk 3/7)`, `data.jsonl (record 2)` or
+// `data.jsonl (record 2, chunk 1/3)`
+impl std::fmt::Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let chunk = format!("chunk {}/{}", self.chunk_index, self.chunk_count);
+        match self.record {
+            Some(record) if self.chunk_count > 1 => {
+                write!(f, "{} (record {}, {})", self.source_path, record, chunk)
+            }
+            Some(record) => write!(f, "{} (record {})", self.source_path, record),
+            None => write!(f, "{} ({})", self.source_path, chunk),
+        }
+    }
+}
+
+// Name by which queries address a document's main vector, the one the graph
+// is built on
+pub const TEXT_FIELD: &str = "text";
+
+// Metadata key holding the ISO 639-1 code of the language a chunk is in
+pub const LANGUAGE_KEY: &str = "lang";
+
+// A chunk and its embedding, ready to be inserted
+pub struct Document {
+    pub embedding: Tensor,
+    pub text: String,
+    pub filename: Option<String>,
+    pub provenance: Option<Provenance>,
+    pub span: Option<Span>,
+    pub metadata: Metadata,
+    // Embeddings of other fields, by field name
+    pub fields: HashMap<String, Tensor>,
+    // Node id to store the document under instead of a new one
+    pub id: Option<Uuid>,
+    // When the document was first added and last replaced, if not now
+    pub added: Option<u64>,
+    pub updated: Option<u64>,
+    // When the document expires, in seconds since the Unix epoch
+    pub expires: Option<u64>,
+}
+
+impl Document {
+    pub fn new(embedding: Tensor, text: String) -> Self {
+        Self {
+            embedding,
+            text,
+            filename: None,
+            provenance: None,
+            span: None,
+            metadata: Metadata::new(),
+            fields: HashMap::new(),
+            id: None,
+            added: None,
+            updated: None,
+            expires: None,
+        }
+    }
+
+    pub fn with_filename(mut self, filename: String) -> Self {
+        self.filename = Some(filename);
+        self
+    }
+
+    // Name the document after where it came from
+    pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+        self.filename = Some(provenance.label());
+        self.provenance = Some(provenance);
+        self
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_field(mut self, name: String, embedding: Tensor) -> Self {
+        self.fields.insert(name, embedding);
+        self
+    }
+
+    pub fn with_id(mut self, id: Uuid) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    // Keep timestamps from elsewhere, such as an export, instead of using now
+    pub fn with_timestamps(mut self, added: u64, updated: Option<u64>) -> Self {
+        self.added = Some(added);
+        self.updated = updated;
+        self
+    }
+
+    // Leave the document out of searches from this time on, if given
+    pub fn with_expiry(mut self, expires: Option<u64>) -> Self {
+        self.expires = expires;
+        self
+    }
+}
+
+// What a result's score measures
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    // Cosine similarity to the query, from -1 to 1. The graph is searched by
+    // cosine distance, 1 - similarity.
+    Cosine,
+    // Reciprocal rank fusion of several rankings. Only comparable between
+    // results of one query, and not a distance from anything.
+    Fused,
+}
+
+// A result's score, higher being better for every metric
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Score {
+    pub metric: Metric,
+    pub value: f32,
+}
+
+impl Score {
+    pub fn cosine(similarity: f32) -> Self {
+        Score {
+            metric: Metric::Cosine,
+            value: similarity,
+        }
+    }
+
+    pub fn from_distance(distance: f32) -> Self {
+        Score::cosine(1.0 - distance)
+    }
+
+    pub fn fused(value: f32) -> Self {
+        Score {
+            metric: Metric::Fused,
+            value,
+        }
+    }
+
+    // Cosine distance to the query; None for fused scores
+    pub fn distance(&self) -> Option<f32> {
+        match self.metric {
+            Metric::Cosine => Some(1.0 - self.value),
+            Metric::Fused => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.metric {
+            Metric::Cosine => write!(f, "{:.4}", self.value),
+            Metric::Fused => write!(f, "{:.4} (fused)", self.value),
+        }
+    }
+}
+
+// A document matching a query
+pub struct SearchResult {
+    pub text: String,
+    pub score: Score,
+    pub filename: Option<String>,
+    pub provenance: Option<Provenance>,
+    pub span: Option<Span>,
+    pub metadata: Metadata,
+    // Seconds since the Unix epoch, if known
+    pub added: Option<u64>,
+    pub updated: Option<u64>,
+}
+
+// Which documents a search or listing may return
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    // Bounds on when a document was added or last updated, in seconds since
+    // the Unix epoch, both inclusive
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    // Languages a document may be in, by the code in its `lang` metadata; any
+    // language, or none, when empty
+    pub languages: Vec<String>,
+    // Source files a document may come from, matched against its source path
+    // as `sources::normalize` gives it; any source when None
+    pub sources: Option<GlobSet>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none()
+            && self.until.is_none()
+            && self.languages.is_empty()
+            && self.sources.is_none()
+    }
+
+    // Whether a document with these timestamps, language and source file
+    // passes. Documents without a timestamp only pass a filter without time
+    // bounds, and documents without a language or source one without those.
+    pub fn admits(
+        &self,
+        added: Option<u64>,
+        updated: Option<u64>,
+        language: Option<&str>,
+        source: Option<&str>,
+    ) -> bool {
+        if let Some(patterns) = &self.sources {
+            if !source.is_some_and(|source| patterns.is_match(sources::normalize(source))) {
+                return false;
+            }
+        }
+        if !self.languages.is_empty()
+            && !language.is_some_and(|language| self.languages.iter().any(|l| l == language))
+        {
+            return false;
+        }
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        let changed = match updated.or(added) {
+            Some(changed) => changed,
+            None => return false,
+        };
+        if let Some(since) = self.since {
+            if changed < since {
+                return false;
+            }
+        }
+        if let Some(until) = self.until {
+            if changed > until {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// A normalized document ready to link into the graph: id, vector, norm,
+// field vectors and payload
+type Prepared = (Uuid, Vec<f32>, f32, HashMap<String, Vec<f32>>, Payload);
+
+// Everything stored alongside a vector
+struct Payload {
+    text: String,
+    filename: Option<String>,
+    provenance: Option<Provenance>,
+    span: Option<Span>,
+    external_id: Option<String>,
+    metadata: Metadata,
+    // Seconds since the Unix epoch
+    added: u64,
+    updated: Option<u64>,
+    expires: Option<u64>,
+}
+
+// A query vector prepared for the store's storage mode
+struct SearchQuery<'a> {
+    vector: &'a [f32],
+    // Product quantization lookup table for asymmetric distance computation
+    adc: Option<Vec<Vec<f32>>>,
+}
+
+// A node and its distance to the query, ordered by distance
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, NodeId);
+
+impl Eq for Scored {}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// A layer as saved by older versions, each node with its own copy of the vector
+#[derive(Deserialize)]
+struct LegacyLayer {
+    nodes: Vec<LegacyNode>,
+}
+
+#[derive(Deserialize)]
+struct LegacyNode {
+    #[serde(flatten)]
+    node: Node,
     neighbors: HashSet<Uuid>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Layer {
-    nodes: Vec<Node>,
-    id_to_index: HashMap<Uuid, usize>,
+// Shape of one layer of the graph
+#[derive(Serialize, Debug)]
+pub struct LayerStats {
+    pub level: usize,
+    pub nodes: usize,
+    // Directed links
+    pub edges: usize,
+    pub mean_degree: f32,
+    pub median_degree: usize,
+    pub p90_degree: usize,
+    pub max_degree: usize,
+    // Nodes with no links in or out
+    pub orphans: usize,
+    // Nodes a search starting at the layer's entry node can never visit
+    pub unreachable: usize,
+}
+
+// Size and health of a store's index, for diagnosing poor recall
+#[derive(Serialize, Debug)]
+pub struct StoreStats {
+    pub layers: Vec<LayerStats>,
+    pub dimension: Option<usize>,
+    pub max_connections: usize,
+    pub index: IndexKind,
+    // IVF lists, 0 until they are trained, and the size of the largest
+    pub ivf_lists: usize,
+    pub largest_list: usize,
+    // Rough bytes held in memory once loaded: vectors, links and payloads
+    pub memory_bytes: usize,
+    // Bytes of vectors read through the memory-mapped vectors file
+    pub mapped_bytes: usize,
+    // Removed documents still in the graph
+    pub tombstones: usize,
+    // Segments saved since the store file was last compacted
+    pub segments: usize,
+}
+
+// A group of similar documents found by `cluster`
+#[derive(Serialize, Debug)]
+pub struct Cluster {
+    pub members: Vec<Uuid>,
+    // Terms that set the group's texts apart from the rest of the store
+    pub terms: Vec<String>,
+}
+
+// An inconsistency found by `check`
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    // Graph layer, for problems in the graph rather than the payloads
+    pub layer: Option<usize>,
+    pub id: Uuid,
+    pub issue: String,
+}
+
+impl Problem {
+    fn new(layer: Option<usize>, id: Uuid, issue: impl Into<String>) -> Self {
+        Self {
+            layer,
+            id,
+            issue: issue.into(),
+        }
+    }
+}
+
+// Bookkeeping for a source file so unchanged files can be skipped on re-index
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SourceRecord {
+    pub hash: String,
+    pub mtime: u64,
+    pub chunk_ids: Vec<Uuid>,
+    // Characters in the file's chunks when it was indexed, and when its
+    // current content was; unknown in stores from before they were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chars: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indexed: Option<u64>,
+}
+
+// Limits on how far `add` may grow a store, kept with its settings
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quota {
+    // Most documents the store may hold
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_documents: Option<usize>,
+    // Most bytes the files a load reads may take up on disk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_empty(&self) -> bool {
+        self.max_documents.is_none() && self.max_bytes.is_none()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct VectorStore {
+    // Every stored vector, removed documents' included, with the fields kept alongside it
+    #[serde(default)]
+    nodes: Vec<Node>,
+    // Position of each node in `nodes`, rebuilt on load
+    #[serde(skip)]
+    node_index: HashMap<Uuid, NodeId>,
+    // Each layer of the graph; the base layer has every node
+    #[serde(default, rename = "graph")]
     layers: Vec<Layer>,
+    // Layers saved by older versions, moved into `nodes` and `graph` on load
+    #[serde(default, rename = "layers", skip_serializing)]
+    legacy_layers: Vec<LegacyLayer>,
+    // Node every search enters the graph at, on the top layer and so on all of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entry_point: Option<Uuid>,
     // Map from UUID to text content
     texts: HashMap<Uuid, String>,
     // Map from UUID to filename (if applicable)
     filenames: HashMap<Uuid, String>,
+    // Map from UUID to the source file and chunk a document came from. Filled
+    // in from filenames on load for documents added before it was recorded.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    provenance: HashMap<Uuid, Provenance>,
+    // Map from source file path to its content hash and chunks
+    #[serde(default)]
+    sources: HashMap<String, SourceRecord>,
+    // Map from caller-supplied document ID to the nodes stored under it
+    #[serde(default)]
+    external_ids: HashMap<String, Vec<Uuid>>,
+    // Reverse of `external_ids`, rebuilt on load
+    #[serde(skip)]
+    external_by_id: HashMap<Uuid, String>,
+    // Map from UUID to metadata (if any)
+    #[serde(default)]
+    metadata: HashMap<Uuid, Metadata>,
+    // Map from UUID to the chunk's byte range in its source (if known)
+    #[serde(default)]
+    spans: HashMap<Uuid, Span>,
+    // Map from UUID to when the document was added, in seconds since the Unix
+    // epoch (missing for documents added by older versions)
+    #[serde(default)]
+    added: HashMap<Uuid, u64>,
+    // Map from UUID to when the document last replaced an earlier version
+    #[serde(default)]
+    updated: HashMap<Uuid, u64>,
+    // Map from UUID to when the document expires. Expired documents are left
+    // out of search results until `remove_expired` drops them.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    expires: HashMap<Uuid, u64>,
+    // Removed documents whose nodes stay in the graph as routing points until
+    // the next vacuum. Searches pass through them but never return them.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    tombstones: HashSet<Uuid>,
+    // When each source removed this session was first added, so chunks of a
+    // changed file that is indexed again keep it
+    #[serde(skip)]
+    replaced_sources: HashMap<String, u64>,
+    // BM25 index over `texts`, rebuilt on load
+    #[serde(skip)]
+    text_index: TextIndex,
     #[serde(skip)]
     #[serde(default)]
     device: Option<Device>,
     max_connections: usize,
     m_l: f32,
+    // Whether node vectors are stored unit-length (false for stores saved by older versions)
+    #[serde(default)]
+    normalized: bool,
+    #[serde(default)]
+    storage: StorageMode,
+    // Model the stored embeddings were produced with; queries must use the same one
+    #[serde(default)]
+    model: ModelSpec,
+    // Prompts put in front of chunks and queries before embedding them
+    #[serde(default, skip_serializing_if = "Prompts::is_empty")]
+    prompts: Prompts,
+    // Trained product quantization codebooks, if any
+    #[serde(default)]
+    pq: Option<ProductQuantizer>,
+    // Search by scanning binary codes of the vectors for candidates, then
+    // re-ranking them, instead of walking the graph
+    #[serde(default)]
+    binary: bool,
+    #[serde(default)]
+    index: IndexKind,
+    // Number of lists an IVF store is partitioned into
+    #[serde(default)]
+    nlist: usize,
+    // The IVF lists, once there are enough documents to train them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ivf: Option<InvertedFile>,
+    // Keep f32 vectors in a flat `<path>.vectors` file that is memory-mapped on load
+    #[serde(default)]
+    mmap_vectors: bool,
+    // Vector dimension of the rows in the vectors file
+    #[serde(default)]
+    dim: usize,
+    // Bumped on every save so a new vectors file never overwrites the one the
+    // previously saved JSON refers to
+    #[serde(default)]
+    vectors_generation: u64,
+    // Number of previous saves kept as `<path>.bak.1` (newest) to `<path>.bak.N`
+    #[serde(default = "default_backups")]
+    backups: usize,
+    #[serde(default, skip_serializing_if = "Quota::is_empty")]
+    quota: Quota,
+    // Number of the first segment not yet merged into this file. Segments
+    // below it are left over from a compaction interrupted before it could
+    // delete them.
+    #[serde(default)]
+    next_segment: u64,
+    // Numbers of the segments replayed on load or sealed since
+    #[serde(skip)]
+    segments: Vec<u64>,
+    // Told from the file on load, so not saved
+    #[serde(skip)]
+    engine: StorageEngine,
+    // Encrypts the store file, WAL and segments; its parameters are kept in
+    // the file's header
+    #[serde(skip)]
+    cipher: Option<Cipher>,
+    // Set by changes the WAL doesn't record, such as rebuilding the graph,
+    // which only a full save of the store file can keep
+    #[serde(skip)]
+    needs_compaction: bool,
+    #[serde(skip)]
+    mmap: Option<Mmap>,
+    // Log of mutations since the last save, attached once the store has a path
+    #[serde(skip)]
+    wal: Option<Wal>,
+    // First WAL write failure from a method that can't return it, reported on save
+    #[serde(skip)]
+    wal_error: Option<std::io::Error>,
+    // Told about each document added as it is linked into the graph
+    #[serde(skip)]
+    progress: Option<Arc<dyn ProgressSink>>,
 }
 
 impl VectorStore {
     pub fn new(device: Device, max_connections: usize) -> Self {
+        Self::new_with_storage(device, max_connections, StorageMode::F32)
+    }
+
+    pub fn new_with_storage(device: Device, max_connections: usize, storage: StorageMode) -> Self {
         let m_l = 1.0 / (max_connections as f32).ln();
         Self {
-            layers: vec![Layer {
-                nodes: Vec::new(),
-                id_to_index: HashMap::new(),
-            }],
+            nodes: Vec::new(),
+            node_index: HashMap::new(),
+            layers: vec![Layer::default()],
+            legacy_layers: Vec::new(),
+            entry_point: None,
             texts: HashMap::new(),
             filenames: HashMap::new(),
+            provenance: HashMap::new(),
+            sources: HashMap::new(),
+            external_ids: HashMap::new(),
+            external_by_id: HashMap::new(),
+            metadata: HashMap::new(),
+            spans: HashMap::new(),
+            added: HashMap::new(),
+            updated: HashMap::new(),
+            expires: HashMap::new(),
+            tombstones: HashSet::new(),
+            replaced_sources: HashMap::new(),
+            text_index: TextIndex::default(),
             device: Some(device),
             max_connections,
             m_l,
+            normalized: true,
+            storage,
+            model: ModelSpec::default(),
+            prompts: Prompts::default(),
+            pq: None,
+            binary: false,
+            index: IndexKind::Hnsw,
+            nlist: DEFAULT_NLIST,
+            ivf: None,
+            mmap_vectors: false,
+            dim: 0,
+            vectors_generation: 0,
+            backups: DEFAULT_BACKUPS,
+            quota: Quota::default(),
+            next_segment: 0,
+            segments: Vec::new(),
+            engine: StorageEngine::Json,
+            cipher: None,
+            needs_compaction: false,
+            mmap: None,
+            wal: None,
+            wal_error: None,
+            progress: None,
+        }
+    }
+
+    // Store vectors in a memory-mapped file next to the JSON instead of inline,
+    // so queries can run against stores larger than memory. Only f32 storage
+    // keeps full vectors, so quantized stores stay inline.
+    pub fn with_mmap_vectors(mut self, enabled: bool) -> Self {
+        self.mmap_vectors = enabled && self.storage == StorageMode::F32;
+        self
+    }
+
+    // Index the vectors with `index`; IVF stores are split into `nlist` lists
+    pub fn with_index(mut self, index: IndexKind, nlist: usize) -> Self {
+        self.index = index;
+        self.nlist = nlist;
+        self
+    }
+
+    pub fn index_kind(&self) -> IndexKind {
+        self.index
+    }
+
+    pub fn nlist(&self) -> usize {
+        self.nlist
+    }
+
+    // Whether an IVF store holds enough documents for its lists to be trained
+    pub fn is_ivf_trained(&self) -> bool {
+        self.ivf.is_some()
+    }
+
+    // Whether searches walk the graph, so new nodes are linked into it
+    fn has_graph(&self) -> bool {
+        self.index == IndexKind::Hnsw
+    }
+
+    pub fn with_engine(mut self, engine: StorageEngine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    // Report documents added from now on to `progress` as they are linked in
+    pub fn set_progress(&mut self, progress: Arc<dyn ProgressSink>) {
+        self.progress = Some(progress);
+    }
+
+    pub fn engine(&self) -> StorageEngine {
+        self.engine
+    }
+
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn with_model(mut self, model: ModelSpec) -> Self {
+        self.model = model;
+        self
+    }
+
+    // Switch a store that holds no vectors yet to another model. The WAL
+    // doesn't record settings, so the next save writes the store in full.
+    pub fn set_model(&mut self, model: ModelSpec, prompts: Prompts) -> Result<()> {
+        if !self.nodes.is_empty() {
+            return Err(ArrowError::Invalid(format!(
+                "Can't switch to {} once the store holds vectors from {}",
+                model, self.model
+            )));
+        }
+        self.model = model;
+        self.prompts = prompts;
+        self.needs_compaction = true;
+        Ok(())
+    }
+
+    pub fn model(&self) -> &ModelSpec {
+        &self.model
+    }
+
+    pub fn with_prompts(mut self, prompts: Prompts) -> Self {
+        self.prompts = prompts;
+        self
+    }
+
+    pub fn prompts(&self) -> &Prompts {
+        &self.prompts
+    }
+
+    // Set how many previous saves are kept as rotating backups
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    pub fn backups(&self) -> usize {
+        self.backups
+    }
+
+    pub fn with_quota(mut self, quota: Quota) -> Self {
+        self.quota = quota;
+        self
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    // Change the limits of a saved store. The WAL doesn't record settings, so
+    // the next save writes the store in full.
+    pub fn set_quota(&mut self, quota: Quota) {
+        self.quota = quota;
+        self.needs_compaction = true;
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn uses_mmap_vectors(&self) -> bool {
+        self.mmap_vectors
+    }
+
+    // The stored vector of a document
+    fn node(&self, id: &Uuid) -> &Node {
+        self.at(self.node_index[id])
+    }
+
+    fn at(&self, node: NodeId) -> &Node {
+        &self.nodes[node as usize]
+    }
+
+    // A node's f32 vector, read from the mapped file when it isn't held inline
+    fn vector_of<'a>(&'a self, node: &'a Node) -> &'a [f32] {
+        match (&self.mmap, node.row) {
+            (Some(mmap), Some(row)) if node.vector.is_empty() => {
+                let bytes = &mmap[row * self.dim * 4..(row + 1) * self.dim * 4];
+                // Safety: the file holds native-endian f32 rows and the mapping is
+                // page aligned, so every row is 4-byte aligned
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f32, self.dim) }
+            }
+            _ => &node.vector,
+        }
+    }
+
+    pub fn storage_mode(&self) -> StorageMode {
+        self.storage
+    }
+
+    fn prepare_query<'a>(&self, query: &'a [f32]) -> SearchQuery<'a> {
+        SearchQuery {
+            vector: query,
+            adc: self.pq.as_ref().map(|pq| pq.distance_table(query)),
+        }
+    }
+
+    // Distance used during graph traversal: from the PQ lookup table once
+    // codebooks are trained, otherwise the full-precision query against the
+    // stored vector
+    fn node_distance(&self, query: &SearchQuery, node: &Node) -> f32 {
+        if let Some(table) = &query.adc {
+            if !node.pq_codes.is_empty() {
+                return ProductQuantizer::adc_distance(table, &node.pq_codes);
+            }
+        }
+        self.stored_distance(query.vector, node)
+    }
+
+    // Distance between a full-precision query and a node's stored vector:
+    // exact for f32 and f16, and asymmetric against the codes for int8, whose
+    // original vectors aren't kept
+    fn stored_distance(&self, query: &[f32], node: &Node) -> f32 {
+        match self.storage {
+            StorageMode::F32 => self.cosine_distance(query, self.vector_of(node)),
+            // Converted as they're read, which costs little next to the memory saved
+            StorageMode::F16 => {
+                let dot: f32 = query
+                    .iter()
+                    .zip(&node.halves)
+                    .map(|(&a, &b)| a * f16::from_bits(b).to_f32())
+                    .sum();
+                1.0 - dot.clamp(-1.0, 1.0)
+            }
+            StorageMode::Int8 => {
+                let dot: f32 = query
+                    .iter()
+                    .zip(&node.codes)
+                    .map(|(&a, &b)| a * b as f32)
+                    .sum();
+                1.0 - (dot * node.scale).clamp(-1.0, 1.0)
+            }
+        }
+    }
+
+    // Full-precision (or dequantized) vector of a node
+    fn node_vector(&self, node: &Node) -> Vec<f32> {
+        match self.storage {
+            StorageMode::F32 => self.vector_of(node).to_vec(),
+            StorageMode::F16 => node.halves.iter().map(|&h| f16::from_bits(h).to_f32()).collect(),
+            StorageMode::Int8 => node.codes.iter().map(|&c| c as f32 * node.scale).collect(),
+        }
+    }
+
+    // Both vectors are unit length, so cosine similarity is just the dot product
+    fn cosine_distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
+        1.0 - dot(v1, v2).clamp(-1.0, 1.0)
+    }
+
+    // Normalize every stored vector in place, for stores saved before vectors
+    // were kept unit-length
+    fn normalize_all(&mut self) {
+        for node in &mut self.nodes {
+            node.norm = normalize(&mut node.vector);
+        }
+        self.normalized = true;
+        self.needs_compaction = true;
+    }
+
+    pub fn add(&mut self, embedding: Tensor, text: String) -> Result<Uuid> {
+        self.add_with_filename(embedding, text, None)
+    }
+
+    pub fn add_with_filename(
+        &mut self,
+        embedding: Tensor,
+        text: String,
+        filename: Option<String>,
+    ) -> Result<Uuid> {
+        let mut vector = embedding.to_vec1::<f32>()?;
+        check_dimension(&mut self.dimension(), vector.len())?;
+        let norm = normalize(&mut vector);
+        let id = Uuid::new_v4();
+        let max_level = self.random_level();
+
+        let payload = Payload {
+            text,
+            provenance: filename.as_deref().map(Provenance::from_label),
+            filename,
+            span: None,
+            external_id: None,
+            metadata: Metadata::new(),
+            added: dates::now(),
+            updated: None,
+            expires: None,
+        };
+        self.store_document(id, vector, norm, HashMap::new(), max_level, &[], payload)?;
+        self.sync_wal()?;
+
+        Ok(id)
+    }
+
+    // Insert or replace a document keyed by a caller-supplied stable ID. Any
+    // nodes previously stored under the ID are removed from the graph first.
+    pub fn upsert(
+        &mut self,
+        external_id: &str,
+        embedding: Tensor,
+        text: String,
+        meta: Metadata,
+    ) -> Result<Uuid> {
+        let ids = self.upsert_chunks(external_id, vec![Document::new(embedding, text)], meta)?;
+        Ok(ids[0])
+    }
+
+    // Upsert a document made of several chunks under one external ID. `meta`
+    // applies to every chunk, on top of each chunk's own metadata.
+    pub fn upsert_chunks(
+        &mut self,
+        external_id: &str,
+        items: Vec<Document>,
+        meta: Metadata,
+    ) -> Result<Vec<Uuid>> {
+        let mut previous = None;
+        if let Some(old_ids) = self.external_ids.remove(external_id) {
+            previous = old_ids.iter().filter_map(|id| self.added_at(id)).min();
+            for id in old_ids {
+                self.remove(&id);
+            }
+        }
+        self.add_batch_with(items, Some(external_id), &meta, previous)
+    }
+
+    // Node ids stored under an external ID
+    pub fn get_by_external_id(&self, external_id: &str) -> &[Uuid] {
+        self.external_ids
+            .get(external_id)
+            .map(|ids| ids.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // External ID a node is stored under, if any
+    pub fn external_id(&self, id: &Uuid) -> Option<&str> {
+        self.external_by_id.get(id).map(String::as_str)
+    }
+
+    pub fn get_metadata(&self, id: &Uuid) -> Option<&Metadata> {
+        self.metadata.get(id)
+    }
+
+    // Set one metadata key of a stored document, or clear it when `value` is None
+    pub fn set_metadata(&mut self, id: &Uuid, key: &str, value: Option<String>) -> bool {
+        if !self.texts.contains_key(id) {
+            return false;
+        }
+        self.log_deferred(WalEntry::SetMetadata {
+            id: *id,
+            key: key.to_string(),
+            value: value.clone(),
+        });
+        self.apply_metadata(id, key, value);
+        true
+    }
+
+    fn apply_metadata(&mut self, id: &Uuid, key: &str, value: Option<String>) {
+        match value {
+            Some(value) => {
+                self.metadata.entry(*id).or_default().insert(key.to_string(), value);
+            }
+            None => {
+                if let Some(metadata) = self.metadata.get_mut(id) {
+                    metadata.remove(key);
+                    if metadata.is_empty() {
+                        self.metadata.remove(id);
+                    }
+                }
+            }
+        }
+    }
+
+    // Add many embeddings at once. Nearest-neighbor candidates for each
+    // sub-batch are searched in parallel against the graph built so far, then
+    // the new nodes are linked in serially.
+    pub fn add_batch(&mut self, items: Vec<Document>) -> Result<Vec<Uuid>> {
+        self.add_batch_with(items, None, &Metadata::new(), None)
+    }
+
+    fn add_batch_with(
+        &mut self,
+        items: Vec<Document>,
+        external_id: Option<&str>,
+        meta: &Metadata,
+        previous: Option<u64>,
+    ) -> Result<Vec<Uuid>> {
+        let now = dates::now();
+        let mut dimension = self.dimension();
+        let mut prepared = Vec::with_capacity(items.len());
+        let mut derived = false;
+        for mut document in items {
+            let mut vector = document.embedding.to_vec1::<f32>()?;
+            check_dimension(&mut dimension, vector.len())?;
+            let norm = normalize(&mut vector);
+            let mut fields = HashMap::with_capacity(document.fields.len());
+            for (name, embedding) in document.fields {
+                let mut field = embedding.to_vec1::<f32>()?;
+                normalize(&mut field);
+                fields.insert(name, field);
+            }
+            for (key, value) in meta {
+                document.metadata.insert(key.clone(), value.clone());
+            }
+            // Documents from elsewhere, such as an export, may only have a filename
+            if document.provenance.is_none() {
+                document.provenance = document.filename.as_deref().map(Provenance::from_label);
+                derived |= document.provenance.is_some();
+            }
+            // A new version of an earlier document keeps its creation time
+            let replaced = previous.or_else(|| {
+                let source = &document.provenance.as_ref()?.source_path;
+                self.replaced_sources.get(source).copied()
+            });
+            let (added, updated) = match (document.added, replaced) {
+                (Some(added), _) => (added, document.updated),
+                (None, Some(replaced)) => (replaced, Some(now)),
+                (None, None) => (now, None),
+            };
+            let payload = Payload {
+                text: document.text,
+                filename: document.filename,
+                provenance: document.provenance,
+                span: document.span,
+                external_id: external_id.map(|e| e.to_string()),
+                metadata: document.metadata,
+                added,
+                updated,
+                expires: document.expires,
+            };
+            let id = document.id.unwrap_or_else(Uuid::new_v4);
+            prepared.push((id, vector, norm, fields, payload));
+        }
+
+        let ids = self.insert_prepared(prepared, true)?;
+        if derived {
+            self.count_chunks();
+        }
+        self.sync_wal()?;
+        Ok(ids)
+    }
+
+    // Link documents into the graph in batches whose neighbor candidates are
+    // searched in parallel, logging each to the WAL unless replaying it
+    fn insert_prepared(&mut self, prepared: Vec<Prepared>, log: bool) -> std::io::Result<Vec<Uuid>> {
+        if prepared.is_empty() {
+            return Ok(Vec::new());
+        }
+        let _span = debug_span!("insert", documents = prepared.len(), log).entered();
+        // Drop removed nodes stored under the same ids before searching for
+        // neighbors, so the new nodes can't link to them
+        for (id, ..) in &prepared {
+            if self.tombstones.remove(id) {
+                self.unlink(id);
+            }
+        }
+
+        let total = prepared.len();
+        // Replayed documents were reported when first added
+        let progress = self.progress.clone().filter(|_| log);
+        let mut ids = Vec::with_capacity(total);
+        let mut remaining = prepared.into_iter().peekable();
+        while remaining.peek().is_some() {
+            let batch: Vec<_> = remaining.by_ref().take(BUILD_BATCH_SIZE).collect();
+            let levels: Vec<usize> = batch.iter().map(|_| self.random_level()).collect();
+            let vectors: Vec<&[f32]> = batch.iter().map(|(_, vector, ..)| &vector[..]).collect();
+            let candidates = self.nearest_candidates(&vectors, &levels);
+
+            for (((id, vector, norm, fields, payload), max_level), nearest) in
+                batch.into_iter().zip(levels).zip(candidates)
+            {
+                if log {
+                    self.store_document(id, vector, norm, fields, max_level, &nearest, payload)?;
+                } else {
+                    self.insert_node(id, vector, norm, fields, max_level, &nearest);
+                    self.insert_payload(id, payload);
+                }
+                ids.push(id);
+                if let Some(progress) = &progress {
+                    progress.event(ProgressEvent::NodeInserted {
+                        done: ids.len(),
+                        total,
+                    });
+                }
+            }
+        }
+        // A new node only gets links back from the neighbors it picked that
+        // still had room, and none from the rest of its batch, so it can be
+        // left unreachable, as can nodes that lost links to unlinked ones
+        for level in 0..self.layers.len() {
+            self.relink_unreachable(level);
+        }
+        if self.ivf.is_none() {
+            self.train_ivf();
+        }
+        Ok(ids)
+    }
+
+    // Search the current graph for each vector's nearest node on every level up
+    // to its own, in parallel. Nodes of the same batch don't see each other;
+    // `insert_node` links them into levels that don't exist yet.
+    fn nearest_candidates(
+        &self,
+        vectors: &[&[f32]],
+        levels: &[usize],
+    ) -> Vec<Vec<Option<NodeId>>> {
+        vectors
+            .par_iter()
+            .zip(levels.par_iter())
+            .map(|(vector, &max_level)| {
+                let mut nearest = vec![None; max_level + 1];
+                if !self.has_graph() {
+                    return nearest;
+                }
+                // Descend from the entry point, keeping the nearest node on each level
+                let query = self.prepare_query(vector);
+                let Some(mut entry) = self.entry_node() else {
+                    return nearest;
+                };
+                for level in (0..self.layers.len()).rev() {
+                    let found = self.search_layer(&query, level, 1, entry).first().map(|c| c.0);
+                    if let Some(node) = found {
+                        entry = node;
+                    }
+                    if let Some(slot) = nearest.get_mut(level) {
+                        *slot = found;
+                    }
+                }
+                nearest
+            })
+            .collect()
+    }
+
+    // Rebuild the graph from the stored vectors with a new `max_connections`,
+    // reassigning levels as if every document were added again. Documents and
+    // their payloads are untouched, so the WAL isn't written; save afterwards.
+    // A small `max_connections` can leave nodes unreachable, so they are
+    // relinked as `repair` would. Tombstones are left out of the new graph.
+    // IVF stores have no graph; their lists are trained again instead.
+    pub fn reindex(&mut self, max_connections: usize) {
+        let nodes: Vec<_> = self
+            .live_nodes()
+            .map(|node| (node.id, self.node_vector(node), node.norm, node.fields.clone()))
+            .collect();
+        self.tombstones.clear();
+
+        self.max_connections = max_connections;
+        self.m_l = 1.0 / (max_connections as f32).ln();
+        self.ivf = None;
+        self.nodes = Vec::with_capacity(nodes.len());
+        self.node_index = HashMap::with_capacity(nodes.len());
+        self.layers = vec![Layer::default()];
+        self.entry_point = None;
+
+        let mut remaining = nodes.into_iter().peekable();
+        while remaining.peek().is_some() {
+            let batch: Vec<_> = remaining.by_ref().take(BUILD_BATCH_SIZE).collect();
+            let levels: Vec<usize> = batch.iter().map(|_| self.random_level()).collect();
+            let vectors: Vec<&[f32]> = batch.iter().map(|(_, vector, ..)| &vector[..]).collect();
+            let candidates = self.nearest_candidates(&vectors, &levels);
+
+            for (((id, vector, norm, fields), max_level), nearest) in
+                batch.into_iter().zip(levels).zip(candidates)
+            {
+                self.insert_node(id, vector, norm, fields, max_level, &nearest);
+            }
+        }
+        for level in 0..self.layers.len() {
+            self.relink_unreachable(level);
+        }
+        self.train_ivf();
+        self.needs_compaction = true;
+    }
+
+    // Partition an IVF store into lists by k-means over its vectors, once it
+    // holds enough documents to train them. The lists aren't in the WAL, so
+    // they are kept by the next full save.
+    fn train_ivf(&mut self) {
+        let documents = self.nodes.len();
+        if self.index != IndexKind::Ivf || documents < self.nlist * IVF_TRAIN_FACTOR {
+            return;
+        }
+        let vectors: Vec<(Uuid, Vec<f32>)> = self
+            .nodes
+            .par_iter()
+            .map(|node| (node.id, self.node_vector(node)))
+            .collect();
+        let _span = debug_span!("train_ivf", nlist = self.nlist, documents).entered();
+        self.ivf = Some(InvertedFile::train(&vectors, self.nlist));
+        self.needs_compaction = true;
+    }
+
+    // Rebuild the graph without its tombstones, returning how many were dropped
+    pub fn vacuum(&mut self) -> usize {
+        let dropped = self.tombstones.len();
+        self.reindex(self.max_connections);
+        dropped
+    }
+
+    // Number of removed documents still in the graph
+    pub fn tombstone_count(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    // Share of the base layer taken up by tombstones
+    pub fn tombstone_ratio(&self) -> f32 {
+        match self.nodes.len() {
+            0 => 0.0,
+            nodes => self.tombstones.len() as f32 / nodes as f32,
+        }
+    }
+
+    // Whether a document's expiry time has passed
+    fn is_expired(&self, id: &Uuid) -> bool {
+        !self.expires.is_empty() && self.expires.get(id).is_some_and(|&at| at <= dates::now())
+    }
+
+    // Whether a node can be a search result: neither removed nor expired
+    fn is_live(&self, id: &Uuid) -> bool {
+        !self.tombstones.contains(id) && !self.is_expired(id)
+    }
+
+    // Remove the documents whose expiry time has passed, returning how many
+    pub fn remove_expired(&mut self) -> usize {
+        let now = dates::now();
+        let expired: Vec<Uuid> = self
+            .expires
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.remove(id);
+        }
+        expired.len()
+    }
+
+    // Nodes of documents that haven't been removed
+    fn live_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes
+            .iter()
+            .filter(|node| !self.tombstones.contains(&node.id))
+    }
+
+    // Log a document to the WAL, then link it into the graph and record its payload
+    #[allow(clippy::too_many_arguments)]
+    fn store_document(
+        &mut self,
+        id: Uuid,
+        vector: Vec<f32>,
+        norm: f32,
+        fields: HashMap<String, Vec<f32>>,
+        max_level: usize,
+        nearest: &[Option<NodeId>],
+        payload: Payload,
+    ) -> std::io::Result<()> {
+        self.log(WalEntry::Add {
+            id,
+            vector: vector.clone(),
+            norm,
+            fields: fields.clone(),
+            text: payload.text.clone(),
+            filename: payload.filename.clone(),
+            provenance: payload.provenance.clone().map(Box::new),
+            span: payload.span,
+            external_id: payload.external_id.clone(),
+            metadata: payload.metadata.clone(),
+            added: payload.added,
+            updated: payload.updated,
+            expires: payload.expires,
+        })?;
+        self.insert_node(id, vector, norm, fields, max_level, nearest);
+        self.insert_payload(id, payload);
+        Ok(())
+    }
+
+    fn insert_payload(&mut self, id: Uuid, payload: Payload) {
+        self.text_index.add(id, &payload.text);
+        self.texts.insert(id, payload.text);
+        if let Some(fname) = payload.filename {
+            self.filenames.insert(id, fname);
+        }
+        if let Some(provenance) = payload.provenance {
+            self.provenance.insert(id, provenance);
+        }
+        if let Some(span) = payload.span {
+            self.spans.insert(id, span);
+        }
+        if payload.added > 0 {
+            self.added.insert(id, payload.added);
+        }
+        if let Some(updated) = payload.updated {
+            self.updated.insert(id, updated);
+        }
+        if let Some(expires) = payload.expires {
+            self.expires.insert(id, expires);
+        }
+        if let Some(external_id) = payload.external_id {
+            self.external_ids
+                .entry(external_id.clone())
+                .or_default()
+                .push(id);
+            self.external_by_id.insert(id, external_id);
+        }
+        if !payload.metadata.is_empty() {
+            self.metadata.insert(id, payload.metadata);
+        }
+    }
+
+    fn random_level(&self) -> usize {
+        if !self.has_graph() {
+            return 0;
+        }
+        // In (0, 1], so a draw of zero can't give an infinite level
+        let uniform = 1.0 - rand::thread_rng().gen::<f32>();
+        (-uniform.ln() * self.m_l).floor() as usize
+    }
+
+    // Store a node's vector, then push the node into every layer up to
+    // `max_level` and link it to its nearest neighbor on each. `nearest` holds
+    // precomputed candidates per level; levels without one are searched here.
+    fn insert_node(
+        &mut self,
+        id: Uuid,
+        vector: Vec<f32>,
+        norm: f32,
+        fields: HashMap<String, Vec<f32>>,
+        max_level: usize,
+        nearest: &[Option<NodeId>],
+    ) {
+        // A node above the current top layer becomes the entry point
+        let raises_top = max_level >= self.layers.len() || self.entry_point.is_none();
+        while self.layers.len() <= max_level {
+            self.layers.push(Layer::default());
+        }
+
+        let pq_codes = match &self.pq {
+            Some(pq) => pq.encode(&vector),
+            None => Vec::new(),
+        };
+        let bits = if self.binary {
+            binary::encode(&vector)
+        } else {
+            Vec::new()
+        };
+        let node = match self.storage {
+            StorageMode::F32 => Node {
+                id,
+                vector: vector.clone(),
+                norm,
+                halves: Vec::new(),
+                codes: Vec::new(),
+                scale: 0.0,
+                pq_codes,
+                bits,
+                row: None,
+                fields,
+            },
+            StorageMode::F16 => Node {
+                id,
+                vector: Vec::new(),
+                norm,
+                halves: vector.iter().map(|&x| f16::from_f32(x).to_bits()).collect(),
+                codes: Vec::new(),
+                scale: 0.0,
+                pq_codes,
+                bits,
+                row: None,
+                fields,
+            },
+            StorageMode::Int8 => {
+                let (codes, scale) = quantize(&vector);
+                Node {
+                    id,
+                    vector: Vec::new(),
+                    norm,
+                    halves: Vec::new(),
+                    codes,
+                    scale,
+                    pq_codes,
+                    bits,
+                    row: None,
+                    fields,
+                }
+            }
+        };
+        let index = self.nodes.len() as NodeId;
+        self.node_index.insert(id, index);
+        self.nodes.push(node);
+
+        for level in 0..=max_level {
+            let neighbor = match nearest.get(level).copied().flatten() {
+                Some(neighbor) => Some(neighbor),
+                None if self.has_graph() => {
+                    self.find_nearest(&vector, level, 1).first().map(|c| c.0)
+                }
+                None => None,
+            };
+
+            self.layers[level].push(index);
+            if let Some(neighbor) = neighbor {
+                self.connect_nodes(level, index, neighbor);
+            }
+        }
+        if raises_top {
+            self.entry_point = Some(id);
+        }
+        if let Some(ivf) = &mut self.ivf {
+            ivf.assign(id, &vector);
+        }
+    }
+
+    fn connect_nodes(&mut self, level: usize, node1: NodeId, node2: NodeId) {
+        let layer = &mut self.layers[level];
+        layer.link(node1, node2, self.max_connections);
+        layer.link(node2, node1, self.max_connections);
+    }
+
+    // Remove a document. Its node stays in the graph as a tombstone so the
+    // paths through it still work; `vacuum` drops it for good.
+    pub fn remove(&mut self, id: &Uuid) -> bool {
+        if !self.texts.contains_key(id) {
+            return false;
+        }
+        self.log_deferred(WalEntry::Remove { id: *id });
+        if let Some(text) = self.texts.remove(id) {
+            self.text_index.remove(id, &text);
+        }
+        self.filenames.remove(id);
+        self.provenance.remove(id);
+        self.metadata.remove(id);
+        self.spans.remove(id);
+        self.added.remove(id);
+        self.updated.remove(id);
+        self.expires.remove(id);
+        if let Some(external_id) = self.external_by_id.remove(id) {
+            if let Some(ids) = self.external_ids.get_mut(&external_id) {
+                ids.retain(|other| other != id);
+                if ids.is_empty() {
+                    self.external_ids.remove(&external_id);
+                }
+            }
+        }
+        if self.node_index.contains_key(id) {
+            self.tombstones.insert(*id);
+        }
+        true
+    }
+
+    // Take a node out of the store and every layer, reconnecting neighbors it
+    // orphaned
+    fn unlink(&mut self, id: &Uuid) {
+        let Some(index) = self.node_index.remove(id) else {
+            return;
+        };
+        let mut affected = Vec::with_capacity(self.layers.len());
+        for layer in &mut self.layers {
+            let mut nodes = Vec::new();
+            if let Some((removed, linked_from)) = layer.remove(index) {
+                nodes.extend(removed);
+                nodes.extend(linked_from);
+            }
+            affected.push(nodes);
+        }
+
+        // The last node takes the removed one's place
+        let last = (self.nodes.len() - 1) as NodeId;
+        let node = self.nodes.swap_remove(index as usize);
+        if index != last {
+            self.node_index.insert(self.at(index).id, index);
+            for layer in &mut self.layers {
+                layer.renumber(last, index);
+            }
+            for node in affected.iter_mut().flatten() {
+                if *node == last {
+                    *node = index;
+                }
+            }
+        }
+        if self.ivf.is_some() {
+            let vector = self.node_vector(&node);
+            if let Some(ivf) = &mut self.ivf {
+                ivf.remove(id, &vector);
+            }
+        }
+
+        for (level, nodes) in affected.into_iter().enumerate() {
+            for node in nodes {
+                self.reconnect_if_orphaned(level, node);
+            }
+        }
+        self.layers.retain(|layer| !layer.is_empty());
+        if self.layers.is_empty() {
+            self.layers.push(Layer::default());
+        }
+        self.settle_entry_point();
+    }
+
+    // Keep the entry point on the top layer, moving it to the layer's first
+    // node if it was removed or the store was saved without one
+    fn settle_entry_point(&mut self) {
+        let top = self.layers.last().filter(|layer| !layer.is_empty());
+        if self.entry_node().is_some_and(|node| top.is_some_and(|layer| layer.contains(node))) {
+            return;
+        }
+        self.entry_point = top.and_then(Layer::entry).map(|node| self.at(node).id);
+    }
+
+    // Node of the entry point
+    fn entry_node(&self) -> Option<NodeId> {
+        self.entry_point.and_then(|id| self.node_index.get(&id).copied())
+    }
+
+    fn reconnect_if_orphaned(&mut self, level: usize, node: NodeId) {
+        let layer = &self.layers[level];
+        if !layer.contains(node) || !layer.neighbors(node).is_empty() || layer.len() < 2 {
+            return;
+        }
+
+        let vector = self.node_vector(self.at(node));
+        let nearest = self
+            .find_nearest(&vector, level, 2)
+            .into_iter()
+            .map(|(candidate, _)| candidate)
+            .find(|&candidate| candidate != node)
+            // The search starts from the entry point, which may be the orphan itself
+            .or_else(|| layer.members().iter().copied().find(|&other| other != node));
+        if let Some(nearest) = nearest {
+            self.connect_nodes(level, node, nearest);
+        }
+    }
+
+    pub fn query(
+        &self,
+        query_embedding: &Tensor,
+        k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        self.query_with_options(query_embedding, k, true)
+    }
+
+    // Query, optionally re-ranking candidates found with PQ or binary codes
+    // against the stored vectors
+    pub fn query_with_options(
+        &self,
+        query_embedding: &Tensor,
+        k: usize,
+        rerank: bool,
+    ) -> Result<Vec<SearchResult>> {
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+        Ok(self.resolve_results(self.search(&query, k, rerank)))
+    }
+
+    // Query among the documents a filter admits. The filter is applied
+    // during the search rather than to its results, so the top k is filled
+    // however few documents pass.
+    pub fn query_filtered(
+        &self,
+        query_embedding: &Tensor,
+        k: usize,
+        rerank: bool,
+        filter: &Filter,
+    ) -> Result<Vec<SearchResult>> {
+        if filter.is_empty() {
+            return self.query_with_options(query_embedding, k, rerank);
+        }
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+        let admits = |id: &Uuid| self.admitted(filter, id);
+        Ok(self.resolve_results(self.search_where(&query, k, rerank, &admits)))
+    }
+
+    // Exact top k among the documents a filter admits, by scanning every
+    // stored vector in parallel. Slower than the graph on large stores, but
+    // never misses a match.
+    pub fn query_exact(
+        &self,
+        query_embedding: &Tensor,
+        k: usize,
+        filter: &Filter,
+    ) -> Result<Vec<SearchResult>> {
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+        let admits = |id: &Uuid| self.admitted(filter, id);
+        Ok(self.resolve_results(self.scan_where(&query, k, &admits)))
+    }
+
+    // The best `per_group` results from each of the `groups` sources nearest the
+    // query, best source first, among the documents a filter admits. Results of
+    // a source are kept together; documents without a source are groups of
+    // their own. The search widens until each group is full or holds all of its
+    // source's chunks, so one very relevant file can't crowd out the others.
+    pub fn query_grouped(
+        &self,
+        query_embedding: &Tensor,
+        groups: usize,
+        per_group: usize,
+        rerank: bool,
+        exact: bool,
+        filter: &Filter,
+    ) -> Result<Vec<SearchResult>> {
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+        let admits = |id: &Uuid| self.admitted(filter, id);
+        let mut k = (groups * per_group * GROUP_CANDIDATE_FACTOR).max(1);
+        loop {
+            let nearest = match (exact, filter.is_empty()) {
+                (true, _) => self.scan_where(&query, k, &admits),
+                (false, true) => self.search(&query, k, rerank),
+                (false, false) => self.search_where(&query, k, rerank, &admits),
+            };
+            let exhausted = nearest.len() < k || k >= self.texts.len();
+            let (grouped, full) = self.group_by_source(nearest, groups, per_group);
+            if full || exhausted {
+                return Ok(self.resolve_results(grouped));
+            }
+            debug!(k, "groups not filled, widening the search");
+            k *= GROUP_CANDIDATE_FACTOR;
+        }
+    }
+
+    // Group results, best first, into the first `groups` sources seen, keeping
+    // up to `per_group` of each. Also returns whether every group is full.
+    fn group_by_source(
+        &self,
+        nearest: Vec<(Uuid, f32)>,
+        groups: usize,
+        per_group: usize,
+    ) -> (Vec<(Uuid, f32)>, bool) {
+        let mut order: Vec<String> = Vec::new();
+        let mut members: HashMap<String, Vec<(Uuid, f32)>> = HashMap::new();
+        for (id, distance) in nearest {
+            let source = match self.provenance.get(&id) {
+                Some(provenance) => provenance.source_path.clone(),
+                None => id.to_string(),
+            };
+            if let Some(group) = members.get_mut(&source) {
+                if group.len() < per_group {
+                    group.push((id, distance));
+                }
+            } else if order.len() < groups {
+                members.insert(source.clone(), vec![(id, distance)]);
+                order.push(source);
+            }
+        }
+
+        // A group is full at `per_group` results, or when it has every chunk
+        // its source was indexed into
+        let full = order.len() == groups
+            && order.iter().all(|source| {
+                let found = members[source].len();
+                let chunks = self.sources.get(source).map(|record| record.chunk_ids.len());
+                found == per_group || chunks.is_some_and(|chunks| found >= chunks)
+            });
+        let grouped = order
+            .into_iter()
+            .flat_map(|source| members.remove(&source).unwrap_or_default())
+            .collect();
+        (grouped, full)
+    }
+
+    // A query embedding as a vector, which must be as long as the stored ones
+    fn query_vector(&self, query_embedding: &Tensor) -> Result<Vec<f32>> {
+        let query = query_embedding.to_vec1::<f32>()?;
+        check_dimension(&mut self.dimension(), query.len())?;
+        Ok(query)
+    }
+
+    // Approximate nearest neighbors of a raw vector through the graph, as
+    // (id, distance) pairs, best first
+    pub fn nearest(&self, query: &[f32], k: usize) -> Vec<(Uuid, f32)> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        self.search(&query, k, true)
+    }
+
+    // Exact nearest neighbors of a raw vector, scanning every stored vector
+    pub fn exact_nearest(&self, query: &[f32], k: usize) -> Vec<(Uuid, f32)> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        self.scan(&query, k)
+    }
+
+    // Approximate nearest neighbors of a raw vector among the documents
+    // `admits` accepts, filtering during the search
+    pub fn nearest_where(
+        &self,
+        query: &[f32],
+        k: usize,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        self.search_where(&query, k, true, admits)
+    }
+
+    // Exact top k for a unit-length query, scanning every live node in parallel
+    fn scan(&self, query: &[f32], k: usize) -> Vec<(Uuid, f32)> {
+        self.scan_where(query, k, &|_| true)
+    }
+
+    // Exact top k among the live nodes `admits` accepts
+    fn scan_where(
+        &self,
+        query: &[f32],
+        k: usize,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        let nodes: Vec<&Node> = self.live_nodes().collect();
+        let _span = debug_span!("exact_search", k, nodes = nodes.len()).entered();
+        self.rank(query, &nodes, k, admits)
+    }
+
+    // Exact top k among the IVF lists nearest the query, falling back to a
+    // scan when the filter admits fewer than k documents in them
+    fn ivf_search(
+        &self,
+        query: &[f32],
+        k: usize,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        let nodes = self.probed_nodes(query);
+        let _span = debug_span!("ivf_search", k, nodes = nodes.len()).entered();
+        let nearest = self.rank(query, &nodes, k, admits);
+        if nearest.len() < k && self.ivf.is_some() {
+            debug!(found = nearest.len(), "too few documents in the probed lists, scanning");
+            return self.scan_where(query, k, admits);
+        }
+        nearest
+    }
+
+    // Live base-layer nodes in the lists of the `nprobe` centroids nearest
+    // the query, or every live node until the lists are trained
+    fn probed_nodes(&self, query: &[f32]) -> Vec<&Node> {
+        let Some(ivf) = &self.ivf else {
+            return self.live_nodes().collect();
+        };
+        ivf.probe(query, NPROBE.load(Ordering::Relaxed))
+            .filter(|id| !self.tombstones.contains(id))
+            .filter_map(|id| self.node_index.get(id))
+            .map(|&index| self.at(index))
+            .collect()
+    }
+
+    // Exact top k of `nodes` among those the filter admits that haven't expired
+    fn rank(
+        &self,
+        query: &[f32],
+        nodes: &[&Node],
+        k: usize,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        let mut scored: Vec<(Uuid, f32)> = nodes
+            .par_iter()
+            .filter(|node| !self.is_expired(&node.id) && admits(&node.id))
+            .map(|node| (node.id, self.stored_distance(query, node)))
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+        scored.truncate(k);
+        scored
+    }
+
+    // Rank the live documents the filter admits by the Hamming distance of
+    // their binary codes to the query's, then re-rank the best of them
+    // against the stored vectors
+    fn hamming_search(
+        &self,
+        query: &[f32],
+        k: usize,
+        rerank: bool,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        let code = binary::encode(query);
+        let nodes: Vec<&Node> = self.live_nodes().collect();
+        let _span = debug_span!("hamming_search", k, nodes = nodes.len()).entered();
+        let mut candidates: Vec<(&Node, u32)> = nodes
+            .par_iter()
+            .filter(|node| !self.is_expired(&node.id) && admits(&node.id))
+            .map(|node| (*node, binary::hamming(&code, &node.bits)))
+            .collect();
+        let wanted = if rerank { k * BINARY_RERANK_FACTOR } else { k };
+        if candidates.len() > wanted {
+            candidates.select_nth_unstable_by_key(wanted, |&(_, hamming)| hamming);
+            candidates.truncate(wanted);
+        }
+
+        let mut nearest: Vec<(Uuid, f32)> = candidates
+            .into_iter()
+            .map(|(node, hamming)| {
+                let distance = if rerank {
+                    self.stored_distance(query, node)
+                } else {
+                    binary::estimated_distance(hamming, query.len())
+                };
+                (node.id, distance)
+            })
+            .collect();
+        nearest.sort_by(|a, b| a.1.total_cmp(&b.1));
+        nearest.truncate(k);
+        nearest
+    }
+
+    // Whether searches scan every vector: the store has no index, or is small
+    // enough that scanning beats the index
+    fn scans_exactly(&self) -> bool {
+        self.index == IndexKind::Flat || self.texts.len() < EXACT_BELOW.load(Ordering::Relaxed)
+    }
+
+    // Group near-identical documents. Walking the base layer in order, each
+    // document not yet claimed keeps every later one whose similarity to it is
+    // at least `threshold`, so a kept document is close to all of its
+    // duplicates. Returns (kept, duplicates) for groups with any duplicates.
+    pub fn duplicates(&self, threshold: f32) -> Vec<(Uuid, Vec<Uuid>)> {
+        let nodes: Vec<&Node> = self.live_nodes().collect();
+        let max_distance = 1.0 - threshold;
+        let mut claimed = vec![false; nodes.len()];
+        let mut groups = Vec::new();
+        for i in 0..nodes.len() {
+            if claimed[i] {
+                continue;
+            }
+            let vector = self.node_vector(nodes[i]);
+            let matches: Vec<usize> = (i + 1..nodes.len())
+                .into_par_iter()
+                .filter(|&j| !claimed[j] && self.stored_distance(&vector, nodes[j]) <= max_distance)
+                .collect();
+            if matches.is_empty() {
+                continue;
+            }
+            for &j in &matches {
+                claimed[j] = true;
+            }
+            groups.push((nodes[i].id, matches.into_iter().map(|j| nodes[j].id).collect()));
+        }
+        groups
+    }
+
+    // Split the documents into at most `k` clusters with k-means over their
+    // vectors, largest first, each with its `terms` most distinctive terms
+    pub fn cluster(&self, k: usize, iterations: usize, terms: usize) -> Vec<Cluster> {
+        let nodes: Vec<&Node> = self.live_nodes().collect();
+        let vectors: Vec<Vec<f32>> = nodes.par_iter().map(|node| self.node_vector(node)).collect();
+        if vectors.is_empty() || k == 0 {
+            return Vec::new();
+        }
+
+        // k-means starts from the first k points, so shuffle to pick them at random
+        let mut points: Vec<&[f32]> = vectors.iter().map(|v| &v[..]).collect();
+        points.shuffle(&mut rand::thread_rng());
+        let centroids = pq::kmeans(&points, k.min(points.len()), iterations);
+
+        let assignments: Vec<usize> = vectors
+            .par_iter()
+            .map(|vector| pq::nearest_centroid(&centroids, vector))
+            .collect();
+        let mut members = vec![Vec::new(); centroids.len()];
+        for (node, c) in nodes.iter().zip(assignments) {
+            members[c].push(node.id);
+        }
+        members.retain(|ids| !ids.is_empty());
+        members.sort_by_key(|ids| std::cmp::Reverse(ids.len()));
+
+        members
+            .into_iter()
+            .map(|ids| {
+                let texts = ids.iter().filter_map(|id| self.texts.get(id)).map(|t| t.as_str());
+                Cluster {
+                    terms: self.text_index.top_terms(texts, terms),
+                    members: ids,
+                }
+            })
+            .collect()
+    }
+
+    // Up to `n` stored vectors picked at random, for use as benchmark queries
+    pub fn sample_vectors(&self, n: usize) -> Vec<Vec<f32>> {
+        self.live_nodes()
+            .collect::<Vec<_>>()
+            .choose_multiple(&mut rand::thread_rng(), n)
+            .map(|node| self.node_vector(node))
+            .collect()
+    }
+
+    // Graph search for a unit-length query
+    fn search(&self, query: &[f32], k: usize, rerank: bool) -> Vec<(Uuid, f32)> {
+        if self.scans_exactly() {
+            debug!(documents = self.texts.len(), index = ?self.index, "scanning every vector");
+            return self.scan(query, k);
+        }
+        if self.binary {
+            return self.hamming_search(query, k, rerank, &|_: &Uuid| true);
+        }
+        if self.index == IndexKind::Ivf {
+            return self.ivf_search(query, k, &|_: &Uuid| true);
+        }
+        let _span = debug_span!("search", k, rerank, layers = self.layers.len()).entered();
+        if self.pq.is_some() && rerank {
+            // Over-fetch with the PQ distance, then re-rank against the stored vectors
+            self.rerank_pq(query, self.find_live(query, k * RERANK_FACTOR), k)
+        } else {
+            self.find_live(query, k)
+        }
+    }
+
+    // The `k` of the candidates a PQ search found that are nearest by the
+    // distance to their stored vectors
+    fn rerank_pq(&self, query: &[f32], candidates: Vec<(Uuid, f32)>, k: usize) -> Vec<(Uuid, f32)> {
+        let mut candidates: Vec<(Uuid, f32)> = candidates
+            .into_iter()
+            .map(|(id, _)| (id, self.stored_distance(query, self.node(&id))))
+            .collect();
+        candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+        candidates.truncate(k);
+        candidates
+    }
+
+    // Filtered search for a unit-length query. A filter admitting a sample of
+    // the store's documents is expected to admit the same share of those the
+    // graph search visits, so it widens the search to visit enough of them.
+    // When that would cost more than comparing the query with each admitted
+    // document, or no sampled document is admitted, those are scanned instead.
+    fn search_where(
+        &self,
+        query: &[f32],
+        k: usize,
+        rerank: bool,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        if self.binary && !self.scans_exactly() {
+            return self.hamming_search(query, k, rerank, admits);
+        }
+        if self.index == IndexKind::Ivf && !self.scans_exactly() {
+            return self.ivf_search(query, k, admits);
+        }
+        // Sampled at random, since documents added together tend to be alike
+        let (sampled, admitted) = self
+            .nodes
+            .choose_multiple(&mut rand::thread_rng(), FILTER_SAMPLE)
+            .filter(|node| !self.tombstones.contains(&node.id))
+            .fold((0, 0), |(sampled, admitted), node| {
+                (sampled + 1, admitted + admits(&node.id) as usize)
+            });
+        let selectivity = admitted as f32 / sampled.max(1) as f32;
+        let ef = (k.max(FILTER_EF) as f32 / selectivity).ceil() as usize;
+        let expected = (selectivity * self.texts.len() as f32) as usize;
+        if self.scans_exactly() || admitted == 0 || expected <= ef * self.max_connections {
+            debug!(selectivity, expected, "selective filter, scanning the admitted documents");
+            return self.scan_where(query, k, admits);
+        }
+
+        let _span = debug_span!("filtered_search", k, ef, selectivity).entered();
+        if self.pq.is_some() && rerank {
+            // Over-fetch with the PQ distance, then re-rank against the stored vectors
+            let candidates =
+                self.find_filtered(query, k * RERANK_FACTOR, ef * RERANK_FACTOR, admits);
+            self.rerank_pq(query, candidates, k)
+        } else {
+            self.find_filtered(query, k, ef, admits)
+        }
+    }
+
+    // Nearest live base-layer nodes that `admits` accepts, by a best-first
+    // walk from where the layers above lead, that keeps expanding while a
+    // node among the `ef` closest seen is unexpanded, or until k are accepted.
+    // Rejected nodes still route the walk, so the paths through them aren't
+    // cut off, but never become results.
+    fn find_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        admits: &(dyn Fn(&Uuid) -> bool + Sync),
+    ) -> Vec<(Uuid, f32)> {
+        let layer = &self.layers[0];
+        let query = self.prepare_query(query);
+        let Some(first) = self.descend(&query, 0).filter(|_| k > 0) else {
+            return Vec::new();
+        };
+        let accepts = |node: NodeId| {
+            let id = &self.at(node).id;
+            self.is_live(id) && admits(id)
+        };
+
+        let start = Scored(self.node_distance(&query, self.at(first)), first);
+        let mut visited = Visited::new(self.nodes.len());
+        visited.insert(first);
+        // Nodes to expand, closest first
+        let mut frontier = BinaryHeap::from([Reverse(start)]);
+        // The `ef` closest nodes seen, farthest on top
+        let mut closest = BinaryHeap::from([start]);
+        // The k closest accepted nodes, farthest on top
+        let mut results = BinaryHeap::new();
+        if accepts(first) {
+            results.push(start);
+        }
+
+        while let Some(Reverse(current)) = frontier.pop() {
+            let filled = results.len() >= k && closest.len() >= ef;
+            if filled && closest.peek().is_some_and(|farthest| current > *farthest) {
+                break;
+            }
+            for &neighbor in layer.neighbors(current.1) {
+                if !layer.contains(neighbor) || !visited.insert(neighbor) {
+                    continue;
+                }
+                let scored = Scored(self.node_distance(&query, self.at(neighbor)), neighbor);
+                if accepts(neighbor) {
+                    results.push(scored);
+                    if results.len() > k {
+                        results.pop();
+                    }
+                }
+                let farther = closest.len() >= ef
+                    && closest.peek().is_some_and(|farthest| scored >= *farthest);
+                if farther && results.len() >= k {
+                    continue;
+                }
+                closest.push(scored);
+                if closest.len() > ef {
+                    closest.pop();
+                }
+                frontier.push(Reverse(scored));
+            }
+        }
+
+        trace!(k, ef, visited = visited.count(), "searched filtered layer");
+        results
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(distance, node)| (self.at(node).id, distance))
+            .collect()
+    }
+
+    // Return every document scoring at least `min_score`, best first. Starts
+    // from the nearest nodes and walks the base layer outwards for as long as
+    // neighbors stay within the score's distance. IVF stores look in the
+    // lists they would probe for a top-k search, and flat stores everywhere.
+    pub fn query_range(
+        &self,
+        query_embedding: &Tensor,
+        min_score: Score,
+    ) -> Result<Vec<SearchResult>> {
+        let Some(max_distance) = min_score.distance() else {
+            return Err(ArrowError::Invalid(
+                "Fused scores depend on the other results, so can't bound a search".to_string(),
+            ));
+        };
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+        if !self.has_graph() {
+            let nodes = self.probed_nodes(&query);
+            let mut matches = self.rank(&query, &nodes, nodes.len(), &|_: &Uuid| true);
+            matches.retain(|&(_, distance)| distance <= max_distance);
+            return Ok(self.resolve_results(matches));
+        }
+
+        let layer = &self.layers[0];
+        let mut visited = Visited::new(self.nodes.len());
+        let mut frontier = Vec::new();
+        let mut matches = Vec::new();
+
+        for (node, _) in self.find_nearest(&query, 0, RANGE_SEEDS) {
+            visited.insert(node);
+            frontier.push(node);
+        }
+        // Seeds farther than the bound are dropped as soon as they are expanded
+        while let Some(current) = frontier.pop() {
+            let node = self.at(current);
+            let dist = self.stored_distance(&query, node);
+            if dist > max_distance {
+                continue;
+            }
+            if self.is_live(&node.id) {
+                matches.push((node.id, dist));
+            }
+            for &neighbor in layer.neighbors(current) {
+                if layer.contains(neighbor) && visited.insert(neighbor) {
+                    frontier.push(neighbor);
+                }
+            }
+        }
+
+        matches.sort_by(|a, b| a.1.total_cmp(&b.1));
+        Ok(self.resolve_results(matches))
+    }
+
+    // Maximal Marginal Relevance: pick k results from a larger candidate pool,
+    // each time taking the one that best balances relevance to the query
+    // against similarity to the results already picked. `lambda` = 1.0 is pure
+    // relevance, 0.0 is pure diversity.
+    pub fn query_mmr(
+        &self,
+        query_embedding: &Tensor,
+        k: usize,
+        lambda: f32,
+    ) -> Result<Vec<SearchResult>> {
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+
+        let candidates: Vec<(Uuid, f32, Vec<f32>)> = self
+            .find_live(&query, k * MMR_CANDIDATE_FACTOR)
+            .into_iter()
+            .map(|(id, _)| {
+                let node = self.node(&id);
+                (id, self.stored_distance(&query, node), self.node_vector(node))
+            })
+            .collect();
+
+        let mut selected: Vec<usize> = Vec::new();
+        let mut remaining: Vec<usize> = (0..candidates.len()).collect();
+        while selected.len() < k && !remaining.is_empty() {
+            let (best_pos, _) = remaining
+                .iter()
+                .enumerate()
+                .map(|(pos, &i)| {
+                    let relevance = 1.0 - candidates[i].1;
+                    let redundancy = selected
+                        .iter()
+                        .map(|&j| 1.0 - self.cosine_distance(&candidates[i].2, &candidates[j].2))
+                        .fold(0.0f32, f32::max);
+                    (pos, lambda * relevance - (1.0 - lambda) * redundancy)
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            selected.push(remaining.swap_remove(best_pos));
+        }
+
+        // Report relevance to the query, in MMR selection order
+        Ok(self.resolve_results(
+            selected
+                .into_iter()
+                .map(|i| (candidates[i].0, candidates[i].1))
+                .collect(),
+        ))
+    }
+
+    // Span of the sentence of a result's text that best matches the query,
+    // relative to the text, for highlighting it
+    pub fn best_sentence(&self, text: &str, query_text: &str) -> Option<Span> {
+        self.text_index.best_sentence(text, query_text)
+    }
+
+    // Hybrid search fusing BM25 keyword ranks with vector similarity ranks
+    // using reciprocal rank fusion. Scores are the fused RRF scores.
+    pub fn query_hybrid(
+        &self,
+        query_embedding: &Tensor,
+        query_text: &str,
+        k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let candidates = k * HYBRID_CANDIDATE_FACTOR;
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+
+        let vector_ranked = self.find_live(&query, candidates);
+        let mut keyword_ranked = self.text_index.search(query_text, candidates);
+        keyword_ranked.retain(|(id, _)| !self.is_expired(id));
+
+        let mut fused: HashMap<Uuid, f32> = HashMap::new();
+        for ranking in [vector_ranked, keyword_ranked] {
+            for (rank, (id, _)) in ranking.into_iter().enumerate() {
+                *fused.entry(id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+            }
+        }
+
+        let mut ranked: Vec<(Uuid, f32)> = fused.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(k);
+
+        Ok(self.resolve_scored(
+            ranked
+                .into_iter()
+                .map(|(id, score)| (id, Score::fused(score)))
+                .collect(),
+        ))
+    }
+
+    // Search with several embeddings of one query, such as paraphrases of it,
+    // and fuse the rankings with reciprocal rank fusion. Scores are the fused
+    // RRF scores.
+    pub fn query_fused(&self, query_embeddings: &[Tensor], k: usize) -> Result<Vec<SearchResult>> {
+        let candidates = k * HYBRID_CANDIDATE_FACTOR;
+        let mut fused: HashMap<Uuid, f32> = HashMap::new();
+        for query_embedding in query_embeddings {
+            let mut query = self.query_vector(query_embedding)?;
+            normalize(&mut query);
+            for (rank, (id, _)) in self.find_live(&query, candidates).into_iter().enumerate() {
+                *fused.entry(id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+            }
+        }
+
+        let mut ranked: Vec<(Uuid, f32)> = fused.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(k);
+        Ok(self.resolve_scored(
+            ranked
+                .into_iter()
+                .map(|(id, score)| (id, Score::fused(score)))
+                .collect(),
+        ))
+    }
+
+    // Rank every document by a weighted average of its fields' similarities to
+    // the query, `TEXT_FIELD` being the main vector. A document without one of
+    // the fields scores 0 for it. The graph only knows the main vector, so
+    // this scans the base layer.
+    pub fn query_fields(
+        &self,
+        query_embedding: &Tensor,
+        weights: &[(String, f32)],
+        k: usize,
+    ) -> Result<Vec<SearchResult>> {
+        let mut query = self.query_vector(query_embedding)?;
+        normalize(&mut query);
+        let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+        if total <= 0.0 {
+            return Err(ArrowError::Invalid(
+                "Field weights must add up to more than 0".to_string(),
+            ));
+        }
+        let known = self.field_names();
+        if let Some((field, _)) = weights
+            .iter()
+            .find(|(field, _)| field != TEXT_FIELD && !known.contains(field))
+        {
+            return Err(ArrowError::NotFound(format!("Vector field '{}'", field)));
+        }
+
+        let nodes: Vec<&Node> = self.live_nodes().filter(|n| !self.is_expired(&n.id)).collect();
+        let mut scored: Vec<(Uuid, f32)> = nodes
+            .par_iter()
+            .map(|node| {
+                let score: f32 = weights
+                    .iter()
+                    .map(|(field, weight)| {
+                        let distance = if field == TEXT_FIELD {
+                            self.stored_distance(&query, node)
+                        } else {
+                            match node.fields.get(field) {
+                                Some(vector) => self.cosine_distance(&query, vector),
+                                None => 1.0,
+                            }
+                        };
+                        weight * (1.0 - distance)
+                    })
+                    .sum();
+                (node.id, 1.0 - score / total)
+            })
+            .collect();
+        scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+        scored.truncate(k);
+        Ok(self.resolve_results(scored))
+    }
+
+    // Names of the extra vector fields held by any document, sorted
+    pub fn field_names(&self) -> Vec<String> {
+        let names: HashSet<&String> = self
+            .live_nodes()
+            .flat_map(|node| node.fields.keys())
+            .collect();
+        let mut names: Vec<String> = names.into_iter().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // Turn (id, distance) pairs into results
+    fn resolve_results(&self, nearest: Vec<(Uuid, f32)>) -> Vec<SearchResult> {
+        let scored = nearest
+            .into_iter()
+            .map(|(id, dist)| (id, Score::from_distance(dist)))
+            .collect();
+        self.resolve_scored(scored)
+    }
+
+    // Turn (id, score) pairs into results
+    fn resolve_scored(&self, scored: Vec<(Uuid, Score)>) -> Vec<SearchResult> {
+        scored
+            .into_iter()
+            .map(|(id, score)| SearchResult {
+                text: self.texts[&id].clone(),
+                score,
+                filename: self.filenames.get(&id).cloned(),
+                provenance: self.provenance.get(&id).cloned(),
+                span: self.spans.get(&id).copied(),
+                metadata: self.metadata.get(&id).cloned().unwrap_or_default(),
+                added: self.added_at(&id),
+                updated: self.updated_at(&id),
+            })
+            .collect()
+    }
+
+    // Nearest base-layer nodes that aren't tombstones or expired, fetching
+    // enough extra candidates to make up for those expected among them
+    fn find_live(&self, query: &[f32], k: usize) -> Vec<(Uuid, f32)> {
+        if self.scans_exactly() {
+            debug!(documents = self.texts.len(), index = ?self.index, "scanning every vector");
+            return self.scan(query, k);
+        }
+        if self.index == IndexKind::Ivf {
+            return self.ivf_search(query, k, &|_: &Uuid| true);
+        }
+        if self.tombstones.is_empty() && self.expires.is_empty() {
+            return self.resolve_nodes(self.find_nearest(query, 0, k));
+        }
+        let nodes = self.nodes.len();
+        // Counting every document with an expiry, as any of them may have expired
+        let dead = self.tombstones.len() + self.expires.len();
+        let live = nodes.saturating_sub(dead).max(1);
+        let fetch = (k * nodes).div_ceil(live) + TOMBSTONE_SLACK;
+        let mut nearest = self.resolve_nodes(self.find_nearest(query, 0, fetch));
+        nearest.retain(|(id, _)| self.is_live(id));
+        nearest.truncate(k);
+        nearest
+    }
+
+    // Document IDs of graph search results
+    fn resolve_nodes(&self, nearest: Vec<(NodeId, f32)>) -> Vec<(Uuid, f32)> {
+        nearest.into_iter().map(|(node, dist)| (self.at(node).id, dist)).collect()
+    }
+
+    // The k nodes on a layer nearest the query, closest first
+    fn find_nearest(&self, query: &[f32], level: usize, k: usize) -> Vec<(NodeId, f32)> {
+        let query = self.prepare_query(query);
+        match self.descend(&query, level) {
+            Some(entry) => self.search_layer(&query, level, k, entry),
+            None => Vec::new(),
+        }
+    }
+
+    // Where a search of `level` starts: the node nearest the query found by
+    // walking each layer above it, entering the top one at the entry point
+    fn descend(&self, query: &SearchQuery, level: usize) -> Option<NodeId> {
+        let mut entry = self.entry_node()?;
+        for upper in (level + 1..self.layers.len()).rev() {
+            if let Some(&(nearest, _)) = self.search_layer(query, upper, 1, entry).first() {
+                entry = nearest;
+            }
+        }
+        Some(entry)
+    }
+
+    // The k nodes on a layer nearest the query, closest first, by a best-first
+    // walk from `entry`: the closest unexpanded node is expanded next, until
+    // it is farther than the farthest of the k closest found
+    fn search_layer(
+        &self,
+        query: &SearchQuery,
+        level: usize,
+        k: usize,
+        entry: NodeId,
+    ) -> Vec<(NodeId, f32)> {
+        let layer = &self.layers[level];
+        if k == 0 || !layer.contains(entry) {
+            return Vec::new();
+        }
+
+        let start = Scored(self.node_distance(query, self.at(entry)), entry);
+        let mut visited = Visited::new(self.nodes.len());
+        visited.insert(entry);
+        // Nodes to expand, closest first
+        let mut candidates = BinaryHeap::from([Reverse(start)]);
+        // The k closest nodes found, farthest on top
+        let mut best = BinaryHeap::from([start]);
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            if best.len() >= k && best.peek().is_some_and(|farthest| current > *farthest) {
+                break;
+            }
+            for &neighbor in layer.neighbors(current.1) {
+                if !layer.contains(neighbor) || !visited.insert(neighbor) {
+                    continue;
+                }
+                let scored = Scored(self.node_distance(query, self.at(neighbor)), neighbor);
+                if best.len() >= k && best.peek().is_some_and(|farthest| scored >= *farthest) {
+                    continue;
+                }
+                best.push(scored);
+                if best.len() > k {
+                    best.pop();
+                }
+                candidates.push(Reverse(scored));
+            }
         }
-    }
 
-    fn cosine_distance(&self, v1: &[f32], v2: &[f32]) -> f32 {
-        let dot: f32 = v1.iter().zip(v2).map(|(a, b)| a * b).sum();
-        let n1: f32 = v1.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let n2: f32 = v2.iter().map(|x| x * x).sum::<f32>().sqrt();
-        1.0 - (dot / (n1 * n2)).clamp(-1.0, 1.0)
+        trace!(level, k, visited = visited.count(), "searched layer");
+        best
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Scored(distance, node)| (node, distance))
+            .collect()
     }
 
-    pub fn add(&mut self, embedding: Tensor, text: String) -> Result<Uuid> {
-        self.add_with_filename(embedding, text, None)
+    // Save the changes made since the last save. Usually the backend appends
+    // just the changes logged in the WAL; the store is written in full
+    // instead when there is no store file yet, when a change the WAL can't
+    // replay was made, or when the backend can't append. Other processes
+    // wait to load the store until the save is done.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let _span = debug_span!("save", path = %path.display()).entered();
+        if let Some(e) = self.wal_error.take() {
+            return Err(e.into());
+        }
+        let _lock = StoreLock::save(path)?;
+        let appended = !self.needs_compaction
+            && self.wal.is_some()
+            && self.engine.backend().append(self, path)?;
+        if !appended {
+            self.write_compacted(path)?;
+        }
+        debug!(appended, documents = self.texts.len(), "saved store");
+        Ok(())
     }
 
-    pub fn add_with_filename(
-        &mut self,
-        embedding: Tensor,
-        text: String,
-        filename: Option<String>,
-    ) -> Result<Uuid> {
-        let vector = embedding.to_vec1::<f32>()?;
-        let id = Uuid::new_v4();
-
-        let max_level = (-rand::thread_rng().gen::<f32>().ln() * self.m_l).floor() as usize;
-        while self.layers.len() <= max_level {
-            self.layers.push(Layer {
-                nodes: Vec::new(),
-                id_to_index: HashMap::new(),
-            });
+    // Merge the appended changes and WAL into a full rewrite of the store
+    pub fn compact<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let _span = debug_span!("compact", path = %path.display()).entered();
+        if let Some(e) = self.wal_error.take() {
+            return Err(e.into());
         }
+        let _lock = StoreLock::save(path)?;
+        Ok(self.write_compacted(path)?)
+    }
 
-        for level in 0..=max_level {
-            let new_node = Node {
-                id: id.clone(),
-                vector: vector.clone(),
-                neighbors: HashSet::new(),
-            };
+    // Number of segments written since the store file was last compacted
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
 
-            let node_index = self.layers[level].nodes.len();
-            self.layers[level].nodes.push(new_node);
-            self.layers[level].id_to_index.insert(id, node_index);
+    // Write the whole store through its backend, then clear the WAL, which
+    // is only cleared once the backend's save succeeds
+    fn write_compacted(&mut self, path: &Path) -> std::io::Result<()> {
+        self.engine.backend().save(self, path)?;
+        self.needs_compaction = false;
+        match &mut self.wal {
+            Some(wal) => wal.reset()?,
+            None => self.wal = Some(Wal::open(path).with_cipher(self.cipher.clone())),
+        }
+        Ok(())
+    }
 
-            if self.layers[level].nodes.len() > 1 {
-                let nearest = self.find_nearest(&vector, level, 1)[0];
-                self.connect_nodes(level, id, nearest.0);
+    // Load a vector store from a JSON or SQLite file
+    pub fn load<P: AsRef<Path>>(path: P, device: Device) -> Result<Self> {
+        let path = path.as_ref();
+        let _span = debug_span!("load", path = %path.display()).entered();
+        if !path.exists() {
+            return Err(ArrowError::NotFound(format!("Vector store {}", path.display())));
+        }
+        // Hold off saves until the store, its appended changes and the WAL
+        // have all been read
+        let _lock = StoreLock::read(path)?;
+        let engine = StorageEngine::of_file(path)?;
+        let backend = engine.backend();
+        let mut store = backend.load(path)?;
+        store.engine = engine;
+        store.device = Some(device);
+        if store.legacy_layers.is_empty() {
+            store.index_nodes();
+        } else {
+            store.migrate_layers();
+        }
+        if store.layers.is_empty() {
+            store.layers.push(Layer::default());
+        }
+        store.settle_entry_point();
+        if !store.normalized {
+            store.normalize_all();
+        }
+        if store.binary {
+            store.encode_binary();
+        }
+        for (external_id, ids) in &store.external_ids {
+            for id in ids {
+                store.external_by_id.insert(*id, external_id.clone());
             }
         }
-
-        self.texts.insert(id, text);
-        if let Some(fname) = filename {
-            self.filenames.insert(id, fname);
+        for (id, text) in &store.texts {
+            store.text_index.add(*id, text);
         }
 
-        Ok(id)
+        // Re-apply the changes appended since the last full save and anything
+        // logged after the last save, then keep logging
+        let mut segments = 0;
+        for entries in backend.iterate(&mut store, path)? {
+            store.replay(entries);
+            segments += 1;
+        }
+        let logged = wal::read_entries(path, store.cipher.as_ref())?;
+        debug!(segments, wal_entries = logged.len(), "replaying changes");
+        store.replay(logged);
+        store.migrate_provenance();
+        store.wal = Some(Wal::open(path).with_cipher(store.cipher.clone()));
+        debug!(documents = store.texts.len(), "loaded store");
+        Ok(store)
     }
 
-    fn connect_nodes(&mut self, level: usize, id1: Uuid, id2: Uuid) {
-        let index1 = self.layers[level].id_to_index[&id1];
-        let index2 = self.layers[level].id_to_index[&id2];
+    // Map each document to its node, and each node to its place on the layers
+    fn index_nodes(&mut self) {
+        self.node_index = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| (node.id, index as NodeId))
+            .collect();
+        for layer in &mut self.layers {
+            layer.index(self.nodes.len());
+        }
+    }
 
-        if self.layers[level].nodes[index1].neighbors.len() < self.max_connections {
-            self.layers[level].nodes[index1].neighbors.insert(id2);
+    // Move the nodes of a store saved with a copy of each vector per layer,
+    // and links by UUID, into `nodes`, keeping only the links on each layer.
+    // The old copies are gone from the file on the next full save.
+    fn migrate_layers(&mut self) {
+        let mut links = Vec::new();
+        for (level, layer) in std::mem::take(&mut self.legacy_layers).into_iter().enumerate() {
+            let mut layer_links = Vec::with_capacity(layer.nodes.len());
+            for LegacyNode { node, neighbors } in layer.nodes {
+                layer_links.push((node.id, neighbors));
+                if level == 0 {
+                    self.nodes.push(node);
+                }
+            }
+            links.push(layer_links);
         }
-        if self.layers[level].nodes[index2].neighbors.len() < self.max_connections {
-            self.layers[level].nodes[index2].neighbors.insert(id1);
+        self.layers.clear();
+        self.index_nodes();
+        for layer_links in links {
+            let (members, neighbors) = layer_links
+                .into_iter()
+                .filter_map(|(id, neighbors)| {
+                    let node = *self.node_index.get(&id)?;
+                    let neighbors = neighbors
+                        .iter()
+                        .filter_map(|neighbor| self.node_index.get(neighbor).copied())
+                        .collect();
+                    Some((node, neighbors))
+                })
+                .unzip();
+            self.layers.push(Layer::new(members, neighbors, self.nodes.len()));
         }
+        self.needs_compaction = true;
     }
 
-    pub fn query(
-        &self,
-        query_embedding: &Tensor,
-        k: usize,
-    ) -> Result<Vec<(String, f32, Option<String>)>> {
-        let query = query_embedding.to_vec1::<f32>()?;
-
-        let mut entry_point = (Uuid::nil(), f32::MAX);
-        for level in (0..self.layers.len()).rev() {
-            if self.layers[level].nodes.is_empty() {
-                continue;
+    // Give documents added before provenance was recorded the source and
+    // chunk their filename names. The store is rewritten with it on the next
+    // save.
+    fn migrate_provenance(&mut self) {
+        let mut migrated = false;
+        for (id, filename) in &self.filenames {
+            if !self.provenance.contains_key(id) {
+                self.provenance.insert(*id, Provenance::from_label(filename));
+                migrated = true;
             }
+        }
+        if migrated {
+            self.count_chunks();
+            self.needs_compaction = true;
+        }
+    }
 
-            // Just get the first node as a starting point if we don't have a better one
-            let first_id = self.layers[level].nodes[0].id;
-            entry_point = (
-                first_id,
-                self.cosine_distance(&query, &self.layers[level].nodes[0].vector),
-            );
+    // Set each document's chunk count to the number of chunks stored from its
+    // source (or record), for provenance read back from filenames
+    fn count_chunks(&mut self) {
+        let mut counts: HashMap<(String, Option<usize>), usize> = HashMap::new();
+        for provenance in self.provenance.values() {
+            let key = (provenance.source_path.clone(), provenance.record);
+            *counts.entry(key).or_default() += 1;
+        }
+        for provenance in self.provenance.values_mut() {
+            let key = (provenance.source_path.clone(), provenance.record);
+            provenance.chunk_count = provenance.chunk_count.max(counts[&key]);
+        }
+    }
 
-            if !self.layers[level].nodes.is_empty() {
-                entry_point = self.find_nearest(&query, level, 1)[0];
+    // Re-apply logged entries. Runs of additions are linked in batches, as
+    // `add_batch` would.
+    fn replay(&mut self, entries: Vec<WalEntry>) {
+        let mut adds = Vec::new();
+        for entry in entries {
+            if !matches!(entry, WalEntry::Add { .. }) {
+                self.replay_adds(std::mem::take(&mut adds));
             }
-
-            if level == 0 {
-                break;
+            match entry {
+                WalEntry::Add {
+                    id,
+                    vector,
+                    norm,
+                    fields,
+                    text,
+                    filename,
+                    provenance,
+                    span,
+                    external_id,
+                    metadata,
+                    added,
+                    updated,
+                    expires,
+                } => {
+                    // Already present if the crash came after the save's rename
+                    if self.texts.contains_key(&id) {
+                        continue;
+                    }
+                    let payload = Payload {
+                        text,
+                        filename,
+                        provenance: provenance.map(|provenance| *provenance),
+                        span,
+                        external_id,
+                        metadata,
+                        added,
+                        updated,
+                        expires,
+                    };
+                    adds.push((id, vector, norm, fields, payload));
+                }
+                WalEntry::Remove { id } => {
+                    self.remove(&id);
+                }
+                WalEntry::Source { path, record } => {
+                    self.sources.insert(path, record);
+                }
+                WalEntry::RemoveSource { path } => {
+                    self.sources.remove(&path);
+                }
+                WalEntry::SetMetadata { id, key, value } => {
+                    if self.texts.contains_key(&id) {
+                        self.apply_metadata(&id, &key, value);
+                    }
+                }
             }
         }
+        self.replay_adds(adds);
+    }
 
-        let nearest = self.find_nearest(&query, 0, k);
-        Ok(nearest
-            .into_iter()
-            .map(|(id, dist)| {
-                let text = self.texts[&id].clone();
-                let filename = self.filenames.get(&id).cloned();
-                (text, 1.0 - dist, filename)
-            })
-            .collect())
+    fn replay_adds(&mut self, adds: Vec<Prepared>) {
+        // Nothing is logged, so this can't fail
+        let _ = self.insert_prepared(adds, false);
     }
 
-    fn find_nearest(&self, query: &[f32], level: usize, k: usize) -> Vec<(Uuid, f32)> {
-        let layer = &self.layers[level];
-        if layer.nodes.is_empty() {
-            return Vec::new();
+    fn log(&mut self, entry: WalEntry) -> std::io::Result<()> {
+        match &mut self.wal {
+            Some(wal) => wal.append(&entry),
+            None => Ok(()),
+        }
+    }
+
+    // Log from a method that can't return an error; the failure surfaces on save
+    fn log_deferred(&mut self, entry: WalEntry) {
+        if let Err(e) = self.log(entry) {
+            self.wal_error.get_or_insert(e);
         }
+    }
 
-        let mut visited = HashSet::new();
-        let first_id = layer.nodes[0].id;
-        let mut best = vec![(
-            first_id,
-            self.cosine_distance(query, &layer.nodes[0].vector),
-        )];
-        visited.insert(first_id);
+    // Flush logged entries to disk so they survive a crash
+    pub fn sync_wal(&mut self) -> Result<()> {
+        match &mut self.wal {
+            Some(wal) => Ok(wal.sync()?),
+            None => Ok(()),
+        }
+    }
 
-        loop {
-            let current = best[0]; // Closest unexpanded node
-            let mut improved = false;
-
-            // Check all neighbors
-            let current_index = layer.id_to_index[&current.0];
-            for &neighbor_id in &layer.nodes[current_index].neighbors {
-                if visited.insert(neighbor_id) {
-                    let neighbor_index = layer.id_to_index[&neighbor_id];
-                    let dist = self.cosine_distance(query, &layer.nodes[neighbor_index].vector);
-                    best.push((neighbor_id, dist));
-                    best.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
-                    if best.len() > k {
-                        best.pop();
-                    }
-                    improved = true;
-                }
-            }
+    // A saved store's file and every `<path>.*` file beside it: its WAL,
+    // backups, vectors files and caches
+    pub fn files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => return Ok(Vec::new()),
+        };
+        let sidecar_prefix = format!("{}.", name);
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
 
-            if !improved {
-                break; // No better neighbors found
+        let mut files = Vec::new();
+        for entry in fs::read_dir(parent)?.flatten() {
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+            let is_store_file = entry_name == name || entry_name.starts_with(&sidecar_prefix);
+            if is_store_file && entry.path().is_file() {
+                // Relative to the same directory as `path`
+                files.push(path.with_file_name(&entry_name));
             }
         }
-
-        best
+        files.sort();
+        Ok(files)
     }
 
-    // Serialize and save the vector store to a file
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
-        let file = File::create(path)?;
-        serde_json::to_writer(file, self)?;
-        Ok(())
+    // Bytes the store saved at `path` takes up on disk: the files a load
+    // reads, including the WAL, but not backups or caches
+    pub fn disk_bytes<P: AsRef<Path>>(&self, path: P) -> u64 {
+        let path = path.as_ref();
+        let saved = self.engine.backend().size(self, path).unwrap_or(0);
+        let logged = fs::metadata(wal::wal_path(path)).map(|meta| meta.len()).unwrap_or(0);
+        saved + logged
     }
 
-    // Load a vector store from a file
-    pub fn load<P: AsRef<Path>>(path: P, device: Device) -> std::io::Result<Self> {
-        let mut file = File::open(path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-
-        let mut store: VectorStore = serde_json::from_str(&contents)?;
-        store.device = Some(device);
-        Ok(store)
+    // Delete a saved store along with its WAL, backups and vectors files
+    pub fn delete_files<P: AsRef<Path>>(path: P) -> Result<()> {
+        for file in Self::files(path)? {
+            fs::remove_file(file)?;
+        }
+        Ok(())
     }
 
     // Method to get tensor from vector for queries after loading
     pub fn vector_to_tensor(&self, vector: &[f32]) -> Result<Tensor> {
         let device = self.device.as_ref().ok_or_else(|| {
-            candle_core::Error::Msg("Device not initialized in VectorStore".to_string())
+            ArrowError::Invalid("Device not initialized in VectorStore".to_string())
         })?;
-        Tensor::from_vec(vector.to_vec(), vector.len(), device)
+        Ok(Tensor::from_vec(vector.to_vec(), vector.len(), device)?)
     }
 
     // Method to get the number of texts in the vector store
@@ -228,6 +2911,377 @@ impl VectorStore {
         self.texts.len()
     }
 
+    // Length of the stored vectors, or None while the store is empty
+    pub fn dimension(&self) -> Option<usize> {
+        let node = self.nodes.first()?;
+        Some(match self.storage {
+            StorageMode::F32 => self.vector_of(node).len(),
+            StorageMode::F16 => node.halves.len(),
+            StorageMode::Int8 => node.codes.len(),
+        })
+    }
+
+    // Rough bytes `documents` more documents holding `text_bytes` of text
+    // would add once loaded, counted as `stats` does with a full set of
+    // links each. None while neither the store nor its model knows the
+    // vector length.
+    pub fn estimated_growth(&self, documents: usize, text_bytes: usize) -> Option<usize> {
+        let dimension = self.dimension().or_else(|| self.model.dimension())?;
+        let width = match self.storage {
+            StorageMode::F32 => 4,
+            StorageMode::F16 => 2,
+            StorageMode::Int8 => 1,
+        };
+        let per_document = 40 + 16 + dimension * width + self.max_connections * 4 + 24 + 48;
+        Some(documents * per_document + text_bytes)
+    }
+
+    // Degree distribution, connectivity and size of the graph. Stores
+    // without a graph have no layers to report.
+    pub fn stats(&self) -> StoreStats {
+        let layers = self
+            .layers
+            .iter()
+            .enumerate()
+            .filter(|_| self.has_graph())
+            .map(|(level, layer)| layer_stats(level, layer, self.entry_node(), self.nodes.len()))
+            .collect();
+
+        // Uuids are 16 bytes; map entries are counted at twice their payload
+        let mut memory_bytes = self.node_index.len() * 40;
+        for node in &self.nodes {
+            memory_bytes += 16
+                + node.vector.len() * 4
+                + node.halves.len() * 2
+                + node.codes.len()
+                + node.pq_codes.len()
+                + node.bits.len() * 8
+                + node.fields.values().map(|vector| vector.len() * 4).sum::<usize>();
+        }
+        for layer in &self.layers {
+            memory_bytes += layer.memory_bytes();
+        }
+        if let Some(ivf) = &self.ivf {
+            memory_bytes += ivf.memory_bytes();
+        }
+        memory_bytes += self.texts.values().map(|text| text.len() + 48).sum::<usize>();
+        memory_bytes += self.filenames.values().map(|name| name.len() + 48).sum::<usize>();
+        memory_bytes += self
+            .metadata
+            .values()
+            .flatten()
+            .map(|(key, value)| key.len() + value.len() + 48)
+            .sum::<usize>();
+
+        let mapped_bytes = match &self.mmap {
+            Some(mmap) => mmap.len(),
+            None => 0,
+        };
+        StoreStats {
+            layers,
+            dimension: self.dimension(),
+            max_connections: self.max_connections,
+            index: self.index,
+            ivf_lists: self.ivf.as_ref().map_or(0, |ivf| ivf.list_count()),
+            largest_list: self.ivf.as_ref().map_or(0, |ivf| ivf.largest_list()),
+            memory_bytes,
+            mapped_bytes,
+            tombstones: self.tombstones.len(),
+            segments: self.segments.len(),
+        }
+    }
+
+    // Look for broken links, index maps that disagree with the node lists,
+    // nodes without text and text without nodes, layer entries without a
+    // node, upper-layer nodes missing from the layers below, nodes searches
+    // can't reach, and IVF lists that disagree with the nodes
+    pub fn check(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let nodes = self.nodes.len();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let index = index as NodeId;
+            if self.node_index.get(&node.id) != Some(&index) {
+                problems.push(Problem::new(None, node.id, "stored twice"));
+            }
+            if !self.texts.contains_key(&node.id) && !self.tombstones.contains(&node.id) {
+                problems.push(Problem::new(None, node.id, "has no text"));
+            }
+            if !self.layers[0].contains(index) {
+                problems.push(Problem::new(Some(0), node.id, "missing from the layer"));
+            }
+        }
+        for (level, layer) in self.layers.iter().enumerate() {
+            let mut seen = Visited::new(nodes);
+            for (node, neighbors) in layer.links() {
+                let Some(id) = self.nodes.get(node as usize).map(|node| node.id) else {
+                    problems.push(Problem::new(
+                        Some(level),
+                        Uuid::nil(),
+                        format!("node {} is in the layer but doesn't exist", node),
+                    ));
+                    continue;
+                };
+                if !seen.insert(node) {
+                    problems.push(Problem::new(Some(level), id, "stored twice in the layer"));
+                }
+                for &neighbor in neighbors {
+                    if neighbor == node {
+                        problems.push(Problem::new(Some(level), id, "links to itself"));
+                    } else if !layer.contains(neighbor) {
+                        let target = match self.nodes.get(neighbor as usize) {
+                            Some(other) => other.id.to_string(),
+                            None => format!("#{}", neighbor),
+                        };
+                        problems.push(Problem::new(
+                            Some(level),
+                            id,
+                            format!("links to missing node {}", target),
+                        ));
+                    }
+                }
+                if level > 0 && !self.layers[level - 1].contains(node) {
+                    problems.push(Problem::new(
+                        Some(level),
+                        id,
+                        format!("missing from layer {}", level - 1),
+                    ));
+                }
+            }
+            if !self.has_graph() {
+                continue;
+            }
+            if level + 1 == self.layers.len() && !layer.is_empty() {
+                match (self.entry_point, self.entry_node()) {
+                    (None, _) => {
+                        problems.push(Problem::new(Some(level), Uuid::nil(), "no entry point"));
+                    }
+                    (Some(id), None) => {
+                        problems.push(Problem::new(None, id, "is the entry point but has no node"));
+                    }
+                    (Some(id), Some(node)) if !layer.contains(node) => {
+                        problems.push(Problem::new(
+                            Some(level),
+                            id,
+                            "is the entry point but missing from the layer",
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            let reached = reachable(layer, self.entry_node(), nodes);
+            for &node in layer.members() {
+                if (node as usize) < nodes && !reached.contains(node) {
+                    problems.push(Problem::new(
+                        Some(level),
+                        self.at(node).id,
+                        "unreachable from the entry node",
+                    ));
+                }
+            }
+        }
+
+        if let Some(ivf) = &self.ivf {
+            let mut listed = HashSet::new();
+            for id in ivf.ids() {
+                if !listed.insert(*id) {
+                    problems.push(Problem::new(Some(0), *id, "listed twice in the IVF lists"));
+                } else if !self.node_index.contains_key(id) {
+                    problems.push(Problem::new(None, *id, "in an IVF list but has no node"));
+                }
+            }
+            for node in &self.nodes {
+                if !listed.contains(&node.id) {
+                    problems.push(Problem::new(Some(0), node.id, "missing from the IVF lists"));
+                }
+            }
+        }
+
+        for id in self.texts.keys() {
+            if !self.node_index.contains_key(id) {
+                problems.push(Problem::new(None, *id, "has text but no vector"));
+            }
+        }
+        for id in &self.tombstones {
+            if self.texts.contains_key(id) {
+                problems.push(Problem::new(None, *id, "is a tombstone but has text"));
+            } else if !self.node_index.contains_key(id) {
+                problems.push(Problem::new(None, *id, "is a tombstone but has no node"));
+            }
+        }
+        let payloads = [
+            ("a filename", self.filenames.keys().collect::<Vec<_>>()),
+            ("metadata", self.metadata.keys().collect()),
+            ("a span", self.spans.keys().collect()),
+            ("a timestamp", self.added.keys().chain(self.updated.keys()).collect()),
+            ("an expiry", self.expires.keys().collect()),
+            ("an external id", self.external_ids.values().flatten().collect()),
+        ];
+        for (kind, ids) in payloads {
+            for id in ids {
+                if !self.texts.contains_key(id) {
+                    problems.push(Problem::new(None, *id, format!("has {} but no text", kind)));
+                }
+            }
+        }
+        problems
+    }
+
+    // Fix what `check` finds: rebuild index maps, drop duplicate nodes, nodes
+    // with neither text nor a tombstone, upper-layer nodes missing below, broken links and
+    // payloads without text, and move the entry point onto the top layer. Then
+    // link every unreachable node from its nearest reachable one and assign
+    // every node to an IVF list again.
+    // Documents without a vector can't be recovered and are dropped. Returns
+    // the number of documents dropped.
+    pub fn repair(&mut self) -> usize {
+        // A tombstone that still has text was never really removed
+        let texts = &self.texts;
+        self.tombstones.retain(|id| !texts.contains_key(id));
+
+        // Keep the first copy of each node with text or a tombstone, and
+        // renumber the nodes after the ones dropped
+        let mut seen = HashSet::new();
+        let mut map = vec![ABSENT; self.nodes.len()];
+        let mut kept = 0;
+        for (index, node) in self.nodes.iter().enumerate() {
+            let stored = self.texts.contains_key(&node.id) || self.tombstones.contains(&node.id);
+            if stored && seen.insert(node.id) {
+                map[index] = kept;
+                kept += 1;
+            }
+        }
+        let mut index = 0;
+        self.nodes.retain(|_| {
+            index += 1;
+            map[index - 1] != ABSENT
+        });
+
+        // Every node belongs in the base layer, and upper-layer nodes in the layers below
+        let nodes = self.nodes.len();
+        for level in 0..self.layers.len() {
+            let (below, above) = self.layers.split_at_mut(level);
+            let below = below.last();
+            let in_below = |node| below.is_none_or(|below| below.contains(node));
+            above[0].retain_mapped(&map, nodes, in_below);
+        }
+        let base = &mut self.layers[0];
+        for node in 0..nodes as NodeId {
+            if !base.contains(node) {
+                base.push(node);
+            }
+        }
+        self.layers.retain(|layer| !layer.is_empty());
+        if self.layers.is_empty() {
+            self.layers.push(Layer::default());
+        }
+        self.index_nodes();
+        self.settle_entry_point();
+
+        let stored: HashSet<Uuid> = self.node_index.keys().copied().collect();
+        self.tombstones.retain(|id| stored.contains(id));
+        let lost: Vec<Uuid> = self
+            .texts
+            .keys()
+            .filter(|id| !stored.contains(id))
+            .copied()
+            .collect();
+        for id in &lost {
+            if let Some(text) = self.texts.remove(id) {
+                self.text_index.remove(id, &text);
+            }
+        }
+        let texts = &self.texts;
+        self.filenames.retain(|id, _| texts.contains_key(id));
+        self.provenance.retain(|id, _| texts.contains_key(id));
+        self.metadata.retain(|id, _| texts.contains_key(id));
+        self.spans.retain(|id, _| texts.contains_key(id));
+        self.added.retain(|id, _| texts.contains_key(id));
+        self.updated.retain(|id, _| texts.contains_key(id));
+        self.expires.retain(|id, _| texts.contains_key(id));
+        for ids in self.external_ids.values_mut() {
+            ids.retain(|id| texts.contains_key(id));
+        }
+        self.external_ids.retain(|_, ids| !ids.is_empty());
+        self.external_by_id.retain(|id, _| texts.contains_key(id));
+        for record in self.sources.values_mut() {
+            record.chunk_ids.retain(|id| texts.contains_key(id));
+        }
+
+        for level in 0..self.layers.len() {
+            self.relink_unreachable(level);
+        }
+        if self.ivf.is_some() {
+            let vectors: Vec<(Uuid, Vec<f32>)> = self
+                .nodes
+                .par_iter()
+                .map(|node| (node.id, self.node_vector(node)))
+                .collect();
+            if let Some(ivf) = &mut self.ivf {
+                ivf.assign_all(&vectors);
+            }
+        }
+        self.needs_compaction = true;
+        lost.len()
+    }
+
+    // Link each node searches can't reach from the nearest node they can.
+    // The inbound link is added even past `max_connections`, since without it
+    // the node stays lost.
+    fn relink_unreachable(&mut self, level: usize) {
+        if !self.has_graph() {
+            return;
+        }
+        let reached = reachable(&self.layers[level], self.entry_node(), self.nodes.len());
+        let lost: Vec<NodeId> = self.layers[level]
+            .members()
+            .iter()
+            .copied()
+            .filter(|&node| !reached.contains(node))
+            .collect();
+        let Some(entry) = self.entry_node() else {
+            return;
+        };
+        for node in lost {
+            let vector = self.node_vector(self.at(node));
+            // Searched from the entry point on this layer rather than where the
+            // layers above lead, so only reachable nodes are found
+            let nearest = self
+                .search_layer(&self.prepare_query(&vector), level, 2, entry)
+                .into_iter()
+                .map(|(candidate, _)| candidate)
+                .find(|&candidate| candidate != node);
+            if let Some(nearest) = nearest {
+                self.layers[level].link(nearest, node, usize::MAX);
+                self.connect_nodes(level, node, nearest);
+            }
+        }
+    }
+
+    // Stored documents whose ID starts with `prefix`
+    pub fn find_ids(&self, prefix: &str) -> Vec<Uuid> {
+        let prefix = prefix.to_lowercase();
+        let mut ids: Vec<Uuid> = self
+            .texts
+            .keys()
+            .filter(|id| id.to_string().starts_with(&prefix))
+            .copied()
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    // Number of links from a node on each layer it is in, from the base layer up
+    pub fn neighbor_counts(&self, id: &Uuid) -> Vec<usize> {
+        let Some(&node) = self.node_index.get(id) else {
+            return Vec::new();
+        };
+        self.layers
+            .iter()
+            .take_while(|layer| layer.contains(node))
+            .map(|layer| layer.neighbors(node).len())
+            .collect()
+    }
+
     // Get a specific embedding by ID
     pub fn get_embedding(&self, id: &Uuid) -> Option<(&String, Option<&String>)> {
         let text = self.texts.get(id)?;
@@ -240,6 +3294,106 @@ impl VectorStore {
         self.texts.keys().cloned().collect()
     }
 
+    // Document IDs in a stable order, optionally only those from one source
+    // file (a filename without its `#chunk` suffix)
+    pub fn list_ids(&self, order: DocumentOrder, source: Option<&str>) -> Vec<Uuid> {
+        let mut ids: Vec<Uuid> = self
+            .texts
+            .keys()
+            .filter(|id| match source {
+                Some(source) => self.source_position(id).0 == source,
+                None => true,
+            })
+            .copied()
+            .collect();
+        match order {
+            DocumentOrder::Source => {
+                ids.sort_by_cached_key(|id| (self.source_position(id), *id));
+            }
+            DocumentOrder::Added => {
+                ids.sort_by_cached_key(|id| (self.added_at(id), self.source_position(id), *id));
+            }
+            DocumentOrder::Id => ids.sort(),
+        }
+        ids
+    }
+
+    // When a document was added, in seconds since the Unix epoch
+    pub fn added_at(&self, id: &Uuid) -> Option<u64> {
+        self.added.get(id).copied()
+    }
+
+    // When a document last replaced an earlier version of itself
+    pub fn updated_at(&self, id: &Uuid) -> Option<u64> {
+        self.updated.get(id).copied()
+    }
+
+    // When a document expires, if it was added with a TTL
+    pub fn expires_at(&self, id: &Uuid) -> Option<u64> {
+        self.expires.get(id).copied()
+    }
+
+    // The language a document was detected to be in
+    pub fn language_of(&self, id: &Uuid) -> Option<&str> {
+        self.metadata.get(id)?.get(LANGUAGE_KEY).map(String::as_str)
+    }
+
+    // Whether a document passes a filter
+    pub fn admitted(&self, filter: &Filter, id: &Uuid) -> bool {
+        let source = self.provenance.get(id).map(|provenance| provenance.source_path.as_str());
+        filter.admits(self.added_at(id), self.updated_at(id), self.language_of(id), source)
+    }
+
+    // A document's source file, record, byte offset and chunk number within
+    // it, to order a file's chunks by
+    fn source_position(&self, id: &Uuid) -> (&str, usize, usize, usize) {
+        let start = self.spans.get(id).map_or(0, |span| span.0);
+        match self.provenance.get(id) {
+            Some(provenance) => (
+                provenance.source_path.as_str(),
+                provenance.record.unwrap_or(0),
+                start,
+                provenance.chunk_index,
+            ),
+            None => ("", 0, start, 0),
+        }
+    }
+
+    // Where a document came from, if it came from a file
+    pub fn provenance(&self, id: &Uuid) -> Option<&Provenance> {
+        self.provenance.get(id)
+    }
+
+    // Everything stored for a document, with its vectors if asked for. The
+    // main vector is scaled back to the length the model gave it.
+    pub fn export(&self, id: &Uuid, include_vectors: bool) -> Option<DumpRecord> {
+        let text = self.texts.get(id)?;
+        let (vector, fields) = match self.node_index.get(id) {
+            Some(&index) if include_vectors => {
+                let node = self.at(index);
+                let mut vector = self.node_vector(node);
+                if node.norm > 0.0 {
+                    vector.iter_mut().for_each(|x| *x *= node.norm);
+                }
+                (Some(vector), node.fields.clone())
+            }
+            _ => (None, HashMap::new()),
+        };
+        Some(DumpRecord {
+            id: Some(id.to_string()),
+            external_id: self.external_by_id.get(id).cloned(),
+            text: text.clone(),
+            filename: self.filenames.get(id).cloned(),
+            span: self.spans.get(id).copied(),
+            metadata: self.metadata.get(id).cloned().unwrap_or_default(),
+            added: self.added_at(id),
+            updated: self.updated_at(id),
+            expires: self.expires_at(id),
+            vector,
+            fields,
+        })
+    }
+
     // Add a StoredEmbedding to the vector store
     pub fn add_stored_embedding(
         &mut self,
@@ -247,7 +3401,7 @@ impl VectorStore {
         text: String,
     ) -> Result<Uuid> {
         let device = self.device.as_ref().ok_or_else(|| {
-            candle_core::Error::Msg("Device not initialized in VectorStore".to_string())
+            ArrowError::Invalid("Device not initialized in VectorStore".to_string())
         })?;
 
         let embedding = Tensor::from_vec(
@@ -258,4 +3412,286 @@ impl VectorStore {
 
         self.add_with_filename(embedding, text, Some(stored_embedding.filename.clone()))
     }
+
+    // Train product quantization codebooks on the stored vectors and encode
+    // every node, so searches use ADC lookup tables
+    pub fn train_pq(&mut self, subspaces: usize, iterations: usize) -> Result<()> {
+        let vectors: Vec<Vec<f32>> = self.nodes.iter().map(|node| self.node_vector(node)).collect();
+        let pq = ProductQuantizer::train(&vectors, subspaces, iterations)
+            .map_err(ArrowError::Invalid)?;
+
+        for index in 0..self.nodes.len() {
+            let vector = self.node_vector(&self.nodes[index]);
+            self.nodes[index].pq_codes = pq.encode(&vector);
+        }
+        self.pq = Some(pq);
+        self.needs_compaction = true;
+        Ok(())
+    }
+
+    // Search with a Hamming pre-filter over binary codes of the vectors from
+    // now on, which is kept in the store
+    pub fn enable_binary(&mut self) {
+        self.binary = true;
+        self.encode_binary();
+        self.needs_compaction = true;
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    fn encode_binary(&mut self) {
+        let codes: Vec<Vec<u64>> = self
+            .nodes
+            .par_iter()
+            .map(|node| binary::encode(&self.node_vector(node)))
+            .collect();
+        for (node, bits) in self.nodes.iter_mut().zip(codes) {
+            node.bits = bits;
+        }
+    }
+
+    // Number of PQ subspaces, if codebooks have been trained
+    pub fn pq_subspaces(&self) -> Option<usize> {
+        self.pq.as_ref().map(|pq| pq.subspaces())
+    }
+
+    // Look up the bookkeeping recorded for a source file
+    pub fn source_record(&self, path: &str) -> Option<&SourceRecord> {
+        self.sources.get(path)
+    }
+
+    // Paths of every tracked source file
+    pub fn source_paths(&self) -> Vec<String> {
+        self.sources.keys().cloned().collect()
+    }
+
+    // Record the hash, mtime and chunk ids for a freshly indexed source file.
+    // A file only touched since it was indexed keeps its indexing time.
+    pub fn record_source(&mut self, path: String, hash: String, mtime: u64, chunk_ids: Vec<Uuid>) {
+        let indexed = match self.sources.get(&path) {
+            Some(record) if record.hash == hash => record.indexed,
+            _ => Some(dates::now()),
+        };
+        let chars = chunk_ids
+            .iter()
+            .filter_map(|id| self.texts.get(id))
+            .map(|text| text.chars().count())
+            .sum();
+        let record = SourceRecord {
+            hash,
+            mtime,
+            chunk_ids,
+            chars: Some(chars),
+            indexed,
+        };
+        self.log_deferred(WalEntry::Source {
+            path: path.clone(),
+            record: record.clone(),
+        });
+        self.sources.insert(path, record);
+    }
+
+    // Drop a tracked source file and all of its chunks, returning the number removed
+    pub fn remove_source(&mut self, path: &str) -> usize {
+        let chunk_ids = self.replacing_source(path);
+        if self.sources.remove(path).is_none() {
+            return 0;
+        }
+        self.log_deferred(WalEntry::RemoveSource {
+            path: path.to_string(),
+        });
+        self.remove_chunks(&chunk_ids)
+    }
+
+    // A tracked source's chunks, to drop with `remove_chunks` once its new
+    // chunks are in. Chunks added for the source until then keep the time
+    // the earliest of these was added.
+    pub fn replacing_source(&mut self, path: &str) -> Vec<Uuid> {
+        let chunk_ids = match self.sources.get(path) {
+            Some(record) => record.chunk_ids.clone(),
+            None => return Vec::new(),
+        };
+        if let Some(added) = chunk_ids.iter().filter_map(|id| self.added_at(id)).min() {
+            self.replaced_sources.insert(path.to_string(), added);
+        }
+        chunk_ids
+    }
+
+    // Drop chunks by id, returning the number that were still stored
+    pub fn remove_chunks(&mut self, ids: &[Uuid]) -> usize {
+        ids.iter().filter(|id| self.remove(id)).count()
+    }
+
+    // Drop chunks of a source file that its record doesn't list, as left by an
+    // add interrupted partway through the file, returning the number removed
+    pub fn remove_untracked(&mut self, path: &str) -> usize {
+        let tracked: HashSet<Uuid> = self
+            .sources
+            .get(path)
+            .map(|record| record.chunk_ids.iter().copied().collect())
+            .unwrap_or_default();
+        self.list_ids(DocumentOrder::Id, Some(path))
+            .into_iter()
+            .filter(|id| !tracked.contains(id) && self.remove(id))
+            .count()
+    }
+}
+
+// Degree percentiles and connectivity of a layer
+fn layer_stats(level: usize, layer: &Layer, entry: Option<NodeId>, nodes: usize) -> LayerStats {
+    let mut degrees: Vec<usize> = layer.links().map(|(_, neighbors)| neighbors.len()).collect();
+    degrees.sort_unstable();
+    let percentile = |p: usize| match degrees.len() {
+        0 => 0,
+        n => degrees[(n - 1) * p / 100],
+    };
+    let edges: usize = degrees.iter().sum();
+
+    let mut linked_to = Visited::new(nodes);
+    for (_, neighbors) in layer.links() {
+        for &neighbor in neighbors.iter().filter(|&&neighbor| (neighbor as usize) < nodes) {
+            linked_to.insert(neighbor);
+        }
+    }
+    let orphans = layer
+        .links()
+        .filter(|&(node, neighbors)| {
+            neighbors.is_empty() && (node as usize) < nodes && !linked_to.contains(node)
+        })
+        .count();
+
+    let reached = reachable(layer, entry, nodes);
+    LayerStats {
+        level,
+        nodes: layer.len(),
+        edges,
+        mean_degree: if degrees.is_empty() {
+            0.0
+        } else {
+            edges as f32 / degrees.len() as f32
+        },
+        median_degree: percentile(50),
+        p90_degree: percentile(90),
+        max_degree: degrees.last().copied().unwrap_or(0),
+        orphans,
+        unreachable: layer
+            .members()
+            .iter()
+            .filter(|&&node| (node as usize) >= nodes || !reached.contains(node))
+            .count(),
+    }
+}
+
+// Fail when a vector's length differs from `expected`, or take it as the
+// expected length when there is none yet
+fn check_dimension(expected: &mut Option<usize>, found: usize) -> Result<()> {
+    match *expected {
+        Some(expected) if expected != found => {
+            Err(ArrowError::DimensionMismatch { expected, found })
+        }
+        Some(_) => Ok(()),
+        None => {
+            *expected = Some(found);
+            Ok(())
+        }
+    }
+}
+
+// Dot product kept as eight separate sums, which the compiler turns into SIMD
+// instructions; a single running sum must be added in order, one at a time
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let a_chunks = a.chunks_exact(8);
+    let b_chunks = b.chunks_exact(8);
+    let tail: f32 = a_chunks
+        .remainder()
+        .iter()
+        .zip(b_chunks.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    let mut lanes = [0.0f32; 8];
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for ((lane, x), y) in lanes.iter_mut().zip(x).zip(y) {
+            *lane += x * y;
+        }
+    }
+    lanes.iter().sum::<f32>() + tail
+}
+
+// Scale a vector to unit length in place, returning its original norm
+fn normalize(vector: &mut [f32]) -> f32 {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for x in vector.iter_mut() {
+            *x /= norm;
+        }
+    }
+    norm
+}
+
+// Quantize a vector to int8 with a single per-vector scale
+fn quantize(vector: &[f32]) -> (Vec<i8>, f32) {
+    let max_abs = vector.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+    if max_abs == 0.0 {
+        return (vec![0; vector.len()], 0.0);
+    }
+    let scale = max_abs / 127.0;
+    let codes = vector
+        .iter()
+        .map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8)
+        .collect();
+    (codes, scale)
+}
+
+// Make a rename durable by syncing the directory that holds it
+fn sync_parent_dir(path: &Path) {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut with_suffix = path.as_os_str().to_owned();
+    with_suffix.push(suffix);
+    PathBuf::from(with_suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents(vectors: &[Vec<f32>]) -> Vec<Document> {
+        vectors
+            .iter()
+            .enumerate()
+            .map(|(i, vector)| {
+                let embedding = Tensor::from_vec(vector.clone(), vector.len(), &Device::Cpu);
+                Document::new(embedding.unwrap(), format!("document {}", i))
+            })
+            .collect()
+    }
+
+    // Near-duplicates added together all pick the same neighbors, whose lists
+    // fill up before every new node gets a link back
+    #[test]
+    fn every_node_of_a_batch_stays_reachable() {
+        let mut rng = rand::thread_rng();
+        let mut store = VectorStore::new(Device::Cpu, 4);
+        let spread: Vec<Vec<f32>> =
+            (0..200).map(|_| (0..8).map(|_| rng.gen_range(-1.0..1.0)).collect()).collect();
+        store.add_batch(documents(&spread)).unwrap();
+        let duplicates: Vec<Vec<f32>> = (0..300)
+            .map(|i| (0..8).map(|d| if d == 0 { 1.0 } else { i as f32 * 1e-4 }).collect())
+            .collect();
+        store.add_batch(documents(&duplicates)).unwrap();
+
+        let problems = store.check();
+        let first = problems.first().map(|problem| problem.issue.clone());
+        assert!(problems.is_empty(), "{} problems, the first {:?}", problems.len(), first);
+    }
 }
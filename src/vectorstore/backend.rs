@@ -0,0 +1,104 @@
+use super::json::JsonFile;
+use super::sqlite::{self, Sqlite};
+use super::{with_suffix, VectorStore};
+use crate::wal::WalEntry;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// How a store is persisted. `VectorStore` keeps everything in memory and
+// logs each change to its WAL as it happens; a backend reads the saved store
+// back, writes it in full, and may save the WAL's changes without a full
+// write. Saving, locking and the WAL itself are the store's business, so a
+// backend only deals with its own files.
+pub trait StorageBackend: Sync {
+    // The store as of its last full save
+    fn load(&self, path: &Path) -> io::Result<VectorStore>;
+
+    // Write the whole store, replacing everything saved before. The store's
+    // WAL is cleared once this returns.
+    fn save(&self, store: &mut VectorStore, path: &Path) -> io::Result<()>;
+
+    // Save the changes in the store's WAL on top of what is saved, returning
+    // false when the backend can't, and the store should be saved in full
+    fn append(&self, store: &mut VectorStore, path: &Path) -> io::Result<bool>;
+
+    // The batches of changes appended since the last full save, oldest first,
+    // for loading to replay on top of `load`
+    fn iterate(&self, store: &mut VectorStore, path: &Path) -> io::Result<Vec<Vec<WalEntry>>>;
+
+    // Bytes of the files `load` and `iterate` read
+    fn size(&self, store: &VectorStore, path: &Path) -> io::Result<u64>;
+}
+
+// How a store is kept on disk
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    // A JSON file, with append-only segments of changes beside it
+    #[default]
+    Json,
+    // A SQLite database with a table per kind of record, saved in one transaction
+    Sqlite,
+}
+
+impl StorageEngine {
+    // Engine for a new store: SQLite for .db, .sqlite and .sqlite3 paths,
+    // otherwise JSON
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("db" | "sqlite" | "sqlite3") => StorageEngine::Sqlite,
+            _ => StorageEngine::Json,
+        }
+    }
+
+    // Engine of an existing store file, told apart by its first bytes
+    pub(super) fn of_file(path: &Path) -> io::Result<Self> {
+        let mut magic = [0; 16];
+        let mut file = File::open(path)?;
+        match file.read_exact(&mut magic) {
+            Ok(()) if &magic == sqlite::MAGIC => Ok(StorageEngine::Sqlite),
+            Ok(()) => Ok(StorageEngine::Json),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(StorageEngine::Json),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(super) fn backend(self) -> &'static dyn StorageBackend {
+        match self {
+            StorageEngine::Json => &JsonFile,
+            StorageEngine::Sqlite => &Sqlite,
+        }
+    }
+}
+
+// Shift `<path>.bak.i` to `<path>.bak.i+1` and keep the current file as
+// `<path>.bak.1`, dropping the oldest of `backups` generations. A file that
+// is replaced by rename can be hard linked; one changed in place is copied.
+pub(super) fn rotate_backups(path: &Path, backups: usize, link: bool) -> io::Result<()> {
+    if backups == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    let oldest = backup_path(path, backups);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for generation in (1..backups).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, generation + 1))?;
+        }
+    }
+
+    let newest = backup_path(path, 1);
+    if !link || fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak.{}", generation))
+}
@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+// Position of a node in the store's `nodes`. The graph refers to nodes by
+// these instead of UUIDs, so a hop is a few vector lookups instead of hashing;
+// UUIDs are translated at the edges of the store.
+pub type NodeId = u32;
+
+// Position of a node that isn't on a layer
+pub const ABSENT: u32 = u32::MAX;
+
+// One layer of the graph: which nodes are on it and their links. The vectors
+// are in the store's `nodes`, once however many layers a node is on.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Layer {
+    // Nodes on the layer
+    members: Vec<NodeId>,
+    // Links of each member, in the same order
+    neighbors: Vec<Vec<NodeId>>,
+    // Position among `members` of each node, or ABSENT. Rebuilt on load.
+    #[serde(skip)]
+    positions: Vec<u32>,
+}
+
+impl Layer {
+    pub fn new(members: Vec<NodeId>, neighbors: Vec<Vec<NodeId>>, nodes: usize) -> Self {
+        let mut layer = Self {
+            members,
+            neighbors,
+            positions: Vec::new(),
+        };
+        layer.neighbors.resize_with(layer.members.len(), Vec::new);
+        layer.index(nodes);
+        layer
+    }
+
+    // Rebuild the positions for a store of `nodes` nodes. Members past the
+    // end are left for `check` to report.
+    pub fn index(&mut self, nodes: usize) {
+        self.positions = vec![ABSENT; nodes];
+        for (position, &node) in self.members.iter().enumerate() {
+            if let Some(slot) = self.positions.get_mut(node as usize) {
+                *slot = position as u32;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    // First member, which becomes the store's entry point if it has none on
+    // the top layer
+    pub fn entry(&self) -> Option<NodeId> {
+        self.members.first().copied()
+    }
+
+    pub fn members(&self) -> &[NodeId] {
+        &self.members
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.position(node).is_some()
+    }
+
+    // Position of a node among the members, if it is on the layer
+    pub fn position(&self, node: NodeId) -> Option<usize> {
+        match self.positions.get(node as usize) {
+            Some(&position) if position != ABSENT => Some(position as usize),
+            _ => None,
+        }
+    }
+
+    // Links of a node on the layer
+    pub fn neighbors(&self, node: NodeId) -> &[NodeId] {
+        match self.position(node) {
+            Some(position) => &self.neighbors[position],
+            None => &[],
+        }
+    }
+
+    // Members with their links
+    pub fn links(&self) -> impl Iterator<Item = (NodeId, &[NodeId])> {
+        self.members.iter().copied().zip(self.neighbors.iter().map(Vec::as_slice))
+    }
+
+    pub fn push(&mut self, node: NodeId) {
+        if self.positions.len() <= node as usize {
+            self.positions.resize(node as usize + 1, ABSENT);
+        }
+        self.positions[node as usize] = self.members.len() as u32;
+        self.members.push(node);
+        self.neighbors.push(Vec::new());
+    }
+
+    // Link `from` to `to`, unless it already has `limit` links
+    pub fn link(&mut self, from: NodeId, to: NodeId, limit: usize) {
+        if let Some(position) = self.position(from) {
+            let neighbors = &mut self.neighbors[position];
+            if neighbors.len() < limit && !neighbors.contains(&to) {
+                neighbors.push(to);
+            }
+        }
+    }
+
+    // Take a node off the layer, returning its links, and the nodes that
+    // linked to it
+    pub fn remove(&mut self, node: NodeId) -> Option<(Vec<NodeId>, Vec<NodeId>)> {
+        let position = self.position(node)?;
+        self.positions[node as usize] = ABSENT;
+        self.members.swap_remove(position);
+        let removed = self.neighbors.swap_remove(position);
+        if let Some(&moved) = self.members.get(position) {
+            self.positions[moved as usize] = position as u32;
+        }
+        // Links are directed, so any node may point at the removed one
+        let mut linked_from = Vec::new();
+        for (&member, neighbors) in self.members.iter().zip(&mut self.neighbors) {
+            let before = neighbors.len();
+            neighbors.retain(|&neighbor| neighbor != node);
+            if neighbors.len() < before {
+                linked_from.push(member);
+            }
+        }
+        Some((removed, linked_from))
+    }
+
+    // Refer to node `from` as `to`, after it moved in the store's `nodes`
+    pub fn renumber(&mut self, from: NodeId, to: NodeId) {
+        for neighbor in self.neighbors.iter_mut().flatten() {
+            if *neighbor == from {
+                *neighbor = to;
+            }
+        }
+        if let Some(position) = self.position(from) {
+            self.members[position] = to;
+            self.positions[to as usize] = position as u32;
+        }
+        self.positions.truncate(from as usize);
+    }
+
+    // Renumber the nodes by `map`, dropping those it maps to ABSENT or that
+    // `keep` rejects, duplicate members, and links to nodes no longer on the
+    // layer or back to the node itself
+    pub fn retain_mapped(&mut self, map: &[u32], nodes: usize, keep: impl Fn(NodeId) -> bool) {
+        let mut seen = vec![false; nodes];
+        let mut kept = Vec::with_capacity(self.members.len());
+        for (&member, neighbors) in self.members.iter().zip(self.neighbors.drain(..)) {
+            let node = map.get(member as usize).copied().unwrap_or(ABSENT);
+            if node == ABSENT || seen[node as usize] || !keep(node) {
+                continue;
+            }
+            seen[node as usize] = true;
+            kept.push((node, neighbors));
+        }
+        self.members = kept.iter().map(|(node, _)| *node).collect();
+        self.index(nodes);
+        for (node, mut neighbors) in kept {
+            neighbors = neighbors
+                .into_iter()
+                .map(|neighbor| map.get(neighbor as usize).copied().unwrap_or(ABSENT))
+                .filter(|&neighbor| neighbor != node && self.contains(neighbor))
+                .collect();
+            neighbors.sort_unstable();
+            neighbors.dedup();
+            self.neighbors.push(neighbors);
+        }
+    }
+
+    // Rough bytes held in memory
+    pub fn memory_bytes(&self) -> usize {
+        self.members.len() * 4
+            + self.neighbors.iter().map(|links| links.len() * 4 + 24).sum::<usize>()
+            + self.positions.len() * 4
+    }
+}
+
+// Nodes a search has already seen, a bit per node
+pub struct Visited(Vec<u64>);
+
+impl Visited {
+    pub fn new(nodes: usize) -> Self {
+        Self(vec![0; nodes.div_ceil(64)])
+    }
+
+    // Mark a node seen, returning whether it wasn't before
+    pub fn insert(&mut self, node: NodeId) -> bool {
+        let (word, bit) = (node as usize / 64, 1 << (node % 64));
+        let new = self.0[word] & bit == 0;
+        self.0[word] |= bit;
+        new
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        self.0[node as usize / 64] & (1 << (node % 64)) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+// Nodes a search can visit: searches enter every layer from the store's entry
+// point and follow links outwards, so anything not reachable from it can't be
+// found
+pub fn reachable(layer: &Layer, entry: Option<NodeId>, nodes: usize) -> Visited {
+    let mut reached = Visited::new(nodes);
+    let mut frontier: Vec<NodeId> =
+        entry.filter(|&node| layer.contains(node)).into_iter().collect();
+    while let Some(node) = frontier.pop() {
+        if (node as usize) < nodes && reached.insert(node) {
+            frontier.extend(layer.neighbors(node));
+        }
+    }
+    reached
+}
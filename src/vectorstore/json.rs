@@ -0,0 +1,224 @@
+use super::backend::{rotate_backups, StorageBackend};
+use super::{sync_parent_dir, with_suffix, VectorStore};
+use crate::crypto::{self, Cipher};
+use crate::wal::{self, WalEntry};
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// A save compacts the segments into the store file once there would be more
+// than this many of them...
+const MAX_SEGMENTS: usize = 16;
+
+// ...or once they hold more than this fraction of the store file's bytes, as
+// every load has to replay them
+const MAX_SEGMENT_FRACTION: f64 = 0.25;
+
+// The store as one JSON file. Saves between full writes seal the WAL as an
+// immutable, numbered segment file beside it, and f32 vectors may be kept in
+// a memory-mapped vectors file. An encrypted store's JSON is sealed whole,
+// behind a header with its key derivation parameters.
+pub struct JsonFile;
+
+impl StorageBackend for JsonFile {
+    fn load(&self, path: &Path) -> io::Result<VectorStore> {
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+        let mut cipher = None;
+        if contents.starts_with(crypto::MAGIC) {
+            let file_cipher = Cipher::from_file(&crypto::secret(path, false)?, &contents)?;
+            contents = file_cipher.open_file(&contents)?;
+            cipher = Some(file_cipher);
+        }
+        let mut store: VectorStore = serde_json::from_slice(&contents)?;
+        store.cipher = cipher;
+        if store.mmap_vectors {
+            store.mmap = map_file(&vectors_path(path, store.vectors_generation))?;
+        }
+        Ok(store)
+    }
+
+    // The JSON is written to a temporary file, fsynced and renamed into
+    // place, so a crash never leaves a truncated store; the segments are
+    // deleted only after the rename succeeds. The previous file is kept as
+    // the newest of the rotating backups.
+    fn save(&self, store: &mut VectorStore, path: &Path) -> io::Result<()> {
+        if let Some(last) = store.segments.last() {
+            store.next_segment = last + 1;
+        }
+
+        let previous_generation = store.vectors_generation;
+        if store.mmap_vectors {
+            write_vectors(store, path)?;
+        }
+
+        let tmp_path = with_suffix(path, ".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        match &store.cipher {
+            Some(cipher) => writer.write_all(&cipher.seal_file(&serde_json::to_vec(&*store)?)?)?,
+            None => serde_json::to_writer(&mut writer, &*store)?,
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        rotate_backups(path, store.backups, true)?;
+        fs::rename(&tmp_path, path)?;
+        sync_parent_dir(path);
+
+        if store.mmap_vectors && previous_generation != store.vectors_generation {
+            // Backups still refer to the last `backups` vectors files
+            if let Some(stale) = previous_generation.checked_sub(store.backups as u64) {
+                let _ = fs::remove_file(vectors_path(path, stale));
+            }
+        }
+
+        for number in segment_numbers(path)? {
+            if number < store.next_segment {
+                fs::remove_file(segment_path(path, number))?;
+            }
+        }
+        store.segments.clear();
+        Ok(())
+    }
+
+    // Seal the WAL as the next segment, unless that would leave too many or
+    // too much to replay
+    fn append(&self, store: &mut VectorStore, path: &Path) -> io::Result<bool> {
+        if segments_too_large(store, path)? {
+            return Ok(false);
+        }
+        let number = match store.segments.last() {
+            Some(last) => last + 1,
+            None => store.next_segment,
+        };
+        if let Some(wal) = &mut store.wal {
+            if wal.seal(&segment_path(path, number))? {
+                sync_parent_dir(path);
+                store.segments.push(number);
+            }
+        }
+        Ok(true)
+    }
+
+    // Segments below the store's `next_segment` are already in the file,
+    // left over from a save interrupted before it could delete them
+    fn iterate(&self, store: &mut VectorStore, path: &Path) -> io::Result<Vec<Vec<WalEntry>>> {
+        let mut batches = Vec::new();
+        for number in segment_numbers(path)? {
+            if number >= store.next_segment {
+                let segment = segment_path(path, number);
+                batches.push(wal::read_file(&segment, store.cipher.as_ref())?);
+                store.segments.push(number);
+            }
+        }
+        Ok(batches)
+    }
+
+    // Older vectors files are kept only for the backups that refer to them
+    fn size(&self, store: &VectorStore, path: &Path) -> io::Result<u64> {
+        let len = |file: PathBuf| fs::metadata(file).map(|meta| meta.len()).unwrap_or(0);
+        let mut bytes = fs::metadata(path)?.len();
+        for &number in &store.segments {
+            bytes += len(segment_path(path, number));
+        }
+        if store.mmap_vectors {
+            bytes += len(vectors_path(path, store.vectors_generation));
+        }
+        Ok(bytes)
+    }
+}
+
+// Whether sealing the WAL as one more segment would leave too many, or too
+// much to replay, next to a store file that exists
+fn segments_too_large(store: &VectorStore, path: &Path) -> io::Result<bool> {
+    let base = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+    if store.segments.len() >= MAX_SEGMENTS {
+        return Ok(true);
+    }
+    let mut bytes = match &store.wal {
+        Some(wal) => wal.size()?,
+        None => 0,
+    };
+    for &number in &store.segments {
+        bytes += fs::metadata(segment_path(path, number)).map_or(0, |m| m.len());
+    }
+    Ok(bytes as f64 > base as f64 * MAX_SEGMENT_FRACTION)
+}
+
+// Write every vector as a row of a new vectors file, then map it and drop
+// the inline copies
+fn write_vectors(store: &mut VectorStore, path: &Path) -> io::Result<()> {
+    let generation = store.vectors_generation + 1;
+    let vectors_path = vectors_path(path, generation);
+
+    let mut dim = store.dim;
+    let mut rows = HashMap::new();
+    let mut writer = BufWriter::new(File::create(&vectors_path)?);
+    for node in &store.nodes {
+        let vector = store.vector_of(node);
+        dim = vector.len();
+        for x in vector {
+            writer.write_all(&x.to_ne_bytes())?;
+        }
+        rows.insert(node.id, rows.len());
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    store.vectors_generation = generation;
+    store.dim = dim;
+    store.mmap = map_file(&vectors_path)?;
+    for node in &mut store.nodes {
+        node.row = Some(rows[&node.id]);
+        node.vector = Vec::new();
+    }
+    Ok(())
+}
+
+// Path of the flat vectors file that accompanies a store using the mmap layout
+fn vectors_path(path: &Path, generation: u64) -> PathBuf {
+    with_suffix(path, &format!(".vectors.{}", generation))
+}
+
+fn segment_path(path: &Path, number: u64) -> PathBuf {
+    with_suffix(path, &format!(".seg.{}", number))
+}
+
+// Numbers of the segment files beside a store, in order
+fn segment_numbers(path: &Path) -> io::Result<Vec<u64>> {
+    let prefix = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => format!("{}.seg.", name),
+        None => return Ok(Vec::new()),
+    };
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut numbers: Vec<u64> = fs::read_dir(parent)?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.strip_prefix(&prefix)?.parse().ok()
+        })
+        .collect();
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+fn map_file(path: &Path) -> io::Result<Option<Mmap>> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        // Empty files can't be mapped
+        return Ok(None);
+    }
+    // Safety: the vectors file is only ever replaced by rename, never modified in place
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(Some(mmap))
+}
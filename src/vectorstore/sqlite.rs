@@ -0,0 +1,497 @@
+use super::backend::{rotate_backups, StorageBackend};
+use super::graph::{Layer, NodeId};
+use super::{Node, Provenance, SourceRecord, StorageMode, VectorStore};
+use crate::wal::WalEntry;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, Row, Transaction};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+// First bytes of every SQLite database file
+pub const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+// Tables of a SQLite store. Vectors are little-endian f32 BLOBs, or f16 in
+// f16 stores (int8 and PQ codes are BLOBs of bytes), each node's data is kept
+// once for all the layers it is in, and the graph is an adjacency table.
+// Everything else about the store, such as its model and index parameters, is
+// one JSON object in `settings`.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS nodes (
+    id TEXT PRIMARY KEY,
+    vector BLOB NOT NULL,
+    norm REAL NOT NULL,
+    codes BLOB NOT NULL,
+    scale REAL NOT NULL,
+    pq_codes BLOB NOT NULL,
+    tombstone INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fields (
+    id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    vector BLOB NOT NULL,
+    PRIMARY KEY (id, name)
+);
+CREATE TABLE IF NOT EXISTS layers (
+    level INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (level, position)
+);
+CREATE TABLE IF NOT EXISTS edges (
+    level INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    neighbor TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS documents (
+    id TEXT PRIMARY KEY,
+    text TEXT NOT NULL,
+    filename TEXT,
+    span_start INTEGER,
+    span_end INTEGER,
+    added INTEGER,
+    updated INTEGER,
+    source_path TEXT,
+    record INTEGER,
+    chunk_index INTEGER,
+    chunk_count INTEGER
+);
+CREATE TABLE IF NOT EXISTS metadata (
+    id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (id, key)
+);
+CREATE TABLE IF NOT EXISTS external_ids (
+    external_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (external_id, position)
+);
+CREATE TABLE IF NOT EXISTS sources (
+    path TEXT PRIMARY KEY,
+    hash TEXT NOT NULL,
+    mtime INTEGER NOT NULL,
+    chars INTEGER,
+    indexed INTEGER
+);
+CREATE TABLE IF NOT EXISTS source_chunks (
+    path TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    PRIMARY KEY (path, position)
+);
+";
+
+const TABLES: &[&str] = &[
+    "settings",
+    "nodes",
+    "fields",
+    "layers",
+    "edges",
+    "documents",
+    "metadata",
+    "external_ids",
+    "sources",
+    "source_chunks",
+];
+
+// Columns added to tables after their first release, with their types, which
+// databases written before them gain on their next save
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("sources", "chars", "INTEGER"),
+    ("sources", "indexed", "INTEGER"),
+    ("documents", "source_path", "TEXT"),
+    ("documents", "record", "INTEGER"),
+    ("documents", "chunk_index", "INTEGER"),
+    ("documents", "chunk_count", "INTEGER"),
+];
+
+// The store as a SQLite database, written in full on every save
+pub struct Sqlite;
+
+impl StorageBackend for Sqlite {
+    fn load(&self, path: &Path) -> io::Result<VectorStore> {
+        read(path)
+    }
+
+    // The database is changed in place, so the backup is a copy
+    fn save(&self, store: &mut VectorStore, path: &Path) -> io::Result<()> {
+        rotate_backups(path, store.backups, false)?;
+        write(store, path)
+    }
+
+    fn append(&self, _store: &mut VectorStore, _path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    fn iterate(&self, _store: &mut VectorStore, _path: &Path) -> io::Result<Vec<Vec<WalEntry>>> {
+        Ok(Vec::new())
+    }
+
+    fn size(&self, _store: &VectorStore, path: &Path) -> io::Result<u64> {
+        Ok(std::fs::metadata(path)?.len())
+    }
+}
+
+// Replace the store's rows in one transaction, so a crash or failed save
+// leaves the previous save as it was
+fn write(store: &mut VectorStore, path: &Path) -> io::Result<()> {
+    let settings = settings(store)?;
+    let mut connection = Connection::open(path).map_err(io::Error::other)?;
+    let transaction = connection.transaction().map_err(io::Error::other)?;
+    write_tables(&transaction, store, &settings).map_err(io::Error::other)?;
+    transaction.commit().map_err(io::Error::other)
+}
+
+fn read(path: &Path) -> io::Result<VectorStore> {
+    let connection = Connection::open(path).map_err(io::Error::other)?;
+    let settings: String = connection
+        .query_row("SELECT value FROM settings WHERE key = 'store'", [], |row| row.get(0))
+        .map_err(io::Error::other)?;
+    let mut store: VectorStore = serde_json::from_str(&settings)?;
+    read_tables(&connection, &mut store).map_err(io::Error::other)?;
+    Ok(store)
+}
+
+// The store's JSON without the graph and documents, which have tables of
+// their own
+fn settings(store: &mut VectorStore) -> io::Result<String> {
+    let contents = (
+        std::mem::take(&mut store.nodes),
+        std::mem::take(&mut store.layers),
+        std::mem::take(&mut store.texts),
+        std::mem::take(&mut store.filenames),
+        std::mem::take(&mut store.sources),
+        std::mem::take(&mut store.external_ids),
+        std::mem::take(&mut store.metadata),
+        std::mem::take(&mut store.spans),
+        std::mem::take(&mut store.added),
+        std::mem::take(&mut store.updated),
+        std::mem::take(&mut store.tombstones),
+        std::mem::take(&mut store.provenance),
+    );
+    let json = serde_json::to_string(&*store);
+    (
+        store.nodes,
+        store.layers,
+        store.texts,
+        store.filenames,
+        store.sources,
+        store.external_ids,
+        store.metadata,
+        store.spans,
+        store.added,
+        store.updated,
+        store.tombstones,
+        store.provenance,
+    ) = contents;
+    Ok(json?)
+}
+
+fn write_tables(
+    transaction: &Transaction,
+    store: &VectorStore,
+    settings: &str,
+) -> rusqlite::Result<()> {
+    transaction.execute_batch(SCHEMA)?;
+    for (table, column, kind) in ADDED_COLUMNS {
+        if !has_column(transaction, table, column)? {
+            let alter = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, kind);
+            transaction.execute(&alter, [])?;
+        }
+    }
+    for table in TABLES {
+        transaction.execute(&format!("DELETE FROM {}", table), [])?;
+    }
+    transaction.execute(
+        "INSERT INTO settings (key, value) VALUES ('store', ?1)",
+        params![settings],
+    )?;
+
+    let mut insert_node = transaction.prepare(
+        "INSERT INTO nodes (id, vector, norm, codes, scale, pq_codes, tombstone)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    let mut insert_field =
+        transaction.prepare("INSERT INTO fields (id, name, vector) VALUES (?1, ?2, ?3)")?;
+    for node in &store.nodes {
+        let id = node.id.to_string();
+        let codes: Vec<u8> = node.codes.iter().map(|&c| c as u8).collect();
+        let vector = match store.storage {
+            StorageMode::F16 => node.halves.iter().flat_map(|x| x.to_le_bytes()).collect(),
+            _ => to_blob(store.vector_of(node)),
+        };
+        insert_node.execute(params![
+            id,
+            vector,
+            node.norm,
+            codes,
+            node.scale,
+            node.pq_codes,
+            store.tombstones.contains(&node.id),
+        ])?;
+        for (name, vector) in &node.fields {
+            insert_field.execute(params![id, name, to_blob(vector)])?;
+        }
+    }
+
+    let mut insert_member =
+        transaction.prepare("INSERT INTO layers (level, position, id) VALUES (?1, ?2, ?3)")?;
+    let mut insert_edge =
+        transaction.prepare("INSERT INTO edges (level, id, neighbor) VALUES (?1, ?2, ?3)")?;
+    for (level, layer) in store.layers.iter().enumerate() {
+        for (position, (node, neighbors)) in layer.links().enumerate() {
+            let id = store.nodes[node as usize].id.to_string();
+            insert_member.execute(params![level, position, id])?;
+            for &neighbor in neighbors {
+                let neighbor = store.nodes[neighbor as usize].id.to_string();
+                insert_edge.execute(params![level, id, neighbor])?;
+            }
+        }
+    }
+
+    let mut insert_document = transaction.prepare(
+        "INSERT INTO documents (id, text, filename, span_start, span_end, added, updated,
+                                source_path, record, chunk_index, chunk_count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+    )?;
+    for (id, text) in &store.texts {
+        let span = store.spans.get(id);
+        let provenance = store.provenance.get(id);
+        insert_document.execute(params![
+            id.to_string(),
+            text,
+            store.filenames.get(id),
+            span.map(|span| span.0),
+            span.map(|span| span.1),
+            store.added.get(id),
+            store.updated.get(id),
+            provenance.map(|p| &p.source_path),
+            provenance.and_then(|p| p.record).map(|record| record as i64),
+            provenance.map(|p| p.chunk_index as i64),
+            provenance.map(|p| p.chunk_count as i64),
+        ])?;
+    }
+    let mut insert_metadata =
+        transaction.prepare("INSERT INTO metadata (id, key, value) VALUES (?1, ?2, ?3)")?;
+    for (id, metadata) in &store.metadata {
+        for (key, value) in metadata {
+            insert_metadata.execute(params![id.to_string(), key, value])?;
+        }
+    }
+    let mut insert_external = transaction
+        .prepare("INSERT INTO external_ids (external_id, position, id) VALUES (?1, ?2, ?3)")?;
+    for (external_id, ids) in &store.external_ids {
+        for (position, id) in ids.iter().enumerate() {
+            insert_external.execute(params![external_id, position, id.to_string()])?;
+        }
+    }
+
+    let mut insert_source = transaction.prepare(
+        "INSERT INTO sources (path, hash, mtime, chars, indexed) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    let mut insert_chunk = transaction
+        .prepare("INSERT INTO source_chunks (path, position, id) VALUES (?1, ?2, ?3)")?;
+    for (path, record) in &store.sources {
+        let chars = record.chars.map(|chars| chars as i64);
+        insert_source.execute(params![path, record.hash, record.mtime, chars, record.indexed])?;
+        for (position, id) in record.chunk_ids.iter().enumerate() {
+            insert_chunk.execute(params![path, position, id.to_string()])?;
+        }
+    }
+    Ok(())
+}
+
+fn read_tables(connection: &Connection, store: &mut VectorStore) -> rusqlite::Result<()> {
+    let mut nodes = HashMap::new();
+    let mut select = connection
+        .prepare("SELECT id, vector, norm, codes, scale, pq_codes, tombstone FROM nodes")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let id = uuid(row, 0)?;
+        let codes: Vec<u8> = row.get(3)?;
+        let vector: Vec<u8> = row.get(1)?;
+        let (vector, halves) = match store.storage {
+            StorageMode::F16 => (Vec::new(), from_half_blob(&vector)),
+            _ => (from_blob(&vector), Vec::new()),
+        };
+        let node = Node {
+            id,
+            vector,
+            norm: row.get(2)?,
+            halves,
+            codes: codes.into_iter().map(|c| c as i8).collect(),
+            scale: row.get(4)?,
+            pq_codes: row.get(5)?,
+            bits: Vec::new(),
+            row: None,
+            fields: HashMap::new(),
+        };
+        if row.get(6)? {
+            store.tombstones.insert(id);
+        }
+        nodes.insert(id, node);
+    }
+
+    let mut select = connection.prepare("SELECT id, name, vector FROM fields")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        if let Some(node) = nodes.get_mut(&uuid(row, 0)?) {
+            node.fields.insert(row.get(1)?, from_blob(&row.get::<_, Vec<u8>>(2)?));
+        }
+    }
+
+    // The nodes are kept in the order of the base layer, which is read first
+    let mut index: HashMap<Uuid, NodeId> = HashMap::new();
+    let mut layers: Vec<Layer> = vec![Layer::default()];
+    let mut select =
+        connection.prepare("SELECT level, id FROM layers ORDER BY level, position")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let level: usize = row.get(0)?;
+        let id = uuid(row, 1)?;
+        if level == 0 {
+            if let Some(node) = nodes.remove(&id) {
+                index.insert(id, store.nodes.len() as NodeId);
+                store.nodes.push(node);
+            }
+        }
+        if let Some(&node) = index.get(&id) {
+            if layers.len() <= level {
+                layers.resize_with(level + 1, Layer::default);
+            }
+            layers[level].push(node);
+        }
+    }
+
+    let mut select = connection.prepare("SELECT level, id, neighbor FROM edges")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let level: usize = row.get(0)?;
+        let (from, to) = (index.get(&uuid(row, 1)?), index.get(&uuid(row, 2)?));
+        if let (Some(layer), Some(&from), Some(&to)) = (layers.get_mut(level), from, to) {
+            layer.link(from, to, usize::MAX);
+        }
+    }
+    store.layers = layers;
+
+    // Databases written before provenance get it from the filenames on load
+    let query = if has_column(connection, "documents", "chunk_count")? {
+        "SELECT id, text, filename, span_start, span_end, added, updated,
+                source_path, record, chunk_index, chunk_count FROM documents"
+    } else {
+        "SELECT id, text, filename, span_start, span_end, added, updated,
+                NULL, NULL, NULL, NULL FROM documents"
+    };
+    let mut select = connection.prepare(query)?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let id = uuid(row, 0)?;
+        store.texts.insert(id, row.get(1)?);
+        if let Some(filename) = row.get(2)? {
+            store.filenames.insert(id, filename);
+        }
+        if let (Some(start), Some(end)) = (row.get(3)?, row.get(4)?) {
+            store.spans.insert(id, (start, end));
+        }
+        if let Some(added) = row.get(5)? {
+            store.added.insert(id, added);
+        }
+        if let Some(updated) = row.get(6)? {
+            store.updated.insert(id, updated);
+        }
+        let (index, count): (Option<i64>, Option<i64>) = (row.get(9)?, row.get(10)?);
+        if let (Some(source_path), Some(index), Some(count)) = (row.get(7)?, index, count) {
+            let record: Option<i64> = row.get(8)?;
+            let provenance = Provenance {
+                source_path,
+                record: record.map(|record| record as usize),
+                chunk_index: index as usize,
+                chunk_count: count as usize,
+            };
+            store.provenance.insert(id, provenance);
+        }
+    }
+
+    let mut select = connection.prepare("SELECT id, key, value FROM metadata")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let metadata = store.metadata.entry(uuid(row, 0)?).or_default();
+        metadata.insert(row.get(1)?, row.get(2)?);
+    }
+
+    let mut select = connection
+        .prepare("SELECT external_id, id FROM external_ids ORDER BY external_id, position")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        store.external_ids.entry(row.get(0)?).or_default().push(uuid(row, 1)?);
+    }
+
+    let query = if has_column(connection, "sources", "indexed")? {
+        "SELECT path, hash, mtime, chars, indexed FROM sources"
+    } else {
+        "SELECT path, hash, mtime, NULL, NULL FROM sources"
+    };
+    let mut select = connection.prepare(query)?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let chars: Option<i64> = row.get(3)?;
+        let record = SourceRecord {
+            hash: row.get(1)?,
+            mtime: row.get(2)?,
+            chunk_ids: Vec::new(),
+            chars: chars.map(|chars| chars as usize),
+            indexed: row.get(4)?,
+        };
+        store.sources.insert(row.get(0)?, record);
+    }
+    let mut select =
+        connection.prepare("SELECT path, id FROM source_chunks ORDER BY path, position")?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        if let Some(record) = store.sources.get_mut(&path) {
+            record.chunk_ids.push(uuid(row, 1)?);
+        }
+    }
+    Ok(())
+}
+
+// Whether a database written by an older version already has `column`
+fn has_column(connection: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut select = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        if row.get::<_, String>(1)? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn uuid(row: &Row, index: usize) -> rusqlite::Result<Uuid> {
+    let text: String = row.get(index)?;
+    Uuid::parse_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+fn to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn from_blob(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+fn from_half_blob(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect()
+}
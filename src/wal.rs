@@ -0,0 +1,271 @@
+use crate::crypto::Cipher;
+use crate::vectorstore::{Metadata, Provenance, SourceRecord, Span};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// A single mutation recorded in the write-ahead log
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalEntry {
+    Add {
+        id: Uuid,
+        vector: Vec<f32>,
+        norm: f32,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        fields: HashMap<String, Vec<f32>>,
+        text: String,
+        filename: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provenance: Option<Box<Provenance>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        span: Option<Span>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        external_id: Option<String>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        metadata: Metadata,
+        // Seconds since the Unix epoch, or 0 if unknown
+        #[serde(default)]
+        added: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        updated: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires: Option<u64>,
+    },
+    Remove {
+        id: Uuid,
+    },
+    Source {
+        path: String,
+        record: SourceRecord,
+    },
+    RemoveSource {
+        path: String,
+    },
+    // Set one metadata key of a document, or clear it when `value` is None
+    SetMetadata {
+        id: Uuid,
+        key: String,
+        value: Option<String>,
+    },
+}
+
+// Append-only log of mutations made since the store was last saved, stored as
+// JSON lines in `<path>.wal`, each sealed as a line of hex for an encrypted store
+pub struct Wal {
+    path: PathBuf,
+    // Opened on the first append, so a store nobody changes can be loaded
+    // from a directory we can't write to
+    writer: Option<BufWriter<File>>,
+    cipher: Option<Cipher>,
+}
+
+impl Wal {
+    pub fn open<P: AsRef<Path>>(store_path: P) -> Self {
+        Self {
+            path: wal_path(store_path.as_ref()),
+            writer: None,
+            cipher: None,
+        }
+    }
+
+    pub fn with_cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    pub fn append(&mut self, entry: &WalEntry) -> std::io::Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+                self.writer.insert(BufWriter::new(file))
+            }
+        };
+        match &self.cipher {
+            Some(cipher) => {
+                let line = cipher.seal_line(&serde_json::to_vec(entry)?)?;
+                writer.write_all(line.as_bytes())?;
+            }
+            None => serde_json::to_writer(&mut *writer, entry)?,
+        }
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    // Make every appended entry durable
+    pub fn sync(&mut self) -> std::io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => {
+                writer.flush()?;
+                writer.get_ref().sync_data()
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Discard all entries once they are covered by a successful save
+    pub fn reset(&mut self) -> std::io::Result<()> {
+        self.sync()?;
+        self.writer = None;
+        File::create(&self.path)?.sync_all()
+    }
+
+    // Make the logged entries durable and move them to an immutable segment
+    // file, starting a new empty log. Returns false, without creating the
+    // segment, if nothing was logged.
+    pub fn seal(&mut self, segment_path: &Path) -> std::io::Result<bool> {
+        self.sync()?;
+        if self.size()? == 0 {
+            return Ok(false);
+        }
+        self.writer = None;
+        fs::rename(&self.path, segment_path)?;
+        Ok(true)
+    }
+
+    // Bytes logged so far
+    pub fn size(&self) -> std::io::Result<u64> {
+        match fs::metadata(&self.path) {
+            Ok(meta) => Ok(meta.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// Read the entries logged next to a store. A torn final line from a crash
+// mid-append ends the log rather than failing the load.
+pub fn read_entries<P: AsRef<Path>>(
+    store_path: P,
+    cipher: Option<&Cipher>,
+) -> std::io::Result<Vec<WalEntry>> {
+    read_file(&wal_path(store_path.as_ref()), cipher)
+}
+
+// Read the entries of a log file, such as a sealed segment
+pub fn read_file(path: &Path, cipher: Option<&Cipher>) -> std::io::Result<Vec<WalEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let json = match cipher {
+            Some(cipher) => match cipher.open_line(&line) {
+                Ok(json) => json,
+                Err(_) => break,
+            },
+            None => line.into_bytes(),
+        };
+        match serde_json::from_slice(&json) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+    }
+    Ok(entries)
+}
+
+pub fn wal_path(store_path: &Path) -> PathBuf {
+    let mut path = store_path.as_os_str().to_owned();
+    path.push(".wal");
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A store path of its own in the temp directory, without a log yet
+    fn store_path(name: &str) -> PathBuf {
+        let name = format!("arrow-wal-{}-{}.json", name, std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(wal_path(&path));
+        path
+    }
+
+    fn removed_ids(entries: &[WalEntry]) -> Vec<Uuid> {
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                WalEntry::Remove { id } => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_entries_in_order() {
+        let path = store_path("order");
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let mut wal = Wal::open(&path);
+        for id in ids {
+            wal.append(&WalEntry::Remove { id }).unwrap();
+        }
+        wal.sync().unwrap();
+
+        assert_eq!(removed_ids(&read_entries(&path, None).unwrap()), ids);
+        fs::remove_file(wal_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn stops_at_a_line_cut_off_mid_append() {
+        let path = store_path("torn");
+        let ids = [Uuid::new_v4(), Uuid::new_v4()];
+        let mut wal = Wal::open(&path);
+        for id in ids {
+            wal.append(&WalEntry::Remove { id }).unwrap();
+        }
+        drop(wal);
+        let mut file = OpenOptions::new().append(true).open(wal_path(&path)).unwrap();
+        file.write_all(br#"{"op":"remove","id":"#).unwrap();
+
+        assert_eq!(removed_ids(&read_entries(&path, None).unwrap()), ids);
+        fs::remove_file(wal_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn reset_and_seal_empty_the_log() {
+        let path = store_path("seal");
+        let segment = path.with_extension("json.seg.0");
+        let mut wal = Wal::open(&path);
+        assert!(!wal.seal(&segment).unwrap());
+        assert!(!segment.exists());
+
+        let id = Uuid::new_v4();
+        wal.append(&WalEntry::Remove { id }).unwrap();
+        assert!(wal.seal(&segment).unwrap());
+        assert_eq!(removed_ids(&read_file(&segment, None).unwrap()), [id]);
+        assert!(read_entries(&path, None).unwrap().is_empty());
+
+        wal.append(&WalEntry::Remove { id }).unwrap();
+        wal.reset().unwrap();
+        assert_eq!(wal.size().unwrap(), 0);
+        assert!(read_entries(&path, None).unwrap().is_empty());
+        fs::remove_file(segment).unwrap();
+        fs::remove_file(wal_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn opens_the_log_on_the_first_append() {
+        let path = store_path("lazy");
+        let mut wal = Wal::open(&path);
+        wal.sync().unwrap();
+        assert_eq!(wal.size().unwrap(), 0);
+        assert!(!wal_path(&path).exists());
+
+        wal.append(&WalEntry::Remove { id: Uuid::new_v4() }).unwrap();
+        assert!(wal_path(&path).exists());
+        fs::remove_file(wal_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn missing_log_has_no_entries() {
+        let path = store_path("missing");
+        assert!(read_entries(&path, None).unwrap().is_empty());
+    }
+}